@@ -0,0 +1,264 @@
+use futures::{future::Either, Future};
+use http::{header::CONTENT_TYPE, Response as HttpResponse, StatusCode};
+use interledger_service::StoreError;
+use serde::Serialize;
+use std::fmt;
+use tower_web::{
+    error::Map,
+    response::{Context, Response, Serializer},
+    util::BufStream,
+};
+
+/// The category of an API error. Clients should match on this rather than on the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorType {
+    InvalidRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    LimitExceeded,
+    Unavailable,
+    PaymentFailed,
+    InternalError,
+}
+
+impl ApiErrorType {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ApiErrorType::InvalidRequest => StatusCode::BAD_REQUEST,
+            ApiErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiErrorType::Forbidden => StatusCode::FORBIDDEN,
+            ApiErrorType::NotFound => StatusCode::NOT_FOUND,
+            ApiErrorType::Conflict => StatusCode::CONFLICT,
+            ApiErrorType::LimitExceeded => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiErrorType::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiErrorType::PaymentFailed => StatusCode::BAD_GATEWAY,
+            ApiErrorType::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The JSON problem details returned by the API when a request fails.
+///
+/// For example: `{"type":"invalid_request","message":"Invalid account id","field":"id"}`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ApiError {
+    #[serde(rename = "type")]
+    pub error_type: ApiErrorType,
+    pub message: String,
+    /// The request field that caused the error, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(error_type: ApiErrorType, message: impl Into<String>) -> Self {
+        ApiError {
+            error_type,
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn invalid_field(field: &str, message: impl Into<String>) -> Self {
+        ApiError {
+            error_type: ApiErrorType::InvalidRequest,
+            message: message.into(),
+            field: Some(field.to_string()),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        ApiError::new(ApiErrorType::Unauthorized, "Invalid or missing credentials")
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::new(ApiErrorType::Forbidden, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(ApiErrorType::NotFound, message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.error_type.status()
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        let error_type = match err {
+            StoreError::NotFound(_) => ApiErrorType::NotFound,
+            StoreError::Conflict(_) => ApiErrorType::Conflict,
+            StoreError::LimitExceeded(_) => ApiErrorType::LimitExceeded,
+            StoreError::Unavailable(_) => ApiErrorType::Unavailable,
+            StoreError::Other(_) => ApiErrorType::InternalError,
+        };
+        ApiError::new(error_type, err.message())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref field) = self.field {
+            write!(f, "{} ({}): {}", self.status(), field, self.message)
+        } else {
+            write!(f, "{}: {}", self.status(), self.message)
+        }
+    }
+}
+
+impl Response for ApiError {
+    type Buf = <String as BufStream>::Item;
+    type Body = Map<String>;
+
+    fn into_http<S: Serializer>(
+        self,
+        context: &Context<S>,
+    ) -> Result<HttpResponse<Self::Body>, tower_web::Error> {
+        let body = serde_json::to_string(&self).expect("ApiError is always serializable");
+        HttpResponse::builder()
+            .status(self.status())
+            .header(CONTENT_TYPE, "application/problem+json")
+            .body(body)
+            .expect("Response with a valid status and header cannot fail to build")
+            .into_http(context)
+    }
+}
+
+/// Resource handlers in tower-web turn every future error into an empty 500 response,
+/// so the error is returned as the other side of an `Either` instead.
+pub(crate) fn respond<F, R>(future: F) -> impl Future<Item = Either<R, ApiError>, Error = ()>
+where
+    F: Future<Item = R, Error = ApiError>,
+{
+    future.then(|result| match result {
+        Ok(response) => Ok(Either::A(response)),
+        Err(err) => {
+            debug!("Responding to API request with error: {}", err);
+            Ok(Either::B(err))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{err, ok};
+    use tower_web::response::DefaultSerializer;
+
+    fn to_http(error: ApiError) -> (StatusCode, String, serde_json::Value) {
+        let request = http::Request::builder().body(()).unwrap();
+        let serializer = DefaultSerializer::new();
+        let context = Context::new(&request, &serializer);
+        let response = error.into_http(&context).unwrap();
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body: Vec<u8> = response.into_body().collect().wait().unwrap();
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn maps_each_type_to_a_status_code() {
+        let cases = [
+            (ApiErrorType::InvalidRequest, 400, "invalid_request"),
+            (ApiErrorType::Unauthorized, 401, "unauthorized"),
+            (ApiErrorType::Forbidden, 403, "forbidden"),
+            (ApiErrorType::NotFound, 404, "not_found"),
+            (ApiErrorType::Conflict, 409, "conflict"),
+            (ApiErrorType::LimitExceeded, 422, "limit_exceeded"),
+            (ApiErrorType::Unavailable, 503, "unavailable"),
+            (ApiErrorType::PaymentFailed, 502, "payment_failed"),
+            (ApiErrorType::InternalError, 500, "internal_error"),
+        ];
+        for (error_type, status, name) in cases.iter() {
+            let (response_status, content_type, body) =
+                to_http(ApiError::new(*error_type, "something went wrong"));
+            assert_eq!(response_status.as_u16(), *status);
+            assert_eq!(content_type, "application/problem+json");
+            assert_eq!(
+                body,
+                json!({
+                    "type": name,
+                    "message": "something went wrong",
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn includes_the_field_in_the_body() {
+        let (status, _, body) = to_http(ApiError::invalid_field("id", "Invalid account id"));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "type": "invalid_request",
+                "message": "Invalid account id",
+                "field": "id",
+            })
+        );
+    }
+
+    #[test]
+    fn converts_store_errors() {
+        let cases = vec![
+            (
+                StoreError::NotFound("a".to_string()),
+                ApiErrorType::NotFound,
+            ),
+            (
+                StoreError::Conflict("b".to_string()),
+                ApiErrorType::Conflict,
+            ),
+            (
+                StoreError::LimitExceeded("c".to_string()),
+                ApiErrorType::LimitExceeded,
+            ),
+            (
+                StoreError::Unavailable("d".to_string()),
+                ApiErrorType::Unavailable,
+            ),
+            (
+                StoreError::Other("e".to_string()),
+                ApiErrorType::InternalError,
+            ),
+        ];
+        for (store_error, error_type) in cases {
+            let message = store_error.message().to_string();
+            let error = ApiError::from(store_error);
+            assert_eq!(error, ApiError::new(error_type, message));
+        }
+    }
+
+    #[test]
+    fn respond_returns_errors_as_responses() {
+        match respond(ok::<_, ApiError>("done")).wait().unwrap() {
+            Either::A(response) => assert_eq!(response, "done"),
+            Either::B(error) => panic!("Unexpected error response: {}", error),
+        }
+
+        let failure = respond(err::<&str, _>(ApiError::unauthorized()))
+            .wait()
+            .unwrap();
+        match failure {
+            Either::B(error) => {
+                let (status, _, body) = to_http(error);
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(
+                    body,
+                    json!({
+                        "type": "unauthorized",
+                        "message": "Invalid or missing credentials",
+                    })
+                );
+            }
+            Either::A(_) => panic!("Expected an error response"),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use super::{format_timestamp, millis_since_epoch};
+use futures::{Future, Stream};
+use http::{header::CONTENT_TYPE, Response, StatusCode};
+use interledger_service::{Account, Event, EventBus};
+use parking_lot::RwLock;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// Rates older than this are considered stale. The Redis store reloads them every minute.
+const MAX_RATES_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// Keeps track of the BTP connections and CCP route updates published on the node's
+/// event bus so they can be reported by the `/health/ready` endpoint.
+#[derive(Clone)]
+pub struct HealthMonitor<A: Account> {
+    connections: Arc<RwLock<HashSet<A::AccountId>>>,
+    route_updates: Arc<RwLock<HashMap<A::AccountId, SystemTime>>>,
+}
+
+impl<A: Account + 'static> HealthMonitor<A> {
+    pub fn new() -> Self {
+        HealthMonitor {
+            connections: Arc::new(RwLock::new(HashSet::new())),
+            route_updates: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Returns a future that records the events published on the bus.
+    /// It should be spawned as a task so the monitor stays up to date.
+    pub fn listen(&self, events: &EventBus<A>) -> impl Future<Item = (), Error = ()> {
+        let connections = self.connections.clone();
+        let route_updates = self.route_updates.clone();
+        events.subscribe().for_each(move |event| {
+            match event {
+                Event::ConnectionOpened { account } => {
+                    connections.write().insert(account.id());
+                }
+                Event::ConnectionClosed { account } => {
+                    connections.write().remove(&account.id());
+                }
+                Event::RoutesReceived { from } => {
+                    route_updates.write().insert(from.id(), SystemTime::now());
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// Describe the node's components and whether it can forward packets.
+    /// Forwarding needs at least one connected BTP peer or a route in the routing table.
+    pub(crate) fn status(
+        &self,
+        store_reachable: bool,
+        rates_updated_at: Option<SystemTime>,
+        routes: usize,
+    ) -> (bool, Value) {
+        let rates_fresh = rates_updated_at
+            .map(|updated_at| {
+                updated_at
+                    .elapsed()
+                    .map(|age| age <= MAX_RATES_AGE)
+                    .unwrap_or(true)
+            })
+            .unwrap_or(false);
+        let route_updates: serde_json::Map<String, Value> = self
+            .route_updates
+            .read()
+            .iter()
+            .map(|(account_id, updated_at)| {
+                (
+                    account_id.to_string(),
+                    json!(format_timestamp(millis_since_epoch(*updated_at))),
+                )
+            })
+            .collect();
+        let btp_connections = self.connections.read().len();
+        let ready = store_reachable && rates_fresh && (btp_connections > 0 || routes > 0);
+        let status = json!({
+            "ready": ready,
+            "store_reachable": store_reachable,
+            "btp_connections": btp_connections,
+            "routes": routes,
+            "rates_updated_at": rates_updated_at.map(|updated_at| format_timestamp(millis_since_epoch(updated_at))),
+            "rates_fresh": rates_fresh,
+            "route_updates_received_at": route_updates,
+        });
+        (ready, status)
+    }
+
+    /// The `/health/ready` response: the status with 200 OK if the node is ready, or 503 otherwise.
+    pub(crate) fn ready_response(
+        &self,
+        store_reachable: bool,
+        rates_updated_at: Option<SystemTime>,
+        routes: usize,
+    ) -> Response<String> {
+        let (ready, status) = self.status(store_reachable, rates_updated_at, routes);
+        Response::builder()
+            .status(if ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            })
+            .header(CONTENT_TYPE, "application/json")
+            .body(status.to_string())
+            .expect("Response with a valid status and header cannot fail to build")
+    }
+}
+
+impl<A: Account + 'static> Default for HealthMonitor<A> {
+    fn default() -> Self {
+        HealthMonitor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn ready_status(
+        health: &HealthMonitor<TestAccount>,
+        store_reachable: bool,
+        rates_updated_at: Option<SystemTime>,
+        routes: usize,
+    ) -> (StatusCode, Value) {
+        let response = health.ready_response(store_reachable, rates_updated_at, routes);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let status = response.status();
+        (status, serde_json::from_str(response.body()).unwrap())
+    }
+
+    #[test]
+    fn ready_with_store_and_fresh_rates() {
+        let health = HealthMonitor::new();
+        let (status, body) = ready_status(&health, true, Some(SystemTime::now()), 1);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["store_reachable"], true);
+        assert_eq!(body["rates_fresh"], true);
+        assert!(body["rates_updated_at"].is_string());
+        assert_eq!(body["btp_connections"], 0);
+        assert_eq!(body["routes"], 1);
+    }
+
+    #[test]
+    fn degraded_without_peers_or_routes() {
+        let health = HealthMonitor::new();
+        let (status, body) = ready_status(&health, true, Some(SystemTime::now()), 0);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["btp_connections"], 0);
+        assert_eq!(body["routes"], 0);
+    }
+
+    #[test]
+    fn degraded_without_store() {
+        let health = HealthMonitor::new();
+        let (status, body) = ready_status(&health, false, Some(SystemTime::now()), 1);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["store_reachable"], false);
+    }
+
+    #[test]
+    fn degraded_with_missing_or_stale_rates() {
+        let health = HealthMonitor::new();
+        let (status, body) = ready_status(&health, true, None, 1);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["rates_fresh"], false);
+        assert_eq!(body["rates_updated_at"], Value::Null);
+
+        let stale = SystemTime::now() - MAX_RATES_AGE - Duration::from_secs(1);
+        let (status, body) = ready_status(&health, true, Some(stale), 1);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["rates_fresh"], false);
+    }
+
+    #[test]
+    fn tracks_connections_and_route_updates() {
+        let events = EventBus::new();
+        let health = HealthMonitor::new();
+        let listener = health.listen(&events);
+        events.publish(Event::ConnectionOpened {
+            account: TestAccount(1),
+        });
+        events.publish(Event::ConnectionOpened {
+            account: TestAccount(2),
+        });
+        events.publish(Event::ConnectionClosed {
+            account: TestAccount(1),
+        });
+        events.publish(Event::RoutesReceived {
+            from: TestAccount(2),
+        });
+        drop(events);
+        listener.wait().unwrap();
+
+        // A connected peer is enough to be ready without any routes
+        let (ready, status) = health.status(true, Some(SystemTime::now()), 0);
+        assert!(ready);
+        assert_eq!(status["btp_connections"], 1);
+        let route_updates = status["route_updates_received_at"].as_object().unwrap();
+        assert_eq!(route_updates.len(), 1);
+        assert!(route_updates["2"].is_string());
+    }
+}
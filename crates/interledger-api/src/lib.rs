@@ -6,29 +6,59 @@ extern crate log;
 #[macro_use]
 extern crate serde_json;
 
-use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
 use futures::{
     future::{err, ok, result, Either},
-    Future,
+    Future, Stream,
 };
-use http::{Request, Response};
-use hyper::{body::Body, error::Error};
-use interledger_http::{HttpAccount, HttpServerService, HttpStore};
+use http::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Response,
+};
+use hyper::Body;
+use interledger_ccp::{CcpRoutingAccount, RouteManagerStore, RoutePolicies, RoutingTableInspector};
+use interledger_http::{HttpAccount, HttpStore};
 use interledger_ildcp::IldcpAccount;
 use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, IncomingService};
-use interledger_service_util::BalanceStore;
-use interledger_spsp::{pay, SpspResponder};
-use serde::Serialize;
+use interledger_service::{Account as AccountTrait, Event, EventBus, IncomingService, StoreError};
+use interledger_service_util::{BalanceStore, Transaction, TransactionStore};
+use interledger_spsp::{pay, Error as SpspError};
+use interledger_webhooks::{WebhookDelivery, WebhookStore};
+use prometheus::{Encoder, TextEncoder};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
     collections::HashMap,
+    fmt, io,
     iter::FromIterator,
     str::{self, FromStr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use tokio::timer::Timeout;
+
+/// How long `/health/ready` waits for the store to respond
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+mod error;
+mod health;
+mod public;
+
+pub use error::{ApiError, ApiErrorType};
+pub use health::HealthMonitor;
+pub use public::PublicApi;
+
+use error::respond;
 
 pub trait NodeAccount: HttpAccount {
     fn is_admin(&self) -> bool;
+
+    /// The limits and routing options that can be changed with `NodeStore::update_account_settings`
+    fn settings(&self) -> AccountSettings;
 }
 
 pub trait NodeStore: Clone + Send + Sync + 'static {
@@ -37,16 +67,31 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
     fn insert_account(
         &self,
         account: AccountDetails,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send>;
 
     // TODO limit the number of results and page through them
-    fn get_all_accounts(&self) -> Box<Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    fn get_all_accounts(&self)
+        -> Box<Future<Item = Vec<Self::Account>, Error = StoreError> + Send>;
 
-    fn set_rates<R>(&self, rates: R) -> Box<Future<Item = (), Error = ()> + Send>
+    /// Check that the store can be reached.
+    fn ping(&self) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// When the exchange rates used to forward packets were last loaded, if they have been.
+    fn rates_updated_at(&self) -> Option<SystemTime>;
+
+    /// Change the limits and routing options of an existing account.
+    /// Errors with `StoreError::NotFound` if the account does not exist.
+    fn update_account_settings(
+        &self,
+        account_id: <Self::Account as AccountTrait>::AccountId,
+        settings: AccountSettings,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    fn set_rates<R>(&self, rates: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (String, f64)>;
 
-    fn set_static_routes<R>(&self, routes: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_static_routes<R>(&self, routes: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (String, <Self::Account as AccountTrait>::AccountId)>;
 
@@ -54,7 +99,70 @@ pub trait NodeStore: Clone + Send + Sync + 'static {
         &self,
         prefix: String,
         account_id: <Self::Account as AccountTrait>::AccountId,
-    ) -> Box<Future<Item = (), Error = ()> + Send>;
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Save an API token along with the scopes it grants.
+    /// Tokens are identified by their `api_token_id` so the tokens themselves are never stored.
+    fn insert_api_token(
+        &self,
+        token_id: String,
+        scopes: Vec<ApiScope>,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Load the scopes granted to the API token with the given ID.
+    /// Errors with `StoreError::NotFound` if the token does not exist.
+    fn get_api_token_scopes(
+        &self,
+        token_id: &str,
+    ) -> Box<Future<Item = Vec<ApiScope>, Error = StoreError> + Send>;
+
+    fn delete_api_token(&self, token_id: &str)
+        -> Box<Future<Item = (), Error = StoreError> + Send>;
+}
+
+/// The ID an API token is stored and revoked by: the hex-encoded SHA-256 hash of the token.
+pub fn api_token_id(token: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+/// Permissions that can be granted to an API token.
+///
+/// Admin accounts are implicitly granted all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    ReadAccounts,
+    ManageAccounts,
+    SetRates,
+    ManageRoutes,
+    SendPayments,
+}
+
+impl FromStr for ApiScope {
+    type Err = ();
+
+    fn from_str(string: &str) -> Result<Self, ()> {
+        match string {
+            "read_accounts" => Ok(ApiScope::ReadAccounts),
+            "manage_accounts" => Ok(ApiScope::ManageAccounts),
+            "set_rates" => Ok(ApiScope::SetRates),
+            "manage_routes" => Ok(ApiScope::ManageRoutes),
+            "send_payments" => Ok(ApiScope::SendPayments),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ApiScope::ReadAccounts => "read_accounts",
+            ApiScope::ManageAccounts => "manage_accounts",
+            ApiScope::SetRates => "set_rates",
+            ApiScope::ManageRoutes => "manage_routes",
+            ApiScope::SendPayments => "send_payments",
+        })
+    }
 }
 
 /// The Account type for the RedisStore.
@@ -80,12 +188,33 @@ pub struct AccountDetails {
     #[serde(default)]
     pub receive_routes: bool,
     pub routing_relation: Option<String>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_balance_threshold: Option<i64>,
 }
 
-#[derive(Response)]
-#[web(status = "200")]
-struct ServerStatus {
-    status: String,
+/// The parts of an account's details that can be changed after it is created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountSettings {
+    pub max_packet_amount: u64,
+    pub min_balance: i64,
+    pub settle_threshold: Option<i64>,
+    pub settle_to: Option<i64>,
+    pub send_routes: bool,
+    pub receive_routes: bool,
+}
+
+impl From<&AccountDetails> for AccountSettings {
+    fn from(details: &AccountDetails) -> Self {
+        AccountSettings {
+            max_packet_amount: details.max_packet_amount,
+            min_balance: details.min_balance,
+            settle_threshold: details.settle_threshold,
+            settle_to: details.settle_to,
+            send_routes: details.send_routes,
+            receive_routes: details.receive_routes,
+        }
+    }
 }
 
 #[derive(Serialize, Response)]
@@ -98,6 +227,12 @@ struct AccountsResponse<A: Serialize> {
 #[web(status = "200")]
 struct Success;
 
+#[derive(Serialize, Response)]
+#[web(status = "200")]
+struct WebhookDeliveriesResponse {
+    deliveries: Vec<WebhookDelivery>,
+}
+
 #[derive(Extract)]
 struct Rates(Vec<(String, f64)>);
 
@@ -111,6 +246,9 @@ struct BalanceResponse {
 struct SpspPayRequest {
     receiver: String,
     source_amount: u64,
+    /// Account to send the payment from. Required when authenticating with
+    /// an API token. Accounts always pay from themselves.
+    source_account: Option<String>,
 }
 
 #[derive(Response)]
@@ -119,154 +257,484 @@ struct SpspPayResponse {
     amount_delivered: u64,
 }
 
-#[derive(Response)]
+#[derive(Extract, Response)]
 #[web(status = "200")]
-struct SpspQueryResponse {
-    destination_account: String,
-    shared_secret: String,
-}
+struct Routes(HashMap<String, String>);
 
 #[derive(Extract, Response)]
 #[web(status = "200")]
-struct Routes(HashMap<String, String>);
+struct AccountRoutePolicies(RoutePolicies);
+
+#[derive(Extract)]
+struct TransactionsQuery {
+    /// RFC 3339 timestamps bounding the results (inclusive)
+    from: Option<String>,
+    to: Option<String>,
+    /// Either "json" (the default) or "csv"
+    format: Option<String>,
+}
 
-pub struct NodeApi<T, S> {
+#[derive(Extract)]
+struct ApiTokenRequest {
+    scopes: Vec<ApiScope>,
+}
+
+#[derive(Response)]
+#[web(status = "200")]
+struct ApiTokenResponse {
+    /// Used to revoke the token. Unlike the token, this is safe to put in URLs and logs
+    id: String,
+    token: String,
+    scopes: Vec<ApiScope>,
+}
+
+/// The credentials an API request was authenticated with.
+enum Requester<A> {
+    /// An API token that was granted the required scope
+    Token,
+    Account(A),
+}
+
+fn parse_account_id<A: AccountTrait>(field: &str, id: &str) -> Result<A::AccountId, ApiError> {
+    A::AccountId::from_str(id).map_err(|_| {
+        debug!("Invalid account id: {}", id);
+        ApiError::invalid_field(field, format!("Invalid account id: {}", id))
+    })
+}
+
+/// Errors loading an account by its credentials mean the request is unauthorized,
+/// unless the store itself failed.
+fn auth_error(err: StoreError) -> ApiError {
+    if err.is_not_found() {
+        ApiError::unauthorized()
+    } else {
+        ApiError::from(err)
+    }
+}
+
+fn account_error<I: fmt::Display>(id: I) -> impl FnOnce(StoreError) -> ApiError {
+    move |err| {
+        if err.is_not_found() {
+            debug!("Account not found: {}", id);
+            ApiError::not_found(format!("Account {} not found", id))
+        } else {
+            ApiError::from(err)
+        }
+    }
+}
+
+fn parse_timestamp(field: &str, timestamp: &Option<String>) -> Result<Option<u64>, ApiError> {
+    if let Some(timestamp) = timestamp {
+        DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .filter(|time| time.timestamp() >= 0)
+            .map(|time| Some(time.timestamp_millis() as u64))
+            .ok_or_else(|| {
+                ApiError::invalid_field(field, format!("Invalid RFC 3339 timestamp: {}", timestamp))
+            })
+    } else {
+        Ok(None)
+    }
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_millis(timestamp))
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn transactions_to_json<I: fmt::Display>(transactions: &[Transaction<I>]) -> String {
+    let transactions: Vec<Value> = transactions
+        .iter()
+        .map(|transaction| {
+            json!({
+                "timestamp": format_timestamp(transaction.timestamp),
+                "from_account": transaction.from_account.to_string(),
+                "to_account": transaction.to_account.to_string(),
+                "incoming_amount": transaction.incoming_amount,
+                "outgoing_amount": transaction.outgoing_amount,
+                "execution_condition": hex::encode(&transaction.execution_condition[..]),
+                "fulfilled": transaction.reject_code.is_none(),
+                "reject_code": transaction.reject_code.map(|code| code.to_string()),
+            })
+        })
+        .collect();
+    Value::Array(transactions).to_string()
+}
+
+fn transactions_to_csv<I: fmt::Display>(transactions: &[Transaction<I>]) -> String {
+    let mut csv = String::from("timestamp,from_account,to_account,incoming_amount,outgoing_amount,execution_condition,fulfilled,reject_code\n");
+    for transaction in transactions {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            format_timestamp(transaction.timestamp),
+            transaction.from_account,
+            transaction.to_account,
+            transaction.incoming_amount,
+            transaction.outgoing_amount,
+            hex::encode(&transaction.execution_condition[..]),
+            transaction.reject_code.is_none(),
+            transaction
+                .reject_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+        ));
+    }
+    csv
+}
+
+/// Format an event as a Server-Sent Event message, or return `None` if the subscriber
+/// should not see it. Subscribers with an `account_id` only see events involving that account.
+fn event_to_sse<A: AccountTrait>(
+    event: &Event<A>,
+    account_id: Option<A::AccountId>,
+) -> Option<String> {
+    let involves = |account: &A| account_id.is_none() || account_id == Some(account.id());
+    let (name, mut data) = match event {
+        Event::BalanceChanged {
+            account,
+            amount,
+            balance,
+        } if involves(account) => (
+            "balance",
+            json!({
+                "account_id": account.id().to_string(),
+                "amount": amount,
+                "balance": balance,
+            }),
+        ),
+        Event::Fulfilled { from, to, amount } if involves(from) || involves(to) => (
+            "payment",
+            json!({
+                "from_account": from.id().to_string(),
+                "to_account": to.id().to_string(),
+                "amount": amount,
+                "fulfilled": true,
+            }),
+        ),
+        Event::Rejected {
+            from,
+            to,
+            amount,
+            code,
+        } if involves(from) || involves(to) => (
+            "payment",
+            json!({
+                "from_account": from.id().to_string(),
+                "to_account": to.id().to_string(),
+                "amount": amount,
+                "fulfilled": false,
+                "reject_code": code.to_string(),
+            }),
+        ),
+        _ => return None,
+    };
+    data["timestamp"] = Value::String(format_timestamp(now_millis()));
+    Some(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+/// The messages sent to an `/events` subscriber, starting with the next event published on the bus
+// io::Error::other is not available on the older compilers this crate still builds with
+#[allow(clippy::io_other_error)]
+fn event_stream<A: AccountTrait>(
+    events: &EventBus<A>,
+    account_id: Option<A::AccountId>,
+) -> impl Stream<Item = String, Error = io::Error> {
+    events
+        .subscribe()
+        .filter_map(move |event| event_to_sse(&event, account_id))
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Event bus closed"))
+}
+
+fn now_millis() -> u64 {
+    millis_since_epoch(SystemTime::now())
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    let since_epoch = time
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is set before the UNIX epoch");
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+fn generate_api_token() -> String {
+    let mut token: [u8; 32] = [0; 32];
+    SystemRandom::new()
+        .fill(&mut token)
+        .expect("Failed to securely generate a random API token!");
+    hex::encode(&token[..])
+}
+
+/// The administrative endpoints for managing accounts, rates, routes and API tokens
+/// and for sending payments from the node.
+///
+/// This should only be reachable from trusted networks; see `PublicApi` for
+/// the endpoints other nodes use to connect to this one.
+pub struct NodeApi<T: NodeStore, S> {
     store: T,
     incoming_handler: S,
-    server_secret: Bytes,
+    events: EventBus<T::Account>,
+    health: HealthMonitor<T::Account>,
+    route_manager: Option<Arc<RoutingTableInspector<T::Account> + Send + Sync>>,
 }
 
 impl_web! {
     impl<T, S, A> NodeApi<T, S>
-    where T: NodeStore<Account = A> + HttpStore<Account = A> + BalanceStore<Account = A> + RouterStore,
+    where T: NodeStore<Account = A> + HttpStore<Account = A> + BalanceStore<Account = A> + TransactionStore<Account = A> + WebhookStore<Account = A> + RouterStore + RouteManagerStore<Account = A>,
     S: IncomingService<A> + Clone + Send + Sync + 'static,
-    A: AccountTrait + HttpAccount + NodeAccount + IldcpAccount + Serialize + 'static,
+    A: AccountTrait + HttpAccount + NodeAccount + IldcpAccount + CcpRoutingAccount + Serialize + 'static,
 
     {
-        pub fn new(server_secret: Bytes, store: T, incoming_handler: S) -> Self {
+        pub fn new(store: T, incoming_handler: S) -> Self {
             NodeApi {
                 store,
                 incoming_handler,
-                server_secret,
+                events: EventBus::new(),
+                health: HealthMonitor::new(),
+                route_manager: None,
             }
         }
 
-        fn validate_admin(&self, authorization: String) -> impl Future<Item = T, Error = Response<()>> {
+        // The bus the balance and payment events streamed from /events are read from
+        pub fn with_events(mut self, events: EventBus<A>) -> Self {
+            self.events = events;
+            self
+        }
+
+        // The monitor whose connections and route updates are reported by /health/ready
+        pub fn with_health(mut self, health: HealthMonitor<A>) -> Self {
+            self.health = health;
+            self
+        }
+
+        // The running Route Manager whose tables are described by the /routes/ccp endpoints
+        pub fn with_route_manager<R>(mut self, route_manager: R) -> Self
+        where R: RoutingTableInspector<A> + Send + Sync + 'static,
+        {
+            self.route_manager = Some(Arc::new(route_manager));
+            self
+        }
+
+        fn get_route_manager(&self) -> Result<Arc<RoutingTableInspector<A> + Send + Sync>, ApiError> {
+            self.route_manager.clone()
+                .ok_or_else(|| ApiError::not_found("The CCP Route Manager is not running"))
+        }
+
+        fn validate_admin(&self, authorization: String) -> impl Future<Item = T, Error = ApiError> {
             let store = self.store.clone();
             self.store.get_account_from_http_auth(&authorization)
+                .map_err(auth_error)
                 .and_then(|account| if account.is_admin() {
                     Ok(store)
                 } else {
-                    Err(())
+                    Err(ApiError::forbidden("Only admin accounts can make this request"))
                 })
-                .map_err(|_| Response::builder().status(401).body(()).unwrap())
         }
 
-        #[get("/")]
-        #[content_type("application/json")]
-        fn get_root(&self) -> Result<ServerStatus, ()> {
-            Ok(ServerStatus {
-                status: "Ready".to_string(),
-            })
+        // Check the authorization against the API tokens first and then against the accounts.
+        // A token that exists but was not granted the scope is rejected outright.
+        fn authenticate(&self, authorization: String, scope: ApiScope) -> impl Future<Item = Requester<A>, Error = ApiError> {
+            let store = self.store.clone();
+            let token_id = api_token_id(authorization.trim_start_matches("Bearer "));
+            self.store.get_api_token_scopes(&token_id)
+                .then(move |result| match result {
+                    Ok(scopes) => if scopes.contains(&scope) {
+                        Either::A(ok(Requester::Token))
+                    } else {
+                        debug!("API token is missing scope: {}", scope);
+                        Either::A(err(ApiError::forbidden(format!("API token is missing scope: {}", scope))))
+                    },
+                    Err(StoreError::NotFound(_)) => Either::B(store.get_account_from_http_auth(&authorization)
+                        .map_err(move |err| {
+                            debug!("No account found with auth: {}", authorization);
+                            auth_error(err)
+                        })
+                        .and_then(|account| Ok(Requester::Account(account)))),
+                    Err(store_err) => Either::A(err(ApiError::from(store_err))),
+                })
+        }
+
+        // Only allow API tokens with the given scope and admin accounts.
+        fn authorize(&self, authorization: String, scope: ApiScope) -> impl Future<Item = T, Error = ApiError> {
+            let store = self.store.clone();
+            self.authenticate(authorization, scope)
+                .and_then(move |requester| match requester {
+                    Requester::Token => Ok(store),
+                    Requester::Account(ref account) if account.is_admin() => Ok(store),
+                    Requester::Account(_) => Err(ApiError::forbidden("Only admin accounts can make this request")),
+                })
         }
 
         #[post("/accounts")]
         #[content_type("application/json")]
-        fn post_accounts(&self, body: AccountDetails, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+        fn post_accounts(&self, body: AccountDetails, authorization: String) -> impl Future<Item = Either<Value, ApiError>, Error = ()> {
             // TODO don't allow accounts to be overwritten
             // TODO add option for non-admin signups (maybe with invite code)
-            self.validate_admin(authorization)
+            respond(self.authorize(authorization, ApiScope::ManageAccounts)
                 .and_then(move |store| store.insert_account(body)
-                // TODO make all Accounts (de)serializable with Serde so all the details can be returned here
-                .and_then(|account| Ok(json!(account)))
-                .map_err(|_| Response::builder().status(500).body(()).unwrap()))
+                    .map_err(ApiError::from)
+                    // TODO make all Accounts (de)serializable with Serde so all the details can be returned here
+                    .and_then(|account| Ok(json!(account)))))
         }
 
         #[get("/accounts")]
         #[content_type("application/json")]
-        fn get_accounts(&self, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+        fn get_accounts(&self, authorization: String) -> impl Future<Item = Either<Value, ApiError>, Error = ()> {
             let store = self.store.clone();
-            self.store.get_account_from_http_auth(&authorization)
-                .map_err(move |_| {
-                    debug!("No account found with auth: {}", authorization);
-                    Response::builder().status(401).body(()).unwrap()
+            respond(self.authenticate(authorization, ApiScope::ReadAccounts)
+                .and_then(move |requester| match requester {
+                    Requester::Account(ref account) if !account.is_admin() => {
+                        let id = account.id();
+                        Either::B(store.get_accounts(vec![id])
+                            .map_err(account_error(id)))
+                    },
+                    _ => Either::A(store.get_all_accounts()
+                        .map_err(ApiError::from)),
                 })
-                .and_then(move |account| if account.is_admin() {
-                    Either::A(store.get_all_accounts()
-                        .map_err(|_| Response::builder().status(500).body(()).unwrap()))
-                } else {
-                    Either::B(store.get_accounts(vec![account.id()])
-                        .map_err(|_| Response::builder().status(404).body(()).unwrap()))
-                })
-                .and_then(|accounts| Ok(json!(accounts)))
+                .and_then(|accounts| Ok(json!(accounts))))
         }
 
         #[get("/accounts/:id")]
         #[content_type("application/json")]
-        fn get_account(&self, id: String, authorization: String) -> impl Future<Item = Value, Error = Response<()>> {
+        fn get_account(&self, id: String, authorization: String) -> impl Future<Item = Either<Value, ApiError>, Error = ()> {
             let store = self.store.clone();
-            let store_clone = store.clone();
-            let parsed_id: Result<A::AccountId, ()> = A::AccountId::from_str(&id).map_err(|_| error!("Invalid id"));
-            result(parsed_id)
-                .map_err(|_| Response::builder().status(400).body(()).unwrap())
+            let authenticate = self.authenticate(authorization, ApiScope::ReadAccounts);
+            respond(result(parse_account_id::<A>("id", &id))
                 .and_then(move |id| {
-                    store.clone().get_account_from_http_auth(&authorization)
-                        .and_then(move |account|
-                            if account.id() == id {
+                    authenticate
+                        .and_then(move |requester| match requester {
+                            Requester::Account(ref account) if account.id() == id => {
                                 Either::A(ok(json!(account)))
-                            } else if account.is_admin() {
-                                Either::B(store_clone.get_accounts(vec![id]).and_then(|accounts| Ok(json!(accounts[0].clone()))))
-                            } else {
-                                Either::A(err(()))
-                            })
-                        .map_err(move |_| {
-                            debug!("No account found with auth: {}", authorization);
-                            Response::builder().status(401).body(()).unwrap()
+                            },
+                            Requester::Account(ref account) if !account.is_admin() => {
+                                Either::A(err(ApiError::forbidden("Accounts can only view their own details")))
+                            },
+                            _ => Either::B(store.get_accounts(vec![id])
+                                .and_then(|accounts| Ok(json!(accounts[0].clone())))
+                                .map_err(account_error(id))),
                         })
-                })
+                }))
         }
 
         // TODO should this be combined into the account record?
         #[get("/accounts/:id/balance")]
         #[content_type("application/json")]
-        fn get_balance(&self, id: String, authorization: String) -> impl Future<Item = BalanceResponse, Error = Response<()>> {
+        fn get_balance(&self, id: String, authorization: String) -> impl Future<Item = Either<BalanceResponse, ApiError>, Error = ()> {
             let store = self.store.clone();
             let store_clone = store.clone();
-            let parsed_id: Result<A::AccountId, ()> = A::AccountId::from_str(&id).map_err(|_| error!("Invalid id"));
-            result(parsed_id)
-                .map_err(|_| Response::builder().status(400).body(()).unwrap())
+            let authenticate = self.authenticate(authorization, ApiScope::ReadAccounts);
+            respond(result(parse_account_id::<A>("id", &id))
                 .and_then(move |id| {
-                    store.clone().get_account_from_http_auth(&authorization)
-                        .and_then(move |account|
-                            if account.id() == id {
+                    authenticate
+                        .and_then(move |requester| match requester {
+                            Requester::Account(account) if account.id() == id => {
                                 Either::A(ok(account))
-                            } else if account.is_admin() {
-                                Either::B(store_clone.get_accounts(vec![id]).and_then(|accounts| Ok(accounts[0].clone())))
-                            } else {
-                                Either::A(err(()))
-                            })
-                        .map_err(move |_| {
-                            debug!("No account found with auth: {}", authorization);
-                            Response::builder().status(401).body(()).unwrap()
+                            },
+                            Requester::Account(ref account) if !account.is_admin() => {
+                                Either::A(err(ApiError::forbidden("Accounts can only view their own balance")))
+                            },
+                            _ => Either::B(store_clone.get_accounts(vec![id])
+                                .and_then(|accounts| Ok(accounts[0].clone()))
+                                .map_err(account_error(id))),
                         })
                         .and_then(move |account| store.get_balance(account)
+                        .map_err(ApiError::from)
                         .and_then(|balance| Ok(BalanceResponse {
                             balance: balance.to_string(),
-                        }))
-                        .map_err(|_| Response::builder().status(404).body(()).unwrap()))
+                        })))
+                }))
+        }
+
+        #[get("/accounts/:id/transactions")]
+        fn get_transactions(&self, id: String, query_string: TransactionsQuery, authorization: String) -> impl Future<Item = Either<Response<String>, ApiError>, Error = ()> {
+            let store = self.store.clone();
+            let authenticate = self.authenticate(authorization, ApiScope::ReadAccounts);
+            let csv = match query_string.format.as_deref() {
+                None | Some("json") => Ok(false),
+                Some("csv") => Ok(true),
+                Some(format) => Err(ApiError::invalid_field("format", format!("Unknown format: {}", format))),
+            };
+            respond(result(parse_account_id::<A>("id", &id))
+                .and_then(move |id| {
+                    let from = parse_timestamp("from", &query_string.from)?;
+                    let to = parse_timestamp("to", &query_string.to)?;
+                    Ok((id, from, to, csv?))
                 })
+                .and_then(move |(id, from, to, csv)| {
+                    authenticate
+                        .and_then(move |requester| match requester {
+                            Requester::Account(ref account) if account.id() != id && !account.is_admin() => {
+                                Err(ApiError::forbidden("Accounts can only view their own transactions"))
+                            },
+                            _ => Ok(()),
+                        })
+                        .and_then(move |_| store.get_transactions(id, from, to)
+                            .map_err(account_error(id)))
+                        .and_then(move |transactions| {
+                            let (content_type, body) = if csv {
+                                ("text/csv", transactions_to_csv(&transactions))
+                            } else {
+                                ("application/json", transactions_to_json(&transactions))
+                            };
+                            Ok(Response::builder()
+                                .header(CONTENT_TYPE, content_type)
+                                .body(body)
+                                .expect("Response with a valid header cannot fail to build"))
+                        })
+                }))
+        }
+
+        #[get("/accounts/:id/webhooks")]
+        #[content_type("application/json")]
+        fn get_webhook_deliveries(&self, id: String, authorization: String) -> impl Future<Item = Either<WebhookDeliveriesResponse, ApiError>, Error = ()> {
+            let store = self.store.clone();
+            let authenticate = self.authenticate(authorization, ApiScope::ReadAccounts);
+            respond(result(parse_account_id::<A>("id", &id))
+                .and_then(move |id| {
+                    authenticate
+                        .and_then(move |requester| match requester {
+                            Requester::Account(ref account) if account.id() != id && !account.is_admin() => {
+                                Err(ApiError::forbidden("Accounts can only view their own webhook deliveries"))
+                            },
+                            _ => Ok(()),
+                        })
+                        .and_then(move |_| store.get_webhook_deliveries(id)
+                            .map_err(account_error(id)))
+                        .and_then(|deliveries| Ok(WebhookDeliveriesResponse { deliveries }))
+                }))
+        }
+
+        // Stream balance changes and payments as Server-Sent Events.
+        // Accounts only receive events involving themselves, while admin accounts
+        // and API tokens receive the events for every account.
+        // Payment amounts are denominated in the sending account's asset.
+        #[get("/events")]
+        fn get_events(&self, authorization: String) -> impl Future<Item = Either<Response<Body>, ApiError>, Error = ()> {
+            let events = self.events.clone();
+            respond(self.authenticate(authorization, ApiScope::ReadAccounts)
+                .and_then(move |requester| {
+                    let account_id = match requester {
+                        Requester::Account(ref account) if !account.is_admin() => Some(account.id()),
+                        _ => None,
+                    };
+                    let stream = event_stream(&events, account_id);
+                    Ok(Response::builder()
+                        .header(CONTENT_TYPE, "text/event-stream")
+                        .header(CACHE_CONTROL, "no-cache")
+                        .body(Body::wrap_stream(stream))
+                        .expect("Response with valid headers cannot fail to build"))
+                }))
         }
 
         #[put("/rates")]
         #[content_type("application/json")]
-        fn post_rates(&self, body: Rates, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
-            self.validate_admin(authorization)
+        fn post_rates(&self, body: Rates, authorization: String) -> impl Future<Item = Either<Success, ApiError>, Error = ()> {
+            respond(self.authorize(authorization, ApiScope::SetRates)
                 .and_then(move |store| store.set_rates(body.0)
                 .and_then(|_| Ok(Success))
                 .map_err(|err| {
-                    error!("Error setting rates: {:?}", err);
-                    Response::builder().status(500).body(()).unwrap()
-                }))
+                    error!("Error setting rates: {}", err);
+                    ApiError::from(err)
+                })))
         }
 
         #[get("/routes")]
@@ -283,18 +751,44 @@ impl_web! {
                 }))))
         }
 
+        #[get("/health/live")]
+        #[content_type("application/json")]
+        fn get_health_live(&self) -> Result<Value, ()> {
+            Ok(json!({ "live": true }))
+        }
+
+        // Responds with 503 if the store cannot be reached, the exchange rates are stale,
+        // or there is no connected peer or route, since the node cannot forward packets in any of those cases
+        #[get("/health/ready")]
+        fn get_health_ready(&self) -> impl Future<Item = Response<String>, Error = ()> {
+            let health = self.health.clone();
+            let rates_updated_at = self.store.rates_updated_at();
+            let routes = self.store.routing_table().len();
+            Timeout::new(self.store.ping(), HEALTH_CHECK_TIMEOUT)
+                .then(move |result| Ok(health.ready_response(result.is_ok(), rates_updated_at, routes)))
+        }
+
+        #[get("/metrics")]
+        fn get_metrics(&self) -> Result<Response<String>, ()> {
+            let mut buffer = Vec::new();
+            let encoder = TextEncoder::new();
+            encoder.encode(&prometheus::gather(), &mut buffer)
+                .map_err(|err| error!("Error encoding metrics: {:?}", err))?;
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, encoder.format_type())
+                .body(String::from_utf8(buffer).map_err(|_| error!("Encoded metrics were not valid UTF-8"))?)
+                .expect("Response with a valid header cannot fail to build"))
+        }
+
         #[put("/routes/static")]
         #[content_type("application/json")]
-        fn post_static_routes(&self, body: Routes, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
-            self.validate_admin(authorization)
+        fn post_static_routes(&self, body: Routes, authorization: String) -> impl Future<Item = Either<Success, ApiError>, Error = ()> {
+            respond(self.authorize(authorization, ApiScope::ManageRoutes)
                 .and_then(move |store| {
                     let mut routes: HashMap<String, A::AccountId> = HashMap::with_capacity(body.0.len());
                     for (prefix, account_id) in body.0 {
-                        if let Ok(account_id) = A::AccountId::from_str(account_id.as_str()) {
-                            routes.insert(prefix, account_id);
-                        } else {
-                            return Err(Response::builder().status(400).body(()).unwrap());
-                        }
+                        let account_id = parse_account_id::<A>(&prefix, &account_id)?;
+                        routes.insert(prefix, account_id);
                     }
                     Ok((store, routes))
                 })
@@ -302,40 +796,109 @@ impl_web! {
                     store.set_static_routes(routes)
                     .and_then(|_| Ok(Success))
                         .map_err(|err| {
-                            error!("Error setting static routes: {:?}", err);
-                            Response::builder().status(500).body(()).unwrap()
+                            error!("Error setting static routes: {}", err);
+                            ApiError::from(err)
                         })
-            })
+            }))
         }
 
         #[put("/routes/static/:prefix")]
         #[content_type("application/json")]
-        fn post_static_route(&self, prefix: String, body: String, authorization: String) -> impl Future<Item = Success, Error = Response<()>> {
-            self.validate_admin(authorization)
+        fn post_static_route(&self, prefix: String, body: String, authorization: String) -> impl Future<Item = Either<Success, ApiError>, Error = ()> {
+            respond(self.authorize(authorization, ApiScope::ManageRoutes)
                 .and_then(move |store| {
-                    if let Ok(account_id) = A::AccountId::from_str(body.as_str()) {
-                        Ok((store, account_id))
-                    } else {
-                        Err(Response::builder().status(400).body(()).unwrap())
-                    }
+                    let account_id = parse_account_id::<A>("account_id", &body)?;
+                    Ok((store, account_id))
                 })
                 .and_then(move |(store, account_id)| {
                     store.set_static_route(prefix, account_id)
                     .and_then(|_| Ok(Success))
                         .map_err(|err| {
-                            error!("Error setting static route: {:?}", err);
-                            Response::builder().status(500).body(()).unwrap()
+                            error!("Error setting static route: {}", err);
+                            ApiError::from(err)
                         })
-                })
+                }))
+        }
+
+        // The CCP Route Manager's view of each prefix: the chosen next hop, path and source,
+        // and the routes in the other peers' Incoming Routing Tables
+        #[get("/routes/ccp")]
+        #[content_type("application/json")]
+        fn get_ccp_routes(&self, authorization: String) -> impl Future<Item = Either<Value, ApiError>, Error = ()> {
+            let route_manager = self.get_route_manager();
+            respond(self.authorize(authorization, ApiScope::ManageRoutes)
+                .and_then(move |_| route_manager)
+                .and_then(|route_manager| route_manager.routing_table_info()
+                    .map_err(|_| ApiError::new(ApiErrorType::InternalError, "Error getting the routing table info")))
+                .map(|info| json!(info)))
+        }
+
+        // The routes we advertise to the account after applying its export policies
+        #[get("/accounts/:id/routes/advertised")]
+        #[content_type("application/json")]
+        fn get_advertised_routes(&self, id: String, authorization: String) -> impl Future<Item = Either<Value, ApiError>, Error = ()> {
+            let authorize = self.authorize(authorization, ApiScope::ManageRoutes);
+            let route_manager = self.get_route_manager();
+            respond(result(parse_account_id::<A>("id", &id))
+                .and_then(move |id| authorize
+                    .and_then(move |store| result(route_manager)
+                        .and_then(move |route_manager| store.get_accounts(vec![id])
+                            .map_err(account_error(id))
+                            .and_then(move |accounts| route_manager.advertised_routes(&accounts[0])
+                                .map_err(move |_| ApiError::new(ApiErrorType::InternalError, format!("Error getting the routes advertised to account {}", id)))))))
+                .map(|routes| json!(routes)))
+        }
+
+        // The CCP import and export policies for the account's route broadcasts
+        #[get("/accounts/:id/routes/policies")]
+        #[content_type("application/json")]
+        fn get_route_policies(&self, id: String, authorization: String) -> impl Future<Item = Either<AccountRoutePolicies, ApiError>, Error = ()> {
+            let authorize = self.authorize(authorization, ApiScope::ManageRoutes);
+            respond(result(parse_account_id::<A>("id", &id))
+                .and_then(move |id| authorize
+                    .and_then(move |store| store.get_route_policies(id)
+                        .map_err(account_error(id))))
+                .and_then(|policies| Ok(AccountRoutePolicies(policies))))
+        }
+
+        #[put("/accounts/:id/routes/policies")]
+        #[content_type("application/json")]
+        fn put_route_policies(&self, id: String, body: AccountRoutePolicies, authorization: String) -> impl Future<Item = Either<Success, ApiError>, Error = ()> {
+            let authorize = self.authorize(authorization, ApiScope::ManageRoutes);
+            let weight = if body.0.valid_weight().is_some() {
+                Ok(())
+            } else {
+                Err(ApiError::invalid_field("weight", "weight must be a finite number that is not negative"))
+            };
+            respond(result(weight.and_then(|_| parse_account_id::<A>("id", &id)))
+                .and_then(move |id| authorize
+                    .and_then(move |store| store.set_route_policies(id, body.0)
+                        .map_err(account_error(id))))
+                .and_then(|_| Ok(Success)))
         }
 
         #[post("/pay")]
         #[content_type("application/json")]
         // TODO add a version that lets you specify the destination amount instead
-        fn post_pay(&self, body: SpspPayRequest, authorization: String) -> impl Future<Item = SpspPayResponse, Error = Response<String>> {
+        fn post_pay(&self, body: SpspPayRequest, authorization: String) -> impl Future<Item = Either<SpspPayResponse, ApiError>, Error = ()> {
             let service = self.incoming_handler.clone();
-            self.store.get_account_from_http_auth(&authorization)
-                .map_err(|_| Response::builder().status(401).body("Unauthorized".to_string()).unwrap())
+            let store = self.store.clone();
+            let source_account = body.source_account.clone();
+            respond(self.authenticate(authorization, ApiScope::SendPayments)
+                .and_then(move |requester| match requester {
+                    Requester::Account(account) => Either::A(ok(account)),
+                    Requester::Token => {
+                        // Tokens are not tied to an account so they must say which one to pay from
+                        let source_account = if let Some(ref id) = source_account {
+                            parse_account_id::<A>("source_account", id)
+                        } else {
+                            Err(ApiError::invalid_field("source_account", "source_account is required when paying with an API token"))
+                        };
+                        Either::B(result(source_account).and_then(move |id| store.get_accounts(vec![id])
+                            .map_err(account_error(id))
+                            .map(|accounts| accounts[0].clone())))
+                    },
+                })
                 .and_then(move |account| {
                     pay(service, account, &body.receiver, body.source_amount)
                         .and_then(|amount_delivered| Ok(SpspPayResponse {
@@ -343,61 +906,186 @@ impl_web! {
                             }))
                         .map_err(|err| {
                             error!("Error sending SPSP payment: {:?}", err);
-                            // TODO give a different error message depending on what type of error it is
-                            Response::builder().status(500).body(format!("Error sending SPSP payment: {:?}", err)).unwrap()
+                            match err {
+                                SpspError::InvalidPaymentPointerError(_) => ApiError::invalid_field("receiver", err.to_string()),
+                                _ => ApiError::new(ApiErrorType::PaymentFailed, err.to_string()),
+                            }
                         })
-                })
+                }))
         }
 
-        #[post("/ilp")]
-        // TODO make sure taking the body as a Vec (instead of Bytes) doesn't cause a copy
-        // for some reason, it complains that Extract isn't implemented for Bytes even though tower-web says it is
-        fn post_ilp(&self, body: Vec<u8>, authorization: String) -> impl Future<Item = Response<Body>, Error = Error> {
-            let request = Request::builder()
-                .header("Authorization", authorization)
-                .body(Body::from(body))
-                .unwrap();
-            HttpServerService::new(self.incoming_handler.clone(), self.store.clone()).handle_http_request(request)
+        #[post("/tokens")]
+        #[content_type("application/json")]
+        fn post_tokens(&self, body: ApiTokenRequest, authorization: String) -> impl Future<Item = Either<ApiTokenResponse, ApiError>, Error = ()> {
+            // Only admin accounts can issue tokens so tokens cannot be used to escalate their own scopes
+            respond(self.validate_admin(authorization)
+                .and_then(move |store| {
+                    let token = generate_api_token();
+                    let id = api_token_id(&token);
+                    let scopes = body.scopes;
+                    store.insert_api_token(id.clone(), scopes.clone())
+                        .map_err(ApiError::from)
+                        .and_then(move |_| Ok(ApiTokenResponse {
+                            id,
+                            token,
+                            scopes,
+                        }))
+                }))
         }
 
-        #[get("/spsp/:id")]
-        fn get_spsp(&self, id: String) -> impl Future<Item = Response<Body>, Error = Response<()>> {
-            let server_secret = self.server_secret.clone();
-            let store = self.store.clone();
-            let id: Result<A::AccountId, ()> = A::AccountId::from_str(&id).map_err(|_| error!("Invalid id: {}", id));
-            result(id)
-                .map_err(|_| Response::builder().status(400).body(()).unwrap())
-                .and_then(move |id| store.get_accounts(vec![id])
-                .map_err(move |_| {
-                    error!("Account not found: {}", id);
-                    Response::builder().status(404).body(()).unwrap()
-                }))
-                .and_then(move |accounts| {
-                    let ilp_address = Bytes::from(accounts[0].client_address());
-                    // TODO return the response without instantiating an SpspResponder (use a simple fn)
-                    Ok(SpspResponder::new(ilp_address, server_secret)
-                        .generate_http_response())
-                    })
-        }
-
-        // TODO resolve payment pointers with subdomains to the correct account
-        // also give accounts aliases to use in the payment pointer instead of the ids
-        #[get("/.well-known/pay")]
-        fn get_well_known(&self) -> impl Future<Item = Response<Body>, Error = Response<()>> {
-            let default_account = A::AccountId::default();
-            let server_secret = self.server_secret.clone();
-            self.store.get_accounts(vec![default_account])
-            .map_err(move |_| {
-                error!("Account not found: {}", default_account);
-                Response::builder().status(404).body(()).unwrap()
-            })
-            .and_then(move |accounts| {
-                let ilp_address = Bytes::from(accounts[0].client_address());
-                Ok(SpspResponder::new(ilp_address, server_secret)
-                    .generate_http_response())
-                })
+        // Tokens are revoked by the ID returned when they were issued so the token itself stays out of URLs
+        #[delete("/tokens/:token_id")]
+        #[content_type("application/json")]
+        fn delete_token(&self, token_id: String, authorization: String) -> impl Future<Item = Either<Success, ApiError>, Error = ()> {
+            respond(self.validate_admin(authorization)
+                .and_then(move |store| store.get_api_token_scopes(&token_id)
+                    .and_then(move |_| store.delete_api_token(&token_id))
+                    .map_err(ApiError::from))
+                .and_then(|_| Ok(Success)))
         }
 
         // TODO add quoting via SPSP/STREAM
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interledger_packet::ErrorCode;
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl AccountTrait for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn balance_changed(id: u64) -> Event<TestAccount> {
+        Event::BalanceChanged {
+            account: TestAccount(id),
+            amount: -100,
+            balance: 400,
+        }
+    }
+
+    fn rejected(from: u64, to: u64) -> Event<TestAccount> {
+        Event::Rejected {
+            from: TestAccount(from),
+            to: TestAccount(to),
+            amount: 100,
+            code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+        }
+    }
+
+    /// Split a Server-Sent Event into its name and data, without the timestamp
+    fn parse_sse(message: &str) -> (String, Value) {
+        assert!(message.ends_with("\n\n"));
+        let mut lines = message.trim_end().lines();
+        let name = lines.next().unwrap().trim_start_matches("event: ");
+        let mut data: Value =
+            serde_json::from_str(lines.next().unwrap().trim_start_matches("data: ")).unwrap();
+        assert!(data["timestamp"].is_string());
+        data.as_object_mut().unwrap().remove("timestamp");
+        (name.to_string(), data)
+    }
+
+    #[test]
+    fn formats_balance_and_payment_events() {
+        let balance = event_to_sse(&balance_changed(1), None).unwrap();
+        assert_eq!(
+            parse_sse(&balance),
+            (
+                "balance".to_string(),
+                json!({
+                    "account_id": "1",
+                    "amount": -100,
+                    "balance": 400,
+                })
+            )
+        );
+
+        let fulfilled = Event::Fulfilled {
+            from: TestAccount(1),
+            to: TestAccount(2),
+            amount: 100,
+        };
+        assert_eq!(
+            parse_sse(&event_to_sse(&fulfilled, None).unwrap()),
+            (
+                "payment".to_string(),
+                json!({
+                    "from_account": "1",
+                    "to_account": "2",
+                    "amount": 100,
+                    "fulfilled": true,
+                })
+            )
+        );
+        assert_eq!(
+            parse_sse(&event_to_sse(&rejected(1, 2), None).unwrap()),
+            (
+                "payment".to_string(),
+                json!({
+                    "from_account": "1",
+                    "to_account": "2",
+                    "amount": 100,
+                    "fulfilled": false,
+                    "reject_code": "T04",
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn only_sends_events_involving_the_account() {
+        assert!(event_to_sse(&balance_changed(1), Some(1)).is_some());
+        assert!(event_to_sse(&balance_changed(2), Some(1)).is_none());
+        assert!(event_to_sse(&rejected(1, 2), Some(1)).is_some());
+        assert!(event_to_sse(&rejected(2, 1), Some(1)).is_some());
+        assert!(event_to_sse(&rejected(2, 3), Some(1)).is_none());
+        // Admins see the events for every account
+        assert!(event_to_sse(&rejected(2, 3), None).is_some());
+    }
+
+    #[test]
+    fn skips_other_events() {
+        let opened = Event::ConnectionOpened {
+            account: TestAccount(1),
+        };
+        assert!(event_to_sse(&opened, None).is_none());
+        let routes = Event::RoutesReceived {
+            from: TestAccount(1),
+        };
+        assert!(event_to_sse(&routes, None).is_none());
+    }
+
+    #[test]
+    fn delivers_published_events_to_subscribers() {
+        let events = EventBus::new();
+        events.publish(balance_changed(1));
+        let admin = event_stream(&events, None);
+        let account = event_stream(&events, Some(2));
+        events.publish(balance_changed(1));
+        events.publish(Event::ConnectionOpened {
+            account: TestAccount(2),
+        });
+        events.publish(rejected(1, 2));
+        drop(events);
+
+        let admin: Vec<String> = admin.collect().wait().unwrap();
+        assert_eq!(
+            admin
+                .iter()
+                .map(|message| parse_sse(message).0)
+                .collect::<Vec<_>>(),
+            vec!["balance", "payment"]
+        );
+        let account: Vec<String> = account.collect().wait().unwrap();
+        assert_eq!(account.len(), 1);
+        assert_eq!(parse_sse(&account[0]).1["to_account"], "2");
+    }
+}
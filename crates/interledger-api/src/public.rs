@@ -0,0 +1,106 @@
+use super::{account_error, parse_account_id};
+use crate::error::{respond, ApiError};
+use bytes::Bytes;
+use futures::{
+    future::{result, Either},
+    Future,
+};
+use http::{Request, Response};
+use hyper::{body::Body, error::Error};
+use interledger_http::{HttpAccount, HttpServerService, HttpStore};
+use interledger_ildcp::IldcpAccount;
+use interledger_service::{Account as AccountTrait, AccountStore, IncomingService};
+use interledger_spsp::SpspResponder;
+
+#[derive(Response)]
+#[web(status = "200")]
+struct ServerStatus {
+    status: String,
+}
+
+/// The endpoints other nodes and senders use to reach this one:
+/// ILP-over-HTTP and SPSP queries.
+///
+/// None of these require admin credentials so this can be exposed publicly
+/// while the `NodeApi` is only reachable from trusted networks.
+pub struct PublicApi<T, S> {
+    store: T,
+    incoming_handler: S,
+    server_secret: Bytes,
+    strict_parsing: bool,
+}
+
+impl_web! {
+    impl<T, S, A> PublicApi<T, S>
+    where T: AccountStore<Account = A> + HttpStore<Account = A>,
+    S: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: AccountTrait + HttpAccount + IldcpAccount + 'static,
+
+    {
+        pub fn new(server_secret: Bytes, store: T, incoming_handler: S) -> Self {
+            PublicApi {
+                store,
+                incoming_handler,
+                server_secret,
+                strict_parsing: false,
+            }
+        }
+
+        // Reject ILP-over-HTTP packets that are not canonically encoded
+        pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+            self.strict_parsing = strict;
+            self
+        }
+
+        #[get("/")]
+        #[content_type("application/json")]
+        fn get_root(&self) -> Result<ServerStatus, ()> {
+            Ok(ServerStatus {
+                status: "Ready".to_string(),
+            })
+        }
+
+        #[post("/ilp")]
+        // TODO make sure taking the body as a Vec (instead of Bytes) doesn't cause a copy
+        // for some reason, it complains that Extract isn't implemented for Bytes even though tower-web says it is
+        fn post_ilp(&self, body: Vec<u8>, authorization: String) -> impl Future<Item = Response<Body>, Error = Error> {
+            let request = Request::builder()
+                .header("Authorization", authorization)
+                .body(Body::from(body))
+                .unwrap();
+            HttpServerService::new(self.incoming_handler.clone(), self.store.clone())
+                .with_strict_parsing(self.strict_parsing)
+                .handle_http_request(request)
+        }
+
+        #[get("/spsp/:id")]
+        fn get_spsp(&self, id: String) -> impl Future<Item = Either<Response<Body>, ApiError>, Error = ()> {
+            let server_secret = self.server_secret.clone();
+            let store = self.store.clone();
+            respond(result(parse_account_id::<A>("id", &id))
+                .and_then(move |id| store.get_accounts(vec![id])
+                .map_err(account_error(id)))
+                .and_then(move |accounts| {
+                    let ilp_address = Bytes::from(accounts[0].client_address());
+                    // TODO return the response without instantiating an SpspResponder (use a simple fn)
+                    Ok(SpspResponder::new(ilp_address, server_secret)
+                        .generate_http_response())
+                    }))
+        }
+
+        // TODO resolve payment pointers with subdomains to the correct account
+        // also give accounts aliases to use in the payment pointer instead of the ids
+        #[get("/.well-known/pay")]
+        fn get_well_known(&self) -> impl Future<Item = Either<Response<Body>, ApiError>, Error = ()> {
+            let default_account = A::AccountId::default();
+            let server_secret = self.server_secret.clone();
+            respond(self.store.get_accounts(vec![default_account])
+            .map_err(account_error(default_account))
+            .and_then(move |accounts| {
+                let ilp_address = Bytes::from(accounts[0].client_address());
+                Ok(SpspResponder::new(ilp_address, server_secret)
+                    .generate_http_response())
+                }))
+        }
+    }
+}
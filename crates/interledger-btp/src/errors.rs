@@ -26,6 +26,11 @@ quick_error! {
             description(err.description())
             cause(err)
         }
+        Packet(err: interledger_packet::ParseError) {
+            from()
+            description(err.description())
+            cause(err)
+        }
         WrongType(descr: String) {
             description(descr)
             display("Wrong Type {}", descr)
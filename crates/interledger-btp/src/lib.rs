@@ -8,14 +8,15 @@
 
 #[macro_use]
 extern crate quick_error;
-#[cfg(test)]
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate prometheus;
 
 use futures::Future;
-use interledger_service::Account;
+use interledger_service::{Account, StoreError};
 use url::Url;
 
 mod client;
@@ -41,7 +42,7 @@ pub trait BtpStore {
     fn get_account_from_btp_token(
         &self,
         token: &str,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send>;
 }
 
 pub struct BtpOpenSignupAccount<'a> {
@@ -64,16 +65,17 @@ pub trait BtpOpenSignupStore {
     fn create_btp_account<'a>(
         &self,
         account: BtpOpenSignupAccount<'a>,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send>;
 }
 
 #[cfg(test)]
 mod client_server {
     use super::*;
     use futures::future::{err, ok, result};
-    use interledger_packet::{ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
     use interledger_service::*;
     use std::{
+        str::FromStr,
         sync::Arc,
         time::{Duration, SystemTime},
     };
@@ -111,7 +113,7 @@ mod client_server {
         fn get_accounts(
             &self,
             account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+        ) -> Box<Future<Item = Vec<Self::Account>, Error = StoreError> + Send> {
             let accounts: Vec<TestAccount> = self
                 .accounts
                 .iter()
@@ -126,7 +128,7 @@ mod client_server {
             if accounts.len() == account_ids.len() {
                 Box::new(ok(accounts))
             } else {
-                Box::new(err(()))
+                Box::new(err(StoreError::NotFound("Account not found".to_string())))
             }
         }
     }
@@ -137,7 +139,7 @@ mod client_server {
         fn get_account_from_btp_token(
             &self,
             token: &str,
-        ) -> Box<Future<Item = Self::Account, Error = ()> + Send> {
+        ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send> {
             Box::new(result(
                 self.accounts
                     .iter()
@@ -149,7 +151,7 @@ mod client_server {
                         }
                     })
                     .cloned()
-                    .ok_or(()),
+                    .ok_or_else(|| StoreError::NotFound("Account not found".to_string())),
             ))
         }
     }
@@ -177,6 +179,8 @@ mod client_server {
                 }
                 .build())
             }),
+            EventBus::new(),
+            true,
         )
         .and_then(|btp_server| {
             btp_server.handle_incoming(incoming_service_fn(|_| {
@@ -224,7 +228,7 @@ mod client_server {
                     from: account.clone(),
                     to: account.clone(),
                     prepare: PrepareBuilder {
-                        destination: b"example.destination",
+                        destination: &Address::from_str("example.destination").unwrap(),
                         amount: 100,
                         execution_condition: &[0; 32],
                         expires_at: SystemTime::now() + Duration::from_secs(30),
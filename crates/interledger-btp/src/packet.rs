@@ -1,19 +1,26 @@
 use super::errors::ParseError;
-use super::oer::{MutBufOerExt, ReadOerExt};
-use byteorder::{BigEndian, ReadBytesExt};
+use super::oer::MutBufOerExt;
 use bytes::BufMut;
 use chrono::{DateTime, TimeZone, Utc};
+use interledger_packet::oer::OerReader;
 use num_bigint::BigUint;
-use std::io::prelude::*;
-use std::io::Cursor;
-use std::ops::Add;
 use std::str;
 
 static GENERALIZED_TIME_FORMAT: &'static str = "%Y%m%d%H%M%S%.3fZ";
 
+/// The largest WebSocket message, and therefore BTP packet, that will be accepted
+pub(crate) const MAX_MESSAGE_SIZE: usize = 40000;
+const MAX_PROTOCOL_NAME_LEN: usize = 255;
+const MAX_ERROR_NAME_LEN: usize = 255;
+// GeneralizedTime strings are 19 bytes long with millisecond precision
+const MAX_TRIGGERED_AT_LEN: usize = 32;
+
 pub trait Serializable<T> {
     fn from_bytes(bytes: &[u8]) -> Result<T, ParseError>;
 
+    /// Like `from_bytes`, but rejects non-canonical encodings and trailing bytes
+    fn from_bytes_strict(bytes: &[u8]) -> Result<T, ParseError>;
+
     fn to_bytes(&self) -> Vec<u8>;
 }
 
@@ -43,18 +50,31 @@ pub enum BtpPacket {
     Error(BtpError),
 }
 
-impl Serializable<BtpPacket> for BtpPacket {
-    fn from_bytes(bytes: &[u8]) -> Result<BtpPacket, ParseError> {
+impl BtpPacket {
+    fn parse(bytes: &[u8], strict: bool) -> Result<BtpPacket, ParseError> {
+        if bytes.is_empty() {
+            return Err(interledger_packet::ParseError::UnexpectedEof("type", 0).into());
+        }
         match PacketType::from(bytes[0]) {
-            PacketType::Message => Ok(BtpPacket::Message(BtpMessage::from_bytes(bytes)?)),
-            PacketType::Response => Ok(BtpPacket::Response(BtpResponse::from_bytes(bytes)?)),
-            PacketType::Error => Ok(BtpPacket::Error(BtpError::from_bytes(bytes)?)),
+            PacketType::Message => Ok(BtpPacket::Message(BtpMessage::parse(bytes, strict)?)),
+            PacketType::Response => Ok(BtpPacket::Response(BtpResponse::parse(bytes, strict)?)),
+            PacketType::Error => Ok(BtpPacket::Error(BtpError::parse(bytes, strict)?)),
             PacketType::Unknown => Err(ParseError::InvalidPacket(format!(
                 "Unknown packet type: {}",
                 bytes[0]
             ))),
         }
     }
+}
+
+impl Serializable<BtpPacket> for BtpPacket {
+    fn from_bytes(bytes: &[u8]) -> Result<BtpPacket, ParseError> {
+        BtpPacket::parse(bytes, false)
+    }
+
+    fn from_bytes_strict(bytes: &[u8]) -> Result<BtpPacket, ParseError> {
+        BtpPacket::parse(bytes, true)
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         match self {
@@ -87,25 +107,49 @@ pub struct ProtocolData {
     pub content_type: ContentType,
     pub data: Vec<u8>,
 }
-fn read_protocol_data<T>(reader: &mut T) -> Result<Vec<ProtocolData>, ParseError>
-where
-    T: ReadOerExt,
-{
+/// Reads the type, request ID and contents shared by all BTP packets and returns a reader over the contents
+fn read_envelope<'a>(
+    bytes: &'a [u8],
+    expected_type: PacketType,
+    type_name: &str,
+    strict: bool,
+) -> Result<(u32, OerReader<'a>), ParseError> {
+    let mut reader = if strict {
+        OerReader::strict(bytes)
+    } else {
+        OerReader::new(bytes)
+    };
+    let packet_type = reader.read_u8("type")?;
+    if PacketType::from(packet_type) != expected_type {
+        return Err(ParseError::InvalidPacket(format!(
+            "Cannot parse {} from packet of type {}, expected type {}",
+            type_name, packet_type, expected_type as u8
+        )));
+    }
+    let request_id = reader.read_u32("request_id")?;
+    let contents = reader.read_var_octet_string_reader("contents", MAX_MESSAGE_SIZE)?;
+    reader.finish("contents")?;
+    Ok((request_id, contents))
+}
+
+fn read_protocol_data(reader: &mut OerReader) -> Result<Vec<ProtocolData>, ParseError> {
     let mut protocol_data = Vec::new();
 
-    let num_entries = reader.read_var_uint()?;
-    let mut i = BigUint::from(0 as u32);
-    while i < num_entries {
-        i = i.add(BigUint::from(1 as u8));
-        let protocol_name = String::from_utf8(reader.read_var_octet_string()?)?;
-        let content_type = ContentType::from(reader.read_u8()?);
-        let data = reader.read_var_octet_string()?;
+    let num_entries = reader.read_var_uint("protocol_data")?;
+    for _ in 0..num_entries {
+        let protocol_name = reader.read_var_octet_string("protocol_name", MAX_PROTOCOL_NAME_LEN)?;
+        let protocol_name = String::from_utf8(protocol_name.to_vec())?;
+        let content_type = ContentType::from(reader.read_u8("content_type")?);
+        let data = reader
+            .read_var_octet_string("data", MAX_MESSAGE_SIZE)?
+            .to_vec();
         protocol_data.push(ProtocolData {
             protocol_name,
             content_type,
             data,
         });
     }
+    reader.finish("protocol_data")?;
     Ok(protocol_data)
 }
 
@@ -127,25 +171,26 @@ pub struct BtpMessage {
     pub request_id: u32,
     pub protocol_data: Vec<ProtocolData>,
 }
-impl Serializable<BtpMessage> for BtpMessage {
-    fn from_bytes(bytes: &[u8]) -> Result<BtpMessage, ParseError> {
-        let mut reader = Cursor::new(bytes);
-        let packet_type = reader.read_u8()?;
-        if PacketType::from(packet_type) != PacketType::Message {
-            return Err(ParseError::InvalidPacket(format!(
-                "Cannot parse Message from packet of type {}, expected type {}",
-                packet_type,
-                PacketType::Message as u8
-            )));
-        }
-        let request_id = reader.read_u32::<BigEndian>()?;
-        let mut contents = Cursor::new(reader.read_var_octet_string()?);
+impl BtpMessage {
+    fn parse(bytes: &[u8], strict: bool) -> Result<BtpMessage, ParseError> {
+        let (request_id, mut contents) =
+            read_envelope(bytes, PacketType::Message, "Message", strict)?;
         let protocol_data = read_protocol_data(&mut contents)?;
         Ok(BtpMessage {
             request_id,
             protocol_data,
         })
     }
+}
+
+impl Serializable<BtpMessage> for BtpMessage {
+    fn from_bytes(bytes: &[u8]) -> Result<BtpMessage, ParseError> {
+        BtpMessage::parse(bytes, false)
+    }
+
+    fn from_bytes_strict(bytes: &[u8]) -> Result<BtpMessage, ParseError> {
+        BtpMessage::parse(bytes, true)
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
@@ -164,25 +209,26 @@ pub struct BtpResponse {
     pub request_id: u32,
     pub protocol_data: Vec<ProtocolData>,
 }
-impl Serializable<BtpResponse> for BtpResponse {
-    fn from_bytes(bytes: &[u8]) -> Result<BtpResponse, ParseError> {
-        let mut reader = Cursor::new(bytes);
-        let packet_type = reader.read_u8()?;
-        if PacketType::from(packet_type) != PacketType::Response {
-            return Err(ParseError::InvalidPacket(format!(
-                "Cannot parse Response from packet of type {}, expected type {}",
-                packet_type,
-                PacketType::Response as u8
-            )));
-        }
-        let request_id = reader.read_u32::<BigEndian>()?;
-        let mut contents = Cursor::new(reader.read_var_octet_string()?);
+impl BtpResponse {
+    fn parse(bytes: &[u8], strict: bool) -> Result<BtpResponse, ParseError> {
+        let (request_id, mut contents) =
+            read_envelope(bytes, PacketType::Response, "Response", strict)?;
         let protocol_data = read_protocol_data(&mut contents)?;
         Ok(BtpResponse {
             request_id,
             protocol_data,
         })
     }
+}
+
+impl Serializable<BtpResponse> for BtpResponse {
+    fn from_bytes(bytes: &[u8]) -> Result<BtpResponse, ParseError> {
+        BtpResponse::parse(bytes, false)
+    }
+
+    fn from_bytes_strict(bytes: &[u8]) -> Result<BtpResponse, ParseError> {
+        BtpResponse::parse(bytes, true)
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
@@ -204,25 +250,20 @@ pub struct BtpError {
     pub data: String,
     pub protocol_data: Vec<ProtocolData>,
 }
-impl Serializable<BtpError> for BtpError {
-    fn from_bytes(bytes: &[u8]) -> Result<BtpError, ParseError> {
-        let mut reader = Cursor::new(bytes);
-        let packet_type = reader.read_u8()?;
-        if PacketType::from(packet_type) != PacketType::Error {
-            return Err(ParseError::InvalidPacket(format!(
-                "Cannot parse Error from packet of type {}, expected type {}",
-                packet_type,
-                PacketType::Error as u8
-            )));
-        }
-        let request_id = reader.read_u32::<BigEndian>()?;
-        let mut contents = Cursor::new(reader.read_var_octet_string()?);
-        let mut code: [u8; 3] = [0; 3];
-        contents.read_exact(&mut code)?;
-        let name = String::from_utf8(contents.read_var_octet_string()?)?;
-        let triggered_at_string = String::from_utf8(contents.read_var_octet_string()?)?;
-        let triggered_at = Utc.datetime_from_str(&triggered_at_string, GENERALIZED_TIME_FORMAT)?;
-        let data = String::from_utf8(contents.read_var_octet_string()?)?;
+impl BtpError {
+    fn parse(bytes: &[u8], strict: bool) -> Result<BtpError, ParseError> {
+        let (request_id, mut contents) = read_envelope(bytes, PacketType::Error, "Error", strict)?;
+        let code = contents.read_bytes(3, "code")?;
+        let name = contents.read_var_octet_string("name", MAX_ERROR_NAME_LEN)?;
+        let name = String::from_utf8(name.to_vec())?;
+        let triggered_at_string =
+            contents.read_var_octet_string("triggered_at", MAX_TRIGGERED_AT_LEN)?;
+        let triggered_at = Utc.datetime_from_str(
+            str::from_utf8(triggered_at_string)?,
+            GENERALIZED_TIME_FORMAT,
+        )?;
+        let data = contents.read_var_octet_string("data", MAX_MESSAGE_SIZE)?;
+        let data = String::from_utf8(data.to_vec())?;
         let protocol_data = read_protocol_data(&mut contents)?;
         Ok(BtpError {
             request_id,
@@ -233,6 +274,16 @@ impl Serializable<BtpError> for BtpError {
             protocol_data,
         })
     }
+}
+
+impl Serializable<BtpError> for BtpError {
+    fn from_bytes(bytes: &[u8]) -> Result<BtpError, ParseError> {
+        BtpError::parse(bytes, false)
+    }
+
+    fn from_bytes_strict(bytes: &[u8]) -> Result<BtpError, ParseError> {
+        BtpError::parse(bytes, true)
+    }
 
     fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
@@ -354,4 +405,110 @@ mod tests {
             assert_eq!(ERROR_1.to_bytes(), *ERROR_1_SERIALIZED);
         }
     }
+
+    mod strict_parsing {
+        use super::*;
+        use interledger_packet::ParseError as PacketParseError;
+        use proptest::prelude::*;
+
+        #[test]
+        fn rejects_empty_buffer() {
+            match BtpPacket::from_bytes_strict(&[]) {
+                Err(ParseError::Packet(PacketParseError::UnexpectedEof("type", 0))) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+
+        #[test]
+        fn rejects_non_canonical_contents_length() {
+            let bytes = hex::decode("0600000002810100").unwrap();
+            match BtpPacket::from_bytes_strict(&bytes) {
+                Err(ParseError::Packet(PacketParseError::NonCanonical("contents", 5))) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+
+        #[test]
+        fn rejects_trailing_bytes() {
+            let bytes = hex::decode("06000000020201000a").unwrap();
+            match BtpPacket::from_bytes_strict(&bytes) {
+                Err(ParseError::Packet(PacketParseError::TrailingBytes("contents", 8))) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+            let bytes = hex::decode("060000000203010000").unwrap();
+            match BtpPacket::from_bytes_strict(&bytes) {
+                Err(ParseError::Packet(PacketParseError::TrailingBytes("protocol_data", 8))) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+
+        #[test]
+        fn lenient_parsing_accepts_non_canonical_encodings() {
+            let bytes = hex::decode("060000000281020100ff").unwrap();
+            assert_eq!(
+                BtpPacket::from_bytes(&bytes).unwrap(),
+                BtpPacket::Message(BtpMessage {
+                    request_id: 2,
+                    protocol_data: Vec::new(),
+                })
+            );
+        }
+
+        #[test]
+        fn rejects_oversized_fields() {
+            let message = BtpMessage {
+                request_id: 2,
+                protocol_data: vec![ProtocolData {
+                    protocol_name: "a".repeat(MAX_PROTOCOL_NAME_LEN + 1),
+                    content_type: ContentType::ApplicationOctetStream,
+                    data: Vec::new(),
+                }],
+            };
+            // The protocol name follows the type, request ID, contents length and entry count
+            match BtpPacket::from_bytes(&message.to_bytes()) {
+                Err(ParseError::Packet(PacketParseError::FieldTooLong(
+                    "protocol_name",
+                    10,
+                    MAX_PROTOCOL_NAME_LEN,
+                ))) => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+
+        fn protocol_data() -> impl Strategy<Value = Vec<ProtocolData>> {
+            prop::collection::vec(
+                (
+                    "[a-z_]{0,20}",
+                    0..2u8,
+                    prop::collection::vec(any::<u8>(), 0..300),
+                )
+                    .prop_map(|(protocol_name, content_type, data)| ProtocolData {
+                        protocol_name,
+                        content_type: ContentType::from(content_type),
+                        data,
+                    }),
+                0..4,
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn message_round_trips(request_id in any::<u32>(), protocol_data in protocol_data()) {
+                let message = BtpPacket::Message(BtpMessage {
+                    request_id,
+                    protocol_data,
+                });
+                prop_assert_eq!(BtpPacket::from_bytes_strict(&message.to_bytes()).unwrap(), message);
+            }
+
+            #[test]
+            fn response_round_trips(request_id in any::<u32>(), protocol_data in protocol_data()) {
+                let response = BtpPacket::Response(BtpResponse {
+                    request_id,
+                    protocol_data,
+                });
+                prop_assert_eq!(BtpPacket::from_bytes_strict(&response.to_bytes()).unwrap(), response);
+            }
+        }
+    }
 }
@@ -2,8 +2,11 @@ use super::{
     packet::*, BtpAccount, BtpOpenSignupAccount, BtpOpenSignupStore, BtpOutgoingService, BtpStore,
 };
 use base64;
-use bytes::{BufMut, Bytes, BytesMut};
-use futures::{future::result, Future, Sink, Stream};
+use bytes::Bytes;
+use futures::{
+    future::{err, result, Either},
+    Future, Sink, Stream,
+};
 use interledger_ildcp::IldcpResponse;
 use interledger_service::*;
 use ring::digest::{digest, SHA256};
@@ -13,8 +16,6 @@ use tokio_tcp::TcpListener;
 use tokio_tungstenite::{accept_async_with_config, stream::Stream as MaybeTlsStream};
 use tungstenite::protocol::{Message, WebSocketConfig};
 
-const MAX_MESSAGE_SIZE: usize = 40000;
-
 /// Returns a BtpOutgoingService that wraps all BTP/WebSocket connections that come
 /// in on the given address. Calling `handle_incoming` with an `IncomingService` will
 /// turn the returned BtpOutgoingService into a bidirectional handler.
@@ -22,10 +23,15 @@ const MAX_MESSAGE_SIZE: usize = 40000;
 /// The separation is designed to enable the returned BtpOutgoingService to be passed
 /// to another service like the Router, and _then_ for the Router to be passed as the
 /// IncomingService to the BTP server.
+///
+/// Connections opening and closing are published on `events`. If `strict_parsing` is set,
+/// BTP and ILP packets that are not canonically encoded are rejected (see `OerReader::strict`).
 pub fn create_server<T, U, A>(
     address: SocketAddr,
     store: U,
     next_outgoing: T,
+    events: EventBus<A>,
+    strict_parsing: bool,
 ) -> impl Future<Item = BtpOutgoingService<T, A>, Error = ()>
 where
     T: OutgoingService<A> + Clone + Send + Sync + 'static,
@@ -37,11 +43,14 @@ where
     }))
     .and_then(move |socket| {
         debug!("Listening on {}", address);
-        let service = BtpOutgoingService::new(next_outgoing);
+        let service = BtpOutgoingService::new(next_outgoing)
+            .with_events(events)
+            .with_strict_parsing(strict_parsing);
 
         let service_clone = service.clone();
-        let handle_incoming = socket
-            .incoming()
+        // Stop accepting connections when the service is closed
+        let handle_incoming = service
+            .valved(socket.incoming())
             .map_err(|err| error!("Error handling incoming connection: {:?}", err))
             .for_each(move |stream| {
                 let service_clone = service_clone.clone();
@@ -94,8 +103,9 @@ where
         let service = BtpOutgoingService::new(next_outgoing);
 
         let service_clone = service.clone();
-        let handle_incoming = socket
-            .incoming()
+        // Stop accepting connections when the service is closed
+        let handle_incoming = service
+            .valved(socket.incoming())
             .map_err(|err| error!("Error handling incoming connection: {:?}", err))
             .for_each(move |stream| {
                 let service_clone = service_clone.clone();
@@ -139,7 +149,12 @@ where
         let token = auth.token.clone();
         store
             .get_account_from_btp_token(&auth.token)
-            .map_err(move |_| warn!("Got unauthorized connection with token: {}", token))
+            .map_err(move |err| match err {
+                StoreError::NotFound(_) => {
+                    warn!("Got unauthorized connection with token: {}", token)
+                }
+                err => error!("Error looking up account for BTP connection: {}", err),
+            })
             .and_then(move |account| {
                 let auth_response = Message::Binary(
                     BtpResponse {
@@ -170,7 +185,11 @@ where
         let request_id = auth.request_id;
         store
             .get_account_from_btp_token(&auth.token)
-            .or_else(move |_| {
+            .or_else(move |store_err| {
+                // Other errors mean we can't tell whether the account already exists
+                if !store_err.is_not_found() {
+                    return Either::B(err(store_err));
+                }
                 let local_part: Bytes = if let Some(username) = auth.username {
                     Bytes::from(username)
                 } else {
@@ -179,25 +198,31 @@ where
                         base64::URL_SAFE_NO_PAD,
                     ))
                 };
-                let mut ilp_address = BytesMut::with_capacity(
-                    ildcp_info.client_address().len() + 1 + local_part.len(),
-                );
-                ilp_address.put(ildcp_info.client_address());
-                ilp_address.put(&b"."[..]);
-                ilp_address.put(local_part);
-                store
-                    .create_btp_account(BtpOpenSignupAccount {
-                        auth_token: &auth.token,
-                        ilp_address: &ilp_address[..],
-                        asset_code: str::from_utf8(ildcp_info.asset_code())
-                            .expect("Asset code provided is not valid utf8"),
-                        asset_scale: ildcp_info.asset_scale(),
-                    })
-                    .and_then(|account| {
-                        debug!("Created new account: {:?}", account);
-                        Ok(account)
-                    })
+                let ilp_address = match ildcp_info.client_address().with_suffix(&local_part[..]) {
+                    Ok(address) => address,
+                    Err(address_err) => {
+                        return Either::B(err(StoreError::Other(format!(
+                            "Cannot create account with username {:?}: {}",
+                            local_part, address_err
+                        ))))
+                    }
+                };
+                Either::A(
+                    store
+                        .create_btp_account(BtpOpenSignupAccount {
+                            auth_token: &auth.token,
+                            ilp_address: &ilp_address[..],
+                            asset_code: str::from_utf8(ildcp_info.asset_code())
+                                .expect("Asset code provided is not valid utf8"),
+                            asset_scale: ildcp_info.asset_scale(),
+                        })
+                        .and_then(|account| {
+                            debug!("Created new account: {:?}", account);
+                            Ok(account)
+                        }),
+                )
             })
+            .map_err(|err| error!("Error getting or creating BTP account: {}", err))
             .and_then(move |account| {
                 let auth_response = Message::Binary(
                     BtpResponse {
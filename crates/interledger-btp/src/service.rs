@@ -10,6 +10,7 @@ use hashbrown::HashMap;
 use interledger_packet::{ErrorCode, Fulfill, Packet, Prepare, Reject, RejectBuilder};
 use interledger_service::*;
 use parking_lot::{Mutex, RwLock};
+use prometheus::IntGauge;
 use rand::random;
 use std::{
     io::{Error as IoError, ErrorKind},
@@ -23,6 +24,14 @@ use tokio_tcp::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use tungstenite::{error::Error as WebSocketError, Message};
 
+lazy_static! {
+    static ref OPEN_CONNECTIONS: IntGauge = register_int_gauge!(
+        "btp_open_connections",
+        "Number of accounts with an open BTP connection"
+    )
+    .unwrap();
+}
+
 type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 type IlpResultChannel = oneshot::Sender<Result<Fulfill, Reject>>;
 type IncomingRequestBuffer<A> = UnboundedReceiver<(A, u32, Prepare)>;
@@ -39,6 +48,8 @@ pub struct BtpOutgoingService<T, A: Account> {
     next_outgoing: T,
     close_all_connections: Arc<Mutex<Option<Trigger>>>,
     stream_valve: Arc<Valve>,
+    events: Option<EventBus<A>>,
+    strict_parsing: bool,
 }
 
 impl<T, A> BtpOutgoingService<T, A>
@@ -57,29 +68,52 @@ where
             next_outgoing,
             close_all_connections: Arc::new(Mutex::new(Some(close_all_connections))),
             stream_valve: Arc::new(stream_valve),
+            events: None,
+            strict_parsing: false,
         }
     }
 
+    /// Publish `ConnectionOpened` and `ConnectionClosed` events on the given bus.
+    pub fn with_events(mut self, events: EventBus<A>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Reject BTP and ILP packets from connections that are not canonically encoded
+    /// or have trailing bytes, instead of parsing them leniently.
+    pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
     /// Close all of the open WebSocket connections
     // TODO is there some more automatic way of knowing when we should close the connections?
     // The problem is that the WS client can be a server too, so it's not clear when we are done with it
     pub fn close(&self) {
         debug!("Closing all WebSocket connections");
+        // Dropping the senders ends the streams forwarding messages to the connections,
+        // which closes each WebSocket with a close frame once the queued messages are sent
+        self.connections.write().clear();
         self.close_all_connections.lock().take();
     }
 
+    /// Stop the stream when the service is closed. This is used to stop accepting new connections.
+    pub(crate) fn valved<S: Stream>(&self, stream: S) -> Valved<S> {
+        self.stream_valve.wrap(stream)
+    }
+
     /// Set up a WebSocket connection so that outgoing Prepare packets can be sent to it,
     /// incoming Prepare packets are buffered in a channel (until an IncomingService is added
     /// via the handle_incoming method), and ILP Fulfill and Reject packets will be
     /// sent back to the Future that sent the outgoing request originally.
     pub(crate) fn add_connection(&self, account: A, connection: WsStream) {
         let account_id = account.id();
+        let event_account = account.clone();
 
         // Set up a channel to forward outgoing packets to the WebSocket connection
         let (tx, rx) = unbounded();
         let (sink, stream) = connection.split();
         let (close_connection, stream) = Valved::new(stream);
-        let stream = self.stream_valve.wrap(stream);
         let forward_to_connection = sink
             .send_all(
                 rx.map_err(|_err| {
@@ -96,9 +130,10 @@ where
         // TODO do we need all this cloning?
         let pending_requests = self.pending_outgoing.clone();
         let incoming_sender = self.incoming_sender.clone();
+        let strict_parsing = self.strict_parsing;
         let handle_incoming = stream.map_err(move |err| error!("Error reading from WebSocket stream for account {}: {:?}", account_id, err)).for_each(move |message| {
           // Handle the packets based on whether they are an incoming request or a response to something we sent
-          match parse_ilp_packet(message) {
+          match parse_ilp_packet(message, strict_parsing) {
             Ok((request_id, Packet::Prepare(prepare))) => {
                 incoming_sender.clone().unbounded_send((account.clone(), request_id, prepare))
                     .map_err(|err| error!("Unable to buffer incoming request: {:?}", err))
@@ -129,12 +164,20 @@ where
 
         let connections = self.connections.clone();
         let keep_connections_open = self.close_all_connections.clone();
+        let events = self.events.clone();
+        let closed_account = event_account.clone();
         let handle_connection = handle_incoming
             .select(forward_to_connection)
             .then(move |_| {
                 let _ = keep_connections_open;
                 let mut connections = connections.write();
                 connections.remove(&account_id);
+                OPEN_CONNECTIONS.set(connections.len() as i64);
+                if let Some(ref events) = events {
+                    events.publish(Event::ConnectionClosed {
+                        account: closed_account,
+                    });
+                }
                 debug!(
                     "WebSocket connection closed for account {} ({} connections still open)",
                     account_id,
@@ -145,7 +188,14 @@ where
         spawn(handle_connection);
 
         // Save the sender side of the channel so we have a way to forward outgoing requests to the WebSocket
-        self.connections.write().insert(account_id, tx);
+        let mut connections = self.connections.write();
+        connections.insert(account_id, tx);
+        OPEN_CONNECTIONS.set(connections.len() as i64);
+        if let Some(ref events) = self.events {
+            events.publish(Event::ConnectionOpened {
+                account: event_account,
+            });
+        }
     }
 
     /// Convert this BtpOutgoingService into a bidirectional BtpService by adding a handler for incoming requests.
@@ -173,29 +223,29 @@ where
                     prepare,
                 };
                 debug!("Handling incoming request: {:?}", &request);
-                incoming_handler_clone
-                    .handle_request(request)
-                    .then(move |result| {
-                        let packet = match result {
-                            Ok(fulfill) => Packet::Fulfill(fulfill),
-                            Err(reject) => Packet::Reject(reject),
-                        };
-                        let message = ilp_packet_to_ws_message(request_id, packet);
-                        connections_clone
-                            .read()
-                            .get(&account_id)
-                            .expect(
-                                "No connection for account (something very strange has happened)",
+                handle_request_in_span(&mut incoming_handler_clone, request).then(move |result| {
+                    let packet = match result {
+                        Ok(fulfill) => Packet::Fulfill(fulfill),
+                        Err(reject) => Packet::Reject(reject),
+                    };
+                    let message = ilp_packet_to_ws_message(request_id, packet);
+                    let connection = connections_clone.read().get(&account_id).cloned();
+                    match connection {
+                        Some(connection) => connection.unbounded_send(message).map_err(|err| {
+                            error!(
+                                "Error sending response to account: {} {:?}",
+                                account_id, err
                             )
-                            .clone()
-                            .unbounded_send(message)
-                            .map_err(|err| {
-                                error!(
-                                    "Error sending response to account: {} {:?}",
-                                    account_id, err
-                                )
-                            })
-                    })
+                        }),
+                        None => {
+                            warn!(
+                                "Dropping response to account {} because its connection was closed",
+                                account_id
+                            );
+                            Ok(())
+                        }
+                    }
+                })
             })
             .then(move |_| {
                 debug!("Finished reading from pending_incoming buffer");
@@ -319,9 +369,14 @@ where
     }
 }
 
-fn parse_ilp_packet(message: Message) -> Result<(u32, Packet), ()> {
+fn parse_ilp_packet(message: Message, strict: bool) -> Result<(u32, Packet), ()> {
     if let Message::Binary(data) = message {
-        let (request_id, ilp_data) = match BtpPacket::from_bytes(&data) {
+        let btp_packet = if strict {
+            BtpPacket::from_bytes_strict(&data)
+        } else {
+            BtpPacket::from_bytes(&data)
+        };
+        let (request_id, ilp_data) = match btp_packet {
             Ok(BtpPacket::Message(message)) => {
                 let ilp_data = message
                     .protocol_data
@@ -349,7 +404,12 @@ fn parse_ilp_packet(message: Message) -> Result<(u32, Packet), ()> {
                 return Err(());
             }
         };
-        if let Ok(packet) = Packet::try_from(BytesMut::from(ilp_data)) {
+        let packet = if strict {
+            Packet::try_from_strict(BytesMut::from(ilp_data))
+        } else {
+            Packet::try_from(BytesMut::from(ilp_data))
+        };
+        if let Ok(packet) = packet {
             Ok((request_id, packet))
         } else {
             Err(())
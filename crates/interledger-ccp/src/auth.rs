@@ -0,0 +1,465 @@
+use crate::packet::{Route, RouteProp};
+use bytes::{BufMut, Bytes};
+use hashbrown::HashMap;
+use interledger_packet::{
+    oer::{MutBufOerExt, OerReader},
+    ParseError, MAX_ADDRESS_LENGTH,
+};
+use parking_lot::RwLock;
+use prometheus::IntCounterVec;
+use ring::signature::{self, Ed25519KeyPair, KeyPair, ED25519};
+use std::{str, sync::Arc};
+use untrusted::Input;
+
+lazy_static! {
+    static ref REJECTED_ROUTES: IntCounterVec = register_int_counter_vec!(
+        "ccp_rejected_routes",
+        "Number of advertised routes that failed route authentication",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+/// The ID of the route property that carries the signatures of the nodes in the route's path
+pub(crate) const SIGNATURES_PROP_ID: u16 = 0x5349;
+const SIGNATURE_LEN: usize = 64;
+
+/// What each node signs: the prefix, the epoch the route was first signed in,
+/// and the path from that node to the origin
+fn signed_message(prefix: &[u8], epoch: u32, path: &[Bytes]) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.put_var_octet_string(prefix);
+    message.put_u32_be(epoch);
+    message.put_var_uint(path.len() as u64);
+    for node in path {
+        message.put_var_octet_string(&node[..]);
+    }
+    message
+}
+
+/// Whether the prefix is the given address or lies underneath it
+fn is_under(prefix: &[u8], address: &[u8]) -> bool {
+    prefix.starts_with(address) && (prefix.len() == address.len() || prefix[address.len()] == b'.')
+}
+
+/// Check the signature of the node at the given index in the route's path
+fn is_signed_by(
+    route: &Route,
+    signatures: &PathSignatures,
+    index: usize,
+    public_key: &[u8],
+) -> bool {
+    let node = &route.path[index];
+    let message = signed_message(&route.prefix[..], signatures.epoch, &route.path[index..]);
+    signatures.signatures.iter().any(|(signer, signature)| {
+        signer == node
+            && signature::verify(
+                &ED25519,
+                Input::from(public_key),
+                Input::from(&message[..]),
+                Input::from(&signature[..]),
+            )
+            .is_ok()
+    })
+}
+
+/// The contents of the signatures route property
+#[derive(Clone, Debug, PartialEq)]
+struct PathSignatures {
+    epoch: u32,
+    /// The address of each node that signed the route and its signature
+    signatures: Vec<(Bytes, Bytes)>,
+}
+
+impl PathSignatures {
+    fn from_route(route: &Route) -> Option<Result<Self, ParseError>> {
+        route
+            .props
+            .iter()
+            .find(|prop| prop.id == SIGNATURES_PROP_ID)
+            .map(|prop| PathSignatures::try_from(&prop.value[..]))
+    }
+
+    fn try_from(data: &[u8]) -> Result<Self, ParseError> {
+        let data = &mut OerReader::new(data);
+        let epoch = data.read_u32("epoch")?;
+        let signatures_len = data.read_var_uint("signatures")?;
+        let mut signatures = Vec::new();
+        for _i in 0..signatures_len {
+            let node = data.read_var_octet_string("node", MAX_ADDRESS_LENGTH)?;
+            let signature = data.read_var_octet_string("signature", SIGNATURE_LEN)?;
+            signatures.push((Bytes::from(node), Bytes::from(signature)));
+        }
+        data.finish("signatures")?;
+        Ok(PathSignatures { epoch, signatures })
+    }
+
+    fn to_prop(&self) -> RouteProp {
+        let mut value = Vec::new();
+        value.put_u32_be(self.epoch);
+        value.put_var_uint(self.signatures.len() as u64);
+        for (node, signature) in self.signatures.iter() {
+            value.put_var_octet_string(&node[..]);
+            value.put_var_octet_string(&signature[..]);
+        }
+        RouteProp {
+            is_optional: true,
+            is_transitive: true,
+            is_partial: false,
+            id: SIGNATURES_PROP_ID,
+            is_utf8: false,
+            value: Bytes::from(value),
+        }
+    }
+}
+
+/// Route authentication using Ed25519 signatures carried in a transitive route property.
+///
+/// Each node that advertises a route, starting with the origin, adds itself to the start of
+/// the path and signs the prefix, the path from itself to the origin, and the epoch of the
+/// first signature. A node that has the public keys of the nodes in the path can therefore
+/// check that the origin advertised the prefix and that no node was added to or removed from
+/// the path after a node it trusts signed it. These signatures replace the hash chain in the
+/// route's `auth` field, which is forwarded unchanged and not checked.
+///
+/// Only the public keys are given to other nodes, so verifiers cannot sign routes themselves.
+/// Once any public keys are configured, routes must come from an origin we have a key for,
+/// for a prefix under the origin's address, and must not be signed in an older epoch than the
+/// last route we accepted from that origin for the same prefix. Intermediate nodes we have no
+/// public key for are not checked.
+#[derive(Clone, Debug, Default)]
+pub struct RouteAuth {
+    key_pair: Option<Arc<Ed25519KeyPair>>,
+    public_keys: HashMap<Bytes, Bytes>,
+    allow_unknown_origins: bool,
+    /// The epoch of the last route accepted from each origin, by origin and prefix
+    last_epochs: Arc<RwLock<HashMap<(Bytes, Bytes), u32>>>,
+}
+
+impl RouteAuth {
+    pub fn new() -> Self {
+        RouteAuth::default()
+    }
+
+    /// Sign the routes we advertise with the Ed25519 key derived from the given seed
+    pub fn with_signing_key(mut self, seed: [u8; 32]) -> Self {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(Input::from(&seed[..]))
+            .expect("Any 32-byte seed is a valid Ed25519 key");
+        self.key_pair = Some(Arc::new(key_pair));
+        self
+    }
+
+    /// The public key other nodes should use to verify our routes, if we sign them
+    pub fn public_key(&self) -> Option<[u8; 32]> {
+        self.key_pair.as_ref().map(|key_pair| {
+            let mut public_key = [0; 32];
+            public_key.copy_from_slice(key_pair.public_key().as_ref());
+            public_key
+        })
+    }
+
+    /// Verify the signatures of the node with the given ILP address using its public key
+    pub fn with_public_key(mut self, node: Bytes, public_key: [u8; 32]) -> Self {
+        self.public_keys.insert(node, Bytes::from(&public_key[..]));
+        self
+    }
+
+    /// Accept routes from origins we do not have a public key for without verifying them,
+    /// instead of rejecting them once any public keys are configured
+    pub fn allow_unknown_origins(mut self, allow: bool) -> Self {
+        self.allow_unknown_origins = allow;
+        self
+    }
+
+    /// Add our signature to a route we are about to advertise, after we have added ourselves to
+    /// the start of its path. The epoch is only used if no other node has signed the route yet.
+    pub(crate) fn sign(&self, route: &mut Route, epoch: u32) {
+        let key_pair = match self.key_pair {
+            Some(ref key_pair) => key_pair,
+            None => return,
+        };
+        let mut signatures = match PathSignatures::from_route(route) {
+            Some(Ok(signatures)) => signatures,
+            // Routes with invalid signatures are rejected before we get here
+            _ => PathSignatures {
+                epoch,
+                signatures: Vec::new(),
+            },
+        };
+        let signature = key_pair.sign(&signed_message(
+            &route.prefix[..],
+            signatures.epoch,
+            &route.path[..],
+        ));
+        signatures
+            .signatures
+            .push((route.path[0].clone(), Bytes::from(signature.as_ref())));
+        route.props.retain(|prop| prop.id != SIGNATURES_PROP_ID);
+        route.props.push(signatures.to_prop());
+    }
+
+    /// Check the signatures of a route advertised to us. Every node in the path that we have a
+    /// public key for must have signed the path from itself to the origin, which is the last
+    /// entry in the path because each node inserts itself at the start.
+    ///
+    /// Rejected routes are logged and counted.
+    pub(crate) fn verify(&self, route: &Route) -> bool {
+        let reason = self.rejection_reason(route);
+        if let Some(reason) = reason {
+            warn!(
+                "Rejecting route for prefix {} with path {:?} because it failed route authentication ({})",
+                str::from_utf8(&route.prefix[..]).unwrap_or("<not utf8>"),
+                route.path,
+                reason
+            );
+            REJECTED_ROUTES.with_label_values(&[reason]).inc();
+            false
+        } else {
+            true
+        }
+    }
+
+    fn rejection_reason(&self, route: &Route) -> Option<&'static str> {
+        let signatures = match PathSignatures::from_route(route) {
+            Some(Err(_)) => return Some("invalid_signature"),
+            signatures => signatures.and_then(Result::ok),
+        };
+        // Routes are only authenticated once we know the keys of some other nodes
+        if self.public_keys.is_empty() {
+            return None;
+        }
+
+        let invalid_signature = route.path.iter().enumerate().any(|(index, node)| {
+            match (self.public_keys.get(node), signatures.as_ref()) {
+                (Some(public_key), Some(signatures)) => {
+                    !is_signed_by(route, signatures, index, &public_key[..])
+                }
+                (Some(_), None) => true,
+                (None, _) => false,
+            }
+        });
+        if invalid_signature {
+            return Some("invalid_signature");
+        }
+
+        let (origin, signatures) = match (route.path.last(), signatures) {
+            (Some(origin), Some(signatures)) if self.public_keys.contains_key(origin) => {
+                (origin, signatures)
+            }
+            _ if self.allow_unknown_origins => return None,
+            _ => return Some("unknown_origin"),
+        };
+        if !is_under(&route.prefix[..], &origin[..]) {
+            return Some("prefix_not_under_origin");
+        }
+
+        // Stop old advertisements from being replayed once the origin has signed a newer one
+        let mut last_epochs = self.last_epochs.write();
+        let last_epoch = last_epochs
+            .entry((origin.clone(), route.prefix.clone()))
+            .or_insert(signatures.epoch);
+        if signatures.epoch < *last_epoch {
+            return Some("stale_epoch");
+        }
+        *last_epoch = signatures.epoch;
+        None
+    }
+}
+
+#[cfg(test)]
+mod route_auth {
+    use super::*;
+
+    const ORIGIN_SEED: [u8; 32] = [1; 32];
+    const FORWARDER_SEED: [u8; 32] = [2; 32];
+
+    /// Advertise the route from the origin through the given nodes, with the nodes
+    /// that have keys signing it
+    fn advertise(hops: Vec<(&'static str, Option<[u8; 32]>)>) -> Route {
+        advertise_prefix("example.origin.a", 3, hops)
+    }
+
+    fn advertise_prefix(
+        prefix: &'static str,
+        epoch: u32,
+        hops: Vec<(&'static str, Option<[u8; 32]>)>,
+    ) -> Route {
+        let mut route = Route {
+            prefix: Bytes::from(prefix),
+            path: Vec::new(),
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        for (node, seed) in hops {
+            route.path.insert(0, Bytes::from(node));
+            if let Some(seed) = seed {
+                RouteAuth::new()
+                    .with_signing_key(seed)
+                    .sign(&mut route, epoch);
+            }
+        }
+        route
+    }
+
+    fn public_key(seed: [u8; 32]) -> [u8; 32] {
+        RouteAuth::new()
+            .with_signing_key(seed)
+            .public_key()
+            .unwrap()
+    }
+
+    fn route_auth() -> RouteAuth {
+        RouteAuth::new()
+            .with_public_key(Bytes::from("example.origin"), public_key(ORIGIN_SEED))
+            .with_public_key(Bytes::from("example.b"), public_key(FORWARDER_SEED))
+    }
+
+    #[test]
+    fn accepts_route_from_origin() {
+        let route = advertise(vec![("example.origin", Some(ORIGIN_SEED))]);
+        assert!(route_auth().verify(&route));
+    }
+
+    #[test]
+    fn accepts_route_forwarded_by_other_nodes() {
+        let route = advertise(vec![
+            ("example.origin", Some(ORIGIN_SEED)),
+            ("example.a", None),
+            ("example.b", Some(FORWARDER_SEED)),
+        ]);
+        assert!(route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_route_signed_with_wrong_key() {
+        let route = advertise(vec![
+            ("example.origin", Some(FORWARDER_SEED)),
+            ("example.a", None),
+        ]);
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_unsigned_route_from_known_origin() {
+        let route = advertise(vec![("example.origin", None), ("example.a", None)]);
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_route_with_changed_path() {
+        let mut route = advertise(vec![
+            ("example.origin", Some(ORIGIN_SEED)),
+            ("example.a", None),
+            ("example.b", Some(FORWARDER_SEED)),
+        ]);
+        route.path.remove(1);
+        assert!(!route_auth().verify(&route));
+
+        let mut route = advertise(vec![("example.origin", Some(ORIGIN_SEED))]);
+        route.path.push(Bytes::from("example.other"));
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_route_with_changed_epoch() {
+        let mut route = advertise(vec![("example.origin", Some(ORIGIN_SEED))]);
+        let mut signatures = PathSignatures::from_route(&route).unwrap().unwrap();
+        signatures.epoch = 4;
+        route.props = vec![signatures.to_prop()];
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_invalid_signatures_prop() {
+        let mut route = advertise(vec![("example.other", None)]);
+        route.props.push(RouteProp {
+            is_optional: true,
+            is_transitive: true,
+            is_partial: false,
+            id: SIGNATURES_PROP_ID,
+            is_utf8: false,
+            value: Bytes::from(&[0, 1][..]),
+        });
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_unknown_origins_unless_allowed() {
+        let route = advertise(vec![("example.other", None), ("example.a", None)]);
+        assert!(!route_auth().verify(&route));
+        assert!(route_auth().allow_unknown_origins(true).verify(&route));
+
+        // The origin must be known even if a node we have a key for forwarded the route
+        let route = advertise(vec![
+            ("example.other", None),
+            ("example.b", Some(FORWARDER_SEED)),
+        ]);
+        assert!(!route_auth().verify(&route));
+    }
+
+    #[test]
+    fn accepts_all_routes_without_public_keys() {
+        let route = advertise(vec![("example.other", None), ("example.a", None)]);
+        assert!(RouteAuth::new().verify(&route));
+    }
+
+    #[test]
+    fn rejects_prefix_outside_origin_address() {
+        let route = advertise_prefix(
+            "example.anyone",
+            3,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        assert!(!route_auth().verify(&route));
+
+        // A prefix that only shares the first bytes of the origin's address is not under it
+        let route = advertise_prefix(
+            "example.originator",
+            3,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        assert!(!route_auth().verify(&route));
+
+        let route = advertise_prefix(
+            "example.origin",
+            3,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        assert!(route_auth().verify(&route));
+    }
+
+    #[test]
+    fn rejects_routes_signed_in_older_epochs() {
+        let route_auth = route_auth();
+        let old = advertise_prefix(
+            "example.origin.a",
+            3,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        let new = advertise_prefix(
+            "example.origin.a",
+            5,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        assert!(route_auth.verify(&old));
+        assert!(route_auth.verify(&new));
+        assert!(route_auth.verify(&new));
+        assert!(!route_auth.verify(&old));
+
+        // Epochs are tracked separately for each prefix
+        let other = advertise_prefix(
+            "example.origin.b",
+            3,
+            vec![("example.origin", Some(ORIGIN_SEED))],
+        );
+        assert!(route_auth.verify(&other));
+    }
+
+    #[test]
+    fn does_not_sign_without_key() {
+        let mut route = advertise(vec![("example.origin", None)]);
+        RouteAuth::new().sign(&mut route, 1);
+        assert!(route.props.is_empty());
+        assert_eq!(RouteAuth::new().public_key(), None);
+    }
+}
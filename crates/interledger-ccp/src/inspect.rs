@@ -0,0 +1,193 @@
+use crate::{packet::Route, state::RoutingState, RoutePolicies, RoutingRelation};
+use bytes::Bytes;
+use futures::Future;
+use hashbrown::HashMap;
+use interledger_service::Account;
+use serde::Serialize;
+use std::{fmt::Display, hash::Hash, str::FromStr};
+
+/// Where the route we use for a prefix came from
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RouteSource {
+    /// A static route set by the node's operator
+    Configured,
+    /// The address of one of our own accounts
+    Local,
+    /// Learned from the Route Update Requests of a peer
+    Peer { account_id: String },
+}
+
+/// A route for a prefix in one of our peers' Incoming Routing Tables
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AlternativeRoute {
+    pub account_id: String,
+    pub path: Vec<String>,
+}
+
+/// The route we use for a prefix and the others we could have used instead
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteInfo {
+    pub prefix: String,
+    /// The ID of the account packets for this prefix are forwarded to
+    pub next_hop: String,
+    /// The nodes the route passes through, starting with the next hop. Empty for our own routes
+    pub path: Vec<String>,
+    pub source: RouteSource,
+    /// The Forwarding Routing Table epoch in which we started advertising this route,
+    /// or None if we do not advertise it
+    pub epoch: Option<u32>,
+    /// The routes for this prefix from the Incoming Routing Tables of the other peers
+    pub alternatives: Vec<AlternativeRoute>,
+}
+
+/// A description of the Route Manager's tables for debugging reachability
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoutingTableInfo {
+    /// The hex-encoded ID of the Forwarding Routing Table we advertise to peers
+    pub routing_table_id: String,
+    /// The current epoch of the Forwarding Routing Table
+    pub epoch: u32,
+    pub routes: Vec<RouteInfo>,
+}
+
+/// A route as we advertise it to a specific peer
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AdvertisedRoute {
+    pub prefix: String,
+    /// The path the peer sees, starting with our address
+    pub path: Vec<String>,
+}
+
+/// Read access to the tables of a running Route Manager, so that the admin API can describe
+/// them without depending on the types of the services the `CcpRouteManager` wraps
+pub trait RoutingTableInspector<A: Account> {
+    /// Describe the routes in the Local Routing Table, see `CcpRouteManager::routing_table_info`
+    fn routing_table_info(&self) -> Box<Future<Item = RoutingTableInfo, Error = ()> + Send>;
+
+    /// List the routes we currently advertise to the given account
+    fn advertised_routes(
+        &self,
+        account: &A,
+    ) -> Box<Future<Item = Vec<AdvertisedRoute>, Error = ()> + Send>;
+}
+
+fn to_string(bytes: &Bytes) -> String {
+    String::from_utf8_lossy(&bytes[..]).to_string()
+}
+
+fn path_to_strings(route: &Route) -> Vec<String> {
+    route.path.iter().map(to_string).collect()
+}
+
+pub(crate) fn routing_table_info<I, A>(
+    state: &RoutingState<I>,
+    configured_routes: &HashMap<Bytes, A>,
+) -> RoutingTableInfo
+where
+    I: Display + FromStr + Eq + Hash + Copy,
+{
+    let mut routes: Vec<RouteInfo> = state
+        .local_table
+        .routes
+        .iter()
+        .map(|(account_id, route)| {
+            let source = if !route.path.is_empty() {
+                RouteSource::Peer {
+                    account_id: account_id.to_string(),
+                }
+            } else if configured_routes.contains_key(&route.prefix) {
+                RouteSource::Configured
+            } else {
+                RouteSource::Local
+            };
+            let advertised = state
+                .forwarding_table
+                .routes
+                .iter()
+                .any(|(_, forwarded)| forwarded.prefix == route.prefix);
+            // The updates are keyed by the epoch they transition from
+            let epoch = if advertised {
+                state
+                    .forwarding_table_updates
+                    .iter()
+                    .rev()
+                    .find(|(_, (new_routes, _))| {
+                        new_routes.iter().any(|new| new.prefix == route.prefix)
+                    })
+                    .map(|(epoch, _)| epoch + 1)
+            } else {
+                None
+            };
+            let alternatives = state
+                .incoming_tables
+                .iter()
+                .filter(|(incoming_id, _)| incoming_id != account_id)
+                .filter_map(|(incoming_id, table)| {
+                    table
+                        .routes
+                        .iter()
+                        .find(|(_, incoming)| incoming.prefix == route.prefix)
+                        .map(|(_, incoming)| AlternativeRoute {
+                            account_id: incoming_id.to_string(),
+                            path: path_to_strings(incoming),
+                        })
+                })
+                .collect();
+            RouteInfo {
+                prefix: to_string(&route.prefix),
+                next_hop: account_id.to_string(),
+                path: path_to_strings(route),
+                source,
+                epoch,
+                alternatives,
+            }
+        })
+        .collect();
+    routes.sort_unstable_by(|a, b| a.prefix.cmp(&b.prefix));
+
+    RoutingTableInfo {
+        routing_table_id: hex::encode(&state.forwarding_table.id[..]),
+        epoch: state.forwarding_table.epoch,
+        routes,
+    }
+}
+
+/// The routes in the Forwarding Routing Table that the peer's export policies permit,
+/// with the aggregate route for our own address if the policies ask for one
+pub(crate) fn advertised_routes<I>(
+    state: &RoutingState<I>,
+    policies: &RoutePolicies,
+    own_address: &[u8],
+    next_hop_relations: &HashMap<I, RoutingRelation>,
+) -> Vec<AdvertisedRoute>
+where
+    I: Display + FromStr + Eq + Hash + Copy,
+{
+    let mut routes: Vec<AdvertisedRoute> = state
+        .forwarding_table
+        .routes
+        .iter()
+        .filter(|(account_id, route)| {
+            let relation = next_hop_relations.get(account_id).cloned();
+            policies.permits_export(&route.prefix, own_address, relation)
+                && !policies.aggregates(&route.prefix, own_address, relation)
+        })
+        .map(|(_, route)| AdvertisedRoute {
+            prefix: to_string(&route.prefix),
+            path: path_to_strings(route),
+        })
+        .collect();
+    if policies.aggregate_child_routes
+        && !own_address.is_empty()
+        && policies.permits_export(own_address, own_address, None)
+    {
+        let own_address = String::from_utf8_lossy(own_address).to_string();
+        routes.push(AdvertisedRoute {
+            prefix: own_address.clone(),
+            path: vec![own_address],
+        });
+    }
+    routes.sort_unstable_by(|a, b| a.prefix.cmp(&b.prefix));
+    routes
+}
@@ -13,22 +13,36 @@
 extern crate log;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate prometheus;
 
 use bytes::Bytes;
 use futures::Future;
 use hashbrown::HashMap;
 use interledger_ildcp::IldcpAccount;
-use interledger_service::Account;
+use interledger_service::{Account, StoreError};
 use std::{str::FromStr, string::ToString};
 
+mod auth;
 #[cfg(test)]
 mod fixtures;
+mod inspect;
 mod packet;
+mod policy;
 mod routing_table;
+mod selection;
 mod server;
+mod state;
 #[cfg(test)]
 mod test_helpers;
 
+pub use auth::RouteAuth;
+pub use inspect::{
+    AdvertisedRoute, AlternativeRoute, RouteInfo, RouteSource, RoutingTableInfo,
+    RoutingTableInspector,
+};
+pub use policy::{RoutePolicies, RoutePolicy};
+pub use selection::{PeerStats, PeerStatsService, RouteSelection};
 pub use server::CcpRouteManager;
 
 #[repr(u8)]
@@ -85,15 +99,47 @@ pub trait RouteManagerStore: Clone {
     fn get_local_and_configured_routes(
         &self,
     ) -> Box<
-        Future<Item = (HashMap<Bytes, Self::Account>, HashMap<Bytes, Self::Account>), Error = ()>
-            + Send,
+        Future<
+                Item = (HashMap<Bytes, Self::Account>, HashMap<Bytes, Self::Account>),
+                Error = StoreError,
+            > + Send,
     >;
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Box<Future<Item = Vec<Self::Account>, Error = StoreError> + Send>;
 
-    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (Bytes, Self::Account)>;
+
+    /// Get the import and export policies for the given account.
+    /// Accounts that have no policies set should get the (permissive) default.
+    fn get_route_policies(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+    ) -> Box<Future<Item = RoutePolicies, Error = StoreError> + Send>;
+
+    fn set_route_policies(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        policies: RoutePolicies,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Save the Route Manager's tables so they can be reloaded after the node restarts.
+    /// The state is an opaque blob that only the `CcpRouteManager` needs to understand.
+    ///
+    /// Each node saves its state under its own ILP address, so that nodes sharing a store
+    /// do not overwrite each other's tables.
+    fn save_routing_state(
+        &self,
+        ilp_address: &[u8],
+        state: Bytes,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Load the state last saved with `save_routing_state` for the given ILP address, if there is one
+    fn load_routing_state(
+        &self,
+        ilp_address: &[u8],
+    ) -> Box<Future<Item = Option<Bytes>, Error = StoreError> + Send>;
 }
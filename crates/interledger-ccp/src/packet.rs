@@ -1,18 +1,14 @@
-use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{BufMut, Bytes};
 use hex;
 use interledger_packet::{
-    oer::{BufOerExt, MutBufOerExt},
-    Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder,
+    oer::{MutBufOerExt, OerReader},
+    Address, Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder, MAX_ADDRESS_LENGTH,
 };
 use std::{
-    io::Read,
-    str,
+    str::FromStr,
     time::{Duration, SystemTime},
 };
 
-pub const CCP_CONTROL_DESTINATION: &[u8] = b"peer.route.control";
-pub const CCP_UPDATE_DESTINATION: &[u8] = b"peer.route.update";
 // pub const PEER_PROTOCOL_FULFILLMENT: [u8; 32] = [0; 32];
 pub const PEER_PROTOCOL_CONDITION: [u8; 32] = [
     102, 104, 122, 173, 248, 98, 189, 119, 108, 143, 193, 139, 142, 159, 142, 32, 8, 151, 20, 133,
@@ -23,8 +19,15 @@ const FLAG_OPTIONAL: u8 = 0x80;
 const FLAG_TRANSITIVE: u8 = 0x40;
 const FLAG_PARTIAL: u8 = 0x20;
 const FLAG_UTF8: u8 = 0x10;
+const MAX_FEATURE_LEN: usize = 255;
+// Route props are carried in the Prepare's data, which is at most 32767 bytes
+const MAX_PROP_VALUE_LEN: usize = 32767;
 
 lazy_static! {
+    pub static ref CCP_CONTROL_DESTINATION: Address =
+        Address::from_str("peer.route.control").unwrap();
+    pub static ref CCP_UPDATE_DESTINATION: Address =
+        Address::from_str("peer.route.update").unwrap();
     pub static ref CCP_RESPONSE: Fulfill = FulfillBuilder {
         fulfillment: &PEER_PROTOCOL_CONDITION,
         data: &[],
@@ -63,17 +66,31 @@ pub struct RouteControlRequest {
 
 impl RouteControlRequest {
     pub fn try_from(prepare: &Prepare) -> Result<Self, ParseError> {
+        RouteControlRequest::parse(prepare, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(prepare: &Prepare) -> Result<Self, ParseError> {
+        RouteControlRequest::parse(prepare, true)
+    }
+
+    fn parse(prepare: &Prepare, strict: bool) -> Result<Self, ParseError> {
         if prepare.expires_at() < SystemTime::now() {
             return Err(ParseError::InvalidPacket("Packet expired".to_string()));
         }
-        RouteControlRequest::try_from_without_expiry(prepare)
+        RouteControlRequest::read(prepare, strict)
     }
 
+    #[cfg(test)]
     pub(crate) fn try_from_without_expiry(prepare: &Prepare) -> Result<Self, ParseError> {
-        if prepare.destination() != CCP_CONTROL_DESTINATION {
+        RouteControlRequest::read(prepare, false)
+    }
+
+    fn read(prepare: &Prepare, strict: bool) -> Result<Self, ParseError> {
+        if *prepare.destination() != *CCP_CONTROL_DESTINATION {
             return Err(ParseError::InvalidPacket(format!(
                 "Packet is not a CCP message. Destination: {}",
-                str::from_utf8(prepare.destination()).unwrap_or("<not utf8>")
+                prepare.destination()
             )));
         }
 
@@ -85,17 +102,24 @@ impl RouteControlRequest {
             )));
         }
 
-        let mut data = prepare.data();
+        let mut data = if strict {
+            OerReader::strict(prepare.data())
+        } else {
+            OerReader::new(prepare.data())
+        };
 
-        let mode = Mode::try_from(data.read_u8()?)?;
+        let mode = Mode::try_from(data.read_u8("mode")?)?;
         let mut last_known_routing_table_id: [u8; 16] = [0; 16];
-        data.read_exact(&mut last_known_routing_table_id)?;
-        let last_known_epoch = data.read_u32::<BigEndian>()?;
-        let num_features = data.read_var_uint()?;
-        let mut features: Vec<String> = Vec::with_capacity(num_features as usize);
+        last_known_routing_table_id
+            .copy_from_slice(data.read_bytes(16, "last_known_routing_table_id")?);
+        let last_known_epoch = data.read_u32("last_known_epoch")?;
+        let num_features = data.read_var_uint("features")?;
+        let mut features: Vec<String> = Vec::new();
         for _i in 0..num_features {
-            features.push(String::from_utf8(data.read_var_octet_string()?.to_vec())?);
+            let feature = data.read_var_octet_string("feature", MAX_FEATURE_LEN)?;
+            features.push(String::from_utf8(feature.to_vec())?);
         }
+        data.finish("features")?;
 
         Ok(RouteControlRequest {
             mode,
@@ -117,7 +141,7 @@ impl RouteControlRequest {
         }
 
         PrepareBuilder {
-            destination: CCP_CONTROL_DESTINATION,
+            destination: &CCP_CONTROL_DESTINATION,
             amount: 0,
             expires_at: SystemTime::now() + Duration::from_millis(PEER_PROTOCOL_EXPIRY_DURATION),
             execution_condition: &PEER_PROTOCOL_CONDITION,
@@ -138,17 +162,16 @@ pub struct RouteProp {
 }
 
 impl RouteProp {
-    // Note this takes a mutable ref to the slice so that it advances the cursor in the original slice
-    pub fn try_from(data: &mut &[u8]) -> Result<Self, ParseError> {
-        let meta = data.read_u8()?;
+    pub fn try_from(data: &mut OerReader) -> Result<Self, ParseError> {
+        let meta = data.read_u8("prop_meta")?;
 
         let is_optional = meta & FLAG_OPTIONAL != 0;
         let is_transitive = meta & FLAG_TRANSITIVE != 0;
         let is_partial = meta & FLAG_PARTIAL != 0;
         let is_utf8 = meta & FLAG_UTF8 != 0;
 
-        let id = data.read_u16::<BigEndian>()?;
-        let value = Bytes::from(data.read_var_octet_string()?);
+        let id = data.read_u16("prop_id")?;
+        let value = Bytes::from(data.read_var_octet_string("prop_value", MAX_PROP_VALUE_LEN)?);
 
         Ok(RouteProp {
             is_optional,
@@ -184,28 +207,42 @@ impl RouteProp {
     }
 }
 
+fn read_address(data: &mut OerReader, field: &'static str) -> Result<Bytes, ParseError> {
+    let address = data.read_var_octet_string(field, MAX_ADDRESS_LENGTH)?;
+    Ok(Address::try_from(Bytes::from(address))?.to_bytes())
+}
+
+/// Prefixes and paths are only checked when the route is processed,
+/// so that one invalid route does not invalidate the rest of the update
+fn read_unchecked_address(data: &mut OerReader, field: &'static str) -> Result<Bytes, ParseError> {
+    Ok(Bytes::from(
+        data.read_var_octet_string(field, MAX_ADDRESS_LENGTH)?,
+    ))
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Route {
     pub(crate) prefix: Bytes,
     pub(crate) path: Vec<Bytes>,
+    /// Passed along unchanged and never checked. Routes are authenticated with the
+    /// Ed25519 signatures in the signatures route property instead (see `RouteAuth`).
     pub(crate) auth: [u8; 32],
     pub(crate) props: Vec<RouteProp>,
 }
 
 impl Route {
-    // Note this takes a mutable ref to the slice so that it advances the cursor in the original slice
-    pub fn try_from(data: &mut &[u8]) -> Result<Self, ParseError> {
-        let prefix = Bytes::from(data.read_var_octet_string()?);
-        let path_len = data.read_var_uint()? as usize;
-        let mut path = Vec::with_capacity(path_len);
+    pub fn try_from(data: &mut OerReader) -> Result<Self, ParseError> {
+        let prefix = read_unchecked_address(data, "prefix")?;
+        let path_len = data.read_var_uint("path")?;
+        let mut path = Vec::new();
         for _i in 0..path_len {
-            path.push(Bytes::from(data.read_var_octet_string()?));
+            path.push(read_unchecked_address(data, "path")?);
         }
         let mut auth: [u8; 32] = [0; 32];
-        data.read_exact(&mut auth)?;
+        auth.copy_from_slice(data.read_bytes(32, "auth")?);
 
-        let prop_len = data.read_var_uint()? as usize;
-        let mut props = Vec::with_capacity(prop_len);
+        let prop_len = data.read_var_uint("props")?;
+        let mut props = Vec::new();
         for _i in 0..prop_len {
             props.push(RouteProp::try_from(data)?);
         }
@@ -249,17 +286,31 @@ pub struct RouteUpdateRequest {
 
 impl RouteUpdateRequest {
     pub fn try_from(prepare: &Prepare) -> Result<Self, ParseError> {
+        RouteUpdateRequest::parse(prepare, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(prepare: &Prepare) -> Result<Self, ParseError> {
+        RouteUpdateRequest::parse(prepare, true)
+    }
+
+    fn parse(prepare: &Prepare, strict: bool) -> Result<Self, ParseError> {
         if prepare.expires_at() < SystemTime::now() {
             return Err(ParseError::InvalidPacket("Packet expired".to_string()));
         }
-        RouteUpdateRequest::try_from_without_expiry(prepare)
+        RouteUpdateRequest::read(prepare, strict)
     }
 
+    #[cfg(test)]
     pub(crate) fn try_from_without_expiry(prepare: &Prepare) -> Result<Self, ParseError> {
-        if prepare.destination() != CCP_UPDATE_DESTINATION {
+        RouteUpdateRequest::read(prepare, false)
+    }
+
+    fn read(prepare: &Prepare, strict: bool) -> Result<Self, ParseError> {
+        if *prepare.destination() != *CCP_UPDATE_DESTINATION {
             return Err(ParseError::InvalidPacket(format!(
                 "Packet is not a CCP message. Destination: {}",
-                str::from_utf8(prepare.destination()).unwrap_or("<not utf8>")
+                prepare.destination()
             )));
         }
 
@@ -271,24 +322,29 @@ impl RouteUpdateRequest {
             )));
         }
 
-        let mut data = prepare.data();
+        let mut data = if strict {
+            OerReader::strict(prepare.data())
+        } else {
+            OerReader::new(prepare.data())
+        };
         let mut routing_table_id: [u8; 16] = [0; 16];
-        data.read_exact(&mut routing_table_id)?;
-        let current_epoch_index = data.read_u32::<BigEndian>()?;
-        let from_epoch_index = data.read_u32::<BigEndian>()?;
-        let to_epoch_index = data.read_u32::<BigEndian>()?;
-        let hold_down_time = data.read_u32::<BigEndian>()?;
-        let speaker = Bytes::from(data.read_var_octet_string()?);
-        let new_routes_len = data.read_var_uint()? as usize;
-        let mut new_routes: Vec<Route> = Vec::with_capacity(new_routes_len);
+        routing_table_id.copy_from_slice(data.read_bytes(16, "routing_table_id")?);
+        let current_epoch_index = data.read_u32("current_epoch_index")?;
+        let from_epoch_index = data.read_u32("from_epoch_index")?;
+        let to_epoch_index = data.read_u32("to_epoch_index")?;
+        let hold_down_time = data.read_u32("hold_down_time")?;
+        let speaker = read_address(&mut data, "speaker")?;
+        let new_routes_len = data.read_var_uint("new_routes")?;
+        let mut new_routes: Vec<Route> = Vec::new();
         for _i in 0..new_routes_len {
             new_routes.push(Route::try_from(&mut data)?);
         }
-        let withdrawn_routes_len = data.read_var_uint()? as usize;
-        let mut withdrawn_routes = Vec::with_capacity(withdrawn_routes_len);
+        let withdrawn_routes_len = data.read_var_uint("withdrawn_routes")?;
+        let mut withdrawn_routes = Vec::new();
         for _i in 0..withdrawn_routes_len {
-            withdrawn_routes.push(Bytes::from(data.read_var_octet_string()?));
+            withdrawn_routes.push(read_unchecked_address(&mut data, "withdrawn_route")?);
         }
+        data.finish("withdrawn_routes")?;
 
         Ok(RouteUpdateRequest {
             routing_table_id,
@@ -320,7 +376,7 @@ impl RouteUpdateRequest {
         }
 
         PrepareBuilder {
-            destination: CCP_UPDATE_DESTINATION,
+            destination: &CCP_UPDATE_DESTINATION,
             amount: 0,
             expires_at: SystemTime::now() + Duration::from_millis(PEER_PROTOCOL_EXPIRY_DURATION),
             execution_condition: &PEER_PROTOCOL_CONDITION,
@@ -391,6 +447,7 @@ mod route_update_request {
     use super::*;
     use crate::fixtures::*;
     use bytes::BytesMut;
+    use proptest::prelude::*;
 
     #[test]
     fn deserialize() {
@@ -468,7 +525,10 @@ mod route_update_request {
         let mut serialized = Vec::new();
         prop.write_to(&mut serialized);
 
-        assert_eq!(prop, RouteProp::try_from(&mut &serialized[..]).unwrap());
+        assert_eq!(
+            prop,
+            RouteProp::try_from(&mut OerReader::new(&serialized[..])).unwrap()
+        );
     }
 
     #[test]
@@ -504,6 +564,103 @@ mod route_update_request {
         let mut serialized = Vec::new();
         route.write_to(&mut serialized);
 
-        assert_eq!(route, Route::try_from(&mut &serialized[..]).unwrap());
+        assert_eq!(
+            route,
+            Route::try_from(&mut OerReader::new(&serialized[..])).unwrap()
+        );
+    }
+
+    #[test]
+    fn leaves_checking_route_addresses_to_route_manager() {
+        let route = Route {
+            prefix: Bytes::from("g"),
+            path: vec![Bytes::from("example.not valid")],
+            auth: [9; 32],
+            props: Vec::new(),
+        };
+
+        let mut serialized = Vec::new();
+        route.write_to(&mut serialized);
+
+        assert_eq!(
+            Route::try_from(&mut OerReader::new(&serialized[..])).unwrap(),
+            route
+        );
+    }
+
+    #[test]
+    fn ignores_trailing_bytes() {
+        let mut data = UPDATE_REQUEST_SIMPLE.to_prepare().data().to_vec();
+        data.push(0);
+        let prepare = PrepareBuilder {
+            destination: &CCP_UPDATE_DESTINATION,
+            amount: 0,
+            expires_at: SystemTime::now(),
+            execution_condition: &PEER_PROTOCOL_CONDITION,
+            data: &data[..],
+        }
+        .build();
+        assert_eq!(
+            RouteUpdateRequest::try_from_without_expiry(&prepare).unwrap(),
+            *UPDATE_REQUEST_SIMPLE
+        );
+    }
+
+    #[test]
+    fn strict_parsing_rejects_trailing_bytes() {
+        let mut data = UPDATE_REQUEST_SIMPLE.to_prepare().data().to_vec();
+        let length = data.len();
+        data.push(0);
+        let prepare = PrepareBuilder {
+            destination: &CCP_UPDATE_DESTINATION,
+            amount: 0,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            execution_condition: &PEER_PROTOCOL_CONDITION,
+            data: &data[..],
+        }
+        .build();
+        assert!(RouteUpdateRequest::try_from(&prepare).is_ok());
+        match RouteUpdateRequest::try_from_strict(&prepare) {
+            Err(ParseError::TrailingBytes("withdrawn_routes", offset)) => {
+                assert_eq!(offset, length)
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn route_round_trips(
+            prefix in "example(\\.[a-z0-9-]{1,10}){1,5}",
+            path in prop::collection::vec("g(\\.[a-z0-9-]{1,10}){1,5}", 0..5),
+            auth in any::<[u8; 32]>(),
+            props in prop::collection::vec(
+                (any::<u8>(), any::<u16>(), prop::collection::vec(any::<u8>(), 0..200)),
+                0..3,
+            ),
+        ) {
+            let route = Route {
+                prefix: Bytes::from(prefix),
+                path: path.into_iter().map(Bytes::from).collect(),
+                auth,
+                props: props
+                    .into_iter()
+                    .map(|(meta, id, value)| RouteProp {
+                        is_optional: meta & FLAG_OPTIONAL != 0,
+                        is_transitive: meta & FLAG_TRANSITIVE != 0,
+                        is_partial: meta & FLAG_PARTIAL != 0,
+                        id,
+                        is_utf8: meta & FLAG_UTF8 != 0,
+                        value: Bytes::from(value),
+                    })
+                    .collect(),
+            };
+
+            let mut serialized = Vec::new();
+            route.write_to(&mut serialized);
+            let mut reader = OerReader::strict(&serialized[..]);
+            prop_assert_eq!(Route::try_from(&mut reader).unwrap(), route);
+            prop_assert!(reader.finish("route").is_ok());
+        }
     }
 }
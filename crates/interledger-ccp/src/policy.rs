@@ -0,0 +1,264 @@
+use crate::{packet::RouteUpdateRequest, routing_table::RoutingTable, RoutingRelation};
+use serde::{Deserialize, Serialize};
+use std::str;
+
+/// Limits on which prefixes are exchanged with an account in one direction.
+///
+/// The default policy permits every prefix.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutePolicy {
+    /// If this is not empty, only prefixes that are equal to or under one of these are permitted
+    /// (`example.a` covers `example.a.b` but not `example.ab`)
+    pub allow_prefixes: Vec<String>,
+    /// Prefixes that are equal to or under any of these are never permitted,
+    /// even if they are allowed above
+    pub deny_prefixes: Vec<String>,
+    /// The shortest prefix (in bytes) that is permitted
+    pub min_prefix_length: Option<usize>,
+    /// The longest prefix (in bytes) that is permitted
+    pub max_prefix_length: Option<usize>,
+}
+
+impl RoutePolicy {
+    /// Check whether a route for the given prefix passes this policy
+    pub fn permits(&self, prefix: &[u8]) -> bool {
+        if !self.allow_prefixes.is_empty()
+            && !self
+                .allow_prefixes
+                .iter()
+                .any(|allowed| is_under(prefix, allowed.as_bytes()))
+        {
+            return false;
+        }
+        if self
+            .deny_prefixes
+            .iter()
+            .any(|denied| is_under(prefix, denied.as_bytes()))
+        {
+            return false;
+        }
+        if let Some(min) = self.min_prefix_length {
+            if prefix.len() < min {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_prefix_length {
+            if prefix.len() > max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Check whether the prefix is the same as the parent or one of its descendants,
+/// matching whole segments of the address.
+fn is_under(prefix: &[u8], parent: &[u8]) -> bool {
+    prefix.starts_with(parent) && (prefix.len() == parent.len() || prefix[parent.len()] == b'.')
+}
+
+/// The routes we accept from (import) and advertise to (export) a specific account.
+///
+/// Policies only apply to Route Update Requests handled after they are set.
+/// Routes that were already imported stay in our tables until they are withdrawn or expire.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutePolicies {
+    /// Applied to the new routes in the Route Update Requests the account sends us
+    pub import: RoutePolicy,
+    /// The most prefixes we will keep in the account's Incoming Routing Table.
+    /// New prefixes beyond this limit are ignored.
+    pub max_imported_prefixes: Option<usize>,
+    /// Applied to the new routes in the Route Update Requests we send to the account
+    pub export: RoutePolicy,
+    /// Only advertise routes to our own address and to our children to this account.
+    /// This is typically used for peers and parents so that we do not provide them transit.
+    pub export_own_and_children_only: bool,
+    /// Advertise a single route for our own address to this account instead of a route for
+    /// each child under it. Routes under our address that go through other accounts are
+    /// still advertised individually.
+    pub aggregate_child_routes: bool,
+    /// Multiplies the cost of the routes through this account when comparing them to other
+    /// peers' routes (1.0 if not set). Only used if cost-aware route selection is enabled.
+    /// Must be finite and not negative.
+    pub weight: Option<f64>,
+}
+
+impl RoutePolicies {
+    /// The weight to multiply the cost of the account's routes by, or None if the
+    /// weight that was set is negative, infinite or NaN
+    pub fn valid_weight(&self) -> Option<f64> {
+        match self.weight {
+            Some(weight) if !weight.is_finite() || weight < 0.0 => None,
+            weight => Some(weight.unwrap_or(1.0)),
+        }
+    }
+
+    /// Remove the new routes in a Route Update Request from the account that the import policy
+    /// does not permit or that would take the account's Incoming Routing Table over its prefix limit.
+    pub(crate) fn filter_import<A: Clone>(
+        &self,
+        table: &RoutingTable<A>,
+        mut update: RouteUpdateRequest,
+    ) -> RouteUpdateRequest {
+        let import = &self.import;
+        update.new_routes.retain(|route| {
+            let permitted = import.permits(&route.prefix);
+            if !permitted {
+                debug!(
+                    "Ignoring route for prefix {} because the import policy does not permit it",
+                    str::from_utf8(&route.prefix).unwrap_or("<not utf8>")
+                );
+            }
+            permitted
+        });
+
+        if let Some(max_prefixes) = self.max_imported_prefixes {
+            let withdrawn = update
+                .withdrawn_routes
+                .iter()
+                .filter(|prefix| table.has_route(prefix))
+                .count();
+            let mut prefixes = table.len().saturating_sub(withdrawn);
+            update.new_routes.retain(|route| {
+                if table.has_route(&route.prefix) {
+                    true
+                } else if prefixes < max_prefixes {
+                    prefixes += 1;
+                    true
+                } else {
+                    warn!(
+                        "Ignoring route for prefix {} because the account already has the maximum of {} prefixes",
+                        str::from_utf8(&route.prefix).unwrap_or("<not utf8>"),
+                        max_prefixes
+                    );
+                    false
+                }
+            });
+        }
+        update
+    }
+
+    /// Check whether a route from our Forwarding Routing Table should be advertised to the account.
+    /// The relation is that of the account the route goes through, if it is known.
+    pub(crate) fn permits_export(
+        &self,
+        prefix: &[u8],
+        own_address: &[u8],
+        next_hop_relation: Option<RoutingRelation>,
+    ) -> bool {
+        if !self.export.permits(prefix) {
+            return false;
+        }
+        if self.export_own_and_children_only && !is_under(prefix, own_address) {
+            return next_hop_relation == Some(RoutingRelation::Child);
+        }
+        true
+    }
+
+    /// Check whether a route from our Forwarding Routing Table is covered by the
+    /// aggregate route for our own address, so it does not need to be advertised separately
+    pub(crate) fn aggregates(
+        &self,
+        prefix: &[u8],
+        own_address: &[u8],
+        next_hop_relation: Option<RoutingRelation>,
+    ) -> bool {
+        self.aggregate_child_routes
+            && next_hop_relation == Some(RoutingRelation::Child)
+            && prefix.len() > own_address.len()
+            && is_under(prefix, own_address)
+    }
+}
+
+#[cfg(test)]
+mod route_policy {
+    use super::*;
+
+    #[test]
+    fn default_permits_everything() {
+        assert!(RoutePolicy::default().permits(b"example.anything"));
+    }
+
+    #[test]
+    fn checks_allow_and_deny_lists() {
+        let policy = RoutePolicy {
+            allow_prefixes: vec!["example.a".to_string()],
+            deny_prefixes: vec!["example.a.secret".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.permits(b"example.a"));
+        assert!(policy.permits(b"example.a.public"));
+        assert!(!policy.permits(b"example.a.secret"));
+        assert!(!policy.permits(b"example.a.secret.1"));
+        assert!(policy.permits(b"example.a.secretive"));
+        assert!(!policy.permits(b"example.b"));
+    }
+
+    #[test]
+    fn matches_whole_segments() {
+        let policy = RoutePolicy {
+            allow_prefixes: vec!["example.a".to_string()],
+            ..Default::default()
+        };
+        assert!(!policy.permits(b"example.ab"));
+        assert!(!policy.permits(b"example"));
+
+        let policies = RoutePolicies {
+            export_own_and_children_only: true,
+            ..Default::default()
+        };
+        assert!(policies.permits_export(b"example.me", b"example.me", None));
+        assert!(policies.permits_export(b"example.me.child", b"example.me", None));
+        assert!(!policies.permits_export(b"example.mine", b"example.me", None));
+    }
+
+    #[test]
+    fn rejects_invalid_weights() {
+        let weight = |weight| {
+            RoutePolicies {
+                weight,
+                ..Default::default()
+            }
+            .valid_weight()
+        };
+        assert_eq!(weight(None), Some(1.0));
+        assert_eq!(weight(Some(0.0)), Some(0.0));
+        assert_eq!(weight(Some(2.5)), Some(2.5));
+        assert_eq!(weight(Some(-1.0)), None);
+        assert_eq!(weight(Some(f64::NAN)), None);
+        assert_eq!(weight(Some(f64::INFINITY)), None);
+    }
+
+    #[test]
+    fn aggregates_children_under_own_address() {
+        let policies = RoutePolicies {
+            aggregate_child_routes: true,
+            ..Default::default()
+        };
+        let child = Some(RoutingRelation::Child);
+        assert!(policies.aggregates(b"example.me.child", b"example.me", child));
+        assert!(!policies.aggregates(b"example.me", b"example.me", child));
+        assert!(!policies.aggregates(b"example.mine", b"example.me", child));
+        assert!(!policies.aggregates(
+            b"example.me.other",
+            b"example.me",
+            Some(RoutingRelation::Peer)
+        ));
+        assert!(!RoutePolicies::default().aggregates(b"example.me.child", b"example.me", child));
+    }
+
+    #[test]
+    fn checks_prefix_length() {
+        let policy = RoutePolicy {
+            min_prefix_length: Some(10),
+            max_prefix_length: Some(12),
+            ..Default::default()
+        };
+        assert!(!policy.permits(b"example.a"));
+        assert!(policy.permits(b"example.ab"));
+        assert!(policy.permits(b"example.abcd"));
+        assert!(!policy.permits(b"example.abcde"));
+    }
+}
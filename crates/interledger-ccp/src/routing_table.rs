@@ -68,7 +68,6 @@ where
         self.epoch = 0;
     }
 
-    #[cfg(test)]
     pub fn set_epoch(&mut self, epoch: u32) {
         self.epoch = epoch;
     }
@@ -109,6 +108,21 @@ where
         self.prefix_map.resolve(prefix)
     }
 
+    /// Whether we have a route for exactly this prefix (rather than a shorter one that covers it)
+    pub fn has_route(&self, prefix: &[u8]) -> bool {
+        self.prefix_map.map.contains_key(prefix)
+    }
+
+    /// The number of prefixes we have routes for
+    pub fn len(&self) -> usize {
+        self.prefix_map.map.len()
+    }
+
+    /// The accounts and routes in the table
+    pub fn routes(&self) -> impl Iterator<Item = &(A, Route)> {
+        self.prefix_map.map.values()
+    }
+
     pub fn get_simplified_table(&self) -> HashMap<Bytes, A> {
         HashMap::from_iter(
             self.prefix_map
@@ -157,6 +171,9 @@ where
                 hex::encode(&self.id[..]),
                 self.epoch
             );
+            // Updates can be empty because we filtered out all of their routes,
+            // so we still need to advance the epoch to avoid seeing a gap next time
+            self.epoch = request.to_epoch_index;
             return Ok(Vec::new());
         }
 
@@ -244,7 +261,7 @@ mod table {
             .handle_update_request(ROUTING_ACCOUNT.clone(), request.clone())
             .unwrap();
         assert_eq!(table.id, request.routing_table_id);
-        assert_eq!(table.epoch, 0);
+        assert_eq!(table.epoch, request.to_epoch_index);
     }
 
     #[test]
@@ -0,0 +1,223 @@
+use futures::Future;
+use hashbrown::HashMap;
+use interledger_packet::ErrorClass;
+use interledger_service::{Account, BoxedIlpFuture, OutgoingRequest, OutgoingService};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How much each new packet moves the averages kept by `PeerStats`
+const STATS_SMOOTHING: f64 = 0.1;
+
+/// The weights used to compare routes to the same prefix learned from different peers.
+///
+/// Routes are still ranked by the relation to the peer (child > peer > parent) first.
+/// Between peers with the same relation, the route with the lowest cost is used, where the cost is
+///
+/// `(hop_cost * path length + failure_cost * failure rate + latency_cost * average latency in seconds
+/// + conversion_cost * percent of the amount lost converting to the peer's asset) * the peer's weight`
+///
+/// The amount lost converting is the `spread` plus what is lost rounding one unit of our asset,
+/// converted at our exchange rates, down to the peer's asset scale.
+/// Routes through peers whose asset we have no exchange rate for are only used if there is
+/// no other route, since we could not forward packets over them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteSelection {
+    pub hop_cost: f64,
+    pub failure_cost: f64,
+    pub latency_cost: f64,
+    pub conversion_cost: f64,
+    /// The fraction of each amount that is lost converting it to another asset, like the spread
+    /// charged on top of our exchange rates (0.0 if not set)
+    pub spread: f64,
+}
+
+impl Default for RouteSelection {
+    fn default() -> Self {
+        RouteSelection {
+            hop_cost: 1.0,
+            failure_cost: 10.0,
+            latency_cost: 1.0,
+            conversion_cost: 0.5,
+            spread: 0.0,
+        }
+    }
+}
+
+impl RouteSelection {
+    /// The cost of converting amounts from our asset to a peer's at the given exchange rate
+    /// (units of the peer's asset per unit of ours). Invalid rates make the peer unusable.
+    pub(crate) fn cost_of_converting(&self, rate: f64, to_scale: u8) -> f64 {
+        if !rate.is_finite() || rate <= 0.0 {
+            return f64::INFINITY;
+        }
+        // One unit of our asset, in the peer's smallest unit
+        let exact = rate * 10f64.powi(i32::from(to_scale));
+        let converted = (exact * (1.0 - self.spread)).floor().max(0.0);
+        let percent_lost = (1.0 - converted / exact) * 100.0;
+        self.conversion_cost * percent_lost
+    }
+}
+
+/// The parts of a route's cost that depend only on the peer it goes through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PeerCost {
+    pub weight: f64,
+    pub fixed_cost: f64,
+}
+
+impl PeerCost {
+    pub fn route_cost(&self, selection: &RouteSelection, path_len: usize) -> f64 {
+        // Unusable peers stay unusable even if they are given a weight of 0
+        if self.fixed_cost.is_infinite() {
+            return self.fixed_cost;
+        }
+        (selection.hop_cost * path_len as f64 + self.fixed_cost) * self.weight
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Stats {
+    failure_rate: f64,
+    latency: f64,
+}
+
+/// Exponentially-weighted averages of the failure rate and latency of the packets
+/// forwarded to each account, shared between the `PeerStatsService` that records them
+/// and the `CcpRouteManager` that uses them to pick routes.
+#[derive(Clone)]
+pub struct PeerStats<I: Eq + Hash> {
+    stats: Arc<RwLock<HashMap<I, Stats>>>,
+}
+
+impl<I: Eq + Hash + Copy> PeerStats<I> {
+    pub fn new() -> Self {
+        PeerStats {
+            stats: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Record the outcome of a packet sent to the account
+    pub fn record(&self, account_id: I, failed: bool, latency: Duration) {
+        let latency = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+        let failed = if failed { 1.0 } else { 0.0 };
+        let mut stats = self.stats.write();
+        let stats = stats.entry(account_id).or_insert(Stats {
+            failure_rate: failed,
+            latency,
+        });
+        stats.failure_rate += (failed - stats.failure_rate) * STATS_SMOOTHING;
+        stats.latency += (latency - stats.latency) * STATS_SMOOTHING;
+    }
+
+    /// The average failure rate (between 0 and 1) and latency in seconds of the packets
+    /// sent to the account, or zeros if we have not sent it any
+    pub fn get(&self, account_id: &I) -> (f64, f64) {
+        self.stats
+            .read()
+            .get(account_id)
+            .map(|stats| (stats.failure_rate, stats.latency))
+            .unwrap_or((0.0, 0.0))
+    }
+}
+
+impl<I: Eq + Hash + Copy> Default for PeerStats<I> {
+    fn default() -> Self {
+        PeerStats::new()
+    }
+}
+
+/// An OutgoingService that records the failure rate and latency of the packets sent to each account.
+///
+/// Temporary and relative errors count as failures. Final errors do not, because they are
+/// usually returned by the receiver rather than caused by the path.
+#[derive(Clone)]
+pub struct PeerStatsService<S, A: Account> {
+    next: S,
+    stats: PeerStats<A::AccountId>,
+}
+
+impl<S, A> PeerStatsService<S, A>
+where
+    S: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(stats: PeerStats<A::AccountId>, next: S) -> Self {
+        PeerStatsService { next, stats }
+    }
+}
+
+impl<S, A> OutgoingService<A> for PeerStatsService<S, A>
+where
+    S: OutgoingService<A>,
+    S::Future: Send + 'static,
+    A: Account + 'static,
+{
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let to = request.to.id();
+        let stats = self.stats.clone();
+        let start = Instant::now();
+        Box::new(self.next.send_request(request).then(move |result| {
+            let failed = match result {
+                Ok(_) => false,
+                Err(ref reject) => reject.code().class() != ErrorClass::Final,
+            };
+            stats.record(to, failed, start.elapsed());
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod peer_stats {
+    use super::*;
+
+    #[test]
+    fn averages_failures_and_latency() {
+        let stats = PeerStats::new();
+        assert_eq!(stats.get(&1), (0.0, 0.0));
+
+        stats.record(1, false, Duration::from_millis(1000));
+        assert_eq!(stats.get(&1), (0.0, 1.0));
+
+        stats.record(1, true, Duration::from_millis(2000));
+        let (failure_rate, latency) = stats.get(&1);
+        assert!((failure_rate - 0.1).abs() < 1e-9);
+        assert!((latency - 1.1).abs() < 1e-9);
+        assert_eq!(stats.get(&2), (0.0, 0.0));
+    }
+
+    #[test]
+    fn calculates_conversion_cost_from_rate_and_spread() {
+        let selection = RouteSelection {
+            spread: 0.01,
+            ..Default::default()
+        };
+        assert!((selection.cost_of_converting(2.0, 9) - 0.5).abs() < 1e-6);
+        assert_eq!(RouteSelection::default().cost_of_converting(2.0, 9), 0.0);
+
+        // Half of each unit is lost rounding 1.5 units down to the peer's scale
+        let cost = RouteSelection::default().cost_of_converting(1.5, 0);
+        assert!((cost - 0.5 * 100.0 / 3.0).abs() < 1e-6);
+
+        assert_eq!(selection.cost_of_converting(0.0, 9), f64::INFINITY);
+        assert_eq!(selection.cost_of_converting(f64::NAN, 9), f64::INFINITY);
+    }
+
+    #[test]
+    fn calculates_route_cost() {
+        let selection = RouteSelection::default();
+        let cost = PeerCost {
+            weight: 2.0,
+            fixed_cost: 0.5,
+        };
+        assert!((cost.route_cost(&selection, 3) - 7.0).abs() < 1e-9);
+    }
+}
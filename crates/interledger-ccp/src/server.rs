@@ -1,4 +1,15 @@
-use crate::{packet::*, routing_table::RoutingTable, CcpRoutingAccount, RouteManagerStore};
+use crate::{
+    auth::RouteAuth,
+    inspect::{
+        advertised_routes, routing_table_info, AdvertisedRoute, RoutingTableInfo,
+        RoutingTableInspector,
+    },
+    packet::*,
+    routing_table::RoutingTable,
+    selection::{PeerCost, PeerStats, RouteSelection},
+    state::{RoutingState, SavedRoutingState, TableState},
+    CcpRoutingAccount, RouteManagerStore, RoutePolicies, RoutingRelation,
+};
 use bytes::Bytes;
 use futures::{
     future::{err, join_all, ok, Either},
@@ -7,29 +18,38 @@ use futures::{
 use hashbrown::HashMap;
 use interledger_packet::*;
 use interledger_service::{
-    Account, BoxedIlpFuture, IncomingRequest, IncomingService, OutgoingRequest, OutgoingService,
+    Account, AccountStore, BoxedIlpFuture, Event, EventBus, IncomingRequest, IncomingService,
+    OutgoingRequest, OutgoingService,
 };
+use interledger_service_util::ExchangeRateStore;
 use parking_lot::{Mutex, RwLock};
-use ring::digest::{digest, SHA256};
+use prometheus::IntGauge;
 use std::{
     cmp::min,
+    collections::BTreeMap,
+    iter::FromIterator,
     str,
     sync::Arc,
     time::{Duration, Instant},
 };
+use stream_cancel::{Trigger, Valve};
 use tokio_executor::spawn;
 use tokio_timer::Interval;
 
+lazy_static! {
+    static ref ROUTING_TABLE_SIZE: IntGauge = register_int_gauge!(
+        "ccp_routing_table_size",
+        "Number of prefixes in the local routing table"
+    )
+    .unwrap();
+}
+
 const DEFAULT_ROUTE_EXPIRY_TIME: u32 = 45000;
 const DEFAULT_BROADCAST_INTERVAL: u64 = 30000;
+/// Past this, the oldest Forwarding Routing Table updates are merged together
+const MAX_FORWARDING_TABLE_UPDATES: usize = 256;
 
-fn hash(preimage: &[u8; 32]) -> [u8; 32] {
-    let mut out = [0; 32];
-    out.copy_from_slice(digest(&SHA256, preimage).as_ref());
-    out
-}
-
-type NewAndWithDrawnRoutes = (Vec<Route>, Vec<Bytes>);
+pub(crate) type NewAndWithDrawnRoutes = (Vec<Route>, Vec<Bytes>);
 
 /// The Routing Manager Service.
 ///
@@ -53,8 +73,10 @@ pub struct CcpRouteManager<S, T, U, A: Account> {
     /// It is the same as the local_table with our own address added to the path of each route.
     forwarding_table: Arc<RwLock<RoutingTable<A>>>,
     last_epoch_updates_sent_for: Arc<Mutex<u32>>,
-    /// These updates are stored such that index 0 is the transition from epoch 0 to epoch 1
-    forwarding_table_updates: Arc<RwLock<HashMap<u32, NewAndWithDrawnRoutes>>>,
+    /// These updates are keyed by the epoch they transition from, so 0 is the transition from
+    /// epoch 0 to epoch 1. The oldest ones are merged into the next once there are more than
+    /// `MAX_FORWARDING_TABLE_UPDATES`, so an update may also cover the epochs since the one before it.
+    forwarding_table_updates: Arc<RwLock<BTreeMap<u32, NewAndWithDrawnRoutes>>>,
     /// This is the routing table we have compile from configuration and
     /// broadcasts we have received from our peers. It is saved to the Store so that
     /// the Router services forwards packets according to what it says.
@@ -64,6 +86,25 @@ pub struct CcpRouteManager<S, T, U, A: Account> {
     /// Updates from peers are applied to our local_table if they are better than the
     /// existing best route and if they do not attempt to overwrite configured routes.
     incoming_tables: Arc<RwLock<HashMap<A::AccountId, RoutingTable<A>>>>,
+    /// When each peer's Incoming Routing Table expires. Every Route Update Request
+    /// (including heartbeats) pushes this back by the request's hold down time.
+    incoming_table_expiries: Arc<RwLock<HashMap<A::AccountId, Instant>>>,
+    /// The mode each peer asked for in its last Route Control Request.
+    /// We only broadcast updates to peers that have not asked us to go Idle.
+    peer_modes: Arc<RwLock<HashMap<A::AccountId, Mode>>>,
+    /// The route policies in effect for the last Route Update Request we sent each account,
+    /// so we can tell the account which routes to add and withdraw when they change
+    export_policies: Arc<RwLock<HashMap<A::AccountId, RoutePolicies>>>,
+    /// Used to sign the routes we advertise and to verify the routes peers send us
+    route_auth: RouteAuth,
+    /// If set, routes learned from peers with the same relation are compared by cost
+    /// rather than only by path length
+    route_selection: Option<RouteSelection>,
+    peer_stats: PeerStats<A::AccountId>,
+    /// The weight from each peer's route policies, recorded when it sends us an update
+    peer_weights: Arc<RwLock<HashMap<A::AccountId, f64>>>,
+    /// Reject route control and update requests that are not canonically encoded
+    strict_parsing: bool,
     store: U,
     /// If true, tasks will be spawned to process Route Update Requests and respond
     /// to Route Control Requests. If false, the response to the incoming request
@@ -72,26 +113,42 @@ pub struct CcpRouteManager<S, T, U, A: Account> {
     /// not need to be run with a proper executor like Tokio. When running this for real,
     /// it is better to respond to peer messages immediately.
     spawn_tasks: bool,
+    /// Stops broadcasting routes when it is dropped
+    stop_broadcasting: Arc<Mutex<Option<Trigger>>>,
+    broadcast_valve: Valve,
+    events: EventBus<A>,
 }
 
 impl<S, T, U, A> CcpRouteManager<S, T, U, A>
 where
     S: IncomingService<A> + Clone + Send + Sync + 'static,
     T: OutgoingService<A> + Clone + Send + Sync + 'static,
-    U: RouteManagerStore<Account = A> + Clone + Send + Sync + 'static,
+    U: RouteManagerStore<Account = A>
+        + AccountStore<Account = A>
+        + ExchangeRateStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     A: CcpRoutingAccount + Send + Sync + 'static,
 {
-    /// Create a new Route Manager service and spawn a task to broadcast the routes
-    /// to peers every 30 seconds.
+    /// Create a new Route Manager service and spawn a task to load the routing state
+    /// saved in the store and then broadcast the routes to peers every 30 seconds.
     pub fn new(account: A, store: U, outgoing: T, next_incoming: S) -> Self {
         let service =
             CcpRouteManager::with_spawn_bool(account, store, outgoing, next_incoming, true);
-        spawn(service.broadcast_routes(DEFAULT_BROADCAST_INTERVAL));
+        let clone = service.clone();
+        spawn(
+            service
+                .load_routing_state()
+                .then(move |_| clone.broadcast_routes(DEFAULT_BROADCAST_INTERVAL)),
+        );
         service
     }
 
     /// Create a new Route Manager Service but don't spawn a task to broadcast routes.
-    /// The `broadcast_routes` method must be called directly to start broadcasting.
+    /// The `broadcast_routes` method must be called directly to start broadcasting
+    /// (after `load_routing_state`, if the saved state should be used).
     pub fn new_without_spawn_broadcast(
         account: A,
         store: U,
@@ -116,6 +173,7 @@ where
             .map(|index| ilp_address.slice_to(index + 1))
             .unwrap_or_else(|| ilp_address.clone());
 
+        let (stop_broadcasting, broadcast_valve) = Valve::new();
         CcpRouteManager {
             account,
             ilp_address,
@@ -123,12 +181,23 @@ where
             next_incoming,
             outgoing,
             forwarding_table: Arc::new(RwLock::new(RoutingTable::default())),
-            forwarding_table_updates: Arc::new(RwLock::new(HashMap::new())),
+            forwarding_table_updates: Arc::new(RwLock::new(BTreeMap::new())),
             last_epoch_updates_sent_for: Arc::new(Mutex::new(0)),
             local_table: Arc::new(RwLock::new(RoutingTable::default())),
             incoming_tables: Arc::new(RwLock::new(HashMap::new())),
+            incoming_table_expiries: Arc::new(RwLock::new(HashMap::new())),
+            peer_modes: Arc::new(RwLock::new(HashMap::new())),
+            export_policies: Arc::new(RwLock::new(HashMap::new())),
+            route_auth: RouteAuth::default(),
+            route_selection: None,
+            peer_stats: PeerStats::new(),
+            peer_weights: Arc::new(RwLock::new(HashMap::new())),
+            strict_parsing: false,
             store,
             spawn_tasks,
+            stop_broadcasting: Arc::new(Mutex::new(Some(stop_broadcasting))),
+            broadcast_valve,
+            events: EventBus::new(),
         }
     }
 
@@ -136,19 +205,278 @@ where
     /// updates to peers on the given interval.
     pub fn broadcast_routes(&self, interval: u64) -> impl Future<Item = (), Error = ()> {
         let clone = self.clone();
-        Interval::new(Instant::now(), Duration::from_millis(interval))
+        self.broadcast_valve
+            .wrap(Interval::new(
+                Instant::now(),
+                Duration::from_millis(interval),
+            ))
             .map_err(|err| error!("Interval error, no longer sending route updates: {:?}", err))
             .for_each(move |_| {
                 let clone = clone.clone();
                 clone
-                    .clone()
-                    .update_best_routes(None)
+                    .expire_routes()
+                    .and_then({
+                        let clone = clone.clone();
+                        move |_| clone.update_best_routes(None)
+                    })
                     .and_then(move |_| clone.send_route_updates())
             })
     }
 
+    /// Restore the tables saved by a previous run of this node so that we keep our routing
+    /// table ID and epoch and peers do not need to resync from scratch.
+    ///
+    /// The saved state is only applied if this Route Manager has not changed its own tables yet.
+    /// Incoming Routing Tables are given the default hold down time before they expire.
+    /// The tables are only saved when our Forwarding Routing Table changes, so peers resend
+    /// any other updates to their tables when they see we are on an older epoch.
+    pub fn load_routing_state(&self) -> impl Future<Item = (), Error = ()> {
+        let service = self.clone();
+        let store = self.store.clone();
+        self.store
+            .load_routing_state(&self.ilp_address[..])
+            .map_err(|err| error!("Error loading routing state: {}", err))
+            .and_then(move |state| {
+                let state = match state {
+                    Some(state) => state,
+                    None => {
+                        debug!("No saved routing state to load");
+                        return Either::A(ok(None));
+                    }
+                };
+                match SavedRoutingState::<A::AccountId>::try_from(&state[..]) {
+                    Ok(state) => Either::B(store.get_accounts(state.account_ids()).then(
+                        move |result| match result {
+                            Ok(accounts) => Ok(Some((state, accounts))),
+                            Err(err) => {
+                                warn!(
+                                    "Not loading saved routing state because its accounts could not be loaded: {}",
+                                    err
+                                );
+                                Ok(None)
+                            }
+                        },
+                    )),
+                    Err(err) => {
+                        warn!("Ignoring invalid saved routing state: {:?}", err);
+                        Either::A(ok(None))
+                    }
+                }
+            })
+            .and_then(move |loaded| {
+                if let Some((state, accounts)) = loaded {
+                    let accounts = HashMap::from_iter(
+                        accounts.into_iter().map(|account| (account.id(), account)),
+                    );
+                    Either::A(service.apply_routing_state(state, &accounts))
+                } else {
+                    Either::B(ok(()))
+                }
+            })
+    }
+
+    fn apply_routing_state(
+        &self,
+        state: SavedRoutingState<A::AccountId>,
+        accounts: &HashMap<A::AccountId, A>,
+    ) -> impl Future<Item = (), Error = ()> {
+        let expires_at =
+            Instant::now() + Duration::from_millis(u64::from(DEFAULT_ROUTE_EXPIRY_TIME));
+        {
+            let mut incoming_tables = self.incoming_tables.write();
+            let mut expiries = self.incoming_table_expiries.write();
+            for (account_id, table) in state.incoming_tables.iter() {
+                if incoming_tables.contains_key(account_id) {
+                    continue;
+                }
+                if let Some(table) = table.to_table(accounts) {
+                    incoming_tables.insert(*account_id, table);
+                    expiries.insert(*account_id, expires_at);
+                }
+            }
+        }
+
+        // Note the locks are taken in the same order as in update_best_routes
+        let mut local_table = self.local_table.write();
+        let mut forwarding_table = self.forwarding_table.write();
+        if forwarding_table.epoch() > 0 {
+            debug!("Not loading saved forwarding table because ours has already changed");
+            return Either::B(ok(()));
+        }
+        match (
+            state.forwarding_table.to_table(accounts),
+            state.local_table.to_table(accounts),
+        ) {
+            (Some(saved_forwarding_table), Some(saved_local_table)) => {
+                debug!(
+                    "Loaded saved routing table {} at epoch {}",
+                    hex::encode(&saved_forwarding_table.id()[..]),
+                    saved_forwarding_table.epoch()
+                );
+                // The updates that led to the saved table are not saved, so a peer that is
+                // behind is sent the whole table instead. It will keep any routes we withdrew
+                // in the epochs it missed until it resyncs with a new routing table ID.
+                let epoch = saved_forwarding_table.epoch();
+                let mut forwarding_table_updates = self.forwarding_table_updates.write();
+                forwarding_table_updates.clear();
+                if epoch > 0 {
+                    let routes = saved_forwarding_table
+                        .routes()
+                        .map(|(_, route)| route.clone())
+                        .collect();
+                    forwarding_table_updates.insert(epoch - 1, (routes, Vec::new()));
+                }
+                *self.last_epoch_updates_sent_for.lock() = epoch;
+                *forwarding_table = saved_forwarding_table;
+                *local_table = saved_local_table;
+                ROUTING_TABLE_SIZE.set(local_table.len() as i64);
+                Either::A(
+                    self.store
+                        .clone()
+                        .set_routes(local_table.get_simplified_table())
+                        .map_err(|err| error!("Error saving routing table: {}", err)),
+                )
+            }
+            _ => {
+                warn!("Not loading saved routing tables because some of their accounts no longer exist");
+                Either::B(ok(()))
+            }
+        }
+    }
+
+    /// A snapshot of the Forwarding, Local and Incoming Routing Tables
+    fn routing_state(&self) -> RoutingState<A::AccountId> {
+        let local_table = self.local_table.read();
+        let forwarding_table = self.forwarding_table.read();
+        RoutingState {
+            forwarding_table: TableState::from_table(&forwarding_table),
+            forwarding_table_updates: self
+                .forwarding_table_updates
+                .read()
+                .iter()
+                .map(|(epoch, update)| (*epoch, update.clone()))
+                .collect(),
+            local_table: TableState::from_table(&local_table),
+            incoming_tables: self.incoming_table_states(),
+        }
+    }
+
+    fn incoming_table_states(&self) -> Vec<(A::AccountId, TableState<A::AccountId>)> {
+        self.incoming_tables
+            .read()
+            .iter()
+            .map(|(account_id, table)| (*account_id, TableState::from_table(table)))
+            .collect()
+    }
+
+    /// Save the current Forwarding, Local and Incoming Routing Tables to the store
+    fn save_routing_state(&self) -> impl Future<Item = (), Error = ()> {
+        let state = {
+            let local_table = self.local_table.read();
+            let forwarding_table = self.forwarding_table.read();
+            SavedRoutingState {
+                forwarding_table: TableState::from_table(&forwarding_table),
+                local_table: TableState::from_table(&local_table),
+                incoming_tables: self.incoming_table_states(),
+            }
+        };
+        self.store
+            .save_routing_state(&self.ilp_address[..], state.to_bytes())
+            .map_err(|err| error!("Error saving routing state: {}", err))
+    }
+
+    /// Describe the current routing tables, including where each of our routes came from
+    /// and the alternatives in the peers' Incoming Routing Tables.
+    pub fn routing_table_info(&self) -> impl Future<Item = RoutingTableInfo, Error = ()> {
+        let state = self.routing_state();
+        self.store
+            .get_local_and_configured_routes()
+            .map_err(|err| error!("Error getting local and configured routes: {}", err))
+            .map(move |(_local_routes, configured_routes)| {
+                routing_table_info(&state, &configured_routes)
+            })
+    }
+
+    /// List the routes we currently advertise to the given account
+    pub fn advertised_routes(
+        &self,
+        account: &A,
+    ) -> impl Future<Item = Vec<AdvertisedRoute>, Error = ()> {
+        if !account.should_send_routes()
+            || self.peer_modes.read().get(&account.id()) == Some(&Mode::Idle)
+        {
+            return Either::A(ok(Vec::new()));
+        }
+        let state = self.routing_state();
+        let relations = HashMap::from_iter(
+            self.forwarding_table
+                .read()
+                .routes()
+                .map(|(next_hop, _)| (next_hop.id(), next_hop.routing_relation())),
+        );
+        let ilp_address = self.ilp_address.clone();
+        let account_id = account.id();
+        Either::B(
+            self.store
+                .get_route_policies(account_id)
+                .map_err(move |err| {
+                    error!(
+                        "Error getting route policies for account {}: {}",
+                        account_id, err
+                    )
+                })
+                .map(move |policies| {
+                    advertised_routes(&state, &policies, &ilp_address[..], &relations)
+                }),
+        )
+    }
+
+    /// Publish a `RoutesReceived` event on the bus whenever a peer's route update is applied.
+    pub fn with_events(mut self, events: EventBus<A>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Reject route control and update requests with non-canonical encodings or trailing bytes
+    /// instead of parsing them leniently.
+    pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
+    /// Sign the routes we advertise and check the signatures of the routes peers send us.
+    /// Without this, routes are accepted from any origin.
+    pub fn with_route_auth(mut self, route_auth: RouteAuth) -> Self {
+        self.route_auth = route_auth;
+        self
+    }
+
+    /// Choose between the routes peers advertise for the same prefix by their cost, using the
+    /// failure rates and latencies recorded in the given stats (see `PeerStatsService`), the
+    /// peers' weights and what is lost converting to their assets at our exchange rates.
+    ///
+    /// Routes learned from peers are then also rechecked on every broadcast interval,
+    /// so that changes in the peers' performance are taken into account.
+    pub fn with_route_selection(
+        mut self,
+        route_selection: RouteSelection,
+        peer_stats: PeerStats<A::AccountId>,
+    ) -> Self {
+        self.route_selection = Some(route_selection);
+        self.peer_stats = peer_stats;
+        self
+    }
+
+    /// Stop the task started by `broadcast_routes`.
+    /// Incoming CCP requests are still handled after this is called.
+    pub fn stop_broadcasting(&self) {
+        debug!("Stopping route broadcasts");
+        self.stop_broadcasting.lock().take();
+    }
+
     /// Handle a CCP Route Control Request. If this is from an account that we broadcast routes to,
-    /// we'll send an outgoing Route Update Request to them.
+    /// we'll record the mode it asked for and, if it is Sync, send an outgoing Route Update Request to them.
+    /// Accounts in Idle mode are skipped by the regular broadcasts until they switch back to Sync.
     fn handle_route_control_request(
         &self,
         request: IncomingRequest<A>,
@@ -163,7 +491,11 @@ where
             .build()));
         }
 
-        let control = RouteControlRequest::try_from(&request.prepare);
+        let control = if self.strict_parsing {
+            RouteControlRequest::try_from_strict(&request.prepare)
+        } else {
+            RouteControlRequest::try_from(&request.prepare)
+        };
         if control.is_err() {
             return Either::A(err(RejectBuilder {
                 code: ErrorCode::F00_BAD_REQUEST,
@@ -180,8 +512,15 @@ where
             control
         );
 
-        // TODO stop sending updates if they are in Idle mode
-        if control.mode == Mode::Sync {
+        self.peer_modes
+            .write()
+            .insert(request.from.id(), control.mode);
+        if control.mode == Mode::Idle {
+            debug!(
+                "Account {} switched to Idle mode, no longer sending it route updates",
+                request.from.id()
+            );
+        } else {
             let (from_epoch_index, to_epoch_index) = {
                 let forwarding_table = self.forwarding_table.read();
                 let to_epoch_index = forwarding_table.epoch();
@@ -221,13 +560,35 @@ where
         Either::A(ok(CCP_RESPONSE.clone()))
     }
 
-    /// Remove invalid routes before processing the Route Update Request
+    /// Remove invalid routes, including those that fail route authentication,
+    /// before processing the Route Update Request
     fn filter_routes(&self, mut update: RouteUpdateRequest) -> RouteUpdateRequest {
+        update.withdrawn_routes.retain(|prefix| {
+            let valid = validate_prefix(&prefix[..]).is_ok();
+            if !valid {
+                warn!(
+                    "Ignoring withdrawn route with an invalid prefix: {:?}",
+                    prefix
+                );
+            }
+            valid
+        });
         update.new_routes = update
             .new_routes
             .into_iter()
             .filter(|route| {
-                if !route.prefix.starts_with(&self.global_prefix) {
+                if let Err(err) = validate_prefix(&route.prefix[..]) {
+                    warn!("Got route with an invalid prefix ({}): {:?}", err, route);
+                    false
+                } else if let Some(err) = route
+                    .path
+                    .iter()
+                    .filter_map(|node| Address::try_from(node.clone()).err())
+                    .next()
+                {
+                    warn!("Got route with an invalid path ({}): {:?}", err, route);
+                    false
+                } else if !route.prefix.starts_with(&self.global_prefix) {
                     warn!("Got route for a different global prefix: {:?}", route);
                     false
                 } else if route.prefix.len() <= self.global_prefix.len() {
@@ -240,7 +601,7 @@ where
                     );
                     false
                 } else {
-                    true
+                    self.route_auth.verify(route)
                 }
             })
             .collect();
@@ -251,18 +612,32 @@ where
     /// If updates are applied to the Incoming Routing Table for this peer, we will
     /// then check whether those routes are better than the current best ones we have in the
     /// Local Routing Table.
+    ///
+    /// Peers that we are not configured to receive routes from are sent a Route Control Request
+    /// asking them to switch to Idle mode so they stop sending us updates.
     fn handle_route_update_request(&self, request: IncomingRequest<A>) -> BoxedIlpFuture {
         if !request.from.should_receive_routes() {
-            return Box::new(err(RejectBuilder {
+            let reject = RejectBuilder {
                 code: ErrorCode::F00_BAD_REQUEST,
                 message: b"Your route broadcasts are not accepted here",
                 triggered_by: &self.ilp_address[..],
                 data: &[],
             }
-            .build()));
+            .build();
+            let future = self.send_route_control_request(request.from, Mode::Idle, [0; 16], 0);
+            if self.spawn_tasks {
+                spawn(future);
+                return Box::new(err(reject));
+            } else {
+                return Box::new(future.then(move |_| Err(reject)));
+            }
         }
 
-        let update = RouteUpdateRequest::try_from(&request.prepare);
+        let update = if self.strict_parsing {
+            RouteUpdateRequest::try_from_strict(&request.prepare)
+        } else {
+            RouteUpdateRequest::try_from(&request.prepare)
+        };
         if update.is_err() {
             return Box::new(err(RejectBuilder {
                 code: ErrorCode::F00_BAD_REQUEST,
@@ -281,20 +656,66 @@ where
 
         let update = self.filter_routes(update);
 
+        let service = self.clone();
+        let ilp_address = self.ilp_address.clone();
+        let from = request.from;
+        let from_id = from.id();
+        Box::new(
+            self.store
+                .get_route_policies(from_id)
+                .map_err(move |store_err| {
+                    error!(
+                        "Error getting route policies for account {}: {}",
+                        from_id, store_err
+                    );
+                    RejectBuilder {
+                        code: ErrorCode::T00_INTERNAL_ERROR,
+                        message: b"Error processing route update",
+                        data: &[],
+                        triggered_by: &ilp_address[..],
+                    }
+                    .build()
+                })
+                .and_then(move |policies| service.apply_route_update(from, update, &policies)),
+        )
+    }
+
+    /// Apply a Route Update Request from the given account to its Incoming Routing Table,
+    /// leaving out the routes its import policies do not permit.
+    fn apply_route_update(
+        &self,
+        from: A,
+        update: RouteUpdateRequest,
+        policies: &RoutePolicies,
+    ) -> BoxedIlpFuture {
+        let hold_down_time = update.hold_down_time;
+
         let mut incoming_tables = self.incoming_tables.write();
-        if !&incoming_tables.contains_key(&request.from.id()) {
-            incoming_tables.insert(
-                request.from.id(),
-                RoutingTable::new(update.routing_table_id),
-            );
+        if !&incoming_tables.contains_key(&from.id()) {
+            incoming_tables.insert(from.id(), RoutingTable::new(update.routing_table_id));
         }
         let ilp_address = self.ilp_address.clone();
-        match (*incoming_tables)
-            .get_mut(&request.from.id())
-            .expect("Should have inserted a routing table for this account")
-            .handle_update_request(request.from.clone(), update)
-        {
+        let table = incoming_tables
+            .get_mut(&from.id())
+            .expect("Should have inserted a routing table for this account");
+        let update = policies.filter_import(table, update);
+        match table.handle_update_request(from.clone(), update) {
             Ok(prefixes_updated) => {
+                let weight = policies.valid_weight().unwrap_or_else(|| {
+                    warn!(
+                        "Ignoring invalid route weight for account {}: {:?}",
+                        from.id(),
+                        policies.weight
+                    );
+                    1.0
+                });
+                self.peer_weights.write().insert(from.id(), weight);
+                self.incoming_table_expiries.write().insert(
+                    from.id(),
+                    Instant::now() + Duration::from_millis(u64::from(hold_down_time)),
+                );
+                self.events
+                    .publish(Event::RoutesReceived { from: from.clone() });
                 let future = self.update_best_routes(Some(prefixes_updated));
                 if self.spawn_tasks {
                     spawn(future);
@@ -324,9 +745,10 @@ where
                     triggered_by: &ilp_address[..],
                 }
                 .build();
-                let table = &incoming_tables[&request.from.id()];
+                let table = &incoming_tables[&from.id()];
                 let future = self.send_route_control_request(
-                    request.from.clone(),
+                    from.clone(),
+                    Mode::Sync,
                     table.id(),
                     table.epoch(),
                 );
@@ -340,22 +762,67 @@ where
         }
     }
 
-    /// Request a Route Update from the specified peer. This is sent when we get
-    /// a Route Update Request from them with a gap in the epochs since the last one we saw.
+    /// Remove the Incoming Routing Tables of peers that have not sent a Route Update Request
+    /// within the hold down time of their last one and withdraw the routes we learned from them.
+    ///
+    /// If the peer starts sending updates again, its next update will not line up with the
+    /// (now empty) table we have for it, so we will send it a Route Control Request to resync.
+    fn expire_routes(&self) -> impl Future<Item = (), Error = ()> {
+        let now = Instant::now();
+        let expired_prefixes: Vec<Bytes> = {
+            let mut incoming_tables = self.incoming_tables.write();
+            let mut expiries = self.incoming_table_expiries.write();
+            let expired_accounts: Vec<A::AccountId> = expiries
+                .iter()
+                .filter(|(_, expires_at)| **expires_at <= now)
+                .map(|(account_id, _)| *account_id)
+                .collect();
+            let mut prefixes = Vec::new();
+            for account_id in expired_accounts {
+                expiries.remove(&account_id);
+                if let Some(table) = incoming_tables.remove(&account_id) {
+                    debug!(
+                        "Routes from account {} expired, withdrawing {} prefixes",
+                        account_id,
+                        table.len()
+                    );
+                    prefixes.extend(
+                        table
+                            .get_simplified_table()
+                            .into_iter()
+                            .map(|(prefix, _)| prefix),
+                    );
+                }
+            }
+            prefixes
+        };
+
+        if expired_prefixes.is_empty() {
+            Either::A(ok(()))
+        } else {
+            Either::B(self.update_best_routes(Some(expired_prefixes)))
+        }
+    }
+
+    /// Send a Route Control Request to the specified peer. In Sync mode this requests a
+    /// Route Update and is sent when we get a Route Update Request from them with a gap
+    /// in the epochs since the last one we saw. In Idle mode it asks them to stop
+    /// sending us updates.
     fn send_route_control_request(
         &self,
         to: A,
+        mode: Mode,
         last_known_routing_table_id: [u8; 16],
         last_known_epoch: u32,
     ) -> impl Future<Item = (), Error = ()> {
         let to_id = to.id();
         let control = RouteControlRequest {
-            mode: Mode::Sync,
+            mode,
             last_known_routing_table_id,
             last_known_epoch,
             features: Vec::new(),
         };
-        debug!("Sending Route Control Request to account: {}, last known table id: {}, last known epoch: {}", to.id(), hex::encode(&last_known_routing_table_id[..]), last_known_epoch);
+        debug!("Sending Route Control Request to account: {}, mode: {:?}, last known table id: {}, last known epoch: {}", to.id(), mode, hex::encode(&last_known_routing_table_id[..]), last_known_epoch);
         let prepare = control.to_prepare();
         self.clone()
             .outgoing
@@ -381,6 +848,9 @@ where
     /// with some new or modified routes that might be better than our existing ones.
     ///
     /// If prefixes is None, this will check the best routes for all local and configured prefixes.
+    ///
+    /// The routing state is saved if the tables changed or if prefixes were given, because
+    /// the Incoming Routing Tables may have changed even if our best routes did not.
     fn update_best_routes(
         &self,
         prefixes: Option<Vec<Bytes>>,
@@ -392,17 +862,34 @@ where
         let ilp_address = self.ilp_address.clone();
         let global_prefix = self.global_prefix.clone();
         let mut store = self.store.clone();
+        let service = self.clone();
 
-        self.store.get_local_and_configured_routes().and_then(
+        self.store.get_local_and_configured_routes()
+            .map_err(|err| error!("Error getting local and configured routes: {}", err))
+            .and_then(
             move |(ref local_routes, ref configured_routes)| {
                 let (better_routes, withdrawn_routes) = {
                     // Note we only use a read lock here and later get a write lock if we need to update the table
                     let local_table = local_table.read();
                     let incoming_tables = incoming_tables.read();
 
+                    let peer_costs = service.route_selection.as_ref().map(|selection| {
+                        (selection, service.peer_costs(selection, &incoming_tables))
+                    });
+
                     // Either check the given prefixes or check all of our local and configured routes
+                    // (and the ones learned from peers, if their costs may have changed)
                     let prefixes_to_check: Box<Iterator<Item = Bytes>> = if let Some(prefixes) = prefixes {
                         Box::new(prefixes.into_iter())
+                    } else if peer_costs.is_some() {
+                        let mut prefixes: Vec<Bytes> = configured_routes.keys()
+                            .chain(local_routes.keys())
+                            .cloned()
+                            .chain(incoming_tables.values().flat_map(|table| table.routes().map(|(_, route)| route.prefix.clone())))
+                            .collect();
+                        prefixes.sort_unstable();
+                        prefixes.dedup();
+                        Box::new(prefixes.into_iter())
                     } else {
                         let routes = configured_routes.iter().chain(local_routes.iter());
                         Box::new(routes.map(|(prefix, _account)| prefix.clone()))
@@ -419,12 +906,13 @@ where
                             configured_routes,
                             &incoming_tables,
                             prefix.as_ref(),
+                            peer_costs.as_ref().map(|(selection, costs)| (*selection, costs)),
                         ) {
-                            if let Some((ref next_account, ref route)) = local_table.get_route(&prefix) {
+                            if let Some((ref next_account, _)) = local_table.get_route(&prefix) {
                                 if next_account.id() == best_next_account.id() {
                                     continue
                                 } else {
-                                    better_routes.push((prefix.clone(), next_account.clone(), route.clone()));
+                                    better_routes.push((prefix.clone(), best_next_account, best_route));
                                 }
                             } else {
                                 better_routes.push((prefix.clone(), best_next_account, best_route));
@@ -467,8 +955,7 @@ where
                                 let old_route = forwarding_table.get_route(&prefix);
                                 if old_route.is_none() || old_route.unwrap().0.id() != account.id() {
                                     route.path.insert(0, ilp_address.clone());
-                                    // Each hop hashes the auth before forwarding
-                                    route.auth = hash(&route.auth);
+                                    service.route_auth.sign(&mut route, forwarding_table.epoch() + 1);
                                     forwarding_table.set_route(prefix.clone(), account.clone(), route.clone());
                                     new_routes.push(route);
                                 }
@@ -481,10 +968,14 @@ where
                         forwarding_table.delete_route(prefix);
                     }
 
+                    ROUTING_TABLE_SIZE.set(local_table.len() as i64);
+
                     let epoch = forwarding_table.increment_epoch();
-                    forwarding_table_updates.insert(epoch, (new_routes, withdrawn_routes));
+                    add_forwarding_table_update(&mut forwarding_table_updates, epoch, (new_routes, withdrawn_routes));
 
-                    Either::A(store.set_routes(local_table.get_simplified_table()))
+                    Either::A(store.set_routes(local_table.get_simplified_table())
+                        .map_err(|err| error!("Error saving routing table: {}", err))
+                        .and_then(move |_| service.save_routing_state()))
                 } else {
                     // The routing table hasn't changed
                     Either::B(ok(()))
@@ -493,10 +984,49 @@ where
         )
     }
 
+    /// The cost of going through each of the peers we have an Incoming Routing Table for
+    fn peer_costs(
+        &self,
+        selection: &RouteSelection,
+        incoming_tables: &HashMap<A::AccountId, RoutingTable<A>>,
+    ) -> HashMap<A::AccountId, PeerCost> {
+        let weights = self.peer_weights.read();
+        let own_asset_code = self.account.asset_code();
+        incoming_tables
+            .iter()
+            .filter_map(|(account_id, table)| {
+                table
+                    .routes()
+                    .next()
+                    .map(|(account, _)| (account_id, account))
+            })
+            .map(|(account_id, account)| {
+                let conversion_cost = if account.asset_code() == own_asset_code {
+                    0.0
+                } else if let Ok(rates) = self
+                    .store
+                    .get_exchange_rates(&[own_asset_code, account.asset_code()])
+                {
+                    selection.cost_of_converting(rates[1] / rates[0], account.asset_scale())
+                } else {
+                    f64::INFINITY
+                };
+                let (failure_rate, latency) = self.peer_stats.get(account_id);
+                let cost = PeerCost {
+                    weight: weights.get(account_id).cloned().unwrap_or(1.0),
+                    fixed_cost: selection.failure_cost * failure_rate
+                        + selection.latency_cost * latency
+                        + conversion_cost,
+                };
+                (*account_id, cost)
+            })
+            .collect()
+    }
+
     /// Send RouteUpdateRequests to all peers that we send routing messages to
     fn send_route_updates(&self) -> impl Future<Item = (), Error = ()> {
-        let mut outgoing = self.outgoing.clone();
-        let account = self.account.clone();
+        let service = self.clone();
+        let peer_modes = self.peer_modes.clone();
         let to_epoch_index = self.forwarding_table.read().epoch();
 
         let from_epoch_index: u32 = {
@@ -511,14 +1041,16 @@ where
             from_epoch_index, to_epoch_index
         );
 
-        let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
-            .to_prepare();
         self.store
             .get_accounts_to_send_routes_to()
+            .map_err(|err| error!("Error getting accounts to send route updates to: {}", err))
             .and_then(move |mut accounts| {
                 accounts.sort_unstable_by_key(|a| a.id().to_string());
                 accounts.dedup_by_key(|a| a.id());
+                {
+                    let peer_modes = peer_modes.read();
+                    accounts.retain(|a| peer_modes.get(&a.id()) != Some(&Mode::Idle));
+                }
 
                 let broadcasting = !accounts.is_empty();
                 if broadcasting {
@@ -530,20 +1062,7 @@ where
                     );
                     Either::A(
                         join_all(accounts.into_iter().map(move |to| {
-                            let to_id = to.id();
-                            outgoing
-                                .send_request(OutgoingRequest {
-                                    from: account.clone(),
-                                    to,
-                                    prepare: prepare.clone(),
-                                })
-                                .map_err(move |err| {
-                                    error!(
-                                        "Error sending route update to account {}: {:?}",
-                                        to_id, err
-                                    )
-                                })
-                                .and_then(|_| Ok(()))
+                            service.send_route_update(to, from_epoch_index, to_epoch_index)
                         }))
                         .and_then(|_| {
                             trace!("Finished sending route updates");
@@ -559,55 +1078,49 @@ where
 
     /// Create a RouteUpdateRequest representing the given range of Forwarding Routing Table epochs.
     /// If the epoch range is not specified, it will create an update for the last epoch only.
+    ///
+    /// The new routes are filtered according to the export policies for the account it is for.
     fn create_route_update(
         &self,
         from_epoch_index: u32,
         to_epoch_index: u32,
+        policies: &RoutePolicies,
     ) -> RouteUpdateRequest {
         let (routing_table_id, current_epoch_index) = {
             let table = self.forwarding_table.read();
             (table.id(), table.epoch())
         };
-        let forwarding_table_updates = self.forwarding_table_updates.read();
-        let epochs_to_take: usize = if to_epoch_index > from_epoch_index {
-            (to_epoch_index - from_epoch_index) as usize
+        // Merge the new routes and withdrawn routes from all of the given epochs
+        let (new_routes, withdrawn_routes) = if to_epoch_index > from_epoch_index {
+            merge_updates(
+                self.forwarding_table_updates
+                    .read()
+                    .range(from_epoch_index..to_epoch_index)
+                    .map(|(_, update)| update),
+            )
         } else {
-            0
+            (Vec::new(), Vec::new())
         };
 
-        // Merge the new routes and withdrawn routes from all of the given epochs
-        let mut new_routes: Vec<Route> = Vec::with_capacity(epochs_to_take);
-        let mut withdrawn_routes: Vec<Bytes> = Vec::new();
-        // Iterate through each of the given epochs
-        for (new, withdrawn) in forwarding_table_updates
-            .values()
-            .skip(from_epoch_index as usize)
-            .take(epochs_to_take)
+        let mut new_routes: Vec<Route> = {
+            let forwarding_table = self.forwarding_table.read();
+            new_routes
+                .into_iter()
+                .filter(|route| {
+                    let relation = forwarding_table
+                        .get_route(&route.prefix)
+                        .map(|(account, _)| account.routing_relation());
+                    self.exports(policies, &route.prefix, relation)
+                })
+                .collect()
+        };
+        // The aggregate route is part of the table we advertise to the account, so it is
+        // sent whenever the account syncs it from the start (or when its policies change)
+        if policies.aggregate_child_routes
+            && from_epoch_index == 0
+            && policies.permits_export(&self.ilp_address[..], &self.ilp_address[..], None)
         {
-            for new_route in new {
-                new_routes.push(new_route.clone());
-                // If the route was previously withdrawn, ignore that now since it was added back
-                if withdrawn_routes.contains(&new_route.prefix) {
-                    withdrawn_routes = withdrawn_routes
-                        .into_iter()
-                        .filter(|prefix| prefix != &new_route.prefix)
-                        .collect();
-                }
-            }
-
-            for withdrawn_route in withdrawn {
-                withdrawn_routes.push(withdrawn_route.clone());
-                // If the route was previously added, ignore that since it was withdrawn later
-                if new_routes
-                    .iter()
-                    .any(|route| route.prefix == withdrawn_route)
-                {
-                    new_routes = new_routes
-                        .into_iter()
-                        .filter(|route| route.prefix != withdrawn_route)
-                        .collect();
-                }
-            }
+            new_routes.push(self.own_address_route());
         }
 
         RouteUpdateRequest {
@@ -615,50 +1128,233 @@ where
             from_epoch_index,
             to_epoch_index,
             current_epoch_index,
-            new_routes: new_routes.clone(),
+            new_routes,
             withdrawn_routes: withdrawn_routes.clone(),
             speaker: self.ilp_address.clone(),
             hold_down_time: DEFAULT_ROUTE_EXPIRY_TIME,
         }
     }
 
+    /// Whether a route from our Forwarding Routing Table is advertised individually under the given policies
+    fn exports(
+        &self,
+        policies: &RoutePolicies,
+        prefix: &[u8],
+        next_hop_relation: Option<RoutingRelation>,
+    ) -> bool {
+        policies.permits_export(prefix, &self.ilp_address[..], next_hop_relation)
+            && !policies.aggregates(prefix, &self.ilp_address[..], next_hop_relation)
+    }
+
+    /// All of the routes in our Forwarding Routing Table that we advertise to an account
+    /// with the given policies, including the aggregate route for our own address
+    fn exported_routes(&self, policies: &RoutePolicies) -> Vec<Route> {
+        let mut routes: Vec<Route> = self
+            .forwarding_table
+            .read()
+            .routes()
+            .filter(|(account, route)| {
+                self.exports(policies, &route.prefix, Some(account.routing_relation()))
+            })
+            .map(|(_, route)| route.clone())
+            .collect();
+        if policies.aggregate_child_routes
+            && policies.permits_export(&self.ilp_address[..], &self.ilp_address[..], None)
+        {
+            routes.push(self.own_address_route());
+        }
+        routes
+    }
+
+    /// Add the routes an account has not been sent because its old policies did not permit them,
+    /// and withdraw the ones its new policies no longer permit (including the child routes
+    /// covered by a new aggregate route, or the aggregate route itself).
+    fn add_policy_changes(
+        &self,
+        update: &mut RouteUpdateRequest,
+        old_policies: &RoutePolicies,
+        new_policies: &RoutePolicies,
+    ) {
+        let old_routes = self.exported_routes(old_policies);
+        let new_routes = self.exported_routes(new_policies);
+        for route in new_routes.iter() {
+            let already_sent = old_routes.iter().any(|old| old.prefix == route.prefix);
+            let in_update = update
+                .new_routes
+                .iter()
+                .any(|new| new.prefix == route.prefix);
+            if !already_sent && !in_update {
+                update.new_routes.push(route.clone());
+            }
+        }
+        for route in old_routes {
+            let still_sent = new_routes.iter().any(|new| new.prefix == route.prefix);
+            if !still_sent && !update.withdrawn_routes.contains(&route.prefix) {
+                update.withdrawn_routes.push(route.prefix);
+            }
+        }
+    }
+
+    /// Start a new epoch without changing the Forwarding Routing Table and return it.
+    /// Peers that are already up to date ignore updates that do not take them to a newer epoch,
+    /// so this is used to send one of them routes that did not change in our table.
+    fn start_empty_epoch(&self) -> impl Future<Item = u32, Error = ()> {
+        let epoch = {
+            let mut forwarding_table = self.forwarding_table.write();
+            let mut forwarding_table_updates = self.forwarding_table_updates.write();
+            let epoch = forwarding_table.increment_epoch();
+            add_forwarding_table_update(
+                &mut forwarding_table_updates,
+                epoch,
+                (Vec::new(), Vec::new()),
+            );
+            epoch + 1
+        };
+        self.save_routing_state().map(move |_| epoch)
+    }
+
+    /// The route for our own address that stands in for the routes to our children
+    pub(crate) fn own_address_route(&self) -> Route {
+        let mut route = Route {
+            prefix: self.ilp_address.clone(),
+            path: vec![self.ilp_address.clone()],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        let epoch = self.forwarding_table.read().epoch();
+        self.route_auth.sign(&mut route, epoch);
+        route
+    }
+
     /// Send a Route Update Request to a specific account for the given epoch range.
-    /// This is used when the peer has fallen behind and has requested a specific range of updates.
+    /// This is used for the regular broadcasts and when the peer has fallen behind
+    /// and has requested a specific range of updates.
     fn send_route_update(
         &self,
         to: A,
         from_epoch_index: u32,
         to_epoch_index: u32,
     ) -> impl Future<Item = (), Error = ()> {
-        let prepare = self
-            .create_route_update(from_epoch_index, to_epoch_index)
-            .to_prepare();
+        let service = self.clone();
+        let clone = self.clone();
+        let export_policies = self.export_policies.clone();
         let to_id = to.id();
-        debug!(
-            "Sending individual route update to account: {} for epochs from: {} to: {}",
-            to.id(),
-            from_epoch_index,
-            to_epoch_index
-        );
-        self.outgoing
-            .clone()
-            .send_request(OutgoingRequest {
-                to,
-                from: self.account.clone(),
-                prepare,
-            })
-            .and_then(|_| Ok(()))
+        self.store
+            .get_route_policies(to_id)
             .map_err(move |err| {
-                error!("Error sending route update to account {}: {:?}", to_id, err)
+                error!(
+                    "Error getting route policies for account {}: {}",
+                    to_id, err
+                )
+            })
+            .and_then(move |policies| {
+                // If the account's policies changed since we last sent it an update, it needs
+                // to be told about routes that did not change in our table. It only applies
+                // updates that take it to a newer epoch, so we start one for it
+                let old_policies = match export_policies.read().get(&to_id) {
+                    Some(old_policies) if *old_policies != policies => Some(old_policies.clone()),
+                    _ => None,
+                };
+                if old_policies.is_some() {
+                    Either::A(
+                        clone
+                            .start_empty_epoch()
+                            .map(move |to_epoch_index| (policies, old_policies, to_epoch_index)),
+                    )
+                } else {
+                    Either::B(ok((policies, None, to_epoch_index)))
+                }
+            })
+            .and_then(move |(policies, old_policies, to_epoch_index)| {
+                let mut update =
+                    service.create_route_update(from_epoch_index, to_epoch_index, &policies);
+                if let Some(ref old_policies) = old_policies {
+                    debug!(
+                        "Route policies for account {} changed, updating the routes we advertise to it",
+                        to_id
+                    );
+                    service.add_policy_changes(&mut update, old_policies, &policies);
+                }
+                debug!(
+                    "Sending route update to account: {} for epochs from: {} to: {}",
+                    to_id, from_epoch_index, to_epoch_index
+                );
+                let export_policies = service.export_policies.clone();
+                service
+                    .outgoing
+                    .clone()
+                    .send_request(OutgoingRequest {
+                        to,
+                        from: service.account.clone(),
+                        prepare: update.to_prepare(),
+                    })
+                    .map(move |_| {
+                        // Only once the account has the routes for these policies
+                        export_policies.write().insert(to_id, policies);
+                    })
+                    .map_err(move |err| {
+                        error!("Error sending route update to account {}: {:?}", to_id, err)
+                    })
             })
     }
 }
 
+/// Combine consecutive Forwarding Routing Table updates into one with the same effect
+fn merge_updates<'a>(
+    updates: impl Iterator<Item = &'a NewAndWithDrawnRoutes>,
+) -> NewAndWithDrawnRoutes {
+    let mut new_routes: Vec<Route> = Vec::new();
+    let mut withdrawn_routes: Vec<Bytes> = Vec::new();
+    for (new, withdrawn) in updates {
+        for new_route in new {
+            // If the route was previously withdrawn, ignore that now since it was added back.
+            // Likewise, only the latest version of a route that changed needs to be sent
+            withdrawn_routes.retain(|prefix| prefix != &new_route.prefix);
+            new_routes.retain(|route| route.prefix != new_route.prefix);
+            new_routes.push(new_route.clone());
+        }
+
+        for withdrawn_route in withdrawn {
+            // If the route was previously added, ignore that since it was withdrawn later
+            new_routes.retain(|route| route.prefix != *withdrawn_route);
+            if !withdrawn_routes.contains(withdrawn_route) {
+                withdrawn_routes.push(withdrawn_route.clone());
+            }
+        }
+    }
+    (new_routes, withdrawn_routes)
+}
+
+/// Record the update for the transition from the given epoch, merging the oldest updates
+/// together so that the log does not grow without bound. A peer that asks for the epochs
+/// covered by a merged update is sent all of it, which leaves it with the same routes.
+fn add_forwarding_table_update(
+    updates: &mut BTreeMap<u32, NewAndWithDrawnRoutes>,
+    epoch: u32,
+    update: NewAndWithDrawnRoutes,
+) {
+    updates.insert(epoch, update);
+    while updates.len() > MAX_FORWARDING_TABLE_UPDATES {
+        let mut oldest = updates.keys().take(2);
+        let (first, second) = match (oldest.next(), oldest.next()) {
+            (Some(first), Some(second)) => (*first, *second),
+            _ => break,
+        };
+        let first_update = updates.remove(&first).expect("Key was just listed");
+        let second_update = updates.remove(&second).expect("Key was just listed");
+        updates.insert(
+            second,
+            merge_updates(vec![&first_update, &second_update].into_iter()),
+        );
+    }
+}
+
 fn get_best_route_for_prefix<A: CcpRoutingAccount>(
     local_routes: &HashMap<Bytes, A>,
     configured_routes: &HashMap<Bytes, A>,
     incoming_tables: &HashMap<A::AccountId, RoutingTable<A>>,
     prefix: &[u8],
+    peer_costs: Option<(&RouteSelection, &HashMap<A::AccountId, PeerCost>)>,
 ) -> Option<(A, Route)> {
     if let Some(account) = configured_routes.get(prefix) {
         return Some((
@@ -698,8 +1394,23 @@ fn get_best_route_for_prefix<A: CcpRoutingAccount>(
                     return (account, route);
                 }
 
-                // Prioritize shortest path
-                if best_route.path.len() < route.path.len() {
+                if let Some((selection, costs)) = peer_costs {
+                    // Prioritize the lowest cost
+                    let route_cost = |account: &A, route: &Route| {
+                        costs
+                            .get(&account.id())
+                            .map(|cost| cost.route_cost(selection, route.path.len()))
+                            .unwrap_or_else(|| selection.hop_cost * route.path.len() as f64)
+                    };
+                    let best_cost = route_cost(best_account, best_route);
+                    let cost = route_cost(account, route);
+                    if best_cost < cost {
+                        return (best_account, best_route);
+                    } else if best_cost > cost {
+                        return (account, route);
+                    }
+                } else if best_route.path.len() < route.path.len() {
+                    // Prioritize shortest path
                     return (best_account, best_route);
                 } else if best_route.path.len() > route.path.len() {
                     return (account, route);
@@ -723,7 +1434,13 @@ impl<S, T, U, A> IncomingService<A> for CcpRouteManager<S, T, U, A>
 where
     S: IncomingService<A> + Clone + Send + Sync + 'static,
     T: OutgoingService<A> + Clone + Send + Sync + 'static,
-    U: RouteManagerStore<Account = A> + Clone + Send + Sync + 'static,
+    U: RouteManagerStore<Account = A>
+        + AccountStore<Account = A>
+        + ExchangeRateStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     A: CcpRoutingAccount + Send + Sync + 'static,
 {
     type Future = BoxedIlpFuture;
@@ -732,9 +1449,9 @@ where
     /// pass it on to the next handler if not
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
         let destination = request.prepare.destination();
-        if destination == CCP_CONTROL_DESTINATION {
+        if *destination == *CCP_CONTROL_DESTINATION {
             Box::new(self.handle_route_control_request(request))
-        } else if destination == CCP_UPDATE_DESTINATION {
+        } else if *destination == *CCP_UPDATE_DESTINATION {
             Box::new(self.handle_route_update_request(request))
         } else {
             Box::new(self.next_incoming.handle_request(request))
@@ -742,11 +1459,35 @@ where
     }
 }
 
+impl<S, T, U, A> RoutingTableInspector<A> for CcpRouteManager<S, T, U, A>
+where
+    S: IncomingService<A> + Clone + Send + Sync + 'static,
+    T: OutgoingService<A> + Clone + Send + Sync + 'static,
+    U: RouteManagerStore<Account = A>
+        + AccountStore<Account = A>
+        + ExchangeRateStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    A: CcpRoutingAccount + Send + Sync + 'static,
+{
+    fn routing_table_info(&self) -> Box<Future<Item = RoutingTableInfo, Error = ()> + Send> {
+        Box::new(CcpRouteManager::routing_table_info(self))
+    }
+
+    fn advertised_routes(
+        &self,
+        account: &A,
+    ) -> Box<Future<Item = Vec<AdvertisedRoute>, Error = ()> + Send> {
+        Box::new(CcpRouteManager::advertised_routes(self, account))
+    }
+}
+
 #[cfg(test)]
 mod ranking_routes {
     use super::*;
     use crate::test_helpers::*;
-    use crate::RoutingRelation;
     use std::iter::FromIterator;
 
     lazy_static! {
@@ -824,31 +1565,124 @@ mod ranking_routes {
 
     #[test]
     fn prioritizes_configured_routes() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.a");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.a", None);
         assert_eq!(best_route.unwrap().0.id(), 4);
     }
 
     #[test]
     fn prioritizes_local_routes_over_broadcasted_ones() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.c");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.c", None);
         assert_eq!(best_route.unwrap().0.id(), 3);
     }
 
     #[test]
     fn prioritizes_children_over_peers() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.d");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.d", None);
         assert_eq!(best_route.unwrap().0.id(), 6);
     }
 
     #[test]
     fn prioritizes_shorter_paths() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.e");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.e", None);
+        assert_eq!(best_route.unwrap().0.id(), 7);
+    }
+
+    #[test]
+    fn prioritizes_cheaper_peers_if_costs_are_given() {
+        let selection = RouteSelection::default();
+        let mut costs = HashMap::from_iter(vec![
+            (
+                7,
+                PeerCost {
+                    weight: 1.0,
+                    fixed_cost: 0.0,
+                },
+            ),
+            (
+                8,
+                PeerCost {
+                    weight: 1.0,
+                    fixed_cost: 0.0,
+                },
+            ),
+        ]);
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            b"example.e",
+            Some((&selection, &costs)),
+        );
         assert_eq!(best_route.unwrap().0.id(), 7);
+
+        costs.get_mut(&7).unwrap().weight = 5.0;
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            b"example.e",
+            Some((&selection, &costs)),
+        );
+        assert_eq!(best_route.unwrap().0.id(), 8);
+    }
+
+    #[test]
+    fn avoids_peers_without_exchange_rate() {
+        let selection = RouteSelection::default();
+        let costs = HashMap::from_iter(vec![
+            (
+                7,
+                PeerCost {
+                    weight: 0.0,
+                    fixed_cost: f64::INFINITY,
+                },
+            ),
+            (
+                8,
+                PeerCost {
+                    weight: 1.0,
+                    fixed_cost: 3.0,
+                },
+            ),
+        ]);
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            b"example.e",
+            Some((&selection, &costs)),
+        );
+        assert_eq!(best_route.unwrap().0.id(), 8);
+    }
+
+    #[test]
+    fn prioritizes_relation_over_cost() {
+        let selection = RouteSelection::default();
+        let costs = HashMap::from_iter(vec![(
+            6,
+            PeerCost {
+                weight: 100.0,
+                fixed_cost: 10.0,
+            },
+        )]);
+        let best_route = get_best_route_for_prefix(
+            &LOCAL,
+            &CONFIGURED,
+            &INCOMING,
+            b"example.d",
+            Some((&selection, &costs)),
+        );
+        assert_eq!(best_route.unwrap().0.id(), 6);
     }
 
     #[test]
     fn returns_none_for_no_route() {
-        let best_route = get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.z");
+        let best_route =
+            get_best_route_for_prefix(&LOCAL, &CONFIGURED, &INCOMING, b"example.z", None);
         assert!(best_route.is_none());
     }
 }
@@ -892,7 +1726,7 @@ mod handle_route_control_request {
         let result = test_service()
             .handle_request(IncomingRequest {
                 prepare: PrepareBuilder {
-                    destination: CCP_CONTROL_DESTINATION,
+                    destination: &CCP_CONTROL_DESTINATION,
                     amount: 0,
                     expires_at: SystemTime::now() + Duration::from_secs(30),
                     data: &[],
@@ -964,6 +1798,29 @@ mod handle_route_control_request {
         assert_eq!(update.current_epoch_index, 1);
         assert_eq!(update.new_routes.len(), 2);
     }
+
+    #[test]
+    fn doesnt_send_update_in_idle_mode() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: RouteControlRequest {
+                    last_known_routing_table_id: [0; 16],
+                    mode: Mode::Idle,
+                    last_known_epoch: 0,
+                    features: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .wait()
+            .unwrap();
+        assert!(outgoing_requests.lock().is_empty());
+        assert_eq!(
+            service.peer_modes.read().get(&ROUTING_ACCOUNT.id()),
+            Some(&Mode::Idle)
+        );
+    }
 }
 
 #[cfg(test)]
@@ -971,6 +1828,7 @@ mod handle_route_update_request {
     use super::*;
     use crate::fixtures::*;
     use crate::test_helpers::*;
+    use crate::RoutePolicy;
     use std::{
         iter::FromIterator,
         time::{Duration, SystemTime},
@@ -992,6 +1850,31 @@ mod handle_route_update_request {
             .unwrap();
     }
 
+    #[test]
+    fn publishes_routes_received_event() {
+        let events = EventBus::new();
+        let subscriber = events.subscribe();
+        let mut service = test_service().with_events(events);
+        let mut update = UPDATE_REQUEST_SIMPLE.clone();
+        update.to_epoch_index = 1;
+        update.from_epoch_index = 0;
+
+        service
+            .handle_request(IncomingRequest {
+                prepare: update.to_prepare(),
+                from: ROUTING_ACCOUNT.clone(),
+            })
+            .wait()
+            .unwrap();
+        drop(service);
+
+        let published: Vec<Event<TestAccount>> = subscriber.collect().wait().unwrap();
+        match published.as_slice() {
+            [Event::RoutesReceived { from }] => assert_eq!(from.id(), ROUTING_ACCOUNT.id()),
+            other => panic!("Unexpected events: {:?}", other),
+        }
+    }
+
     #[test]
     fn rejects_from_non_receiving_account() {
         let result = test_service()
@@ -1007,12 +1890,29 @@ mod handle_route_update_request {
         );
     }
 
+    #[test]
+    fn asks_non_receiving_account_to_go_idle() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        service
+            .handle_request(IncomingRequest {
+                prepare: UPDATE_REQUEST_SIMPLE.to_prepare(),
+                from: NON_ROUTING_ACCOUNT.clone(),
+            })
+            .wait()
+            .unwrap_err();
+
+        let request = &outgoing_requests.lock()[0];
+        assert_eq!(request.to.id(), NON_ROUTING_ACCOUNT.id());
+        let control = RouteControlRequest::try_from(&request.prepare).unwrap();
+        assert_eq!(control.mode, Mode::Idle);
+    }
+
     #[test]
     fn rejects_invalid_packet() {
         let result = test_service()
             .handle_request(IncomingRequest {
                 prepare: PrepareBuilder {
-                    destination: CCP_UPDATE_DESTINATION,
+                    destination: &CCP_UPDATE_DESTINATION,
                     amount: 0,
                     expires_at: SystemTime::now() + Duration::from_secs(30),
                     data: &[],
@@ -1029,6 +1929,42 @@ mod handle_route_update_request {
         );
     }
 
+    #[test]
+    fn rejects_non_canonical_packet_with_strict_parsing() {
+        let mut update = UPDATE_REQUEST_SIMPLE.clone();
+        update.to_epoch_index = 1;
+        update.from_epoch_index = 0;
+        let mut data = update.to_prepare().data().to_vec();
+        data.push(0);
+        let prepare = PrepareBuilder {
+            destination: &CCP_UPDATE_DESTINATION,
+            amount: 0,
+            expires_at: SystemTime::now() + Duration::from_secs(30),
+            data: &data[..],
+            execution_condition: &PEER_PROTOCOL_CONDITION,
+        }
+        .build();
+        let result = test_service()
+            .with_strict_parsing(true)
+            .handle_request(IncomingRequest {
+                prepare: prepare.clone(),
+                from: ROUTING_ACCOUNT.clone(),
+            })
+            .wait();
+        assert_eq!(
+            str::from_utf8(result.unwrap_err().message()).unwrap(),
+            "Invalid route update request"
+        );
+
+        let result = test_service()
+            .handle_request(IncomingRequest {
+                prepare,
+                from: ROUTING_ACCOUNT.clone(),
+            })
+            .wait();
+        assert!(result.is_ok());
+    }
+
     #[test]
     fn adds_table_on_first_request() {
         let mut service = test_service();
@@ -1088,6 +2024,32 @@ mod handle_route_update_request {
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
 
+    #[test]
+    fn filters_routes_with_invalid_addresses() {
+        let service = test_service();
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        for (prefix, path) in &[
+            ("example.valid", "example.a"),
+            ("example..invalid", "example.a"),
+            ("example.invalid-path", "example.not valid"),
+        ] {
+            request.new_routes.push(Route {
+                prefix: Bytes::from(*prefix),
+                path: vec![Bytes::from(*path)],
+                auth: [0; 32],
+                props: Vec::new(),
+            });
+        }
+        request.withdrawn_routes = vec![Bytes::from("example.withdrawn"), Bytes::from("")];
+        let request = service.filter_routes(request);
+        assert_eq!(request.new_routes.len(), 1);
+        assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
+        assert_eq!(
+            request.withdrawn_routes,
+            vec![Bytes::from("example.withdrawn")]
+        );
+    }
+
     #[test]
     fn filters_routing_loops() {
         let service = test_service();
@@ -1113,6 +2075,44 @@ mod handle_route_update_request {
         assert_eq!(request.new_routes[0].prefix, Bytes::from("example.valid"));
     }
 
+    #[test]
+    fn filters_routes_that_fail_authentication() {
+        let origin = RouteAuth::new().with_signing_key([1; 32]);
+        let service = test_service().with_route_auth(
+            RouteAuth::new()
+                .with_public_key(Bytes::from("example.origin"), origin.public_key().unwrap()),
+        );
+        let mut valid = Route {
+            prefix: Bytes::from("example.origin.valid"),
+            path: vec![Bytes::from("example.origin")],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        origin.sign(&mut valid, 1);
+        let mut request = UPDATE_REQUEST_SIMPLE.clone();
+        request.new_routes = vec![
+            valid,
+            Route {
+                prefix: Bytes::from("example.origin.forged"),
+                path: vec![Bytes::from("example.origin")],
+                auth: [0; 32],
+                props: Vec::new(),
+            },
+            Route {
+                prefix: Bytes::from("example.unknown"),
+                path: vec![Bytes::from("example.unknown")],
+                auth: [0; 32],
+                props: Vec::new(),
+            },
+        ];
+        let request = service.filter_routes(request);
+        assert_eq!(request.new_routes.len(), 1);
+        assert_eq!(
+            request.new_routes[0].prefix,
+            Bytes::from("example.origin.valid")
+        );
+    }
+
     #[test]
     fn updates_local_routing_table() {
         let mut service = test_service();
@@ -1337,18 +2337,189 @@ mod handle_route_update_request {
         let control = RouteControlRequest::try_from(&request.prepare).unwrap();
         assert_eq!(control.last_known_epoch, 1);
     }
+
+    #[test]
+    fn applies_import_policy() {
+        let mut service = test_service();
+        service.store.policies.lock().insert(
+            ROUTING_ACCOUNT.id(),
+            RoutePolicies {
+                import: RoutePolicy {
+                    deny_prefixes: vec!["example.prefix2".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_some());
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix2")
+            .is_none());
+    }
+
+    #[test]
+    fn limits_imported_prefixes() {
+        let mut service = test_service();
+        service.store.policies.lock().insert(
+            ROUTING_ACCOUNT.id(),
+            RoutePolicies {
+                max_imported_prefixes: Some(1),
+                ..Default::default()
+            },
+        );
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(
+            service.incoming_tables.read()[&ROUTING_ACCOUNT.id()].len(),
+            1
+        );
+        assert_eq!(service.local_table.read().len(), 1);
+    }
+
+    #[test]
+    fn advances_epoch_if_all_routes_are_filtered() {
+        let mut service = test_service();
+        service.store.policies.lock().insert(
+            ROUTING_ACCOUNT.id(),
+            RoutePolicies {
+                import: RoutePolicy {
+                    allow_prefixes: vec!["example.other".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(
+            service.incoming_tables.read()[&ROUTING_ACCOUNT.id()].epoch(),
+            1
+        );
+    }
+
+    #[test]
+    fn keeps_routes_until_hold_down_time_passes() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+
+        service.expire_routes().wait().unwrap();
+        assert_eq!(service.incoming_tables.read().len(), 1);
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_some());
+    }
+
+    #[test]
+    fn withdraws_expired_routes() {
+        let mut service = test_service();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        request.hold_down_time = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+
+        service.expire_routes().wait().unwrap();
+        assert!(service.incoming_tables.read().is_empty());
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix1")
+            .is_none());
+        assert!((*service.local_table.read())
+            .get_route(b"example.prefix2")
+            .is_none());
+        assert!(service.store.routes.lock().is_empty());
+        let (_, withdrawn) = &service.forwarding_table_updates.read()[&1];
+        assert_eq!(withdrawn.len(), 2);
+    }
+
+    #[test]
+    fn resyncs_after_routes_expire() {
+        let (mut service, outgoing_requests) = test_service_with_routes();
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 1;
+        request.from_epoch_index = 0;
+        request.hold_down_time = 0;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap();
+        service.expire_routes().wait().unwrap();
+
+        // The peer comes back and sends its next update
+        let mut request = UPDATE_REQUEST_COMPLEX.clone();
+        request.to_epoch_index = 2;
+        request.from_epoch_index = 1;
+        service
+            .handle_request(IncomingRequest {
+                from: ROUTING_ACCOUNT.clone(),
+                prepare: request.to_prepare(),
+            })
+            .wait()
+            .unwrap_err();
+
+        let request = &outgoing_requests.lock()[0];
+        let control = RouteControlRequest::try_from(&request.prepare).unwrap();
+        assert_eq!(control.mode, Mode::Sync);
+        assert_eq!(control.last_known_epoch, 0);
+    }
 }
 
 #[cfg(test)]
 mod create_route_update {
     use super::*;
     use crate::test_helpers::*;
+    use crate::{RoutePolicy, RoutingRelation};
     use std::iter::FromIterator;
 
     #[test]
     fn heartbeat_message_for_empty_table() {
         let service = test_service();
-        let update = service.create_route_update(0, 0);
+        let update = service.create_route_update(0, 0, &RoutePolicies::default());
         assert_eq!(update.from_epoch_index, 0);
         assert_eq!(update.to_epoch_index, 0);
         assert_eq!(update.current_epoch_index, 0);
@@ -1360,7 +2531,7 @@ mod create_route_update {
     fn includes_the_given_range_of_epochs() {
         let service = test_service();
         (*service.forwarding_table.write()).set_epoch(4);
-        *service.forwarding_table_updates.write() = HashMap::from_iter(vec![
+        *service.forwarding_table_updates.write() = BTreeMap::from_iter(vec![
             (
                 0,
                 (
@@ -1410,7 +2581,7 @@ mod create_route_update {
                 ),
             ),
         ]);
-        let update = service.create_route_update(1, 3);
+        let update = service.create_route_update(1, 3, &RoutePolicies::default());
         assert_eq!(update.from_epoch_index, 1);
         assert_eq!(update.to_epoch_index, 3);
         assert_eq!(update.current_epoch_index, 4);
@@ -1426,32 +2597,326 @@ mod create_route_update {
         assert!(!new_routes.contains(&"example.m"));
         assert_eq!(update.withdrawn_routes[0], &Bytes::from("example.m"));
     }
-}
-
-#[cfg(test)]
-mod send_route_updates {
-    use super::*;
-    use crate::test_helpers::*;
 
     #[test]
-    fn broadcasts_to_all_accounts_we_send_updates_to() {
-        let (service, outgoing_requests) = test_service_with_routes();
-        service.send_route_updates().wait().unwrap();
-        let mut accounts: Vec<u64> = outgoing_requests
-            .lock()
-            .iter()
-            .map(|request| request.to.id())
-            .collect();
-        accounts.sort_unstable();
-        assert_eq!(accounts, vec![1, 2]);
-    }
+    fn merges_oldest_updates() {
+        let service = test_service();
+        let updates_to_add = MAX_FORWARDING_TABLE_UPDATES as u32 + 10;
+        {
+            let mut updates = service.forwarding_table_updates.write();
+            for epoch in 0..updates_to_add {
+                let route = Route {
+                    prefix: Bytes::from(format!("example.{}", epoch)),
+                    path: vec![Bytes::from("example.x")],
+                    auth: [1; 32],
+                    props: Vec::new(),
+                };
+                let withdrawn = if epoch == 1 {
+                    vec![Bytes::from("example.0")]
+                } else {
+                    Vec::new()
+                };
+                add_forwarding_table_update(&mut updates, epoch, (vec![route], withdrawn));
+            }
+            assert_eq!(updates.len(), MAX_FORWARDING_TABLE_UPDATES);
+        }
+        service.forwarding_table.write().set_epoch(updates_to_add);
 
-    #[test]
-    fn broadcasts_configured_and_local_routes() {
-        let (service, outgoing_requests) = test_service_with_routes();
+        let update = service.create_route_update(0, updates_to_add, &RoutePolicies::default());
+        assert_eq!(update.new_routes.len() as u32, updates_to_add - 1);
+        assert_eq!(update.withdrawn_routes, vec![Bytes::from("example.0")]);
 
-        // This is normally spawned as a task when the service is created
-        service.update_best_routes(None).wait().unwrap();
+        // Peers that are behind by an epoch in a merged update are sent all of it
+        let update = service.create_route_update(5, updates_to_add, &RoutePolicies::default());
+        assert_eq!(update.new_routes.len() as u32, updates_to_add - 1);
+
+        let update = service.create_route_update(
+            updates_to_add - 1,
+            updates_to_add,
+            &RoutePolicies::default(),
+        );
+        assert_eq!(update.new_routes.len(), 1);
+    }
+
+    fn add_forwarding_routes<S, T>(service: &CcpRouteManager<S, T, TestStore, TestAccount>) {
+        let mut child = TestAccount::new(3, "example.child");
+        child.relation = RoutingRelation::Child;
+        let peer = TestAccount::new(4, "example.peer");
+        let mut routes = Vec::new();
+        for (prefix, account) in &[
+            ("example.child", child),
+            ("example.peer", peer.clone()),
+            ("example.peer.secret", peer),
+        ] {
+            let route = Route {
+                prefix: Bytes::from(*prefix),
+                path: vec![Bytes::from("example.connector")],
+                auth: [0; 32],
+                props: Vec::new(),
+            };
+            service.forwarding_table.write().set_route(
+                route.prefix.clone(),
+                account.clone(),
+                route.clone(),
+            );
+            routes.push(route);
+        }
+        service.forwarding_table.write().set_epoch(1);
+        service
+            .forwarding_table_updates
+            .write()
+            .insert(0, (routes, Vec::new()));
+    }
+
+    #[test]
+    fn applies_export_policy() {
+        let service = test_service();
+        add_forwarding_routes(&service);
+        let update = service.create_route_update(
+            0,
+            1,
+            &RoutePolicies {
+                export: RoutePolicy {
+                    deny_prefixes: vec!["example.peer.secret".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let new_routes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|r| str::from_utf8(r.prefix.as_ref()).unwrap())
+            .collect();
+        assert_eq!(new_routes.len(), 2);
+        assert!(!new_routes.contains(&"example.peer.secret"));
+    }
+
+    #[test]
+    fn only_exports_own_and_children_routes() {
+        let service = test_service();
+        add_forwarding_routes(&service);
+        let update = service.create_route_update(
+            0,
+            1,
+            &RoutePolicies {
+                export_own_and_children_only: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(update.new_routes.len(), 1);
+        assert_eq!(update.new_routes[0].prefix, Bytes::from("example.child"));
+    }
+
+    fn add_routes_under_own_address<S, T>(service: &CcpRouteManager<S, T, TestStore, TestAccount>) {
+        let mut routes = Vec::new();
+        for (prefix, relation) in &[
+            ("example.connector.child1", RoutingRelation::Child),
+            ("example.connector.child2", RoutingRelation::Child),
+            ("example.connector.elsewhere", RoutingRelation::Peer),
+            ("example.remote", RoutingRelation::Peer),
+        ] {
+            let mut account = TestAccount::new(routes.len() as u64 + 3, prefix);
+            account.relation = *relation;
+            let route = Route {
+                prefix: Bytes::from(*prefix),
+                path: vec![Bytes::from("example.connector")],
+                auth: [0; 32],
+                props: Vec::new(),
+            };
+            service.forwarding_table.write().set_route(
+                route.prefix.clone(),
+                account,
+                route.clone(),
+            );
+            routes.push(route);
+        }
+        service.forwarding_table.write().set_epoch(1);
+        service
+            .forwarding_table_updates
+            .write()
+            .insert(0, (routes, Vec::new()));
+    }
+
+    fn prefixes(update: &RouteUpdateRequest) -> Vec<&str> {
+        let mut prefixes: Vec<&str> = update
+            .new_routes
+            .iter()
+            .map(|r| str::from_utf8(r.prefix.as_ref()).unwrap())
+            .collect();
+        prefixes.sort_unstable();
+        prefixes
+    }
+
+    #[test]
+    fn aggregates_child_routes() {
+        let service = test_service();
+        add_routes_under_own_address(&service);
+        let policies = RoutePolicies {
+            aggregate_child_routes: true,
+            ..Default::default()
+        };
+        let update = service.create_route_update(0, 1, &policies);
+        assert_eq!(
+            prefixes(&update),
+            vec![
+                "example.connector",
+                "example.connector.elsewhere",
+                "example.remote"
+            ]
+        );
+        let aggregate = update
+            .new_routes
+            .iter()
+            .find(|route| route.prefix == service.ilp_address)
+            .unwrap();
+        assert_eq!(aggregate.path, vec![service.ilp_address.clone()]);
+
+        // The aggregate was already sent when the account synced from the start
+        let update = service.create_route_update(1, 1, &policies);
+        assert!(update.new_routes.is_empty());
+    }
+
+    #[test]
+    fn updates_synced_account_when_policies_change() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        add_routes_under_own_address(&service);
+        let peer = TestAccount::new(10, "example.peer");
+        let last_update = || {
+            let requests = outgoing_requests.lock();
+            RouteUpdateRequest::try_from(&requests.last().unwrap().prepare).unwrap()
+        };
+        service
+            .send_route_update(peer.clone(), 0, 1)
+            .wait()
+            .unwrap();
+        assert!(prefixes(&last_update()).contains(&"example.connector.child1"));
+
+        service
+            .store
+            .set_route_policies(
+                10,
+                RoutePolicies {
+                    aggregate_child_routes: true,
+                    ..Default::default()
+                },
+            )
+            .wait()
+            .unwrap();
+        service
+            .send_route_update(peer.clone(), 1, 1)
+            .wait()
+            .unwrap();
+        let mut update = last_update();
+        // A new epoch is started so the account does not ignore the update
+        assert_eq!(update.to_epoch_index, 2);
+        assert_eq!(service.forwarding_table.read().epoch(), 2);
+        assert_eq!(prefixes(&update), vec!["example.connector"]);
+        update.withdrawn_routes.sort_unstable();
+        assert_eq!(
+            update.withdrawn_routes,
+            vec![
+                Bytes::from("example.connector.child1"),
+                Bytes::from("example.connector.child2")
+            ]
+        );
+
+        // Other accounts are sent an empty update for the new epoch
+        let update = service.create_route_update(1, 2, &RoutePolicies::default());
+        assert!(update.new_routes.is_empty());
+
+        service
+            .store
+            .set_route_policies(10, RoutePolicies::default())
+            .wait()
+            .unwrap();
+        service
+            .send_route_update(peer.clone(), 2, 2)
+            .wait()
+            .unwrap();
+        let update = last_update();
+        assert_eq!(update.to_epoch_index, 3);
+        assert_eq!(
+            prefixes(&update),
+            vec!["example.connector.child1", "example.connector.child2"]
+        );
+        assert_eq!(
+            update.withdrawn_routes,
+            vec![Bytes::from("example.connector")]
+        );
+
+        // Nothing changes when the policies stay the same
+        service.send_route_update(peer, 3, 3).wait().unwrap();
+        let update = last_update();
+        assert_eq!(update.to_epoch_index, 3);
+        assert!(update.new_routes.is_empty());
+        assert!(update.withdrawn_routes.is_empty());
+    }
+
+    #[test]
+    fn advertises_each_child_by_default() {
+        let service = test_service();
+        add_routes_under_own_address(&service);
+        let update = service.create_route_update(0, 1, &RoutePolicies::default());
+        assert_eq!(
+            prefixes(&update),
+            vec![
+                "example.connector.child1",
+                "example.connector.child2",
+                "example.connector.elsewhere",
+                "example.remote"
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod send_route_updates {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn broadcasts_to_all_accounts_we_send_updates_to() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.send_route_updates().wait().unwrap();
+        let mut accounts: Vec<u64> = outgoing_requests
+            .lock()
+            .iter()
+            .map(|request| request.to.id())
+            .collect();
+        accounts.sort_unstable();
+        assert_eq!(accounts, vec![1, 2]);
+    }
+
+    #[test]
+    fn skips_idle_accounts_until_they_sync() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        service.peer_modes.write().insert(1, Mode::Idle);
+        service.send_route_updates().wait().unwrap();
+        let accounts: Vec<u64> = outgoing_requests
+            .lock()
+            .drain(..)
+            .map(|request| request.to.id())
+            .collect();
+        assert_eq!(accounts, vec![2]);
+
+        service.peer_modes.write().insert(1, Mode::Sync);
+        service.send_route_updates().wait().unwrap();
+        let mut accounts: Vec<u64> = outgoing_requests
+            .lock()
+            .iter()
+            .map(|request| request.to.id())
+            .collect();
+        accounts.sort_unstable();
+        assert_eq!(accounts, vec![1, 2]);
+    }
+
+    #[test]
+    fn broadcasts_configured_and_local_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+
+        // This is normally spawned as a task when the service is created
+        service.update_best_routes(None).wait().unwrap();
 
         service.send_route_updates().wait().unwrap();
         let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
@@ -1465,6 +2930,27 @@ mod send_route_updates {
         assert!(prefixes.contains(&"example.configured.1"));
     }
 
+    #[test]
+    fn authenticates_originated_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        let route_auth = RouteAuth::new().with_signing_key([1; 32]);
+        let verifier = RouteAuth::new().with_public_key(
+            service.ilp_address.clone(),
+            route_auth.public_key().unwrap(),
+        );
+        let service = service.with_route_auth(route_auth);
+        service.update_best_routes(None).wait().unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        assert_eq!(update.new_routes.len(), 2);
+        // These prefixes are not under our address, so other nodes cannot trust us for them
+        for route in update.new_routes.iter() {
+            assert!(!verifier.verify(route));
+        }
+        assert!(verifier.verify(&service.own_address_route()));
+    }
+
     #[test]
     fn broadcasts_received_routes() {
         let (service, outgoing_requests) = test_service_with_routes();
@@ -1508,6 +2994,63 @@ mod send_route_updates {
         assert!(prefixes.contains(&"example.remote"));
     }
 
+    #[test]
+    fn signs_forwarded_routes() {
+        let (service, outgoing_requests) = test_service_with_routes();
+        let origin = RouteAuth::new().with_signing_key([1; 32]);
+        let route_auth = RouteAuth::new()
+            .with_signing_key([2; 32])
+            .with_public_key(Bytes::from("example.peer"), origin.public_key().unwrap());
+        let verifier = RouteAuth::new()
+            .with_public_key(Bytes::from("example.peer"), origin.public_key().unwrap())
+            .with_public_key(
+                service.ilp_address.clone(),
+                route_auth.public_key().unwrap(),
+            );
+        let service = service.with_route_auth(route_auth);
+        service.update_best_routes(None).wait().unwrap();
+
+        let mut route = Route {
+            prefix: Bytes::from("example.peer.remote"),
+            path: vec![Bytes::from("example.peer")],
+            auth: [0; 32],
+            props: Vec::new(),
+        };
+        origin.sign(&mut route, 1);
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(10, "example.peer"),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [0; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Bytes::from("example.peer"),
+                    new_routes: vec![route],
+                    withdrawn_routes: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .wait()
+            .unwrap();
+
+        service.send_route_updates().wait().unwrap();
+        let update = RouteUpdateRequest::try_from(&outgoing_requests.lock()[0].prepare).unwrap();
+        let forwarded = update
+            .new_routes
+            .iter()
+            .find(|route| &route.prefix[..] == b"example.peer.remote")
+            .unwrap();
+        assert_eq!(forwarded.path.len(), 2);
+        assert!(verifier.verify(forwarded));
+
+        // Our signature covers the rest of the path
+        let mut lengthened = forwarded.clone();
+        lengthened.path.insert(1, Bytes::from("example.other"));
+        assert!(!verifier.verify(&lengthened));
+    }
+
     #[test]
     fn broadcasts_withdrawn_routes() {
         let (service, outgoing_requests) = test_service_with_routes();
@@ -1573,3 +3116,348 @@ mod send_route_updates {
         );
     }
 }
+
+#[cfg(test)]
+mod routing_state {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn receive_remote_route<S, T>(service: &CcpRouteManager<S, T, TestStore, TestAccount>)
+    where
+        S: IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        T: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+    {
+        service
+            .handle_route_update_request(IncomingRequest {
+                from: TestAccount::new(1, "example.local.1"),
+                prepare: RouteUpdateRequest {
+                    routing_table_id: [5; 16],
+                    current_epoch_index: 1,
+                    from_epoch_index: 0,
+                    to_epoch_index: 1,
+                    hold_down_time: 30000,
+                    speaker: Bytes::from("example.local.1"),
+                    new_routes: vec![Route {
+                        prefix: Bytes::from("example.remote"),
+                        path: vec![Bytes::from("example.local.1")],
+                        auth: [0; 32],
+                        props: Vec::new(),
+                    }],
+                    withdrawn_routes: Vec::new(),
+                }
+                .to_prepare(),
+            })
+            .wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn restores_saved_tables() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+        assert_eq!(service.store.routing_state.lock().len(), 1);
+
+        let (mut restarted, _outgoing_requests) = test_service_with_routes();
+        restarted.store = service.store.clone();
+        restarted.load_routing_state().wait().unwrap();
+
+        assert_eq!(
+            restarted.forwarding_table.read().id(),
+            service.forwarding_table.read().id()
+        );
+        assert_eq!(
+            restarted.forwarding_table.read().epoch(),
+            service.forwarding_table.read().epoch()
+        );
+        // Peers that are behind are sent the whole table
+        let update = restarted.create_route_update(0, 2, &RoutePolicies::default());
+        assert_eq!(
+            update.new_routes.len(),
+            service.forwarding_table.read().len()
+        );
+        assert!(update.withdrawn_routes.is_empty());
+        assert_eq!(restarted.incoming_tables.read()[&1].epoch(), 1);
+        assert_eq!(
+            restarted
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            1
+        );
+        assert!(restarted.incoming_table_expiries.read().contains_key(&1));
+    }
+
+    #[test]
+    fn only_saves_when_epoch_changes() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        assert_eq!(service.store.routing_state.lock().len(), 1);
+
+        service.store.routing_state.lock().clear();
+        service.update_best_routes(None).wait().unwrap();
+        assert!(service.store.routing_state.lock().is_empty());
+    }
+
+    #[test]
+    fn ignores_state_saved_by_other_node() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+        {
+            let mut saved = service.store.routing_state.lock();
+            let state = saved.remove(&b"example.connector"[..]).unwrap();
+            saved.insert(Bytes::from("example.other-connector"), state);
+        }
+
+        let (mut restarted, _outgoing_requests) = test_service_with_routes();
+        restarted.store = service.store.clone();
+        let id = restarted.forwarding_table.read().id();
+        restarted.load_routing_state().wait().unwrap();
+
+        assert_eq!(restarted.forwarding_table.read().id(), id);
+        assert!(restarted.incoming_tables.read().is_empty());
+    }
+
+    #[test]
+    fn keeps_forwarding_table_that_already_changed() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+
+        let (restarted, _outgoing_requests) = test_service_with_routes();
+        restarted.update_best_routes(None).wait().unwrap();
+        *restarted.store.routing_state.lock() = service.store.routing_state.lock().clone();
+        let id = restarted.forwarding_table.read().id();
+        restarted.load_routing_state().wait().unwrap();
+
+        assert_eq!(restarted.forwarding_table.read().id(), id);
+        assert_eq!(restarted.incoming_tables.read().len(), 1);
+    }
+
+    #[test]
+    fn ignores_state_with_missing_accounts() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        service.update_best_routes(None).wait().unwrap();
+        receive_remote_route(&service);
+
+        let mut restarted = test_service();
+        restarted.store.routing_state = service.store.routing_state.clone();
+        let id = restarted.forwarding_table.read().id();
+        restarted.load_routing_state().wait().unwrap();
+
+        assert_eq!(restarted.forwarding_table.read().id(), id);
+        assert!(restarted.incoming_tables.read().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod inspecting_routes {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::{RoutePolicy, RouteSource};
+
+    fn receive_remote_routes<S, T>(service: &CcpRouteManager<S, T, TestStore, TestAccount>)
+    where
+        S: IncomingService<TestAccount> + Clone + Send + Sync + 'static,
+        T: OutgoingService<TestAccount> + Clone + Send + Sync + 'static,
+    {
+        // This is normally spawned as a task when the service is created
+        service.update_best_routes(None).wait().unwrap();
+
+        for (id, path) in &[
+            (10, vec!["example.peer"]),
+            (11, vec!["example.other", "example.far"]),
+        ] {
+            service
+                .handle_route_update_request(IncomingRequest {
+                    from: TestAccount::new(*id, path[0]),
+                    prepare: RouteUpdateRequest {
+                        routing_table_id: [0; 16],
+                        current_epoch_index: 1,
+                        from_epoch_index: 0,
+                        to_epoch_index: 1,
+                        hold_down_time: 30000,
+                        speaker: Bytes::from(path[0]),
+                        new_routes: vec![Route {
+                            prefix: Bytes::from("example.remote"),
+                            path: path.iter().cloned().map(Bytes::from).collect(),
+                            auth: [0; 32],
+                            props: Vec::new(),
+                        }],
+                        withdrawn_routes: Vec::new(),
+                    }
+                    .to_prepare(),
+                })
+                .wait()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn switches_away_from_failing_peer() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let peer_stats = PeerStats::new();
+        let service = service.with_route_selection(RouteSelection::default(), peer_stats.clone());
+        receive_remote_routes(&service);
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            10
+        );
+
+        for _ in 0..5 {
+            peer_stats.record(10, true, Duration::from_millis(100));
+        }
+        // This is normally called by the broadcast task
+        service.update_best_routes(None).wait().unwrap();
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            11
+        );
+    }
+
+    #[test]
+    fn uses_peer_weights_from_route_policies() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        let service = service.with_route_selection(RouteSelection::default(), PeerStats::new());
+        service.store.policies.lock().insert(
+            10,
+            RoutePolicies {
+                weight: Some(5.0),
+                ..Default::default()
+            },
+        );
+        receive_remote_routes(&service);
+        assert_eq!(
+            service
+                .local_table
+                .read()
+                .get_route(b"example.remote")
+                .unwrap()
+                .0
+                .id(),
+            11
+        );
+    }
+
+    #[test]
+    fn describes_sources_and_alternatives() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_remote_routes(&service);
+
+        let info = service.routing_table_info().wait().unwrap();
+        assert_eq!(info.epoch, 2);
+        let prefixes: Vec<&str> = info
+            .routes
+            .iter()
+            .map(|route| route.prefix.as_str())
+            .collect();
+        assert_eq!(
+            prefixes,
+            vec![
+                "example.configured.1",
+                "example.connector.other-local",
+                "example.local.1",
+                "example.remote"
+            ]
+        );
+
+        assert_eq!(info.routes[0].source, RouteSource::Configured);
+        assert_eq!(info.routes[0].epoch, Some(1));
+        // Our own routes are not advertised
+        assert_eq!(info.routes[1].source, RouteSource::Local);
+        assert_eq!(info.routes[1].epoch, None);
+
+        let remote = &info.routes[3];
+        assert_eq!(
+            remote.source,
+            RouteSource::Peer {
+                account_id: "10".to_string()
+            }
+        );
+        assert_eq!(remote.next_hop, "10");
+        assert_eq!(remote.path, vec!["example.peer".to_string()]);
+        assert_eq!(remote.epoch, Some(2));
+        assert_eq!(remote.alternatives.len(), 1);
+        assert_eq!(remote.alternatives[0].account_id, "11");
+        assert_eq!(
+            remote.alternatives[0].path,
+            vec!["example.other".to_string(), "example.far".to_string()]
+        );
+    }
+
+    #[test]
+    fn lists_routes_advertised_to_each_peer() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_remote_routes(&service);
+        service.store.policies.lock().insert(
+            1,
+            RoutePolicies {
+                export: RoutePolicy {
+                    deny_prefixes: vec!["example.remote".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let advertised = service
+            .advertised_routes(&TestAccount::new(1, "example.local.1"))
+            .wait()
+            .unwrap();
+        let prefixes: Vec<&str> = advertised
+            .iter()
+            .map(|route| route.prefix.as_str())
+            .collect();
+        assert_eq!(prefixes, vec!["example.configured.1", "example.local.1"]);
+        assert_eq!(advertised[0].path, vec!["example.connector".to_string()]);
+
+        let advertised = service
+            .advertised_routes(&TestAccount::new(2, "example.configured.1"))
+            .wait()
+            .unwrap();
+        assert_eq!(advertised.len(), 3);
+
+        service.peer_modes.write().insert(2, Mode::Idle);
+        let advertised = service
+            .advertised_routes(&TestAccount::new(2, "example.configured.1"))
+            .wait()
+            .unwrap();
+        assert!(advertised.is_empty());
+    }
+
+    #[test]
+    fn inspects_through_trait_object() {
+        let (service, _outgoing_requests) = test_service_with_routes();
+        receive_remote_routes(&service);
+        let inspector: Arc<RoutingTableInspector<TestAccount> + Send + Sync> =
+            Arc::new(service.clone());
+        assert_eq!(
+            inspector.routing_table_info().wait().unwrap(),
+            service.routing_table_info().wait().unwrap()
+        );
+
+        // Idle peers are not sent any routes
+        service.peer_modes.write().insert(1, Mode::Idle);
+        assert!(inspector
+            .advertised_routes(&TestAccount::new(1, "example.local.1"))
+            .wait()
+            .unwrap()
+            .is_empty());
+    }
+}
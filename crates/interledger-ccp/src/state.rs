@@ -0,0 +1,216 @@
+use crate::{packet::Route, routing_table::RoutingTable, server::NewAndWithDrawnRoutes};
+use bytes::{BufMut, Bytes};
+use hashbrown::HashMap;
+use interledger_packet::{
+    oer::{MutBufOerExt, OerReader},
+    ParseError,
+};
+use interledger_service::Account;
+use std::{fmt::Display, hash::Hash, str, str::FromStr};
+
+/// Incremented if the encoding of the saved state changes
+const MAX_ACCOUNT_ID_LEN: usize = 255;
+const STATE_VERSION: u8 = 2;
+
+/// The contents of a routing table, with the accounts replaced by their IDs
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TableState<I> {
+    pub id: [u8; 16],
+    pub epoch: u32,
+    pub routes: Vec<(I, Route)>,
+}
+
+impl<I> TableState<I>
+where
+    I: Display + FromStr + Eq + Hash + Copy,
+{
+    pub fn from_table<A: Account<AccountId = I>>(table: &RoutingTable<A>) -> Self {
+        TableState {
+            id: table.id(),
+            epoch: table.epoch(),
+            routes: table
+                .routes()
+                .map(|(account, route)| (account.id(), route.clone()))
+                .collect(),
+        }
+    }
+
+    /// Rebuild the routing table. Returns None if any of the accounts are missing
+    pub fn to_table<A: Account<AccountId = I>>(
+        &self,
+        accounts: &HashMap<I, A>,
+    ) -> Option<RoutingTable<A>> {
+        let mut table = RoutingTable::new(self.id);
+        table.set_epoch(self.epoch);
+        for (account_id, route) in self.routes.iter() {
+            let account = accounts.get(account_id)?;
+            table.set_route(route.prefix.clone(), account.clone(), route.clone());
+        }
+        Some(table)
+    }
+
+    fn write_to<B: BufMut>(&self, buf: &mut B) {
+        buf.put(&self.id[..]);
+        buf.put_u32_be(self.epoch);
+        buf.put_var_uint(self.routes.len() as u64);
+        for (account_id, route) in self.routes.iter() {
+            buf.put_var_octet_string(account_id.to_string().as_bytes());
+            route.write_to(buf);
+        }
+    }
+
+    fn try_from(data: &mut OerReader) -> Result<Self, ParseError> {
+        let mut id = [0; 16];
+        id.copy_from_slice(data.read_bytes(16, "table_id")?);
+        let epoch = data.read_u32("epoch")?;
+        let routes_len = data.read_var_uint("routes")?;
+        let mut routes = Vec::new();
+        for _i in 0..routes_len {
+            let account_id = read_account_id(data)?;
+            routes.push((account_id, Route::try_from(data)?));
+        }
+        Ok(TableState { id, epoch, routes })
+    }
+}
+
+/// A snapshot of the Route Manager's tables, used to describe them for debugging
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RoutingState<I> {
+    pub forwarding_table: TableState<I>,
+    /// The new and withdrawn routes, keyed by the last epoch of the forwarding table they
+    /// transition from
+    pub forwarding_table_updates: Vec<(u32, NewAndWithDrawnRoutes)>,
+    pub local_table: TableState<I>,
+    pub incoming_tables: Vec<(I, TableState<I>)>,
+}
+
+/// What the Route Manager needs to pick up where it left off after a restart: the current
+/// Forwarding, Local and Incoming Routing Tables, but not the updates that led to them.
+///
+/// This is saved to the store as an opaque blob using the same OER encoding as the CCP messages.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SavedRoutingState<I> {
+    pub forwarding_table: TableState<I>,
+    pub local_table: TableState<I>,
+    pub incoming_tables: Vec<(I, TableState<I>)>,
+}
+
+impl<I> SavedRoutingState<I>
+where
+    I: Display + FromStr + Eq + Hash + Copy,
+{
+    /// The IDs of all of the accounts referenced in the saved tables
+    pub fn account_ids(&self) -> Vec<I> {
+        let mut ids: Vec<I> = Vec::new();
+        let tables = Some(&self.forwarding_table)
+            .into_iter()
+            .chain(Some(&self.local_table))
+            .chain(self.incoming_tables.iter().map(|(_, table)| table));
+        for (account_id, _) in tables.flat_map(|table| table.routes.iter()) {
+            if !ids.contains(account_id) {
+                ids.push(*account_id);
+            }
+        }
+        for (account_id, _) in self.incoming_tables.iter() {
+            if !ids.contains(account_id) {
+                ids.push(*account_id);
+            }
+        }
+        ids
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = Vec::new();
+        buf.put_u8(STATE_VERSION);
+        self.forwarding_table.write_to(&mut buf);
+        self.local_table.write_to(&mut buf);
+        buf.put_var_uint(self.incoming_tables.len() as u64);
+        for (account_id, table) in self.incoming_tables.iter() {
+            buf.put_var_octet_string(account_id.to_string().as_bytes());
+            table.write_to(&mut buf);
+        }
+        Bytes::from(buf)
+    }
+
+    pub fn try_from(data: &[u8]) -> Result<Self, ParseError> {
+        // The state is only ever written by `to_bytes`, so anything else means it is corrupt
+        let data = &mut OerReader::strict(data);
+        let version = data.read_u8("version")?;
+        if version != STATE_VERSION {
+            return Err(ParseError::InvalidPacket(format!(
+                "Unknown routing state version: {}",
+                version
+            )));
+        }
+        let forwarding_table = TableState::try_from(data)?;
+        let local_table = TableState::try_from(data)?;
+
+        let incoming_len = data.read_var_uint("incoming_tables")?;
+        let mut incoming_tables = Vec::new();
+        for _i in 0..incoming_len {
+            let account_id = read_account_id(data)?;
+            incoming_tables.push((account_id, TableState::try_from(data)?));
+        }
+        data.finish("incoming_tables")?;
+
+        Ok(SavedRoutingState {
+            forwarding_table,
+            local_table,
+            incoming_tables,
+        })
+    }
+}
+
+fn read_account_id<I: FromStr>(data: &mut OerReader) -> Result<I, ParseError> {
+    let account_id = data.read_var_octet_string("account_id", MAX_ACCOUNT_ID_LEN)?;
+    str::from_utf8(account_id)
+        .ok()
+        .and_then(|account_id| I::from_str(account_id).ok())
+        .ok_or_else(|| ParseError::InvalidPacket("Invalid account ID".to_string()))
+}
+
+#[cfg(test)]
+mod routing_state {
+    use super::*;
+
+    fn route(prefix: &str) -> Route {
+        Route {
+            prefix: Bytes::from(prefix),
+            path: vec![Bytes::from("example.connector")],
+            auth: [7; 32],
+            props: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let state: SavedRoutingState<u64> = SavedRoutingState {
+            forwarding_table: TableState {
+                id: [1; 16],
+                epoch: 3,
+                routes: vec![(1, route("example.a")), (2, route("example.b"))],
+            },
+            local_table: TableState {
+                id: [2; 16],
+                epoch: 0,
+                routes: vec![(1, route("example.a"))],
+            },
+            incoming_tables: vec![(
+                2,
+                TableState {
+                    id: [3; 16],
+                    epoch: 9,
+                    routes: vec![(2, route("example.b"))],
+                },
+            )],
+        };
+        let bytes = state.to_bytes();
+        assert_eq!(SavedRoutingState::try_from(&bytes[..]).unwrap(), state);
+        assert_eq!(state.account_ids(), vec![1, 2]);
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        assert!(SavedRoutingState::<u64>::try_from(&[1, 0, 0, 0, 0][..]).is_err());
+    }
+}
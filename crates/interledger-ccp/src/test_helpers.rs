@@ -9,9 +9,10 @@ use futures::{
 use hashbrown::HashMap;
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::{
-    incoming_service_fn, outgoing_service_fn, BoxedIlpFuture, IncomingService, OutgoingRequest,
-    OutgoingService,
+    incoming_service_fn, outgoing_service_fn, AccountStore, BoxedIlpFuture, IncomingService,
+    OutgoingRequest, OutgoingService,
 };
+use interledger_service_util::ExchangeRateStore;
 use parking_lot::Mutex;
 use std::{iter::FromIterator, sync::Arc};
 
@@ -94,6 +95,8 @@ pub struct TestStore {
     pub local: HashMap<Bytes, TestAccount>,
     pub configured: HashMap<Bytes, TestAccount>,
     pub routes: Arc<Mutex<HashMap<Bytes, TestAccount>>>,
+    pub policies: Arc<Mutex<HashMap<u64, RoutePolicies>>>,
+    pub routing_state: Arc<Mutex<HashMap<Bytes, Bytes>>>,
 }
 
 impl TestStore {
@@ -102,6 +105,8 @@ impl TestStore {
             local: HashMap::new(),
             configured: HashMap::new(),
             routes: Arc::new(Mutex::new(HashMap::new())),
+            policies: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -113,6 +118,8 @@ impl TestStore {
             local: local,
             configured: configured,
             routes: Arc::new(Mutex::new(HashMap::new())),
+            policies: Arc::new(Mutex::new(HashMap::new())),
+            routing_state: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -123,15 +130,17 @@ impl RouteManagerStore for TestStore {
     fn get_local_and_configured_routes(
         &self,
     ) -> Box<
-        Future<Item = (HashMap<Bytes, TestAccount>, HashMap<Bytes, TestAccount>), Error = ()>
-            + Send,
+        Future<
+                Item = (HashMap<Bytes, TestAccount>, HashMap<Bytes, TestAccount>),
+                Error = StoreError,
+            > + Send,
     > {
         Box::new(ok((self.local.clone(), self.configured.clone())))
     }
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+    ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
         let mut accounts: Vec<TestAccount> = self
             .local
             .values()
@@ -144,13 +153,88 @@ impl RouteManagerStore for TestStore {
         Box::new(ok(accounts))
     }
 
-    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (Bytes, TestAccount)>,
     {
         *self.routes.lock() = HashMap::from_iter(routes.into_iter());
         Box::new(ok(()))
     }
+
+    fn get_route_policies(
+        &self,
+        account_id: u64,
+    ) -> Box<Future<Item = RoutePolicies, Error = StoreError> + Send> {
+        Box::new(ok(self
+            .policies
+            .lock()
+            .get(&account_id)
+            .cloned()
+            .unwrap_or_default()))
+    }
+
+    fn set_route_policies(
+        &self,
+        account_id: u64,
+        policies: RoutePolicies,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        self.policies.lock().insert(account_id, policies);
+        Box::new(ok(()))
+    }
+
+    fn save_routing_state(
+        &self,
+        ilp_address: &[u8],
+        state: Bytes,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        self.routing_state
+            .lock()
+            .insert(Bytes::from(ilp_address), state);
+        Box::new(ok(()))
+    }
+
+    fn load_routing_state(
+        &self,
+        ilp_address: &[u8],
+    ) -> Box<Future<Item = Option<Bytes>, Error = StoreError> + Send> {
+        Box::new(ok(self.routing_state.lock().get(ilp_address).cloned()))
+    }
+}
+
+impl AccountStore for TestStore {
+    type Account = TestAccount;
+
+    fn get_accounts(
+        &self,
+        account_ids: Vec<u64>,
+    ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
+        let routes = self.routes.lock();
+        let mut accounts = Vec::with_capacity(account_ids.len());
+        for id in account_ids {
+            let account = self
+                .local
+                .values()
+                .chain(self.configured.values())
+                .chain(routes.values())
+                .find(|account| account.id == id);
+            match account {
+                Some(account) => accounts.push(account.clone()),
+                None => {
+                    return Box::new(err(StoreError::NotFound(format!(
+                        "Account {} not found",
+                        id
+                    ))))
+                }
+            }
+        }
+        Box::new(ok(accounts))
+    }
+}
+
+impl ExchangeRateStore for TestStore {
+    fn get_exchange_rates(&self, asset_codes: &[&str]) -> Result<Vec<f64>, ()> {
+        Ok(vec![1.0; asset_codes.len()])
+    }
 }
 
 pub fn test_service() -> CcpRouteManager<
@@ -7,7 +7,7 @@
 extern crate log;
 
 use futures::Future;
-use interledger_service::Account;
+use interledger_service::{Account, StoreError};
 use url::Url;
 
 mod client;
@@ -28,8 +28,9 @@ pub trait HttpStore: Clone + Send + Sync + 'static {
 
     /// Load account details based on the full HTTP Authorization header
     /// received on the incoming HTTP request.
+    /// Errors with `StoreError::NotFound` if no account has that authorization.
     fn get_account_from_http_auth(
         &self,
         auth_header: &str,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send>;
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send>;
 }
@@ -16,6 +16,7 @@ use interledger_service::*;
 pub struct HttpServerService<S, T> {
     next: S,
     store: T,
+    strict_parsing: bool,
 }
 
 impl<S, T> HttpServerService<S, T>
@@ -24,7 +25,18 @@ where
     T: HttpStore,
 {
     pub fn new(next: S, store: T) -> Self {
-        HttpServerService { next, store }
+        HttpServerService {
+            next,
+            store,
+            strict_parsing: false,
+        }
+    }
+
+    /// Reject Prepare packets with non-canonical encodings or trailing bytes
+    /// instead of parsing them leniently.
+    pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
     }
 
     // TODO support certificate-based authentication
@@ -41,9 +53,22 @@ where
             Either::A(
                 self.store
                     .get_account_from_http_auth(&authorization)
-                    .map_err(move |_err| {
-                        error!("Authorization not found in the DB: {}", authorization);
-                        Response::builder().status(401).body(Body::empty()).unwrap()
+                    .map_err(move |err| {
+                        let status = match err {
+                            StoreError::NotFound(_) => {
+                                error!("Authorization not found in the DB: {}", authorization);
+                                401
+                            }
+                            StoreError::Unavailable(_) => {
+                                error!("Unable to check authorization because the store is unavailable: {}", err);
+                                503
+                            }
+                            _ => {
+                                error!("Error checking authorization: {}", err);
+                                500
+                            }
+                        };
+                        Response::builder().status(status).body(Body::empty()).unwrap()
                     }),
             )
         } else {
@@ -59,14 +84,18 @@ where
         request: Request<Body>,
     ) -> impl Future<Item = Response<Body>, Error = Error> {
         let mut next = self.next.clone();
+        let strict_parsing = self.strict_parsing;
         self.check_authorization(&request)
-            .and_then(|from_account| {
-                parse_prepare_from_request(request).and_then(move |prepare| {
+            .and_then(move |from_account| {
+                parse_prepare_from_request(request, strict_parsing).and_then(move |prepare| {
                     // Call the inner ILP service
-                    next.handle_request(IncomingRequest {
-                        from: from_account,
-                        prepare,
-                    })
+                    handle_request_in_span(
+                        &mut next,
+                        IncomingRequest {
+                            from: from_account,
+                            prepare,
+                        },
+                    )
                     .then(ilp_response_to_http_response)
                 })
             })
@@ -94,18 +123,23 @@ where
 
 fn parse_prepare_from_request(
     request: Request<Body>,
+    strict: bool,
 ) -> impl Future<Item = Prepare, Error = Response<Body>> + 'static {
     request
         .into_body()
         .concat2()
         .map_err(|_err| Response::builder().status(500).body(Body::empty()).unwrap())
-        .and_then(|body| {
+        .and_then(move |body| {
             let bytes = body.into_bytes().try_mut().unwrap_or_else(|bytes| {
                 debug!("Copying bytes from incoming HTTP request into Prepare packet");
                 BytesMut::from(bytes)
             });
-            Prepare::try_from(bytes)
-                .map_err(|_err| Response::builder().status(400).body(Body::empty()).unwrap())
+            let prepare = if strict {
+                Prepare::try_from_strict(bytes)
+            } else {
+                Prepare::try_from(bytes)
+            };
+            prepare.map_err(|_err| Response::builder().status(400).body(Body::empty()).unwrap())
         })
 }
 
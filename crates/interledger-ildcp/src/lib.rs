@@ -20,6 +20,9 @@ pub use packet::*;
 pub use server::IldcpService;
 
 pub trait IldcpAccount: Account {
+    /// The address assigned to this account. This may be empty for accounts
+    /// (such as the one for a node's parent) that learn their address via ILDCP,
+    /// so it is not an `Address` until it has been validated.
     fn client_address(&self) -> &[u8];
     fn asset_scale(&self) -> u8;
     fn asset_code(&self) -> &str;
@@ -2,14 +2,14 @@ use byteorder::ReadBytesExt;
 use bytes::{BufMut, Bytes, BytesMut};
 use interledger_packet::{
     oer::{predict_var_octet_string, BufOerExt, MutBufOerExt},
-    Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder,
+    Address, Fulfill, FulfillBuilder, ParseError, Prepare, PrepareBuilder,
 };
 use std::{
     fmt, str,
+    str::FromStr,
     time::{Duration, SystemTime},
 };
 
-static ILDCP_DESTINATION: &'static [u8] = b"peer.config";
 static PEER_PROTOCOL_FULFILLMENT: [u8; 32] = [0; 32];
 static PEER_PROTOCOL_CONDITION: [u8; 32] = [
     102, 104, 122, 173, 248, 98, 189, 119, 108, 143, 193, 139, 142, 159, 142, 32, 8, 151, 20, 133,
@@ -18,12 +18,13 @@ static PEER_PROTOCOL_CONDITION: [u8; 32] = [
 const ASSET_SCALE_LEN: usize = 1;
 
 lazy_static! {
+    static ref ILDCP_DESTINATION: Address = Address::from_str("peer.config").unwrap();
     static ref PEER_PROTOCOL_EXPIRY_DURATION: Duration = Duration::from_secs(60);
 }
 
 pub fn is_ildcp_request(prepare: &Prepare) -> bool {
     prepare.execution_condition() == PEER_PROTOCOL_CONDITION
-        && prepare.destination() == ILDCP_DESTINATION
+        && *prepare.destination() == *ILDCP_DESTINATION
 }
 
 #[derive(Debug, Default)]
@@ -36,7 +37,7 @@ impl IldcpRequest {
 
     pub fn to_prepare(&self) -> Prepare {
         PrepareBuilder {
-            destination: ILDCP_DESTINATION,
+            destination: &ILDCP_DESTINATION,
             amount: 0,
             execution_condition: &PEER_PROTOCOL_CONDITION,
             expires_at: SystemTime::now() + *PEER_PROTOCOL_EXPIRY_DURATION,
@@ -55,6 +56,7 @@ impl From<IldcpRequest> for Prepare {
 #[derive(Clone, PartialEq)]
 pub struct IldcpResponse {
     buffer: Bytes,
+    client_address: Address,
     asset_scale: u8,
     asset_code_offset: usize,
 }
@@ -80,7 +82,7 @@ impl IldcpResponse {
         let mut reader = &buffer[..];
         let buffer_len = reader.len();
 
-        reader.skip_var_octet_string()?;
+        let client_address = Address::try_from(buffer.slice_ref(reader.read_var_octet_string()?))?;
         let asset_scale = reader.read_u8()?;
 
         let asset_code_offset = buffer_len - reader.len();
@@ -88,13 +90,14 @@ impl IldcpResponse {
 
         Ok(IldcpResponse {
             buffer,
+            client_address,
             asset_scale,
             asset_code_offset,
         })
     }
 
-    pub fn client_address(&self) -> &[u8] {
-        (&self.buffer[..]).peek_var_octet_string().unwrap()
+    pub fn client_address(&self) -> &Address {
+        &self.client_address
     }
 
     pub fn asset_scale(&self) -> u8 {
@@ -113,7 +116,7 @@ impl fmt::Debug for IldcpResponse {
         write!(
             f,
             "IldcpResponse {{ client_address: \"{}\", asset_code: \"{}\", asset_scale: {} }}",
-            str::from_utf8(self.client_address()).unwrap_or("<not utf8>"),
+            self.client_address(),
             str::from_utf8(self.asset_code()).unwrap_or("<not utf8>"),
             self.asset_scale
         )
@@ -122,7 +125,7 @@ impl fmt::Debug for IldcpResponse {
 
 #[derive(Debug, PartialEq)]
 pub struct IldcpResponseBuilder<'a> {
-    pub client_address: &'a [u8],
+    pub client_address: &'a Address,
     pub asset_scale: u8,
     pub asset_code: &'a str,
 }
@@ -135,13 +138,14 @@ impl<'a> IldcpResponseBuilder<'a> {
         let mut buffer = BytesMut::with_capacity(buf_size);
 
         buffer.put_var_octet_string_length(self.client_address.len());
-        buffer.put_slice(self.client_address);
+        buffer.put_slice(&self.client_address[..]);
         buffer.put_u8(self.asset_scale);
         buffer.put_var_octet_string_length(self.asset_code.len());
         buffer.put_slice(self.asset_code.as_bytes());
 
         IldcpResponse {
             buffer: buffer.freeze(),
+            client_address: self.client_address.clone(),
             asset_scale: self.asset_scale,
             asset_code_offset: address_size + ASSET_SCALE_LEN,
         }
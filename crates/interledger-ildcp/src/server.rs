@@ -1,6 +1,7 @@
 use super::packet::*;
 use super::IldcpAccount;
-use futures::future::ok;
+use bytes::Bytes;
+use futures::future::{err, ok};
 use interledger_packet::*;
 use interledger_service::*;
 use std::{marker::PhantomData, str};
@@ -35,14 +36,32 @@ where
 
     fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
         if is_ildcp_request(&request.prepare) {
+            let client_address = match Address::try_from(Bytes::from(request.from.client_address()))
+            {
+                Ok(address) => address,
+                Err(error) => {
+                    error!(
+                        "Cannot respond to ILDCP request, account has invalid address \"{}\": {}",
+                        str::from_utf8(request.from.client_address()).unwrap_or("<not utf8>"),
+                        error
+                    );
+                    return Box::new(err(RejectBuilder {
+                        code: ErrorCode::T00_INTERNAL_ERROR,
+                        message: b"Account has an invalid ILP address",
+                        triggered_by: &[],
+                        data: &[],
+                    }
+                    .build()));
+                }
+            };
             let builder = IldcpResponseBuilder {
-                client_address: &request.from.client_address(),
+                client_address: &client_address,
                 asset_code: request.from.asset_code(),
                 asset_scale: request.from.asset_scale(),
             };
             debug!(
-                "Responding to query for ILDCP info by account: {:?}",
-                str::from_utf8(&request.from.client_address()[..]).unwrap_or("<not utf8>")
+                "Responding to query for ILDCP info by account: {}",
+                client_address
             );
             let response = builder.build();
             let fulfill = Fulfill::from(response);
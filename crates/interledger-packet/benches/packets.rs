@@ -4,12 +4,14 @@ use bytes::BytesMut;
 use chrono::{DateTime, Utc};
 use criterion::{criterion_group, criterion_main, Criterion};
 use lazy_static::lazy_static;
+use std::str::FromStr;
 
-use ilp::{ErrorCode, Fulfill, Prepare, Reject};
+use ilp::{Address, ErrorCode, Fulfill, Prepare, Reject};
 use ilp::{FulfillBuilder, PrepareBuilder, RejectBuilder};
 use interledger_packet as ilp;
 
 lazy_static! {
+    static ref DESTINATION: Address = Address::from_str("example.alice").unwrap();
     static ref PREPARE: PrepareBuilder<'static> = PrepareBuilder {
         amount: 107,
         expires_at: DateTime::parse_from_rfc3339("2017-12-23T01:21:40.549Z")
@@ -20,7 +22,7 @@ lazy_static! {
             \x74\xe1\x13\x6d\xc7\x1c\x9e\x5f\x28\x3b\xec\x83\x46\x1c\xbf\x12\
             \x61\xc4\x01\x4f\x72\xd4\x8f\x8d\xd6\x54\x53\xa0\xb8\x4e\x7d\xe1\
         ",
-        destination: b"example.alice",
+        destination: &DESTINATION,
         data: b"\
             \x5d\xb3\x43\xfd\xc4\x18\x98\xf6\xdf\x42\x02\x32\x91\x39\xdc\x24\
             \x2d\xd0\xf5\x58\xa8\x11\xb4\x6b\x28\x91\x8f\xda\xb3\x7c\x6c\xb0\
@@ -0,0 +1,302 @@
+//! ILP addresses, as defined in [RFC 15](https://github.com/interledger/rfcs/blob/master/0015-ilp-addresses/0015-ilp-addresses.md).
+
+use std::{fmt, ops::Deref, str, str::FromStr};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use quick_error::quick_error;
+
+/// The maximum length of an ILP address, in bytes
+pub const MAX_ADDRESS_LENGTH: usize = 1023;
+
+static SCHEMES: &[&[u8]] = &[
+    b"g", b"private", b"example", b"peer", b"self", b"test", b"test1", b"test2", b"test3", b"local",
+];
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum AddressError {
+        InvalidLength(length: usize) {
+            description("invalid address length")
+            display("Address is {} bytes long, it must be between 1 and {} bytes", length, MAX_ADDRESS_LENGTH)
+        }
+        InvalidScheme(scheme: String) {
+            description("invalid allocation scheme")
+            display("Invalid allocation scheme: \"{}\"", scheme)
+        }
+        MissingSegments {
+            description("address has no segments after the allocation scheme")
+        }
+        InvalidSegment(index: usize) {
+            description("invalid address segment")
+            display("Segment {} is empty or contains characters other than A-Z, a-z, 0-9, _, ~ and -", index)
+        }
+    }
+}
+
+fn is_segment_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'~' || byte == b'-'
+}
+
+fn validate(address: &[u8]) -> Result<(), AddressError> {
+    if address.is_empty() || address.len() > MAX_ADDRESS_LENGTH {
+        return Err(AddressError::InvalidLength(address.len()));
+    }
+
+    let mut segments = address.split(|b| *b == b'.');
+    // split always returns at least one item
+    let scheme = segments.next().unwrap();
+    if !SCHEMES.contains(&scheme) {
+        return Err(AddressError::InvalidScheme(
+            String::from_utf8_lossy(scheme).to_string(),
+        ));
+    }
+
+    let mut count = 0;
+    for (index, segment) in segments.enumerate() {
+        if segment.is_empty() || !segment.iter().cloned().all(is_segment_char) {
+            return Err(AddressError::InvalidSegment(index + 1));
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Err(AddressError::MissingSegments);
+    }
+    Ok(())
+}
+
+/// Check that the bytes are a valid prefix of ILP addresses, as used for routing.
+///
+/// A prefix is either a full address or an allocation scheme on its own (like `g`),
+/// and either may end with a period.
+pub fn validate_prefix(prefix: &[u8]) -> Result<(), AddressError> {
+    let without_period = if prefix.len() > 1 && prefix.ends_with(b".") {
+        &prefix[..prefix.len() - 1]
+    } else {
+        prefix
+    };
+    match validate(without_period) {
+        Err(AddressError::MissingSegments) => Ok(()),
+        result => result,
+    }
+}
+
+/// A validated ILP address, such as `g.us.bank.alice`.
+///
+/// Addresses start with an allocation scheme followed by one or more segments,
+/// all separated by periods. Segments may only contain alphanumeric characters,
+/// `_`, `~` and `-`, and the whole address may be at most 1023 bytes long.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(Bytes);
+
+impl Address {
+    // TODO change this to `TryFrom` when it is stabilized
+    pub fn try_from(bytes: Bytes) -> Result<Self, AddressError> {
+        validate(&bytes[..])?;
+        Ok(Address(bytes))
+    }
+
+    /// Used for bytes that are already known to be a valid address
+    pub(crate) fn new_unchecked(bytes: Bytes) -> Self {
+        Address(bytes)
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // Validated addresses only contain ASCII characters
+        str::from_utf8(&self.0[..]).unwrap()
+    }
+
+    #[inline]
+    pub fn to_bytes(&self) -> Bytes {
+        self.0.clone()
+    }
+
+    /// The allocation scheme, which is the first segment of the address
+    pub fn scheme(&self) -> &str {
+        self.segments().next().unwrap()
+    }
+
+    /// Iterate over the period-separated segments of the address, starting with the scheme
+    pub fn segments(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.as_str().split('.')
+    }
+
+    /// Whether this address is the given prefix or lies underneath it.
+    /// Unlike a plain byte comparison, `g.alice` does not have the prefix `g.al`.
+    pub fn has_prefix(&self, prefix: &[u8]) -> bool {
+        if !self.0.starts_with(prefix) {
+            return false;
+        }
+        self.0.len() == prefix.len() || prefix.ends_with(b".") || self.0[prefix.len()] == b'.'
+    }
+
+    /// The address one level below this one, with the given segments appended
+    pub fn with_suffix(&self, suffix: &[u8]) -> Result<Address, AddressError> {
+        let mut child = BytesMut::with_capacity(self.0.len() + 1 + suffix.len());
+        child.put_slice(&self.0[..]);
+        child.put_u8(b'.');
+        child.put_slice(suffix);
+        Address::try_from(child.freeze())
+    }
+}
+
+impl Deref for Address {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl From<Address> for Bytes {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Address::try_from(Bytes::from(s))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Address({})", self.as_str())
+    }
+}
+
+impl PartialEq<[u8]> for Address {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == other
+    }
+}
+
+impl<'a> PartialEq<&'a [u8]> for Address {
+    fn eq(&self, other: &&'a [u8]) -> bool {
+        self.0 == *other
+    }
+}
+
+#[cfg(test)]
+mod test_address {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_addresses() {
+        for address in &[
+            "g.us.bank.alice",
+            "test.a-b_c~d",
+            "private.0",
+            "peer.config",
+            "example.alice",
+        ] {
+            assert_eq!(Address::from_str(address).unwrap().as_str(), *address);
+        }
+        let long = format!("g.{}", "a".repeat(MAX_ADDRESS_LENGTH - 2));
+        assert!(Address::from_str(&long).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert_eq!(Address::from_str(""), Err(AddressError::InvalidLength(0)));
+        let long = format!("g.{}", "a".repeat(MAX_ADDRESS_LENGTH - 1));
+        assert_eq!(
+            Address::from_str(&long),
+            Err(AddressError::InvalidLength(MAX_ADDRESS_LENGTH + 1))
+        );
+        assert_eq!(
+            Address::from_str("h.alice"),
+            Err(AddressError::InvalidScheme("h".to_string()))
+        );
+        assert_eq!(Address::from_str("g"), Err(AddressError::MissingSegments));
+        assert_eq!(
+            Address::from_str("g.alice..bob"),
+            Err(AddressError::InvalidSegment(2))
+        );
+        assert_eq!(
+            Address::from_str("g.alice."),
+            Err(AddressError::InvalidSegment(2))
+        );
+        assert_eq!(
+            Address::from_str("g.al ice"),
+            Err(AddressError::InvalidSegment(1))
+        );
+        assert_eq!(
+            Address::try_from(Bytes::from(&b"g.\xc3\xa9"[..])),
+            Err(AddressError::InvalidSegment(1))
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let address = Address::from_str("g.us.bank").unwrap();
+        assert_eq!(address.scheme(), "g");
+        assert_eq!(
+            address.segments().collect::<Vec<_>>(),
+            vec!["g", "us", "bank"]
+        );
+        assert_eq!(address.segments().next_back(), Some("bank"));
+    }
+
+    #[test]
+    fn prefixes() {
+        let address = Address::from_str("g.alice.bob").unwrap();
+        assert!(address.has_prefix(b"g.alice"));
+        assert!(address.has_prefix(b"g.alice."));
+        assert!(address.has_prefix(b"g.alice.bob"));
+        assert!(!address.has_prefix(b"g.al"));
+        assert!(!address.has_prefix(b"g.alice.bob.carl"));
+    }
+
+    #[test]
+    fn validates_prefixes() {
+        for prefix in &["g", "g.", "example.alice", "example.alice.", "test.a-b"] {
+            assert_eq!(validate_prefix(prefix.as_bytes()), Ok(()));
+        }
+        assert_eq!(validate_prefix(b""), Err(AddressError::InvalidLength(0)));
+        assert_eq!(
+            validate_prefix(b"."),
+            Err(AddressError::InvalidScheme(String::new()))
+        );
+        assert_eq!(
+            validate_prefix(b"gg"),
+            Err(AddressError::InvalidScheme("gg".to_string()))
+        );
+        assert_eq!(
+            validate_prefix(b"g.alice.."),
+            Err(AddressError::InvalidSegment(2))
+        );
+    }
+
+    #[test]
+    fn children() {
+        let address = Address::from_str("g.alice").unwrap();
+        assert_eq!(
+            address.with_suffix(b"bob").unwrap(),
+            Address::from_str("g.alice.bob").unwrap()
+        );
+        assert_eq!(
+            address.with_suffix(b"bob.carl").unwrap().as_str(),
+            "g.alice.bob.carl"
+        );
+        assert_eq!(
+            address.with_suffix(b"b b"),
+            Err(AddressError::InvalidSegment(2))
+        );
+    }
+}
@@ -3,6 +3,8 @@ use std::string::FromUtf8Error;
 
 use quick_error::quick_error;
 
+use crate::AddressError;
+
 quick_error! {
     #[derive(Debug)]
     pub enum ParseError {
@@ -26,6 +28,28 @@ quick_error! {
             description(err.description())
             cause(err)
         }
+        InvalidAddress(err: AddressError) {
+            from()
+            description(err.description())
+            display("Invalid Address {}", err)
+            cause(err)
+        }
+        UnexpectedEof(field: &'static str, offset: usize) {
+            description("buffer ended before the end of a field")
+            display("Buffer ended while reading {} at offset {}", field, offset)
+        }
+        NonCanonical(field: &'static str, offset: usize) {
+            description("non-canonical OER encoding")
+            display("Non-canonical encoding of {} at offset {}", field, offset)
+        }
+        FieldTooLong(field: &'static str, offset: usize, max_length: usize) {
+            description("field is longer than the maximum length")
+            display("{} at offset {} is longer than the maximum of {} bytes", field, offset, max_length)
+        }
+        TrailingBytes(field: &'static str, offset: usize) {
+            description("unexpected bytes after the end of a field")
+            display("Unexpected bytes after {} at offset {}", field, offset)
+        }
         WrongType(descr: String) {
             description(descr)
             display("Wrong Type {}", descr)
@@ -34,10 +58,10 @@ quick_error! {
             description(descr)
             display("Invalid Packet {}", descr)
         }
-        Other(err: Box<std::error::Error>) {
+        Other(err: Box<dyn std::error::Error>) {
             cause(&**err)
             description(err.description())
-            display("Error {}", err.description())
+            display("Error {}", err)
         }
     }
 }
@@ -1,21 +1,22 @@
 //! ILP packet test data.
 
+use std::str::FromStr;
 use std::time::SystemTime;
 
 use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 
-use super::ErrorCode;
+use super::{Address, ErrorCode};
 use super::{Fulfill, FulfillBuilder, Prepare, PrepareBuilder, Reject, RejectBuilder};
 
 lazy_static! {
     pub static ref PREPARE: Prepare = PREPARE_BUILDER.build();
     pub static ref PREPARE_BUILDER: PrepareBuilder<'static> = PrepareBuilder {
         amount: 107,
-        destination: b"example.alice",
+        destination: &DESTINATION,
         expires_at: *EXPIRES_AT,
         execution_condition: &EXECUTION_CONDITION,
-        data: &DATA,
+        data: DATA,
     };
     pub static ref PREPARE_BYTES: &'static [u8] = b"\
         \x0c\x82\x01\x4b\x00\x00\x00\x00\x00\x00\x00\x6b\x32\x30\x31\x38\x30\x36\
@@ -38,6 +39,7 @@ lazy_static! {
         \x4b\xe9\x02\x19\x70\x9d\x0c\x26\xd3\xb5\xf4\xad\x87\x9d\x84\x94\xbb\x3a\
         \xeb\xfe\x61\x2e\xc5\x40\x41\xe4\xa3\x80\xf0\
     ";
+    pub static ref DESTINATION: Address = Address::from_str("example.alice").unwrap();
     pub static ref EXPIRES_AT: SystemTime =
         DateTime::parse_from_rfc3339("2018-06-07T20:48:42.483Z")
             .unwrap()
@@ -54,7 +56,7 @@ lazy_static! {
     pub static ref FULFILL: Fulfill = FULFILL_BUILDER.build();
     pub static ref FULFILL_BUILDER: FulfillBuilder<'static> = FulfillBuilder {
         fulfillment: &FULFILLMENT,
-        data: &DATA,
+        data: DATA,
     };
     pub static ref FULFILL_BYTES: &'static [u8] = b"\
         \x0d\x82\x01\x24\x11\x7b\x43\x4f\x1a\x54\xe9\x04\x4f\x4f\x54\x92\x3b\x2c\
@@ -88,7 +90,7 @@ lazy_static! {
         code: ErrorCode::F99_APPLICATION_ERROR,
         message: b"Some error",
         triggered_by: b"example.connector",
-        data: &DATA,
+        data: DATA,
     };
     pub static ref REJECT_BYTES: &'static [u8] = b"\
         \x0e\x82\x01\x24\x46\x39\x39\x11\x65\x78\x61\x6d\x70\x6c\x65\x2e\x63\x6f\
@@ -111,7 +113,7 @@ lazy_static! {
     ";
 }
 
-pub static DATA: &'static [u8] = b"\
+pub static DATA: &[u8] = b"\
     \x6c\x99\xf6\xa9\x69\x47\x30\x28\xef\x46\xe0\x9b\x47\x15\x81\xc9\x15\xb6\
     \xd5\x49\x63\x29\xc1\xe3\xa1\xc2\x74\x8d\x74\x22\xa7\xbd\xcc\x79\x8e\x28\
     \x6c\xab\xe3\x19\x7c\xcc\xfc\x21\x3e\x93\x0b\x8d\xba\x57\xc7\xab\xdf\x2d\
@@ -2,6 +2,7 @@
 //!
 //! Interledger packet serialization/deserialization.
 
+mod address;
 mod error;
 mod errors;
 #[cfg(test)]
@@ -9,6 +10,7 @@ mod fixtures;
 pub mod oer;
 mod packet;
 
+pub use self::address::{validate_prefix, Address, AddressError, MAX_ADDRESS_LENGTH};
 pub use self::error::{ErrorClass, ErrorCode};
 pub use self::errors::ParseError;
 
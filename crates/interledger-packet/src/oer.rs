@@ -1,12 +1,15 @@
 use std::io::{Error, ErrorKind, Result};
-use std::u64;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use bytes::{Buf, BufMut, BytesMut, IntoBuf};
 
+use crate::ParseError;
+
 const HIGH_BIT: u8 = 0x80;
 const LOWER_SEVEN_BITS: u8 = 0x7f;
 
+type ReaderResult<T> = std::result::Result<T, ParseError>;
+
 /// Returns the size (in bytes) of the buffer that encodes a VarOctetString of
 /// `length` bytes.
 pub fn predict_var_octet_string(length: usize) -> usize {
@@ -96,7 +99,7 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
         let length = self.read_u8()?;
         if length & HIGH_BIT != 0 {
             let length_prefix_length = (length & LOWER_SEVEN_BITS) as usize;
-            // TODO check for canonical length
+            // Non-canonical lengths are accepted here, use `OerReader::strict` to reject them
             Ok(self.read_uint::<BigEndian>(length_prefix_length)? as usize)
         } else {
             Ok(length as usize)
@@ -115,6 +118,178 @@ impl<'a> BufOerExt<'a> for &'a [u8] {
     }
 }
 
+/// Reads OER-encoded fields, checking each one against a maximum length.
+///
+/// Unlike the `BufOerExt` methods, the errors name the field that could not be read
+/// and its offset from the start of the outermost buffer. A reader created with
+/// `OerReader::strict` also rejects anything that is not canonically encoded.
+#[derive(Clone, Debug)]
+pub struct OerReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+    end: usize,
+    strict: bool,
+}
+
+impl<'a> OerReader<'a> {
+    /// Creates a reader that accepts non-canonical lengths and VarUInts and ignores
+    /// trailing bytes, like the `BufOerExt` methods
+    pub fn new(buffer: &'a [u8]) -> Self {
+        OerReader {
+            buffer,
+            position: 0,
+            end: buffer.len(),
+            strict: false,
+        }
+    }
+
+    /// Creates a reader that rejects non-canonical lengths and VarUInts and trailing bytes
+    pub fn strict(buffer: &'a [u8]) -> Self {
+        OerReader {
+            strict: true,
+            ..OerReader::new(buffer)
+        }
+    }
+
+    #[inline]
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// The offset of the next field from the start of the buffer
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.end - self.position
+    }
+
+    pub fn read_bytes(&mut self, length: usize, field: &'static str) -> ReaderResult<&'a [u8]> {
+        if self.remaining() < length {
+            return Err(ParseError::UnexpectedEof(field, self.position));
+        }
+        let bytes = &self.buffer[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self, field: &'static str) -> ReaderResult<u8> {
+        Ok(self.read_bytes(1, field)?[0])
+    }
+
+    pub fn read_u16(&mut self, field: &'static str) -> ReaderResult<u16> {
+        Ok(BigEndian::read_u16(self.read_bytes(2, field)?))
+    }
+
+    pub fn read_u32(&mut self, field: &'static str) -> ReaderResult<u32> {
+        Ok(BigEndian::read_u32(self.read_bytes(4, field)?))
+    }
+
+    pub fn read_u64(&mut self, field: &'static str) -> ReaderResult<u64> {
+        Ok(BigEndian::read_u64(self.read_bytes(8, field)?))
+    }
+
+    /// Reads the length prefix of a VarOctetString, which must be at most `max_length`.
+    /// In strict mode, lengths under 128 must use the short form and long-form lengths
+    /// must not have leading zero bytes.
+    pub fn read_var_octet_string_length(
+        &mut self,
+        field: &'static str,
+        max_length: usize,
+    ) -> ReaderResult<usize> {
+        let offset = self.position;
+        let length = self.read_u8(field)?;
+        let length = if length & HIGH_BIT == 0 {
+            u64::from(length)
+        } else {
+            let length_of_length = (length & LOWER_SEVEN_BITS) as usize;
+            if length_of_length == 0 {
+                return Err(ParseError::NonCanonical(field, offset));
+            }
+            if length_of_length > 8 {
+                return Err(ParseError::FieldTooLong(field, offset, max_length));
+            }
+            let length =
+                BigEndian::read_uint(self.read_bytes(length_of_length, field)?, length_of_length);
+            if self.strict && (length < 128 || predict_var_uint_size(length) != length_of_length) {
+                return Err(ParseError::NonCanonical(field, offset));
+            }
+            length
+        };
+        if length > max_length as u64 {
+            return Err(ParseError::FieldTooLong(field, offset, max_length));
+        }
+        Ok(length as usize)
+    }
+
+    /// Reads a VarOctetString of at most `max_length` bytes
+    pub fn read_var_octet_string(
+        &mut self,
+        field: &'static str,
+        max_length: usize,
+    ) -> ReaderResult<&'a [u8]> {
+        let offset = self.position;
+        let length = self.read_var_octet_string_length(field, max_length)?;
+        self.read_bytes(length, field)
+            .map_err(|_| ParseError::UnexpectedEof(field, offset))
+    }
+
+    /// Reads a VarOctetString and returns a reader over its contents,
+    /// which reports offsets relative to the start of this reader's buffer
+    pub fn read_var_octet_string_reader(
+        &mut self,
+        field: &'static str,
+        max_length: usize,
+    ) -> ReaderResult<OerReader<'a>> {
+        let offset = self.position;
+        let length = self.read_var_octet_string_length(field, max_length)?;
+        self.read_reader(length, field)
+            .map_err(|_| ParseError::UnexpectedEof(field, offset))
+    }
+
+    /// Reads `length` bytes and returns a reader over them,
+    /// which reports offsets relative to the start of this reader's buffer
+    pub fn read_reader(
+        &mut self,
+        length: usize,
+        field: &'static str,
+    ) -> ReaderResult<OerReader<'a>> {
+        self.read_bytes(length, field)?;
+        Ok(OerReader {
+            buffer: self.buffer,
+            position: self.position - length,
+            end: self.position,
+            strict: self.strict,
+        })
+    }
+
+    /// Reads a VarUInt of at most 8 bytes, which must not have leading zero bytes in strict mode
+    pub fn read_var_uint(&mut self, field: &'static str) -> ReaderResult<u64> {
+        let offset = self.position;
+        let size = self.read_var_octet_string_length(field, 8)?;
+        if size == 0 {
+            return Err(ParseError::NonCanonical(field, offset));
+        }
+        let value = BigEndian::read_uint(self.read_bytes(size, field)?, size);
+        if self.strict && predict_var_uint_size(value) != size {
+            return Err(ParseError::NonCanonical(field, offset));
+        }
+        Ok(value)
+    }
+
+    /// Returns an error in strict mode if there are any bytes left after the given field
+    pub fn finish(&self, field: &'static str) -> ReaderResult<()> {
+        if self.strict && self.position < self.end {
+            Err(ParseError::TrailingBytes(field, self.position))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 pub trait MutBufOerExt: BufMut + Sized {
     /// Encodes bytes as variable-length octet encoded string and puts it into `Buf`.
     #[inline]
@@ -326,6 +501,183 @@ mod test_buf_oer_ext {
     }
 }
 
+#[cfg(test)]
+mod test_oer_reader {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn read_var_octet_string(buffer: &[u8]) -> ReaderResult<&[u8]> {
+        OerReader::strict(buffer).read_var_octet_string("test", 4096)
+    }
+
+    fn read_var_uint(buffer: &[u8]) -> ReaderResult<u64> {
+        OerReader::strict(buffer).read_var_uint("test")
+    }
+
+    #[test]
+    fn reads_canonical_var_octet_strings() {
+        assert_eq!(read_var_octet_string(&[0x00]).unwrap(), &[]);
+        assert_eq!(read_var_octet_string(&[0x02, 0x01, 0x02]).unwrap(), &[1, 2]);
+        let mut long = vec![0x81, 0x80];
+        long.extend(&[0x00; 128][..]);
+        assert_eq!(read_var_octet_string(&long).unwrap(), &[0; 128][..]);
+    }
+
+    #[test]
+    fn rejects_non_canonical_lengths() {
+        // Short lengths in the long form
+        let mut short = vec![0x81, 0x01, 0xff];
+        match read_var_octet_string(&short) {
+            Err(ParseError::NonCanonical("test", 0)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // Long lengths with leading zeros
+        short.splice(0..2, vec![0x82, 0x00, 0x80]);
+        short.extend(&[0x00; 127][..]);
+        match read_var_octet_string(&short) {
+            Err(ParseError::NonCanonical("test", 0)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // Long form without any length bytes
+        match read_var_octet_string(&[0x80]) {
+            Err(ParseError::NonCanonical("test", 0)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_fields() {
+        let mut too_long = vec![0x82, 0x10, 0x01];
+        too_long.extend(&[0x00; 4097][..]);
+        match read_var_octet_string(&too_long) {
+            Err(ParseError::FieldTooLong("test", 0, 4096)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match read_var_octet_string(&[0x89, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]) {
+            Err(ParseError::FieldTooLong("test", 0, 4096)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match read_var_uint(&[0x09, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]) {
+            Err(ParseError::FieldTooLong("test", 0, 8)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical_var_uints() {
+        assert_eq!(read_var_uint(&[0x01, 0x00]).unwrap(), 0);
+        assert_eq!(read_var_uint(&[0x02, 0x01, 0x00]).unwrap(), 256);
+        match read_var_uint(&[0x00]) {
+            Err(ParseError::NonCanonical("test", 0)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match read_var_uint(&[0x02, 0x00, 0x01]) {
+            Err(ParseError::NonCanonical("test", 0)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_offsets_of_nested_fields() {
+        let buffer = [0xaa, 0x05, 0x01, 0x02, 0x02, 0x00, 0x01];
+        let mut reader = OerReader::strict(&buffer[..]);
+        reader.read_u8("first").unwrap();
+        let mut nested = reader.read_var_octet_string_reader("nested", 100).unwrap();
+        assert_eq!(nested.read_var_uint("value").unwrap(), 2);
+        assert_eq!(nested.offset(), 4);
+        match nested.read_var_uint("other") {
+            Err(ParseError::NonCanonical("other", 4)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(reader.finish("nested").is_ok());
+    }
+
+    #[test]
+    fn lenient_reader_accepts_non_canonical_encodings() {
+        let mut reader = OerReader::new(&[0x81, 0x01, 0xff, 0x02, 0x00, 0x01, 0xff][..]);
+        assert_eq!(reader.read_var_octet_string("test", 4096).unwrap(), &[0xff]);
+        assert_eq!(reader.read_var_uint("test").unwrap(), 1);
+        assert!(reader.finish("test").is_ok());
+
+        // Field lengths are still checked
+        match OerReader::new(&[0x82, 0x10, 0x01][..]).read_var_octet_string("test", 4096) {
+            Err(ParseError::FieldTooLong("test", 0, 4096)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn nested_readers_inherit_strictness() {
+        let buffer = [0x04, 0x81, 0x01, 0xff, 0x00];
+        let mut nested = OerReader::new(&buffer[..])
+            .read_var_octet_string_reader("outer", 16)
+            .unwrap();
+        assert!(!nested.is_strict());
+        assert_eq!(nested.read_var_octet_string("inner", 16).unwrap(), &[0xff]);
+
+        let mut nested = OerReader::strict(&buffer[..])
+            .read_var_octet_string_reader("outer", 16)
+            .unwrap();
+        assert!(nested.is_strict());
+        match nested.read_var_octet_string("inner", 16) {
+            Err(ParseError::NonCanonical("inner", 1)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut reader = OerReader::strict(&[0x01, 0x01, 0xff][..]);
+        reader.read_var_uint("value").unwrap();
+        match reader.finish("value") {
+            Err(ParseError::TrailingBytes("value", 2)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn var_octet_strings_round_trip(bytes in prop::collection::vec(any::<u8>(), 0..2000)) {
+            let mut buffer = Vec::new();
+            buffer.put_var_octet_string(&bytes[..]);
+            let mut reader = OerReader::strict(&buffer[..]);
+            prop_assert_eq!(reader.read_var_octet_string("test", 2000).unwrap(), &bytes[..]);
+            prop_assert!(reader.finish("test").is_ok());
+        }
+
+        #[test]
+        fn var_uints_round_trip(value in any::<u64>()) {
+            let mut buffer = Vec::new();
+            buffer.put_var_uint(value);
+            let mut reader = OerReader::strict(&buffer[..]);
+            prop_assert_eq!(reader.read_var_uint("test").unwrap(), value);
+            prop_assert!(reader.finish("test").is_ok());
+        }
+
+        // Anything the strict reader accepts must be the canonical encoding of what it read
+        #[test]
+        fn only_accepts_canonical_var_uints(buffer in prop::collection::vec(any::<u8>(), 1..10)) {
+            let mut reader = OerReader::strict(&buffer[..]);
+            if let Ok(value) = reader.read_var_uint("test") {
+                let mut encoded = Vec::new();
+                encoded.put_var_uint(value);
+                prop_assert_eq!(&encoded[..], &buffer[..reader.offset()]);
+            }
+        }
+
+        #[test]
+        fn only_accepts_canonical_lengths(buffer in prop::collection::vec(any::<u8>(), 1..10)) {
+            let mut reader = OerReader::strict(&buffer[..]);
+            if let Ok(length) = reader.read_var_octet_string_length("test", usize::MAX) {
+                let mut encoded = Vec::new();
+                encoded.put_var_octet_string_length(length);
+                prop_assert_eq!(&encoded[..], &buffer[..reader.offset()]);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod buf_mut_oer_ext {
     use super::*;
@@ -335,7 +687,7 @@ mod buf_mut_oer_ext {
         let long_varstr = &[0x00; 256][..];
         let long_buffer = {
             let mut buffer = vec![0x82, 0x01, 0x00];
-            buffer.extend_from_slice(&long_varstr);
+            buffer.extend_from_slice(long_varstr);
             buffer
         };
 
@@ -1,4 +1,3 @@
-use hex;
 use std::fmt;
 use std::io::prelude::*;
 use std::io::Cursor;
@@ -6,19 +5,21 @@ use std::str;
 use std::time::SystemTime;
 
 use byteorder::{BigEndian, ReadBytesExt};
-use bytes::{BufMut, BytesMut};
-use chrono::{DateTime, TimeZone, Utc};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 
-use super::oer::{self, BufOerExt, MutBufOerExt};
-use super::{ErrorCode, ParseError};
+use super::oer::{self, BufOerExt, MutBufOerExt, OerReader};
+use super::{Address, ErrorCode, ParseError, MAX_ADDRESS_LENGTH};
 
 const AMOUNT_LEN: usize = 8;
 const EXPIRY_LEN: usize = 17;
 const CONDITION_LEN: usize = 32;
 const FULFILLMENT_LEN: usize = 32;
 const ERROR_CODE_LEN: usize = 3;
+const MAX_DATA_LEN: usize = 32767;
+const MAX_MESSAGE_LEN: usize = 8191;
 
-static INTERLEDGER_TIMESTAMP_FORMAT: &'static str = "%Y%m%d%H%M%S%3f";
+static INTERLEDGER_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S%3f";
 
 // TODO TryFrom([u8])
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -53,10 +54,19 @@ pub enum Packet {
 
 impl Packet {
     pub fn try_from(buffer: BytesMut) -> Result<Self, ParseError> {
+        Packet::parse(buffer, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(buffer: BytesMut) -> Result<Self, ParseError> {
+        Packet::parse(buffer, true)
+    }
+
+    fn parse(buffer: BytesMut, strict: bool) -> Result<Self, ParseError> {
         match buffer.first() {
-            Some(&12) => Ok(Packet::Prepare(Prepare::try_from(buffer)?)),
-            Some(&13) => Ok(Packet::Fulfill(Fulfill::try_from(buffer)?)),
-            Some(&14) => Ok(Packet::Reject(Reject::try_from(buffer)?)),
+            Some(&12) => Ok(Packet::Prepare(Prepare::parse(buffer, strict)?)),
+            Some(&13) => Ok(Packet::Fulfill(Fulfill::parse(buffer, strict)?)),
+            Some(&14) => Ok(Packet::Reject(Reject::parse(buffer, strict)?)),
             _ => Err(ParseError::InvalidPacket(format!(
                 "Unknown packet type: {:?}",
                 buffer.first(),
@@ -95,10 +105,13 @@ impl From<Reject> for Packet {
 
 #[derive(PartialEq, Clone)]
 pub struct Prepare {
-    buffer: BytesMut,
+    /// Frozen so that the destination can be a view into it rather than a copy
+    /// (unless it is short enough to be stored inline)
+    buffer: Bytes,
     content_offset: usize,
     amount: u64,
     expires_at: SystemTime,
+    destination: Address,
     data_offset: usize,
 }
 
@@ -107,38 +120,47 @@ pub struct PrepareBuilder<'a> {
     pub amount: u64,
     pub expires_at: SystemTime,
     pub execution_condition: &'a [u8; 32],
-    pub destination: &'a [u8],
+    pub destination: &'a Address,
     pub data: &'a [u8],
 }
 
 impl Prepare {
     // TODO change this to `TryFrom` when it is stabilized
     pub fn try_from(buffer: BytesMut) -> Result<Self, ParseError> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Prepare, &buffer)?;
-        let content_len = content.len();
-        let amount = content.read_u64::<BigEndian>()?;
-
-        let mut expires_at = [0x00; 17];
-        content.read_exact(&mut expires_at)?;
-        let expires_at = str::from_utf8(&expires_at[..])?;
-        let expires_at: DateTime<Utc> =
-            Utc.datetime_from_str(&expires_at, INTERLEDGER_TIMESTAMP_FORMAT)?;
+        Prepare::parse(buffer, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(buffer: BytesMut) -> Result<Self, ParseError> {
+        Prepare::parse(buffer, true)
+    }
+
+    fn parse(buffer: BytesMut, strict: bool) -> Result<Self, ParseError> {
+        let buffer = buffer.freeze();
+        let (content_offset, mut reader) =
+            deserialize_envelope(PacketType::Prepare, &buffer, strict)?;
+        let amount = reader.read_u64("amount")?;
+
+        let expires_at = reader.read_bytes(EXPIRY_LEN, "expires_at")?;
+        let expires_at = str::from_utf8(expires_at)?;
+        let expires_at = NaiveDateTime::parse_from_str(expires_at, INTERLEDGER_TIMESTAMP_FORMAT)?;
+        let expires_at: DateTime<Utc> = Utc.from_utc_datetime(&expires_at);
         let expires_at = SystemTime::from(expires_at);
 
-        // Skip execution condition.
-        content.skip(CONDITION_LEN)?;
-        // Skip the data.
-        // TODO make sure address is only ASCII characters
-        content.skip_var_octet_string()?;
+        reader.read_bytes(CONDITION_LEN, "execution_condition")?;
+        let destination = reader.read_var_octet_string("destination", MAX_ADDRESS_LENGTH)?;
+        let destination = Address::try_from(buffer.slice_ref(destination))?;
 
-        let data_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        let data_offset = reader.offset();
+        reader.read_var_octet_string("data", MAX_DATA_LEN)?;
+        reader.finish("data")?;
 
         Ok(Prepare {
             buffer,
             content_offset,
             amount,
             expires_at,
+            destination,
             data_offset,
         })
     }
@@ -151,9 +173,12 @@ impl Prepare {
     #[inline]
     pub fn set_amount(&mut self, amount: u64) {
         self.amount = amount;
-        let mut cursor = Cursor::new(&mut self.buffer);
-        cursor.set_position(self.content_offset as u64);
-        cursor.put_u64_be(amount);
+        let content_offset = self.content_offset;
+        self.modify_buffer(|buffer| {
+            let mut cursor = Cursor::new(buffer);
+            cursor.set_position(content_offset as u64);
+            cursor.put_u64_be(amount);
+        });
     }
 
     #[inline]
@@ -165,12 +190,31 @@ impl Prepare {
     pub fn set_expires_at(&mut self, expires_at: SystemTime) {
         self.expires_at = expires_at;
         let offset = self.content_offset + AMOUNT_LEN;
-        write!(
-            &mut self.buffer[offset..],
-            "{}",
-            DateTime::<Utc>::from(expires_at).format(INTERLEDGER_TIMESTAMP_FORMAT),
-        )
-        .unwrap();
+        self.modify_buffer(|buffer| {
+            write!(
+                &mut buffer[offset..],
+                "{}",
+                DateTime::<Utc>::from(expires_at).format(INTERLEDGER_TIMESTAMP_FORMAT),
+            )
+            .unwrap();
+        });
+    }
+
+    /// Change the bytes of the packet. The destination shares the buffer, so it is let go
+    /// of while the buffer is changed and taken from the new buffer afterwards.
+    /// The buffer is only copied if the packet was cloned.
+    fn modify_buffer<F>(&mut self, modify: F)
+    where
+        F: FnOnce(&mut BytesMut),
+    {
+        let destination_end = self.data_offset;
+        let destination_start = destination_end - self.destination.len();
+        self.destination = Address::new_unchecked(Bytes::new());
+        let mut buffer = into_buffer_mut(std::mem::replace(&mut self.buffer, Bytes::new()));
+        modify(&mut buffer);
+        self.buffer = buffer.freeze();
+        self.destination =
+            Address::new_unchecked(self.buffer.slice(destination_start, destination_end));
     }
 
     /// The returned value always has a length of 32.
@@ -182,9 +226,8 @@ impl Prepare {
     }
 
     #[inline]
-    pub fn destination(&self) -> &[u8] {
-        let offset = self.content_offset + AMOUNT_LEN + EXPIRY_LEN + CONDITION_LEN;
-        (&self.buffer[offset..]).peek_var_octet_string().unwrap()
+    pub fn destination(&self) -> &Address {
+        &self.destination
     }
 
     #[inline]
@@ -195,20 +238,35 @@ impl Prepare {
     }
 
     #[inline]
-    pub fn into_data(mut self) -> BytesMut {
-        oer::extract_var_octet_string(self.buffer.split_off(self.data_offset)).unwrap()
+    pub fn into_data(self) -> BytesMut {
+        let data_offset = self.data_offset;
+        oer::extract_var_octet_string(BytesMut::from(self).split_off(data_offset)).unwrap()
     }
 }
 
 impl From<Prepare> for BytesMut {
     fn from(prepare: Prepare) -> Self {
-        prepare.buffer
+        // Drop the destination first so the buffer is not shared
+        let Prepare {
+            buffer,
+            destination,
+            ..
+        } = prepare;
+        drop(destination);
+        into_buffer_mut(buffer)
     }
 }
 
+/// Get the buffer back without copying it, unless something else still refers to it
+fn into_buffer_mut(buffer: Bytes) -> BytesMut {
+    buffer
+        .try_mut()
+        .unwrap_or_else(|buffer| BytesMut::from(&buffer[..]))
+}
+
 impl fmt::Debug for Prepare {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Prepare {{ destination: \"{}\", amount: {}, expires_at: {:?}, execution_condition: {}, data_length: {} }}", str::from_utf8(self.destination()).map_err(|_| fmt::Error)?, self.amount(), DateTime::<Utc>::from(self.expires_at()).to_rfc3339(), hex::encode(self.execution_condition()), self.data().len())
+        write!(f, "Prepare {{ destination: \"{}\", amount: {}, expires_at: {:?}, execution_condition: {}, data_length: {} }}", self.destination(), self.amount(), DateTime::<Utc>::from(self.expires_at()).to_rfc3339(), hex::encode(self.execution_condition()), self.data().len())
     }
 }
 
@@ -236,14 +294,15 @@ impl<'a> PrepareBuilder<'a> {
         let mut buffer = writer.into_inner();
 
         buffer.put_slice(&self.execution_condition[..]);
-        buffer.put_var_octet_string(self.destination);
+        buffer.put_var_octet_string(&self.destination[..]);
         buffer.put_var_octet_string(self.data);
 
         Prepare {
-            buffer,
+            buffer: buffer.freeze(),
             content_offset,
             amount: self.amount,
             expires_at: self.expires_at,
+            destination: self.destination.clone(),
             data_offset: buf_size - data_size,
         }
     }
@@ -263,10 +322,21 @@ pub struct FulfillBuilder<'a> {
 
 impl Fulfill {
     pub fn try_from(buffer: BytesMut) -> Result<Self, ParseError> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Fulfill, &buffer)?;
+        Fulfill::parse(buffer, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(buffer: BytesMut) -> Result<Self, ParseError> {
+        Fulfill::parse(buffer, true)
+    }
 
-        content.skip(FULFILLMENT_LEN)?;
-        content.skip_var_octet_string()?;
+    fn parse(buffer: BytesMut, strict: bool) -> Result<Self, ParseError> {
+        let (content_offset, mut reader) =
+            deserialize_envelope(PacketType::Fulfill, &buffer, strict)?;
+
+        reader.read_bytes(FULFILLMENT_LEN, "fulfillment")?;
+        reader.read_var_octet_string("data", MAX_DATA_LEN)?;
+        reader.finish("data")?;
 
         Ok(Fulfill {
             buffer,
@@ -325,7 +395,7 @@ impl<'a> FulfillBuilder<'a> {
         buffer.put_var_octet_string_length(content_len);
         let content_offset = buffer.len();
         buffer.put_slice(&self.fulfillment[..]);
-        buffer.put_var_octet_string(&self.data[..]);
+        buffer.put_var_octet_string(self.data);
         Fulfill {
             buffer,
             content_offset,
@@ -352,21 +422,31 @@ pub struct RejectBuilder<'a> {
 
 impl Reject {
     pub fn try_from(buffer: BytesMut) -> Result<Self, ParseError> {
-        let (content_offset, mut content) = deserialize_envelope(PacketType::Reject, &buffer)?;
-        let content_len = content.len();
+        Reject::parse(buffer, false)
+    }
+
+    /// Like `try_from`, but rejects non-canonical encodings and trailing bytes
+    pub fn try_from_strict(buffer: BytesMut) -> Result<Self, ParseError> {
+        Reject::parse(buffer, true)
+    }
+
+    fn parse(buffer: BytesMut, strict: bool) -> Result<Self, ParseError> {
+        let (_content_offset, mut reader) =
+            deserialize_envelope(PacketType::Reject, &buffer, strict)?;
 
         let mut code = [0; 3];
-        content.read_exact(&mut code)?;
+        code.copy_from_slice(reader.read_bytes(ERROR_CODE_LEN, "code")?);
         let code = ErrorCode::new(code);
 
-        let triggered_by_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        let triggered_by_offset = reader.offset();
+        reader.read_var_octet_string("triggered_by", MAX_ADDRESS_LENGTH)?;
 
-        let message_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        let message_offset = reader.offset();
+        reader.read_var_octet_string("message", MAX_MESSAGE_LEN)?;
 
-        let data_offset = content_offset + content_len - content.len();
-        content.skip_var_octet_string()?;
+        let data_offset = reader.offset();
+        reader.read_var_octet_string("data", MAX_DATA_LEN)?;
+        reader.finish("data")?;
 
         Ok(Reject {
             buffer,
@@ -428,10 +508,15 @@ impl fmt::Debug for Reject {
 }
 
 impl<'a> RejectBuilder<'a> {
+    /// Fields longer than the limits the packet format allows are truncated
+    /// so that the Reject can always be parsed
     pub fn build(&self) -> Reject {
-        let triggered_by_size = oer::predict_var_octet_string(self.triggered_by.len());
-        let message_size = oer::predict_var_octet_string(self.message.len());
-        let data_size = oer::predict_var_octet_string(self.data.len());
+        let triggered_by = truncate(self.triggered_by, MAX_ADDRESS_LENGTH);
+        let message = truncate(self.message, MAX_MESSAGE_LEN);
+        let data = truncate(self.data, MAX_DATA_LEN);
+        let triggered_by_size = oer::predict_var_octet_string(triggered_by.len());
+        let message_size = oer::predict_var_octet_string(message.len());
+        let data_size = oer::predict_var_octet_string(data.len());
         let content_len = ERROR_CODE_LEN + triggered_by_size + message_size + data_size;
         let buf_size = 1 + oer::predict_var_octet_string(content_len);
         let mut buffer = BytesMut::with_capacity(buf_size);
@@ -439,9 +524,9 @@ impl<'a> RejectBuilder<'a> {
         buffer.put_u8(PacketType::Reject as u8);
         buffer.put_var_octet_string_length(content_len);
         buffer.put_slice(&<[u8; 3]>::from(self.code)[..]);
-        buffer.put_var_octet_string(self.triggered_by);
-        buffer.put_var_octet_string(self.message);
-        buffer.put_var_octet_string(self.data);
+        buffer.put_var_octet_string(triggered_by);
+        buffer.put_var_octet_string(message);
+        buffer.put_var_octet_string(data);
         Reject {
             buffer,
             code: self.code,
@@ -452,26 +537,55 @@ impl<'a> RejectBuilder<'a> {
     }
 }
 
+/// Returns at most `max_length` bytes of `bytes`, without splitting a UTF-8 character
+fn truncate(bytes: &[u8], max_length: usize) -> &[u8] {
+    if bytes.len() <= max_length {
+        return bytes;
+    }
+    match str::from_utf8(bytes) {
+        Ok(string) => {
+            let end = (0..=max_length)
+                .rev()
+                .find(|&index| string.is_char_boundary(index))
+                .unwrap_or(0);
+            &bytes[..end]
+        }
+        Err(_) => &bytes[..max_length],
+    }
+}
+
+/// Checks the packet type and length prefix and returns the offset of the packet's contents
+/// along with a reader over them. Bytes after the contents are only rejected in strict mode.
 fn deserialize_envelope(
     packet_type: PacketType,
-    mut reader: &[u8],
-) -> Result<(usize, &[u8]), ParseError> {
-    let got_type = reader.read_u8()?;
-    if got_type == packet_type as u8 {
-        let content_offset = 1 + {
-            // This could probably be determined a better way...
-            let mut peek = &reader[..];
-            let before = peek.len();
-            peek.read_var_octet_string_length()?;
-            before - peek.len()
-        };
-        let content = reader.peek_var_octet_string()?;
-        Ok((content_offset, content))
+    buffer: &[u8],
+    strict: bool,
+) -> Result<(usize, OerReader<'_>), ParseError> {
+    let mut reader = if strict {
+        OerReader::strict(buffer)
     } else {
-        Err(ParseError::InvalidPacket(format!(
+        OerReader::new(buffer)
+    };
+    let got_type = reader.read_u8("type")?;
+    if got_type != packet_type as u8 {
+        return Err(ParseError::InvalidPacket(format!(
             "Unexpected packet type: {:?}",
             got_type,
-        )))
+        )));
+    }
+
+    let length_offset = reader.offset();
+    let content_len = reader.read_var_octet_string_length("length", usize::MAX)?;
+    let content_offset = reader.offset();
+    if reader.remaining() < content_len {
+        Err(ParseError::UnexpectedEof("packet", length_offset))
+    } else if strict && reader.remaining() > content_len {
+        Err(ParseError::TrailingBytes(
+            "packet",
+            content_offset + content_len,
+        ))
+    } else {
+        Ok((content_offset, reader.read_reader(content_len, "packet")?))
     }
 }
 
@@ -576,6 +690,7 @@ mod test_packet {
 mod test_prepare {
     use super::*;
     use crate::fixtures::{self, PREPARE, PREPARE_BUILDER, PREPARE_BYTES};
+    use std::str::FromStr;
 
     #[test]
     fn test_try_from() {
@@ -592,21 +707,34 @@ mod test_prepare {
         })
         .is_err());
 
-        // A packet with junk data appened to the end.
-        let with_junk_data = Prepare::try_from({
+        // A destination that is not a valid ILP address.
+        assert!(Prepare::try_from({
+            let mut with_bad_destination = BytesMut::from(*PREPARE_BYTES);
+            let offset = with_bad_destination
+                .windows(13)
+                .position(|window| window == b"example.alice")
+                .unwrap();
+            with_bad_destination[offset + 7] = b' ';
+            with_bad_destination
+        })
+        .is_err());
+
+        // A packet with junk data appended to the end.
+        let with_junk_data = {
             let mut buffer = BytesMut::from(*PREPARE_BYTES);
             buffer.extend_from_slice(&[0x11, 0x12, 0x13]);
             buffer
-        })
-        .unwrap();
-        assert_eq!(with_junk_data.amount(), PREPARE.amount());
-        assert_eq!(with_junk_data.expires_at(), *fixtures::EXPIRES_AT);
+        };
         assert_eq!(
-            with_junk_data.execution_condition(),
-            fixtures::EXECUTION_CONDITION
+            Prepare::try_from(with_junk_data.clone()).unwrap().amount(),
+            PREPARE.amount(),
         );
-        assert_eq!(with_junk_data.destination(), PREPARE.destination());
-        assert_eq!(with_junk_data.data(), fixtures::DATA);
+        match Prepare::try_from_strict(with_junk_data) {
+            Err(ParseError::TrailingBytes("packet", offset)) => {
+                assert_eq!(offset, PREPARE_BYTES.len())
+            }
+            result => panic!("Expected trailing bytes error, got: {:?}", result),
+        }
     }
 
     #[test]
@@ -632,6 +760,34 @@ mod test_prepare {
         assert_eq!(BytesMut::from(prepare), *PREPARE_BYTES);
     }
 
+    #[test]
+    fn test_destination_shares_buffer() {
+        fn shares_buffer(prepare: &Prepare) -> bool {
+            let start = prepare.buffer.as_ptr() as usize;
+            let destination = prepare.destination().as_ptr() as usize;
+            destination > start && destination < start + prepare.buffer.len()
+        }
+        // Short destinations are copied into the Bytes struct itself rather than shared
+        let destination =
+            Address::from_str("example.an-address-that-is-too-long-to-store-inline").unwrap();
+        let bytes = BytesMut::from(
+            PrepareBuilder {
+                destination: &destination,
+                ..*PREPARE_BUILDER
+            }
+            .build(),
+        );
+        let mut prepare = Prepare::try_from(bytes.clone()).unwrap();
+        assert!(shares_buffer(&prepare));
+
+        // Changing a packet does not change its clones
+        let original = prepare.clone();
+        prepare.set_amount(1);
+        assert!(shares_buffer(&prepare));
+        assert_eq!(*prepare.destination(), destination);
+        assert_eq!(BytesMut::from(original), bytes);
+    }
+
     #[test]
     fn test_expires_at() {
         assert_eq!(PREPARE.expires_at(), *fixtures::EXPIRES_AT);
@@ -673,15 +829,22 @@ mod test_fulfill {
             *FULFILL
         );
 
-        // A packet with junk data appened to the end.
-        let with_junk_data = Fulfill::try_from({
+        // A packet with junk data appended to the end.
+        let with_junk_data = {
             let mut buffer = BytesMut::from(*FULFILL_BYTES);
             buffer.extend_from_slice(&[0x11, 0x12, 0x13]);
             buffer
-        })
-        .unwrap();
-        assert_eq!(with_junk_data.fulfillment(), fixtures::FULFILLMENT);
-        assert_eq!(with_junk_data.data(), fixtures::DATA);
+        };
+        assert_eq!(
+            Fulfill::try_from(with_junk_data.clone()).unwrap().data(),
+            FULFILL.data(),
+        );
+        match Fulfill::try_from_strict(with_junk_data) {
+            Err(ParseError::TrailingBytes("packet", offset)) => {
+                assert_eq!(offset, FULFILL_BYTES.len())
+            }
+            result => panic!("Expected trailing bytes error, got: {:?}", result),
+        }
 
         // Fail to parse a packet missing a data field, even if a VarStr is in
         // the junk data.
@@ -724,17 +887,22 @@ mod test_reject {
             *REJECT,
         );
 
-        // A packet with junk data appened to the end.
-        let with_junk_data = Reject::try_from({
+        // A packet with junk data appended to the end.
+        let with_junk_data = {
             let mut buffer = BytesMut::from(*REJECT_BYTES);
             buffer.extend_from_slice(&[0x11, 0x12, 0x13]);
             buffer
-        })
-        .unwrap();
-        assert_eq!(with_junk_data.code(), REJECT_BUILDER.code);
-        assert_eq!(with_junk_data.message(), REJECT_BUILDER.message);
-        assert_eq!(with_junk_data.triggered_by(), REJECT_BUILDER.triggered_by);
-        assert_eq!(with_junk_data.data(), fixtures::DATA);
+        };
+        assert_eq!(
+            Reject::try_from(with_junk_data.clone()).unwrap().data(),
+            REJECT.data(),
+        );
+        match Reject::try_from_strict(with_junk_data) {
+            Err(ParseError::TrailingBytes("packet", offset)) => {
+                assert_eq!(offset, REJECT_BYTES.len())
+            }
+            result => panic!("Expected trailing bytes error, got: {:?}", result),
+        }
     }
 
     #[test]
@@ -742,6 +910,28 @@ mod test_reject {
         assert_eq!(BytesMut::from(REJECT.clone()), *REJECT_BYTES,);
     }
 
+    #[test]
+    fn truncates_oversized_fields() {
+        let triggered_by = vec![b'a'; MAX_ADDRESS_LENGTH + 1];
+        // Multi-byte characters are not split
+        let message = "é".repeat(MAX_MESSAGE_LEN / 2 + 1);
+        let data = vec![0x42; MAX_DATA_LEN + 1];
+        let reject = RejectBuilder {
+            code: REJECT_BUILDER.code,
+            triggered_by: &triggered_by[..],
+            message: message.as_bytes(),
+            data: &data[..],
+        }
+        .build();
+        assert_eq!(reject.triggered_by(), &triggered_by[..MAX_ADDRESS_LENGTH]);
+        assert_eq!(reject.message(), &message.as_bytes()[..MAX_MESSAGE_LEN - 1]);
+        assert_eq!(reject.data(), &data[..MAX_DATA_LEN]);
+        assert_eq!(
+            Reject::try_from_strict(BytesMut::from(reject.clone())).unwrap(),
+            reject
+        );
+    }
+
     #[test]
     fn test_code() {
         assert_eq!(REJECT.code(), REJECT_BUILDER.code);
@@ -767,7 +957,7 @@ mod test_reject {
 mod test_max_packet_amount_details {
     use super::*;
 
-    static BYTES: &'static [u8] = b"\
+    static BYTES: &[u8] = b"\
         \x00\x00\x00\x00\x00\x03\x02\x01\
         \x00\x00\x00\x00\x00\x06\x05\x04\
     ";
@@ -779,7 +969,7 @@ mod test_max_packet_amount_details {
 
     #[test]
     fn test_from_bytes() {
-        assert_eq!(MaxPacketAmountDetails::from_bytes(&BYTES).unwrap(), DETAILS,);
+        assert_eq!(MaxPacketAmountDetails::from_bytes(BYTES).unwrap(), DETAILS,);
         assert_eq!(
             MaxPacketAmountDetails::from_bytes(&[][..])
                 .unwrap_err()
@@ -803,3 +993,154 @@ mod test_max_packet_amount_details {
         assert_eq!(DETAILS.max_amount(), 0x060504);
     }
 }
+
+#[cfg(test)]
+mod test_strict_parsing {
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::fixtures::PREPARE_BYTES;
+
+    #[test]
+    fn rejects_non_canonical_packet_length() {
+        // A Fulfill with no data whose length prefix uses the long form
+        let mut bytes = vec![PacketType::Fulfill as u8, 0x81, 33];
+        bytes.extend_from_slice(&[0; 32]);
+        bytes.push(0);
+        match Fulfill::try_from_strict(BytesMut::from(bytes)) {
+            Err(ParseError::NonCanonical("length", 1)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let bytes = &PREPARE_BYTES[..PREPARE_BYTES.len() - 1];
+        match Prepare::try_from_strict(BytesMut::from(bytes)) {
+            Err(ParseError::UnexpectedEof("packet", 1)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_bytes_after_data() {
+        let mut buffer = BytesMut::with_capacity(36);
+        buffer.put_u8(PacketType::Fulfill as u8);
+        buffer.put_var_octet_string(&[0x42; 32 + 2][..]);
+        // Replace the last two bytes with an empty data field and one extra byte
+        let length = buffer.len();
+        buffer[length - 2] = 0x00;
+        match Fulfill::try_from_strict(buffer) {
+            Err(ParseError::TrailingBytes("data", offset)) if offset == length - 1 => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_destination() {
+        let mut buffer = Vec::new();
+        buffer.put_u64_be(0);
+        buffer.put_slice(b"20171224161432279");
+        buffer.put_slice(&[0; 32]);
+        buffer.put_var_octet_string(&[b'a'; MAX_ADDRESS_LENGTH + 1][..]);
+        buffer.put_var_octet_string(&[][..]);
+        let mut packet = Vec::new();
+        packet.put_u8(PacketType::Prepare as u8);
+        packet.put_var_octet_string(buffer);
+        match Prepare::try_from_strict(BytesMut::from(packet)) {
+            Err(ParseError::FieldTooLong("destination", offset, max_length))
+                if offset == 4 + 8 + 17 + 32 && max_length == MAX_ADDRESS_LENGTH => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn lenient_parsing_accepts_non_canonical_lengths() {
+        let mut bytes = vec![PacketType::Fulfill as u8, 0x81, 34];
+        bytes.extend_from_slice(&[0; 32]);
+        bytes.extend_from_slice(&[0x81, 0x00]);
+        let fulfill = Fulfill::try_from(BytesMut::from(bytes)).unwrap();
+        assert_eq!(fulfill.fulfillment(), &[0; 32][..]);
+        assert!(fulfill.data().is_empty());
+    }
+
+    fn address() -> impl Strategy<Value = Address> {
+        "(g|private|example|test)(\\.[a-zA-Z0-9_~-]{1,20}){1,10}"
+            .prop_map(|address| Address::from_str(&address).unwrap())
+    }
+
+    fn bytes(max_length: usize) -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(any::<u8>(), 0..=max_length)
+    }
+
+    proptest! {
+        #[test]
+        fn prepare_round_trips(
+            amount in any::<u64>(),
+            // Timestamps are only encoded to the millisecond, up to the year 9999
+            expires_at in 0..253_402_300_799_999u64,
+            execution_condition in any::<[u8; 32]>(),
+            destination in address(),
+            data in bytes(MAX_DATA_LEN),
+        ) {
+            let prepare = PrepareBuilder {
+                amount,
+                expires_at: UNIX_EPOCH + Duration::from_millis(expires_at),
+                execution_condition: &execution_condition,
+                destination: &destination,
+                data: &data[..],
+            }
+            .build();
+            let parsed = Prepare::try_from_strict(BytesMut::from(prepare.clone())).unwrap();
+            prop_assert_eq!(parsed, prepare);
+        }
+
+        #[test]
+        fn fulfill_round_trips(fulfillment in any::<[u8; 32]>(), data in bytes(MAX_DATA_LEN)) {
+            let fulfill = FulfillBuilder {
+                fulfillment: &fulfillment,
+                data: &data[..],
+            }
+            .build();
+            let parsed = Fulfill::try_from_strict(BytesMut::from(fulfill.clone())).unwrap();
+            prop_assert_eq!(parsed, fulfill);
+        }
+
+        #[test]
+        fn reject_round_trips(
+            code in any::<[u8; 3]>(),
+            triggered_by in bytes(MAX_ADDRESS_LENGTH),
+            message in bytes(MAX_MESSAGE_LEN),
+            data in bytes(MAX_DATA_LEN),
+        ) {
+            let reject = RejectBuilder {
+                code: ErrorCode::new(code),
+                triggered_by: &triggered_by[..],
+                message: &message[..],
+                data: &data[..],
+            }
+            .build();
+            let parsed = Reject::try_from_strict(BytesMut::from(reject.clone())).unwrap();
+            prop_assert_eq!(parsed, reject);
+        }
+
+        #[test]
+        fn rejects_appended_bytes(data in bytes(200), extra in prop::collection::vec(any::<u8>(), 1..10)) {
+            let fulfill = FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &data[..],
+            }
+            .build();
+            let mut buffer = BytesMut::from(fulfill);
+            let length = buffer.len();
+            buffer.extend_from_slice(&extra[..]);
+            match Packet::try_from_strict(buffer) {
+                Err(ParseError::TrailingBytes("packet", offset)) if offset == length => {}
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+    }
+}
@@ -29,6 +29,9 @@ pub trait RouterStore: AccountStore + Clone + Send + Sync + 'static {
     /// Note that this is synchronous because it assumes that Stores should
     /// keep the routing table in memory and use PubSub or polling to keep it updated.
     /// This ensures that individual packets can be routed without hitting the underlying store.
+    ///
+    /// Keys are route prefixes rather than full addresses: the empty prefix is a catch-all
+    /// and prefixes may end with a '.', neither of which is a valid `Address`.
     // TODO avoid using HashMap because it means it'll be cloned a lot
     fn routing_table(&self) -> HashMap<Bytes, <Self::Account as Account>::AccountId>;
 }
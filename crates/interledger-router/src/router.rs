@@ -4,6 +4,7 @@ use futures::{future::err, Future};
 use interledger_packet::{ErrorCode, RejectBuilder};
 use interledger_service::*;
 use std::str;
+use tracing::{field::display, Span};
 
 /// The router implements the IncomingService trait and uses the routing table
 /// to determine the `to` (or "next hop") Account for the given request.
@@ -36,7 +37,7 @@ where
     type Future = BoxedIlpFuture;
 
     fn handle_request(&mut self, request: IncomingRequest<T::Account>) -> Self::Future {
-        let destination = Bytes::from(request.prepare.destination());
+        let destination = request.prepare.destination().to_bytes();
         let mut next_hop: Option<<T::Account as Account>::AccountId> = None;
         let routing_table = self.store.routing_table();
 
@@ -44,7 +45,7 @@ where
         if let Some(account_id) = routing_table.get(&destination) {
             debug!(
                 "Found direct route for address: \"{}\". Account: {}",
-                str::from_utf8(&destination[..]).unwrap_or("<not utf8>"),
+                request.prepare.destination(),
                 account_id
             );
             next_hop = Some(*account_id);
@@ -56,8 +57,8 @@ where
                     str::from_utf8(&route.0[..]).unwrap_or("<not utf8>"),
                     route.1
                 );
-                // Check if the route prefix matches or is empty (meaning it's a catch-all address)
-                if (route.0.is_empty() || destination.starts_with(&route.0[..]))
+                // Check if the route prefix matches on a segment boundary or is empty (meaning it's a catch-all address)
+                if (route.0.is_empty() || request.prepare.destination().has_prefix(&route.0[..]))
                     && route.0.len() >= matching_prefix.len()
                 {
                     next_hop.replace(route.1);
@@ -67,7 +68,7 @@ where
             if let Some(account_id) = next_hop {
                 debug!(
                     "Found matching route for address: \"{}\". Prefix: \"{}\", account: {}",
-                    str::from_utf8(&destination[..]).unwrap_or("<not utf8>"),
+                    request.prepare.destination(),
                     str::from_utf8(&matching_prefix[..]).unwrap_or("<not utf8>"),
                     account_id,
                 );
@@ -77,14 +78,21 @@ where
         }
 
         if let Some(account_id) = next_hop {
+            Span::current().record("to", display(account_id));
             let mut next = self.next.clone();
             Box::new(
                 self.store
                     .get_accounts(vec![account_id])
-                    .map_err(move |_| {
-                        error!("No record found for account: {}", account_id);
+                    .map_err(move |err| {
+                        let code = if err.is_not_found() {
+                            error!("No record found for account: {}", account_id);
+                            ErrorCode::F02_UNREACHABLE
+                        } else {
+                            error!("Error loading account {}: {}", account_id, err);
+                            ErrorCode::T00_INTERNAL_ERROR
+                        };
                         RejectBuilder {
-                            code: ErrorCode::F02_UNREACHABLE,
+                            code,
                             message: &[],
                             triggered_by: &[],
                             data: &[],
@@ -114,10 +122,11 @@ mod tests {
     use super::*;
     use futures::future::ok;
     use hashbrown::HashMap;
-    use interledger_packet::{FulfillBuilder, PrepareBuilder};
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
     use parking_lot::Mutex;
     use std::iter::FromIterator;
+    use std::str::FromStr;
     use std::sync::Arc;
     use std::time::UNIX_EPOCH;
 
@@ -142,7 +151,7 @@ mod tests {
         fn get_accounts(
             &self,
             account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+        ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
             Box::new(ok(account_ids.into_iter().map(TestAccount).collect()))
         }
     }
@@ -172,7 +181,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -203,7 +212,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -236,7 +245,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -267,7 +276,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -298,7 +307,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -310,6 +319,37 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn prefix_must_match_whole_segments() {
+        let mut router = Router::new(
+            TestStore {
+                routes: HashMap::from_iter(vec![(Bytes::from("example.dest"), 1)]),
+            },
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = router
+            .handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: &Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+            })
+            .wait();
+        assert!(result.is_err());
+    }
+
     #[test]
     fn finds_longest_matching_prefix() {
         let to: Arc<Mutex<Option<TestAccount>>> = Arc::new(Mutex::new(None));
@@ -340,7 +380,7 @@ mod tests {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     execution_condition: &[1; 32],
                     expires_at: UNIX_EPOCH,
@@ -352,4 +392,55 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(to.lock().take().unwrap().0, 2);
     }
+
+    #[derive(Clone)]
+    struct UnavailableStore;
+
+    impl AccountStore for UnavailableStore {
+        type Account = TestAccount;
+
+        fn get_accounts(
+            &self,
+            _account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
+        ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
+            Box::new(err(StoreError::Unavailable(
+                "Connection refused".to_string(),
+            )))
+        }
+    }
+
+    impl RouterStore for UnavailableStore {
+        fn routing_table(&self) -> HashMap<Bytes, u64> {
+            HashMap::from_iter(vec![(Bytes::from("example.destination"), 1)])
+        }
+    }
+
+    #[test]
+    fn temporary_error_if_store_unavailable() {
+        let mut router = Router::new(
+            UnavailableStore,
+            outgoing_service_fn(|_| {
+                Ok(FulfillBuilder {
+                    fulfillment: &[0; 32],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        let result = router
+            .handle_request(IncomingRequest {
+                from: TestAccount(0),
+                prepare: PrepareBuilder {
+                    destination: &Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[1; 32],
+                    expires_at: UNIX_EPOCH,
+                    data: &[],
+                }
+                .build(),
+            })
+            .wait();
+        assert_eq!(result.unwrap_err().code(), ErrorCode::T00_INTERNAL_ERROR);
+    }
 }
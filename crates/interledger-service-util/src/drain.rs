@@ -0,0 +1,192 @@
+use bytes::Bytes;
+use futures::{future::err, Future, Stream};
+use interledger_packet::{ErrorCode, RejectBuilder};
+use interledger_service::*;
+use parking_lot::Mutex;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::timer::Interval;
+
+/// How often `drain` checks whether the packets in flight have been handled
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct DrainState {
+    draining: bool,
+    in_flight: usize,
+    /// The latest expiry of the packets that are currently in flight
+    latest_expiry: Option<SystemTime>,
+}
+
+/// Keeps track of the Prepare packets being handled by the services after it so that
+/// the node can stop accepting new packets and wait for the ones in flight before it shuts down.
+///
+/// This should be the first IncomingService in the chain so that it covers every packet.
+#[derive(Clone)]
+pub struct DrainService<S> {
+    ilp_address: Bytes,
+    next: S,
+    state: Arc<Mutex<DrainState>>,
+}
+
+impl<S> DrainService<S> {
+    /// The node's ILP address is used as the `triggered_by` of the Rejects sent while draining
+    pub fn new(ilp_address: Bytes, next: S) -> Self {
+        DrainService {
+            ilp_address,
+            next,
+            state: Arc::new(Mutex::new(DrainState::default())),
+        }
+    }
+
+    /// Reject all of the Prepare packets that come in from now on and return a future that
+    /// resolves once the packets already in flight have been handled (or have all expired).
+    ///
+    /// This uses the tokio timer so it must be run inside a tokio runtime.
+    pub fn drain(&self) -> impl Future<Item = (), Error = ()> {
+        let state = self.state.clone();
+        {
+            let mut state = state.lock();
+            state.draining = true;
+            debug!(
+                "Draining {} packets that are still in flight",
+                state.in_flight
+            );
+        }
+        Interval::new_interval(DRAIN_POLL_INTERVAL)
+            .map_err(|err| error!("Interval error while draining packets: {:?}", err))
+            .take_while(move |_| {
+                let state = state.lock();
+                let expired = state
+                    .latest_expiry
+                    .map(|expiry| expiry <= SystemTime::now())
+                    .unwrap_or(true);
+                if state.in_flight > 0 && expired {
+                    warn!(
+                        "Stopped waiting for {} packets that are still in flight after they all expired",
+                        state.in_flight
+                    );
+                }
+                Ok(state.in_flight > 0 && !expired)
+            })
+            .for_each(|_| Ok(()))
+    }
+}
+
+/// Marks a packet as no longer in flight when its future completes or is dropped
+struct InFlight(Arc<Mutex<DrainState>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            state.latest_expiry = None;
+        }
+    }
+}
+
+impl<S, A> IncomingService<A> for DrainService<S>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    type Future = BoxedIlpFuture;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        let in_flight = {
+            let mut state = self.state.lock();
+            if state.draining {
+                return Box::new(err(RejectBuilder {
+                    code: ErrorCode::T03_CONNECTOR_BUSY,
+                    message: b"Node is shutting down",
+                    triggered_by: &self.ilp_address[..],
+                    data: &[],
+                }
+                .build()));
+            }
+            let expires_at = request.prepare.expires_at();
+            state.in_flight += 1;
+            state.latest_expiry = Some(match state.latest_expiry {
+                Some(latest) if latest > expires_at => latest,
+                _ => expires_at,
+            });
+            InFlight(self.state.clone())
+        };
+
+        Box::new(self.next.handle_request(request).then(move |result| {
+            drop(in_flight);
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::sync::oneshot;
+    use interledger_packet::{Address, FulfillBuilder, PrepareBuilder};
+    use std::str::FromStr;
+    use tokio::runtime::Runtime;
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn request() -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(1),
+            prepare: PrepareBuilder {
+                destination: &Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                execution_condition: &[0; 32],
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[test]
+    fn waits_for_packets_in_flight() {
+        let (fulfill, fulfilled) = oneshot::channel::<()>();
+        let fulfilled = Arc::new(Mutex::new(Some(fulfilled)));
+        let mut service = DrainService::new(
+            Bytes::from("example.node"),
+            incoming_service_fn(move |_| {
+                fulfilled
+                    .lock()
+                    .take()
+                    .expect("Only one packet should be forwarded")
+                    .then(|_| {
+                        Ok(FulfillBuilder {
+                            fulfillment: &[0; 32],
+                            data: &[],
+                        }
+                        .build())
+                    })
+            }),
+        );
+        let mut runtime = Runtime::new().unwrap();
+
+        let in_flight = service.handle_request(request());
+        let drained = service.drain();
+        let reject = service.handle_request(request()).wait().unwrap_err();
+        assert_eq!(reject.code(), ErrorCode::T03_CONNECTOR_BUSY);
+        assert_eq!(reject.triggered_by(), b"example.node");
+
+        fulfill.send(()).unwrap();
+        assert!(runtime.block_on(in_flight).is_ok());
+        runtime.block_on(drained).unwrap();
+        assert_eq!(service.state.lock().in_flight, 0);
+    }
+}
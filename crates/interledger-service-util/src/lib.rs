@@ -2,15 +2,25 @@
 //!
 //! Miscellaneous, small Interledger Services.
 
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate prometheus;
 
+mod drain;
 mod max_packet_amount;
+mod metrics;
+mod packet_events;
 mod rates_and_balances;
 mod validator;
 
+pub use self::drain::DrainService;
 pub use self::max_packet_amount::{MaxPacketAmountAccount, MaxPacketAmountService};
+pub use self::metrics::MetricsService;
+pub use self::packet_events::PacketEventService;
 pub use self::rates_and_balances::{
-    BalanceStore, ExchangeRateAndBalanceService, ExchangeRateStore,
+    BalanceStore, ExchangeRateAndBalanceService, ExchangeRateStore, Transaction, TransactionStore,
 };
 pub use self::validator::ValidatorService;
@@ -0,0 +1,235 @@
+use futures::Future;
+use interledger_ildcp::IldcpAccount;
+use interledger_packet::{Fulfill, Reject};
+use interledger_service::*;
+use prometheus::{exponential_buckets, HistogramVec, IntCounterVec};
+use std::{marker::PhantomData, time::Instant};
+
+lazy_static! {
+    static ref PREPARE_PACKETS: IntCounterVec = register_int_counter_vec!(
+        "ilp_prepare_packets_total",
+        "Number of ILP Prepare packets received or sent",
+        &["direction", "account", "asset_code"]
+    )
+    .unwrap();
+    static ref FULFILL_PACKETS: IntCounterVec = register_int_counter_vec!(
+        "ilp_fulfill_packets_total",
+        "Number of Prepare packets that were fulfilled",
+        &["direction", "account", "asset_code"]
+    )
+    .unwrap();
+    static ref REJECT_PACKETS: IntCounterVec = register_int_counter_vec!(
+        "ilp_reject_packets_total",
+        "Number of Prepare packets that were rejected, by ILP error code",
+        &["direction", "account", "asset_code", "code"]
+    )
+    .unwrap();
+    static ref PREPARE_AMOUNTS: HistogramVec = register_histogram_vec!(
+        "ilp_prepare_amount",
+        "Amounts of Prepare packets, denominated in the account's asset and scale",
+        &["direction", "account", "asset_code"],
+        exponential_buckets(1.0, 10.0, 13).unwrap()
+    )
+    .unwrap();
+    static ref PACKET_DURATION: HistogramVec = register_histogram_vec!(
+        "ilp_packet_duration_seconds",
+        "Time from receiving or sending a Prepare until it was fulfilled or rejected",
+        &["direction", "account", "asset_code"]
+    )
+    .unwrap();
+}
+
+/// Records Prometheus metrics for the packets passing through it.
+///
+/// As an IncomingService, the metrics are labeled with the account the packets came from.
+/// As an OutgoingService, they are labeled with the account the packets are sent to.
+/// The metrics are registered with the default `prometheus` registry.
+#[derive(Clone)]
+pub struct MetricsService<S, A> {
+    next: S,
+    account_type: PhantomData<A>,
+}
+
+impl<S, A> MetricsService<S, A>
+where
+    S: IncomingService<A>,
+    A: IldcpAccount,
+{
+    pub fn incoming(next: S) -> Self {
+        MetricsService {
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+impl<S, A> MetricsService<S, A>
+where
+    S: OutgoingService<A>,
+    A: IldcpAccount,
+{
+    pub fn outgoing(next: S) -> Self {
+        MetricsService {
+            next,
+            account_type: PhantomData,
+        }
+    }
+}
+
+fn record<F>(
+    direction: &'static str,
+    account: &impl IldcpAccount,
+    amount: u64,
+    future: F,
+) -> BoxedIlpFuture
+where
+    F: Future<Item = Fulfill, Error = Reject> + Send + 'static,
+{
+    let account_id = account.id().to_string();
+    let asset_code = account.asset_code().to_string();
+    let labels = [direction, account_id.as_str(), asset_code.as_str()];
+    PREPARE_PACKETS.with_label_values(&labels).inc();
+    PREPARE_AMOUNTS
+        .with_label_values(&labels)
+        .observe(amount as f64);
+
+    let start = Instant::now();
+    Box::new(future.then(move |result| {
+        let labels = [direction, account_id.as_str(), asset_code.as_str()];
+        let elapsed = start.elapsed();
+        PACKET_DURATION
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9);
+        match result {
+            Ok(_) => FULFILL_PACKETS.with_label_values(&labels).inc(),
+            Err(ref reject) => REJECT_PACKETS
+                .with_label_values(&[
+                    direction,
+                    account_id.as_str(),
+                    asset_code.as_str(),
+                    &reject.code().to_string(),
+                ])
+                .inc(),
+        }
+        result
+    }))
+}
+
+impl<S, A> IncomingService<A> for MetricsService<S, A>
+where
+    S: IncomingService<A>,
+    A: IldcpAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    fn handle_request(&mut self, request: IncomingRequest<A>) -> Self::Future {
+        let from = request.from.clone();
+        let amount = request.prepare.amount();
+        record("incoming", &from, amount, self.next.handle_request(request))
+    }
+}
+
+impl<S, A> OutgoingService<A> for MetricsService<S, A>
+where
+    S: OutgoingService<A>,
+    A: IldcpAccount,
+{
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let to = request.to.clone();
+        let amount = request.prepare.amount();
+        record("outgoing", &to, amount, self.next.send_request(request))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::{err, ok};
+    use interledger_packet::{Address, ErrorCode, FulfillBuilder, PrepareBuilder, RejectBuilder};
+    use prometheus::core::Metric;
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &[u8] {
+            b"example.account"
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+    }
+
+    fn request(from: u64) -> IncomingRequest<TestAccount> {
+        IncomingRequest {
+            from: TestAccount(from),
+            prepare: PrepareBuilder {
+                destination: &Address::from_str("example.destination").unwrap(),
+                amount: 100,
+                execution_condition: &[0; 32],
+                expires_at: SystemTime::now() + Duration::from_secs(30),
+                data: &[],
+            }
+            .build(),
+        }
+    }
+
+    #[test]
+    fn counts_fulfills_and_rejects() {
+        let mut fulfilling = MetricsService::incoming(incoming_service_fn(|_| {
+            ok(FulfillBuilder {
+                fulfillment: &[0; 32],
+                data: &[],
+            }
+            .build())
+        }));
+        let mut rejecting = MetricsService::incoming(incoming_service_fn(|_| {
+            err(RejectBuilder {
+                code: ErrorCode::F02_UNREACHABLE,
+                message: &[],
+                triggered_by: &[],
+                data: &[],
+            }
+            .build())
+        }));
+
+        fulfilling.handle_request(request(1001)).wait().unwrap();
+        rejecting.handle_request(request(1001)).wait().unwrap_err();
+        rejecting.handle_request(request(1001)).wait().unwrap_err();
+
+        let labels = ["incoming", "1001", "XYZ"];
+        assert_eq!(PREPARE_PACKETS.with_label_values(&labels).get(), 3);
+        assert_eq!(FULFILL_PACKETS.with_label_values(&labels).get(), 1);
+        assert_eq!(
+            REJECT_PACKETS
+                .with_label_values(&["incoming", "1001", "XYZ", "F02"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            PREPARE_AMOUNTS
+                .with_label_values(&labels)
+                .metric()
+                .get_histogram()
+                .get_sample_sum(),
+            300.0
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use futures::Future;
+use interledger_service::*;
+
+/// Publishes `PrepareForwarded`, `Fulfilled` and `Rejected` events for the packets sent through it.
+///
+/// This should be the first OutgoingService in the chain so that the events include packets
+/// rejected by the services after it and the amounts are in the `from` account's asset.
+#[derive(Clone)]
+pub struct PacketEventService<S, A: Account> {
+    next: S,
+    events: EventBus<A>,
+}
+
+impl<S, A> PacketEventService<S, A>
+where
+    S: OutgoingService<A>,
+    A: Account,
+{
+    pub fn new(events: EventBus<A>, next: S) -> Self {
+        PacketEventService { next, events }
+    }
+}
+
+impl<S, A> OutgoingService<A> for PacketEventService<S, A>
+where
+    S: OutgoingService<A>,
+    A: Account + Sync + 'static,
+{
+    type Future = BoxedIlpFuture;
+
+    fn send_request(&mut self, request: OutgoingRequest<A>) -> Self::Future {
+        let from = request.from.clone();
+        let to = request.to.clone();
+        let amount = request.prepare.amount();
+        self.events.publish(Event::PrepareForwarded {
+            from: from.clone(),
+            to: to.clone(),
+            destination: request.prepare.destination().to_bytes(),
+            amount,
+        });
+
+        let events = self.events.clone();
+        Box::new(self.next.send_request(request).then(move |result| {
+            events.publish(match result {
+                Ok(_) => Event::Fulfilled { from, to, amount },
+                Err(ref reject) => Event::Rejected {
+                    from,
+                    to,
+                    amount,
+                    code: reject.code(),
+                },
+            });
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{future::err, Stream};
+    use interledger_packet::{Address, ErrorCode, PrepareBuilder, RejectBuilder};
+    use std::str::FromStr;
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount(u64);
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn publishes_forwarded_and_rejected_events() {
+        let events = EventBus::new();
+        let subscriber = events.subscribe();
+        let mut service = PacketEventService::new(
+            events,
+            outgoing_service_fn(|_| {
+                err(RejectBuilder {
+                    code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                    message: &[],
+                    triggered_by: &[],
+                    data: &[],
+                }
+                .build())
+            }),
+        );
+
+        service
+            .send_request(OutgoingRequest {
+                from: TestAccount(1),
+                to: TestAccount(2),
+                prepare: PrepareBuilder {
+                    destination: &Address::from_str("example.destination").unwrap(),
+                    amount: 100,
+                    execution_condition: &[0; 32],
+                    expires_at: SystemTime::now() + Duration::from_secs(30),
+                    data: &[],
+                }
+                .build(),
+            })
+            .wait()
+            .unwrap_err();
+        drop(service);
+
+        let published: Vec<Event<TestAccount>> = subscriber.collect().wait().unwrap();
+        assert_eq!(published.len(), 2);
+        match published[0] {
+            Event::PrepareForwarded {
+                ref from,
+                ref to,
+                ref destination,
+                amount,
+            } => {
+                assert_eq!((from.0, to.0, amount), (1, 2, 100));
+                assert_eq!(&destination[..], b"example.destination");
+            }
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+        match published[1] {
+            Event::Rejected { code, amount, .. } => {
+                assert_eq!(code, ErrorCode::T04_INSUFFICIENT_LIQUIDITY);
+                assert_eq!(amount, 100);
+            }
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+}
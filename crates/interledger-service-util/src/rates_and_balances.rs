@@ -2,31 +2,74 @@ use futures::{future::err, Future};
 use interledger_ildcp::IldcpAccount;
 use interledger_packet::{ErrorCode, Fulfill, Reject, RejectBuilder};
 use interledger_service::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::spawn;
 
 pub trait BalanceStore: AccountStore {
     /// Fetch the current balance for the given account.
-    fn get_balance(&self, account: Self::Account) -> Box<Future<Item = i64, Error = ()> + Send>;
+    fn get_balance(
+        &self,
+        account: Self::Account,
+    ) -> Box<Future<Item = i64, Error = StoreError> + Send>;
 
     /// Subtract the `incoming_amount` from the `from_account`'s balance.
     /// Add the `outgoing_amount` to the `to_account`'s balance.
+    /// Returns the new balances of the `from_account` and `to_account`.
     fn update_balances(
         &self,
         from_account: Self::Account,
         incoming_amount: u64,
         to_account: Self::Account,
         outgoing_amount: u64,
-    ) -> Box<Future<Item = (), Error = ()> + Send>;
+    ) -> Box<Future<Item = (i64, i64), Error = StoreError> + Send>;
 
     /// Roll back the effect of a previous `update_balances` call.
     /// Add the `incoming_amount` to the `from_account`'s balance.
     /// Subtract the `outgoing_amount` from the `to_account`'s balance.
+    /// Returns the new balances of the `from_account` and `to_account`.
     fn undo_balance_update(
         &self,
         from_account: Self::Account,
         incoming_amount: u64,
         to_account: Self::Account,
         outgoing_amount: u64,
-    ) -> Box<Future<Item = (), Error = ()> + Send>;
+    ) -> Box<Future<Item = (i64, i64), Error = StoreError> + Send>;
+}
+
+/// A packet that moved (or tried to move) money between two accounts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction<I> {
+    /// Milliseconds since the UNIX epoch when the packet was fulfilled or rejected
+    pub timestamp: u64,
+    pub from_account: I,
+    pub to_account: I,
+    /// The amount before conversion, denominated in the `from_account`'s asset
+    pub incoming_amount: u64,
+    /// The amount after conversion, denominated in the `to_account`'s asset
+    pub outgoing_amount: u64,
+    pub execution_condition: [u8; 32],
+    /// The code the packet was rejected with, or `None` if it was fulfilled
+    pub reject_code: Option<ErrorCode>,
+}
+
+type TransactionsFuture<I> = Box<Future<Item = Vec<Transaction<I>>, Error = StoreError> + Send>;
+
+/// An append-only ledger of the packets that affected each account's balance.
+pub trait TransactionStore: AccountStore {
+    /// Append the transaction to the ledgers of both the `from_account` and the `to_account`.
+    fn record_transaction(
+        &self,
+        transaction: Transaction<<Self::Account as Account>::AccountId>,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Load the transactions involving the given account, oldest first.
+    /// `from` and `to` are inclusive bounds on the timestamp.
+    fn get_transactions(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> TransactionsFuture<<Self::Account as Account>::AccountId>;
 }
 
 pub trait ExchangeRateStore {
@@ -34,27 +77,72 @@ pub trait ExchangeRateStore {
 }
 
 #[derive(Clone)]
-pub struct ExchangeRateAndBalanceService<S, T> {
+pub struct ExchangeRateAndBalanceService<S, T: AccountStore> {
     next: S,
     store: T,
+    events: EventBus<T::Account>,
+    record_rejected: bool,
 }
 
 // TODO allow ExchangeRateStore and BalanceStore to be separate objects passed into the constructor
 impl<S, T> ExchangeRateAndBalanceService<S, T>
 where
     S: OutgoingService<T::Account>,
-    T: ExchangeRateStore + BalanceStore,
+    T: ExchangeRateStore + BalanceStore + TransactionStore,
 {
+    /// Every fulfilled packet is recorded in the store's transaction ledger.
     pub fn new(store: T, next: S) -> Self {
-        ExchangeRateAndBalanceService { next, store }
+        ExchangeRateAndBalanceService {
+            next,
+            store,
+            events: EventBus::new(),
+            record_rejected: false,
+        }
+    }
+
+    /// Publish a `BalanceChanged` event on the given bus whenever this service adjusts a balance.
+    pub fn with_events(mut self, events: EventBus<T::Account>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Also record rejected packets in the transaction ledger.
+    pub fn record_rejected(mut self, record_rejected: bool) -> Self {
+        self.record_rejected = record_rejected;
+        self
     }
 }
 
+fn now_millis() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is set before the UNIX epoch");
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+fn publish_balance_changes<A: Account>(
+    events: &EventBus<A>,
+    (from, from_amount): (&A, i64),
+    (to, to_amount): (&A, i64),
+    (from_balance, to_balance): (i64, i64),
+) {
+    events.publish(Event::BalanceChanged {
+        account: from.clone(),
+        amount: from_amount,
+        balance: from_balance,
+    });
+    events.publish(Event::BalanceChanged {
+        account: to.clone(),
+        amount: to_amount,
+        balance: to_balance,
+    });
+}
+
 impl<S, T> OutgoingService<T::Account> for ExchangeRateAndBalanceService<S, T>
 where
     // TODO can we make these non-'static?
     S: OutgoingService<T::Account> + Send + Clone + 'static,
-    T: BalanceStore + ExchangeRateStore + Clone + Send + Sync + 'static,
+    T: BalanceStore + ExchangeRateStore + TransactionStore + Clone + Send + Sync + 'static,
     T::Account: IldcpAccount + Send + Sync + 'static,
 {
     type Future = BoxedIlpFuture;
@@ -94,33 +182,71 @@ where
 
         let mut next = self.next.clone();
         let store = self.store.clone();
+        let ledger = self.store.clone();
+        let events = self.events.clone();
+        let record_rejected = self.record_rejected;
         let from = request.from.clone();
         let to = request.to.clone();
         let incoming_amount = request.prepare.amount();
+        let mut transaction = Transaction {
+            timestamp: 0,
+            from_account: from.id(),
+            to_account: to.id(),
+            incoming_amount,
+            outgoing_amount,
+            execution_condition: [0; 32],
+            reject_code: None,
+        };
+        transaction
+            .execution_condition
+            .copy_from_slice(request.prepare.execution_condition());
 
         request.prepare.set_amount(outgoing_amount);
         Box::new(
             self.store
                 .update_balances(from.clone(), incoming_amount, to.clone(), outgoing_amount)
-                .map_err(|_| {
-                    debug!("Rejecting packet because it would exceed a balance limit");
+                .map_err(|err| {
+                    let code = match err {
+                        StoreError::LimitExceeded(_) => {
+                            debug!("Rejecting packet because it would exceed a balance limit");
+                            ErrorCode::T04_INSUFFICIENT_LIQUIDITY
+                        }
+                        _ => {
+                            error!("Error updating balances: {}", err);
+                            ErrorCode::T00_INTERNAL_ERROR
+                        }
+                    };
                     RejectBuilder {
-                        code: ErrorCode::T04_INSUFFICIENT_LIQUIDITY,
+                        code,
                         message: &[],
                         triggered_by: &[],
                         data: &[],
                     }
                     .build()
                 })
-                .and_then(move |_| {
+                .and_then(move |balances| {
+                    publish_balance_changes(&events, (&from, -(incoming_amount as i64)), (&to, outgoing_amount as i64), balances);
                     next.send_request(request)
                         .or_else(move |err| store.undo_balance_update(from.clone(), incoming_amount, to.clone(), outgoing_amount)
                         .then(move |result| {
-                            if result.is_err() {
-                                error!("Error rolling back balance change for accounts: {} and {}. Incoming amount was: {}, outgoing amount was: {}", from.id(), to.id(), incoming_amount, outgoing_amount);
+                            match result {
+                                Ok(balances) => publish_balance_changes(&events, (&from, incoming_amount as i64), (&to, -(outgoing_amount as i64)), balances),
+                                Err(_) => error!("Error rolling back balance change for accounts: {} and {}. Incoming amount was: {}, outgoing amount was: {}", from.id(), to.id(), incoming_amount, outgoing_amount),
                             }
                             Err(err)
                         }))
+                })
+                .then(move |result| {
+                    transaction.reject_code = result.as_ref().err().map(Reject::code);
+                    if transaction.reject_code.is_none() || record_rejected {
+                        transaction.timestamp = now_millis();
+                        // The packet's outcome is the same whether or not it could be recorded,
+                        // so the response is not held up waiting for the ledger write
+                        spawn(ledger.record_transaction(transaction).map_err(|err| {
+                            error!("Error recording transaction in the ledger: {}", err)
+                        }));
+                    }
+                    result
                 }),
         )
     }
@@ -163,6 +163,7 @@ mod incoming {
     use interledger_packet::*;
     use interledger_service::incoming_service_fn;
     use std::{
+        str::FromStr,
         sync::{Arc, Mutex},
         time::SystemTime,
     };
@@ -183,7 +184,7 @@ mod incoming {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     expires_at: SystemTime::now() + Duration::from_secs(30),
                     execution_condition: &[
@@ -216,7 +217,7 @@ mod incoming {
             .handle_request(IncomingRequest {
                 from: TestAccount(0),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     expires_at: SystemTime::now() - Duration::from_secs(30),
                     execution_condition: &[
@@ -243,6 +244,7 @@ mod outgoing {
     use super::*;
     use interledger_packet::*;
     use std::{
+        str::FromStr,
         sync::{Arc, Mutex},
         time::SystemTime,
     };
@@ -264,7 +266,7 @@ mod outgoing {
                 from: TestAccount(1),
                 to: TestAccount(2),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     expires_at: SystemTime::now() + Duration::from_secs(30),
                     execution_condition: &[
@@ -298,7 +300,7 @@ mod outgoing {
                 from: TestAccount(1),
                 to: TestAccount(2),
                 prepare: PrepareBuilder {
-                    destination: b"example.destination",
+                    destination: &Address::from_str("example.destination").unwrap(),
                     amount: 100,
                     expires_at: SystemTime::now() + Duration::from_secs(30),
                     execution_condition: &[
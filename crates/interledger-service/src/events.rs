@@ -0,0 +1,166 @@
+use super::Account;
+use bytes::Bytes;
+use futures::sync::mpsc::{channel, Receiver, Sender};
+use interledger_packet::ErrorCode;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::warn;
+
+/// How many events a subscriber can fall behind by before it is disconnected
+const SUBSCRIBER_BUFFER_SIZE: usize = 1024;
+
+/// Something that happened to a packet or an account, published on an `EventBus`.
+#[derive(Clone, Debug)]
+pub enum Event<A: Account> {
+    /// A Prepare packet was sent from the `from` account to the `to` account.
+    /// The `amount` is denominated in the `from` account's asset.
+    PrepareForwarded {
+        from: A,
+        to: A,
+        destination: Bytes,
+        amount: u64,
+    },
+    /// A forwarded Prepare packet was fulfilled
+    Fulfilled {
+        from: A,
+        to: A,
+        amount: u64,
+    },
+    /// A forwarded Prepare packet was rejected, either by the next hop or by this node
+    Rejected {
+        from: A,
+        to: A,
+        amount: u64,
+        code: ErrorCode,
+    },
+    /// This node fulfilled a Prepare packet addressed to one of its own accounts (`to`).
+    /// The `amount` is denominated in the `to` account's asset.
+    PaymentReceived {
+        from: A,
+        to: A,
+        amount: u64,
+    },
+    /// The account's balance went up (positive `amount`) or down (negative `amount`)
+    /// and is now `balance`
+    BalanceChanged {
+        account: A,
+        amount: i64,
+        balance: i64,
+    },
+    ConnectionOpened {
+        account: A,
+    },
+    ConnectionClosed {
+        account: A,
+    },
+    /// A CCP route update from the peer was applied to this node's view of its routing table
+    RoutesReceived {
+        from: A,
+    },
+}
+
+/// Delivers `Event`s to every subscriber.
+///
+/// Cloning the bus shares the same set of subscribers, so one bus can be passed
+/// to all of the services that publish events.
+///
+/// Publishing never blocks: a subscriber that stops reading and falls too far behind
+/// is disconnected (its stream ends) instead of buffering events without limit.
+#[derive(Clone)]
+pub struct EventBus<A: Account> {
+    subscribers: Arc<Mutex<Vec<Sender<Event<A>>>>>,
+}
+
+impl<A: Account> EventBus<A> {
+    pub fn new() -> Self {
+        EventBus {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Get a stream of all events published after this is called.
+    /// Dropping the stream unsubscribes from the bus.
+    pub fn subscribe(&self) -> Receiver<Event<A>> {
+        let (sender, receiver) = channel(SUBSCRIBER_BUFFER_SIZE);
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: Event<A>) {
+        let mut subscribers = self.subscribers.lock();
+        let remaining = subscribers
+            .drain(..)
+            .filter_map(|mut subscriber| match subscriber.try_send(event.clone()) {
+                Ok(_) => Some(subscriber),
+                Err(ref err) if err.is_full() => {
+                    warn!(
+                        "Disconnecting event subscriber that fell more than {} events behind",
+                        SUBSCRIBER_BUFFER_SIZE
+                    );
+                    None
+                }
+                Err(_) => None,
+            })
+            .collect();
+        *subscribers = remaining;
+    }
+}
+
+impl<A: Account> Default for EventBus<A> {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+
+    #[derive(Clone, Debug)]
+    struct TestAccount;
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            0
+        }
+    }
+
+    fn connection_opened() -> Event<TestAccount> {
+        Event::ConnectionOpened {
+            account: TestAccount,
+        }
+    }
+
+    #[test]
+    fn delivers_events_to_every_subscriber() {
+        let events = EventBus::new();
+        let first = events.subscribe();
+        let second = events.subscribe();
+        events.publish(connection_opened());
+        drop(events);
+        assert_eq!(first.collect().wait().unwrap().len(), 1);
+        assert_eq!(second.collect().wait().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn disconnects_lagging_subscribers() {
+        let events = EventBus::new();
+        let lagging = events.subscribe();
+        for _ in 0..SUBSCRIBER_BUFFER_SIZE * 2 {
+            events.publish(connection_opened());
+        }
+        assert!(events.subscribers.lock().is_empty());
+        // The subscriber gets the events that were buffered and then its stream ends
+        let received = lagging.collect().wait().unwrap();
+        assert!(received.len() >= SUBSCRIBER_BUFFER_SIZE);
+        assert!(received.len() < SUBSCRIBER_BUFFER_SIZE * 2);
+
+        let subscriber = events.subscribe();
+        events.publish(connection_opened());
+        drop(events);
+        assert_eq!(subscriber.collect().wait().unwrap().len(), 1);
+    }
+}
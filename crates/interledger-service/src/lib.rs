@@ -28,13 +28,21 @@
 
 use futures::{Future, IntoFuture};
 use interledger_packet::{Fulfill, Prepare, Reject};
+use rand::random;
 use std::{
     cmp::Eq,
-    fmt::{Debug, Display},
+    error::Error as StdError,
+    fmt::{self, Debug, Display},
     hash::Hash,
     marker::PhantomData,
-    str::FromStr,
+    str::{self, FromStr},
 };
+use tracing::{field, info_span, Span};
+use tracing_futures::{Instrument, Instrumented};
+
+mod events;
+
+pub use events::{Event, EventBus};
 
 /// The base trait that Account types from other Services extend.
 /// This trait only assumes that the account has an ID that can be compared with others.
@@ -98,12 +106,62 @@ pub type BoxedIlpFuture = Box<Future<Item = Fulfill, Error = Reject> + Send + 's
 pub trait AccountStore {
     type Account: Account;
 
+    /// Load the accounts with the given IDs.
+    /// Errors with `StoreError::NotFound` if any of the accounts do not exist.
     fn get_accounts(
         &self,
         account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-    ) -> Box<Future<Item = Vec<Self::Account>, Error = ()> + Send>;
+    ) -> Box<Future<Item = Vec<Self::Account>, Error = StoreError> + Send>;
+}
+
+/// Errors returned by Store implementations.
+///
+/// Each variant carries a human-readable description of what went wrong so that
+/// callers can log it or pass it along (for example, in an API response).
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreError {
+    /// The requested record (such as an account) does not exist
+    NotFound(String),
+    /// The record conflicts with one that already exists
+    Conflict(String),
+    /// The operation would exceed a configured limit (such as an account's min balance)
+    LimitExceeded(String),
+    /// The underlying database could not be reached
+    Unavailable(String),
+    Other(String),
+}
+
+impl StoreError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, StoreError::NotFound(_))
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            StoreError::NotFound(message) => message,
+            StoreError::Conflict(message) => message,
+            StoreError::LimitExceeded(message) => message,
+            StoreError::Unavailable(message) => message,
+            StoreError::Other(message) => message,
+        }
+    }
 }
 
+impl Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            StoreError::NotFound(_) => "Not found",
+            StoreError::Conflict(_) => "Conflict",
+            StoreError::LimitExceeded(_) => "Limit exceeded",
+            StoreError::Unavailable(_) => "Store unavailable",
+            StoreError::Other(_) => "Store error",
+        };
+        write!(f, "{}: {}", kind, self.message())
+    }
+}
+
+impl StdError for StoreError {}
+
 /// Create an IncomingService that calls the given handler for each request.
 pub fn incoming_service_fn<A, B, F>(handler: F) -> ServiceFn<F, A>
 where
@@ -164,3 +222,33 @@ where
         Box::new((self.handler)(request).into_future())
     }
 }
+
+/// Create the tracing span for a Prepare packet arriving at this node.
+///
+/// The span has a random `id` so the logs from every service that handles the packet can be tied together.
+/// The `to` field is recorded once the packet has been routed.
+pub fn packet_span<A: Account>(request: &IncomingRequest<A>) -> Span {
+    info_span!(
+        "packet",
+        id = %format!("{:016x}", random::<u64>()),
+        from = %request.from.id(),
+        to = field::Empty,
+        destination = %str::from_utf8(request.prepare.destination()).unwrap_or("<not utf8>"),
+        amount = request.prepare.amount(),
+    )
+}
+
+/// Pass a request to the service inside the packet's span.
+/// Incoming edges (ILP-over-HTTP, BTP) should use this to start handling every packet.
+pub fn handle_request_in_span<S, A>(
+    service: &mut S,
+    request: IncomingRequest<A>,
+) -> Instrumented<S::Future>
+where
+    S: IncomingService<A>,
+    A: Account,
+{
+    let span = packet_span(&request);
+    let future = span.in_scope(|| service.handle_request(request));
+    future.instrument(span)
+}
@@ -1,8 +1,10 @@
 use super::{Error, SpspResponse};
 use futures::Future;
+use interledger_packet::Address;
 use interledger_service::{Account, IncomingService};
 use interledger_stream::send_money;
 use reqwest::r#async::Client;
+use std::str::FromStr;
 
 pub fn query(server: &str) -> impl Future<Item = SpspResponse, Error = Error> {
     let server = payment_pointer_to_url(server);
@@ -33,30 +35,35 @@ where
     A: Account,
 {
     trace!("Querying receiver: {}", receiver);
-    query(receiver).and_then(move |spsp| {
-        debug!(
-            "Sending SPSP payment to address: {}",
-            spsp.destination_account
-        );
-        send_money(
-            service,
-            &from_account,
-            spsp.destination_account.as_bytes(),
-            &spsp.shared_secret,
-            source_amount,
-        )
-        .map(move |(amount_delivered, _plugin)| {
-            debug!(
-                "Sent SPSP payment of {} and delivered {} of the receiver's units",
-                source_amount, amount_delivered
-            );
-            amount_delivered
+    query(receiver)
+        .and_then(|spsp| {
+            Address::from_str(&spsp.destination_account)
+                .map(|destination_account| (spsp, destination_account))
+                .map_err(|err| {
+                    Error::InvalidResponseError(format!("Invalid destination account: {}", err))
+                })
         })
-        .map_err(move |err| {
-            error!("Error sending payment: {:?}", err);
-            Error::SendMoneyError(source_amount)
+        .and_then(move |(spsp, destination_account)| {
+            debug!("Sending SPSP payment to address: {}", destination_account);
+            send_money(
+                service,
+                &from_account,
+                &destination_account,
+                &spsp.shared_secret,
+                source_amount,
+            )
+            .map(move |(amount_delivered, _plugin)| {
+                debug!(
+                    "Sent SPSP payment of {} and delivered {} of the receiver's units",
+                    source_amount, amount_delivered
+                );
+                amount_delivered
+            })
+            .map_err(move |err| {
+                error!("Error sending payment: {:?}", err);
+                Error::SendMoneyError(source_amount)
+            })
         })
-    })
 }
 
 fn payment_pointer_to_url(payment_pointer: &str) -> String {
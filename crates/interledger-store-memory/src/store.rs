@@ -8,7 +8,7 @@ use hashbrown::HashMap;
 use interledger_btp::{BtpOpenSignupAccount, BtpOpenSignupStore, BtpStore};
 use interledger_http::HttpStore;
 use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, AccountStore};
+use interledger_service::{Account as AccountTrait, AccountStore, StoreError};
 use parking_lot::{Mutex, RwLock};
 use std::{
     cmp::max,
@@ -111,7 +111,7 @@ impl AccountStore for InMemoryStore {
     fn get_accounts(
         &self,
         accounts_ids: Vec<u64>,
-    ) -> Box<Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Box<Future<Item = Vec<Account>, Error = StoreError> + Send> {
         let accounts: Vec<Account> = accounts_ids
             .iter()
             .filter_map(|account_id| self.accounts.read().get(account_id).cloned())
@@ -119,7 +119,10 @@ impl AccountStore for InMemoryStore {
         if accounts.len() == accounts_ids.len() {
             Box::new(ok(accounts))
         } else {
-            Box::new(err(()))
+            Box::new(err(StoreError::NotFound(format!(
+                "Not all of the accounts were found: {:?}",
+                accounts_ids
+            ))))
         }
     }
 }
@@ -130,11 +133,13 @@ impl HttpStore for InMemoryStore {
     fn get_account_from_http_auth(
         &self,
         auth_header: &str,
-    ) -> Box<Future<Item = Account, Error = ()> + Send> {
+    ) -> Box<Future<Item = Account, Error = StoreError> + Send> {
         if let Some(account_id) = self.http_auth.read().get(auth_header) {
             Box::new(ok(self.accounts.read()[account_id].clone()))
         } else {
-            Box::new(err(()))
+            Box::new(err(StoreError::NotFound(
+                "No account found with the given HTTP auth".to_string(),
+            )))
         }
     }
 }
@@ -151,11 +156,13 @@ impl BtpStore for InMemoryStore {
     fn get_account_from_btp_token(
         &self,
         token: &str,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send> {
         if let Some(account_id) = self.btp_auth.read().get(&(token.to_string())) {
             Box::new(ok(self.accounts.read()[account_id].clone()))
         } else {
-            Box::new(err(()))
+            Box::new(err(StoreError::NotFound(
+                "No account found with the given BTP token".to_string(),
+            )))
         }
     }
 }
@@ -166,7 +173,7 @@ impl BtpOpenSignupStore for InMemoryStore {
     fn create_btp_account<'a>(
         &self,
         account: BtpOpenSignupAccount<'a>,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send> {
         let account_id = {
             let next_id: u64 = *self.next_account_id.lock();
             *self.next_account_id.lock() += 1;
@@ -206,7 +213,11 @@ mod tests {
         assert_eq!(accounts[0].id(), 0);
         assert_eq!(accounts[1].id(), 4);
 
-        assert!(store.get_accounts(vec![0, 5]).wait().is_err());
+        assert!(store
+            .get_accounts(vec![0, 5])
+            .wait()
+            .unwrap_err()
+            .is_not_found());
     }
 
     #[test]
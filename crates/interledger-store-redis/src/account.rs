@@ -1,22 +1,27 @@
+use super::crypto::{decrypt_token, encrypt_token, DecryptionKey, EncryptionKey};
 use bytes::Bytes;
-use interledger_api::{AccountDetails, NodeAccount};
+use interledger_api::{AccountDetails, AccountSettings, NodeAccount};
 use interledger_btp::BtpAccount;
 use interledger_ccp::{CcpRoutingAccount, RoutingRelation};
 use interledger_http::HttpAccount;
 use interledger_ildcp::IldcpAccount;
+use interledger_packet::Address;
 use interledger_service::Account as AccountTrait;
 use interledger_service_util::MaxPacketAmountAccount;
+use interledger_webhooks::WebhookAccount;
 use redis::{from_redis_value, ErrorKind, FromRedisValue, RedisError, ToRedisArgs, Value};
 use serde::Serializer;
 use std::{
     collections::HashMap,
+    fmt,
     str::{self, FromStr},
 };
 use url::Url;
 
-const ACCOUNT_DETAILS_FIELDS: usize = 18;
+const ACCOUNT_DETAILS_FIELDS: usize = 22;
+const REDACTED: &str = "redacted";
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Account {
     pub(crate) id: u64,
     #[serde(serialize_with = "address_to_string")]
@@ -29,8 +34,9 @@ pub struct Account {
     #[serde(serialize_with = "optional_url_to_string")]
     pub(crate) http_endpoint: Option<Url>,
     pub(crate) http_incoming_authorization: Option<String>,
+    #[serde(serialize_with = "redact_optional_secret")]
     pub(crate) http_outgoing_authorization: Option<String>,
-    #[serde(serialize_with = "optional_url_to_string")]
+    #[serde(serialize_with = "optional_url_to_redacted_string")]
     pub(crate) btp_uri: Option<Url>,
     pub(crate) btp_incoming_authorization: Option<String>,
     pub(crate) is_admin: bool,
@@ -43,6 +49,11 @@ pub struct Account {
     pub(crate) routing_relation: RoutingRelation,
     pub(crate) send_routes: bool,
     pub(crate) receive_routes: bool,
+    #[serde(serialize_with = "optional_url_to_string")]
+    pub(crate) webhook_url: Option<Url>,
+    #[serde(serialize_with = "redact_optional_secret")]
+    pub(crate) webhook_secret: Option<String>,
+    pub(crate) webhook_balance_threshold: Option<i64>,
 }
 
 fn address_to_string<S>(address: &Bytes, serializer: S) -> Result<S::Ok, S::Error>
@@ -63,6 +74,32 @@ where
     }
 }
 
+fn redact_optional_secret<S>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if secret.is_some() {
+        serializer.serialize_str(REDACTED)
+    } else {
+        serializer.serialize_none()
+    }
+}
+
+fn optional_url_to_redacted_string<S>(url: &Option<Url>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    optional_url_to_string(&url.as_ref().map(redact_url_password), serializer)
+}
+
+fn redact_url_password(url: &Url) -> Url {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        url.set_password(Some(REDACTED)).unwrap();
+    }
+    url
+}
+
 fn routing_relation_to_string<S>(
     relation: &RoutingRelation,
     serializer: S,
@@ -90,9 +127,16 @@ impl Account {
         } else {
             RoutingRelation::Child
         };
+        let webhook_url = if let Some(ref url) = details.webhook_url {
+            Some(Url::parse(url).map_err(|err| error!("Invalid URL: {:?}", err))?)
+        } else {
+            None
+        };
+        let ilp_address = Address::try_from(Bytes::from(details.ilp_address))
+            .map_err(|err| error!("Invalid ILP address: {}", err))?;
         Ok(Account {
             id,
-            ilp_address: Bytes::from(details.ilp_address),
+            ilp_address: ilp_address.to_bytes(),
             asset_code: details.asset_code.to_uppercase(),
             asset_scale: details.asset_scale,
             max_packet_amount: details.max_packet_amount,
@@ -109,75 +153,223 @@ impl Account {
             send_routes: details.send_routes,
             receive_routes: details.receive_routes,
             routing_relation,
+            webhook_url,
+            webhook_secret: details.webhook_secret,
+            webhook_balance_threshold: details.webhook_balance_threshold,
         })
     }
+
+    pub(crate) fn encrypt_tokens(
+        mut self,
+        encryption_key: &EncryptionKey,
+    ) -> AccountWithEncryptedTokens {
+        let encrypted_http_outgoing_authorization = self
+            .http_outgoing_authorization
+            .take()
+            .map(|auth| encrypt_token(encryption_key, auth.as_bytes()));
+        let encrypted_btp_outgoing_token = if let Some(ref mut btp_uri) = self.btp_uri {
+            let token = btp_uri
+                .password()
+                .map(|token| encrypt_token(encryption_key, token.as_bytes()));
+            btp_uri.set_password(None).unwrap();
+            token
+        } else {
+            None
+        };
+        let encrypted_webhook_secret = self
+            .webhook_secret
+            .take()
+            .map(|secret| encrypt_token(encryption_key, secret.as_bytes()));
+        AccountWithEncryptedTokens {
+            account: self,
+            encrypted_http_outgoing_authorization,
+            encrypted_btp_outgoing_token,
+            encrypted_webhook_secret,
+        }
+    }
+}
+
+// Outgoing credentials are redacted so they do not end up in the logs
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("id", &self.id)
+            .field(
+                "ilp_address",
+                &str::from_utf8(&self.ilp_address[..]).map_err(|_| fmt::Error)?,
+            )
+            .field("asset_code", &self.asset_code)
+            .field("asset_scale", &self.asset_scale)
+            .field("max_packet_amount", &self.max_packet_amount)
+            .field("min_balance", &self.min_balance)
+            .field("http_endpoint", &self.http_endpoint)
+            .field(
+                "http_incoming_authorization",
+                &self.http_incoming_authorization,
+            )
+            .field(
+                "http_outgoing_authorization",
+                &self.http_outgoing_authorization.as_ref().map(|_| REDACTED),
+            )
+            .field("btp_uri", &self.btp_uri.as_ref().map(redact_url_password))
+            .field(
+                "btp_incoming_authorization",
+                &self.btp_incoming_authorization,
+            )
+            .field("is_admin", &self.is_admin)
+            .field("xrp_address", &self.xrp_address)
+            .field("settle_threshold", &self.settle_threshold)
+            .field("settle_to", &self.settle_to)
+            .field("routing_relation", &self.routing_relation)
+            .field("send_routes", &self.send_routes)
+            .field("receive_routes", &self.receive_routes)
+            .field("webhook_url", &self.webhook_url)
+            .field(
+                "webhook_secret",
+                &self.webhook_secret.as_ref().map(|_| REDACTED),
+            )
+            .field("webhook_balance_threshold", &self.webhook_balance_threshold)
+            .finish()
+    }
 }
 
-impl ToRedisArgs for Account {
+/// The form an Account is stored in Redis, with the outgoing credentials
+/// (the HTTP outgoing authorization and the password of the BTP URI)
+/// and the webhook secret encrypted.
+#[derive(Clone)]
+pub(crate) struct AccountWithEncryptedTokens {
+    pub(crate) account: Account,
+    encrypted_http_outgoing_authorization: Option<Bytes>,
+    encrypted_btp_outgoing_token: Option<Bytes>,
+    encrypted_webhook_secret: Option<Bytes>,
+}
+
+impl AccountWithEncryptedTokens {
+    pub(crate) fn decrypt_tokens(mut self, decryption_key: &DecryptionKey) -> Result<Account, ()> {
+        let account_id = self.account.id;
+        if let Some(ref encrypted) = self.encrypted_http_outgoing_authorization {
+            let auth = decrypt_token(decryption_key, &encrypted[..])
+                .and_then(|auth| String::from_utf8(auth.to_vec()).map_err(|_| ()))
+                .map_err(|_| {
+                    error!(
+                        "Unable to decrypt HTTP outgoing authorization for account: {}",
+                        account_id
+                    )
+                })?;
+            self.account.http_outgoing_authorization = Some(auth);
+        }
+        if let Some(ref encrypted) = self.encrypted_btp_outgoing_token {
+            let token = decrypt_token(decryption_key, &encrypted[..])
+                .and_then(|token| String::from_utf8(token.to_vec()).map_err(|_| ()))
+                .map_err(|_| error!("Unable to decrypt BTP token for account: {}", account_id))?;
+            if let Some(ref mut btp_uri) = self.account.btp_uri {
+                btp_uri.set_password(Some(token.as_str())).map_err(|_| {
+                    error!("Unable to set BTP token on URI for account: {}", account_id)
+                })?;
+            }
+        }
+        if let Some(ref encrypted) = self.encrypted_webhook_secret {
+            let secret = decrypt_token(decryption_key, &encrypted[..])
+                .and_then(|secret| String::from_utf8(secret.to_vec()).map_err(|_| ()))
+                .map_err(|_| {
+                    error!(
+                        "Unable to decrypt webhook secret for account: {}",
+                        account_id
+                    )
+                })?;
+            self.account.webhook_secret = Some(secret);
+        }
+        Ok(self.account)
+    }
+}
+
+impl ToRedisArgs for AccountWithEncryptedTokens {
     fn write_redis_args(&self, out: &mut Vec<Vec<u8>>) {
         let mut rv = Vec::with_capacity(ACCOUNT_DETAILS_FIELDS * 2);
+        let account = &self.account;
 
         "id".write_redis_args(&mut rv);
-        self.id.write_redis_args(&mut rv);
-        if !self.ilp_address.is_empty() {
+        account.id.write_redis_args(&mut rv);
+        if !account.ilp_address.is_empty() {
             "ilp_address".write_redis_args(&mut rv);
-            rv.push(self.ilp_address.to_vec());
+            rv.push(account.ilp_address.to_vec());
         }
-        if !self.asset_code.is_empty() {
+        if !account.asset_code.is_empty() {
             "asset_code".write_redis_args(&mut rv);
-            self.asset_code.write_redis_args(&mut rv);
+            account.asset_code.write_redis_args(&mut rv);
         }
         "asset_scale".write_redis_args(&mut rv);
-        self.asset_scale.write_redis_args(&mut rv);
+        account.asset_scale.write_redis_args(&mut rv);
         "max_packet_amount".write_redis_args(&mut rv);
-        self.max_packet_amount.write_redis_args(&mut rv);
+        account.max_packet_amount.write_redis_args(&mut rv);
         "is_admin".write_redis_args(&mut rv);
-        self.is_admin.write_redis_args(&mut rv);
+        account.is_admin.write_redis_args(&mut rv);
         "routing_relation".write_redis_args(&mut rv);
-        self.routing_relation.to_string().write_redis_args(&mut rv);
+        account
+            .routing_relation
+            .to_string()
+            .write_redis_args(&mut rv);
         "min_balance".write_redis_args(&mut rv);
-        self.min_balance.write_redis_args(&mut rv);
+        account.min_balance.write_redis_args(&mut rv);
 
         // Write optional fields
-        if let Some(http_endpoint) = self.http_endpoint.as_ref() {
+        if let Some(http_endpoint) = account.http_endpoint.as_ref() {
             "http_endpoint".write_redis_args(&mut rv);
             http_endpoint.as_str().write_redis_args(&mut rv);
         }
-        if let Some(http_incoming_authorization) = self.http_incoming_authorization.as_ref() {
+        if let Some(http_incoming_authorization) = account.http_incoming_authorization.as_ref() {
             "http_incoming_authorization".write_redis_args(&mut rv);
             http_incoming_authorization.write_redis_args(&mut rv);
         }
-        if let Some(http_outgoing_authorization) = self.http_outgoing_authorization.as_ref() {
+        if let Some(http_outgoing_authorization) =
+            self.encrypted_http_outgoing_authorization.as_ref()
+        {
             "http_outgoing_authorization".write_redis_args(&mut rv);
-            http_outgoing_authorization.write_redis_args(&mut rv);
+            rv.push(http_outgoing_authorization.to_vec());
         }
-        if let Some(btp_uri) = self.btp_uri.as_ref() {
+        if let Some(btp_uri) = account.btp_uri.as_ref() {
             "btp_uri".write_redis_args(&mut rv);
             btp_uri.as_str().write_redis_args(&mut rv);
         }
-        if let Some(btp_incoming_authorization) = self.btp_incoming_authorization.as_ref() {
+        if let Some(btp_outgoing_token) = self.encrypted_btp_outgoing_token.as_ref() {
+            "btp_outgoing_token".write_redis_args(&mut rv);
+            rv.push(btp_outgoing_token.to_vec());
+        }
+        if let Some(btp_incoming_authorization) = account.btp_incoming_authorization.as_ref() {
             "btp_incoming_authorization".write_redis_args(&mut rv);
             btp_incoming_authorization.write_redis_args(&mut rv);
         }
-        if let Some(xrp_address) = self.xrp_address.as_ref() {
+        if let Some(xrp_address) = account.xrp_address.as_ref() {
             "xrp_address".write_redis_args(&mut rv);
             xrp_address.write_redis_args(&mut rv);
         }
-        if let Some(settle_threshold) = self.settle_threshold {
+        if let Some(settle_threshold) = account.settle_threshold {
             "settle_threshold".write_redis_args(&mut rv);
             settle_threshold.write_redis_args(&mut rv);
         }
-        if let Some(settle_to) = self.settle_to {
+        if let Some(settle_to) = account.settle_to {
             "settle_to".write_redis_args(&mut rv);
             settle_to.write_redis_args(&mut rv);
         }
-        if self.send_routes {
+        if account.send_routes {
             "send_routes".write_redis_args(&mut rv);
-            self.send_routes.write_redis_args(&mut rv);
+            account.send_routes.write_redis_args(&mut rv);
         }
-        if self.receive_routes {
+        if account.receive_routes {
             "receive_routes".write_redis_args(&mut rv);
-            self.receive_routes.write_redis_args(&mut rv);
+            account.receive_routes.write_redis_args(&mut rv);
+        }
+        if let Some(webhook_url) = account.webhook_url.as_ref() {
+            "webhook_url".write_redis_args(&mut rv);
+            webhook_url.as_str().write_redis_args(&mut rv);
+        }
+        if let Some(webhook_secret) = self.encrypted_webhook_secret.as_ref() {
+            "webhook_secret".write_redis_args(&mut rv);
+            rv.push(webhook_secret.to_vec());
+        }
+        if let Some(webhook_balance_threshold) = account.webhook_balance_threshold {
+            "webhook_balance_threshold".write_redis_args(&mut rv);
+            webhook_balance_threshold.write_redis_args(&mut rv);
         }
 
         debug_assert!(rv.len() <= ACCOUNT_DETAILS_FIELDS * 2);
@@ -187,7 +379,7 @@ impl ToRedisArgs for Account {
     }
 }
 
-impl FromRedisValue for Account {
+impl FromRedisValue for AccountWithEncryptedTokens {
     fn from_redis_value(v: &Value) -> Result<Self, RedisError> {
         let hash: HashMap<String, Value> = HashMap::from_redis_value(v)?;
         let ilp_address: String = get_value("ilp_address", &hash)?;
@@ -198,14 +390,14 @@ impl FromRedisValue for Account {
         } else {
             RoutingRelation::Child
         };
-        Ok(Account {
+        let account = Account {
             id: get_value("id", &hash)?,
             ilp_address: Bytes::from(ilp_address.as_bytes()),
             asset_code: get_value("asset_code", &hash)?,
             asset_scale: get_value("asset_scale", &hash)?,
             http_endpoint: get_url_option("http_endpoint", &hash)?,
             http_incoming_authorization: get_value_option("http_incoming_authorization", &hash)?,
-            http_outgoing_authorization: get_value_option("http_outgoing_authorization", &hash)?,
+            http_outgoing_authorization: None,
             btp_uri: get_url_option("btp_uri", &hash)?,
             btp_incoming_authorization: get_value_option("btp_incoming_authorization", &hash)?,
             max_packet_amount: get_value("max_packet_amount", &hash)?,
@@ -217,6 +409,18 @@ impl FromRedisValue for Account {
             routing_relation,
             send_routes: get_bool("send_routes", &hash),
             receive_routes: get_bool("receive_routes", &hash),
+            webhook_url: get_url_option("webhook_url", &hash)?,
+            webhook_secret: None,
+            webhook_balance_threshold: get_value_option("webhook_balance_threshold", &hash)?,
+        };
+        Ok(AccountWithEncryptedTokens {
+            account,
+            encrypted_http_outgoing_authorization: get_bytes_option(
+                "http_outgoing_authorization",
+                &hash,
+            )?,
+            encrypted_btp_outgoing_token: get_bytes_option("btp_outgoing_token", &hash)?,
+            encrypted_webhook_secret: get_bytes_option("webhook_secret", &hash)?,
         })
     }
 }
@@ -247,6 +451,15 @@ where
     }
 }
 
+fn get_bytes_option(key: &str, map: &HashMap<String, Value>) -> Result<Option<Bytes>, RedisError> {
+    if let Some(value) = map.get(key) {
+        let value: Vec<u8> = from_redis_value(value)?;
+        Ok(Some(Bytes::from(value)))
+    } else {
+        Ok(None)
+    }
+}
+
 fn get_url_option(key: &str, map: &HashMap<String, Value>) -> Result<Option<Url>, RedisError> {
     if let Some(ref value) = map.get(key) {
         let value: String = from_redis_value(value)?;
@@ -321,6 +534,17 @@ impl NodeAccount for Account {
     fn is_admin(&self) -> bool {
         self.is_admin
     }
+
+    fn settings(&self) -> AccountSettings {
+        AccountSettings {
+            max_packet_amount: self.max_packet_amount,
+            min_balance: self.min_balance,
+            settle_threshold: self.settle_threshold,
+            settle_to: self.settle_to,
+            send_routes: self.send_routes,
+            receive_routes: self.receive_routes,
+        }
+    }
 }
 
 impl CcpRoutingAccount for Account {
@@ -336,3 +560,17 @@ impl CcpRoutingAccount for Account {
         self.receive_routes
     }
 }
+
+impl WebhookAccount for Account {
+    fn webhook_url(&self) -> Option<&Url> {
+        self.webhook_url.as_ref()
+    }
+
+    fn webhook_secret(&self) -> Option<&[u8]> {
+        self.webhook_secret.as_ref().map(|secret| secret.as_bytes())
+    }
+
+    fn webhook_balance_threshold(&self) -> Option<i64> {
+        self.webhook_balance_threshold
+    }
+}
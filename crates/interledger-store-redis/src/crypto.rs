@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use ring::{
+    aead, digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+const NONCE_LENGTH: usize = 12;
+static ENCRYPTION_KEY_GENERATION_STRING: &[u8] = b"ilp_store_redis_encryption_key";
+
+/// Key used to encrypt outgoing credentials before they are written to Redis.
+pub(crate) struct EncryptionKey(aead::SealingKey);
+
+/// Key used to decrypt outgoing credentials when Accounts are loaded from Redis.
+pub(crate) struct DecryptionKey(aead::OpeningKey);
+
+/// Derive the AES-256-GCM key pair from the node's server secret.
+pub(crate) fn generate_keys(server_secret: &[u8]) -> (EncryptionKey, DecryptionKey) {
+    let signing_key = hmac::SigningKey::new(&digest::SHA256, server_secret);
+    let key = hmac::sign(&signing_key, ENCRYPTION_KEY_GENERATION_STRING);
+    let encryption_key = aead::SealingKey::new(&aead::AES_256_GCM, key.as_ref())
+        .expect("Failed to create encryption key");
+    let decryption_key = aead::OpeningKey::new(&aead::AES_256_GCM, key.as_ref())
+        .expect("Failed to create decryption key");
+    (EncryptionKey(encryption_key), DecryptionKey(decryption_key))
+}
+
+/// Encrypt the token with a random nonce. The output is `nonce, ciphertext, auth tag`.
+pub(crate) fn encrypt_token(encryption_key: &EncryptionKey, token: &[u8]) -> Bytes {
+    let mut nonce: [u8; NONCE_LENGTH] = [0; NONCE_LENGTH];
+    SystemRandom::new()
+        .fill(&mut nonce[..])
+        .expect("Failed to securely generate a random nonce!");
+
+    let tag_len = aead::AES_256_GCM.tag_len();
+    let mut in_out = Vec::with_capacity(NONCE_LENGTH + token.len() + tag_len);
+    in_out.extend_from_slice(&nonce[..]);
+    in_out.extend_from_slice(token);
+    in_out.extend_from_slice(&vec![0; tag_len][..]);
+
+    aead::seal_in_place(
+        &encryption_key.0,
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::empty(),
+        &mut in_out[NONCE_LENGTH..],
+        tag_len,
+    )
+    .expect("Failed to encrypt token");
+    Bytes::from(in_out)
+}
+
+pub(crate) fn decrypt_token(decryption_key: &DecryptionKey, encrypted: &[u8]) -> Result<Bytes, ()> {
+    if encrypted.len() < NONCE_LENGTH + aead::AES_256_GCM.tag_len() {
+        return Err(());
+    }
+    let mut nonce: [u8; NONCE_LENGTH] = [0; NONCE_LENGTH];
+    nonce.copy_from_slice(&encrypted[..NONCE_LENGTH]);
+    let mut ciphertext = encrypted[NONCE_LENGTH..].to_vec();
+
+    let token = aead::open_in_place(
+        &decryption_key.0,
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::empty(),
+        0,
+        &mut ciphertext[..],
+    )
+    .map_err(|_| ())?;
+    Ok(Bytes::from(&token[..]))
+}
+
+/// Whether a stored credential was written in plaintext, before credentials were encrypted.
+/// Anything that decrypts with this key, or is not valid UTF-8, is treated as encrypted.
+pub(crate) fn is_plaintext(decryption_key: &DecryptionKey, value: &[u8]) -> bool {
+    decrypt_token(decryption_key, value).is_err() && std::str::from_utf8(value).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_tokens() {
+        let (encryption_key, decryption_key) = generate_keys(&[0; 32]);
+        let encrypted = encrypt_token(&encryption_key, b"test token");
+        assert_ne!(&encrypted[..], &b"test token"[..]);
+        let decrypted = decrypt_token(&decryption_key, &encrypted[..]).unwrap();
+        assert_eq!(&decrypted[..], &b"test token"[..]);
+    }
+
+    #[test]
+    fn fails_with_wrong_key() {
+        let (encryption_key, _) = generate_keys(&[0; 32]);
+        let (_, decryption_key) = generate_keys(&[1; 32]);
+        let encrypted = encrypt_token(&encryption_key, b"test token");
+        assert!(decrypt_token(&decryption_key, &encrypted[..]).is_err());
+    }
+
+    #[test]
+    fn fails_on_truncated_ciphertext() {
+        let (encryption_key, decryption_key) = generate_keys(&[0; 32]);
+        let encrypted = encrypt_token(&encryption_key, b"test token");
+        assert!(decrypt_token(&decryption_key, &encrypted[..10]).is_err());
+    }
+
+    #[test]
+    fn detects_plaintext_tokens() {
+        let (encryption_key, decryption_key) = generate_keys(&[0; 32]);
+        let encrypted = encrypt_token(&encryption_key, b"test token");
+        assert!(!is_plaintext(&decryption_key, &encrypted[..]));
+        assert!(is_plaintext(&decryption_key, b"test token"));
+    }
+}
@@ -7,6 +7,7 @@ extern crate log;
 extern crate serde;
 
 mod account;
+mod crypto;
 mod store;
 
 pub use account::Account;
@@ -1,26 +1,33 @@
 use super::account::*;
+use super::crypto::{encrypt_token, generate_keys, is_plaintext, DecryptionKey, EncryptionKey};
 use bytes::Bytes;
 use futures::{
     future::{err, ok, result, Either},
     Future, Stream,
 };
 use hashbrown::{HashMap, HashSet};
-use interledger_api::{AccountDetails, NodeStore};
+use interledger_api::{AccountDetails, AccountSettings, ApiScope, NodeStore};
 use interledger_btp::BtpStore;
-use interledger_ccp::RouteManagerStore;
+use interledger_ccp::{RouteManagerStore, RoutePolicies};
 use interledger_http::HttpStore;
+use interledger_packet::ErrorCode;
 use interledger_router::RouterStore;
-use interledger_service::{Account as AccountTrait, AccountStore};
-use interledger_service_util::{BalanceStore, ExchangeRateStore};
-use parking_lot::RwLock;
-use redis::{self, cmd, r#async::SharedConnection, Client, PipelineCommands, Value};
+use interledger_service::{Account as AccountTrait, AccountStore, StoreError};
+use interledger_service_util::{BalanceStore, ExchangeRateStore, Transaction, TransactionStore};
+use interledger_webhooks::{WebhookDelivery, WebhookStore};
+use parking_lot::{Mutex, RwLock};
+use redis::{self, cmd, r#async::SharedConnection, Client, PipelineCommands, RedisError, Value};
+use serde::{Deserialize, Serialize};
 use std::{
     iter::FromIterator,
+    str::FromStr,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
+use stream_cancel::{Trigger, Valve};
 use tokio_executor::spawn;
 use tokio_timer::Interval;
+use url::Url;
 
 const POLL_INTERVAL: u64 = 60000; // 1 minute
 
@@ -42,17 +49,62 @@ if min_balance then
     min_balance = tonumber(min_balance)
     local balance = tonumber(redis.call('HGET', 'balances:' .. from_asset_code, from_id))
     if balance < min_balance + from_amount then
-        error('Cannot subtract ' .. from_amount .. ' from balance. Current balance of account: ' .. from_id .. ' is: ' .. balance .. ' and min balance is: ' .. min_balance)
+        return redis.error_reply('LIMIT_EXCEEDED Cannot subtract ' .. from_amount .. ' from balance. Current balance of account: ' .. from_id .. ' is: ' .. balance .. ' and min balance is: ' .. min_balance)
     end
 end
 local from_balance = redis.call('HINCRBY', 'balances:' .. from_asset_code, from_id, 0 - from_amount)
 local to_balance = redis.call('HINCRBY', 'balances:' .. to_asset_code, to_id, to_amount)
 return {from_balance, to_balance}";
 
+// The records are serialized without an ID, so the ID is spliced in as the first field.
+// Without it, two identical transactions in the same millisecond would be stored as one member
+static RECORD_TRANSACTION: &str = "
+local id = redis.call('INCR', KEYS[3])
+local record = '{\"id\":' .. id .. ',' .. string.sub(ARGV[2], 2)
+redis.call('ZADD', KEYS[1], ARGV[1], record)
+redis.call('ZADD', KEYS[2], ARGV[1], record)
+return id";
+
+// Checking that the account exists and updating it in one script means the account
+// cannot be deleted in between (which would leave behind a partial account record)
+static UPDATE_ACCOUNT_SETTINGS: &str = "
+local account_key = KEYS[1]
+local send_routes_to_key = KEYS[2]
+if redis.call('EXISTS', account_key) == 0 then
+    return redis.error_reply('NOT_FOUND Account ' .. ARGV[1] .. ' does not exist')
+end
+redis.call('HMSET', account_key, 'max_packet_amount', ARGV[2], 'min_balance', ARGV[3], 'send_routes', ARGV[4], 'receive_routes', ARGV[5])
+if ARGV[4] == 'true' then
+    redis.call('SADD', send_routes_to_key, ARGV[1])
+else
+    redis.call('SREM', send_routes_to_key, ARGV[1])
+end
+local optional_fields = {settle_threshold = ARGV[6], settle_to = ARGV[7]}
+for field, value in pairs(optional_fields) do
+    if value == '' then
+        redis.call('HDEL', account_key, field)
+    else
+        redis.call('HSET', account_key, field, value)
+    end
+end
+return redis.status_reply('OK')";
+
 static ROUTES_KEY: &str = "routes";
 static RATES_KEY: &str = "rates";
 static STATIC_ROUTES_KEY: &str = "routes:static";
+/// Hash of account IDs to the JSON-encoded CCP route policies for that account
+static ROUTE_POLICIES_KEY: &str = "routes:policies";
 static NEXT_ACCOUNT_ID_KEY: &str = "next_account_id";
+static NEXT_TRANSACTION_ID_KEY: &str = "next_transaction_id";
+static SEND_ROUTES_TO_KEY: &str = "send_routes_to";
+/// Hash of API token IDs (the SHA-256 hashes of the tokens) to the scopes they grant
+static API_TOKENS_KEY: &str = "api_tokens";
+/// Error code returned by the UPDATE_BALANCES script when the min balance would be exceeded
+static LIMIT_EXCEEDED_ERROR_CODE: &str = "LIMIT_EXCEEDED";
+/// Error code returned by scripts when the record they modify does not exist
+static NOT_FOUND_ERROR_CODE: &str = "NOT_FOUND";
+/// Number of webhook deliveries kept in each account's log
+const WEBHOOK_DELIVERIES_TO_KEEP: isize = 100;
 
 fn account_details_key(account_id: u64) -> String {
     format!("accounts:{}", account_id)
@@ -62,18 +114,93 @@ fn balance_key(asset_code: &str) -> String {
     format!("balances:{}", asset_code.to_lowercase())
 }
 
+/// The CCP Route Manager's saved tables for the node with the given ILP address
+fn routing_state_key(ilp_address: &[u8]) -> String {
+    format!("routes:ccp_state:{}", String::from_utf8_lossy(ilp_address))
+}
+
+fn transactions_key(account_id: u64) -> String {
+    format!("transactions:{}", account_id)
+}
+
+fn webhook_deliveries_key(account_id: u64) -> String {
+    format!("webhook_deliveries:{}", account_id)
+}
+
+/// Transactions are stored as JSON in a sorted set for each account, scored by their timestamp.
+#[derive(Serialize, Deserialize)]
+struct TransactionRecord {
+    /// Assigned by the store when the record is saved (records saved before IDs were added have none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    timestamp: u64,
+    from_account: u64,
+    to_account: u64,
+    incoming_amount: u64,
+    outgoing_amount: u64,
+    execution_condition: String,
+    reject_code: Option<String>,
+}
+
+impl From<Transaction<u64>> for TransactionRecord {
+    fn from(transaction: Transaction<u64>) -> Self {
+        TransactionRecord {
+            id: None,
+            timestamp: transaction.timestamp,
+            from_account: transaction.from_account,
+            to_account: transaction.to_account,
+            incoming_amount: transaction.incoming_amount,
+            outgoing_amount: transaction.outgoing_amount,
+            execution_condition: hex::encode(&transaction.execution_condition[..]),
+            reject_code: transaction.reject_code.map(|code| code.to_string()),
+        }
+    }
+}
+
+impl TransactionRecord {
+    fn into_transaction(self) -> Option<Transaction<u64>> {
+        let mut execution_condition = [0; 32];
+        let condition = hex::decode(&self.execution_condition).ok()?;
+        if condition.len() != 32 {
+            return None;
+        }
+        execution_condition.copy_from_slice(&condition[..]);
+        let reject_code = match self.reject_code {
+            Some(code) if code.len() == 3 => {
+                let mut bytes = [0; 3];
+                bytes.copy_from_slice(code.as_bytes());
+                Some(ErrorCode::new(bytes))
+            }
+            Some(_) => return None,
+            None => None,
+        };
+        Some(Transaction {
+            timestamp: self.timestamp,
+            from_account: self.from_account,
+            to_account: self.to_account,
+            incoming_amount: self.incoming_amount,
+            outgoing_amount: self.outgoing_amount,
+            execution_condition,
+            reject_code,
+        })
+    }
+}
+
 pub use redis::IntoConnectionInfo;
 
-pub fn connect<R>(redis_uri: R) -> impl Future<Item = RedisStore, Error = ()>
+/// Connect to the Redis database. The `secret` is used to derive the key
+/// that encrypts outgoing account credentials stored in the database.
+pub fn connect<R>(redis_uri: R, secret: [u8; 32]) -> impl Future<Item = RedisStore, Error = ()>
 where
     R: IntoConnectionInfo,
 {
-    connect_with_poll_interval(redis_uri, POLL_INTERVAL)
+    connect_with_poll_interval(redis_uri, secret, POLL_INTERVAL)
 }
 
 #[doc(hidden)]
 pub fn connect_with_poll_interval<R>(
     redis_uri: R,
+    secret: [u8; 32],
     poll_interval: u64,
 ) -> impl Future<Item = RedisStore, Error = ()>
 where
@@ -88,24 +215,40 @@ where
                 .map_err(|err| error!("Error connecting to Redis: {:?}", err))
         })
         .and_then(move |connection| {
+            let (encryption_key, decryption_key) = generate_keys(&secret[..]);
+            let (stop_polling, polling_valve) = Valve::new();
             let store = RedisStore {
                 connection: Arc::new(connection),
                 exchange_rates: Arc::new(RwLock::new(HashMap::new())),
+                rates_updated_at: Arc::new(RwLock::new(None)),
                 routes: Arc::new(RwLock::new(HashMap::new())),
+                encryption_key: Arc::new(encryption_key),
+                decryption_key: Arc::new(decryption_key),
+                stop_polling: Arc::new(Mutex::new(Some(stop_polling))),
             };
 
             // Start polling for rate updates
             // Note: if this behavior changes, make sure to update the Drop implementation
             let connection_clone = Arc::downgrade(&store.connection);
             let exchange_rates = store.exchange_rates.clone();
-            let poll_rates = Interval::new(Instant::now(), Duration::from_millis(poll_interval))
+            let rates_updated_at = store.rates_updated_at.clone();
+            let poll_rates = polling_valve
+                .wrap(Interval::new(
+                    Instant::now(),
+                    Duration::from_millis(poll_interval),
+                ))
                 .map_err(|err| error!("Interval error: {:?}", err))
                 .for_each(move |_| {
                     if let Some(connection) = connection_clone.upgrade() {
-                        Either::A(update_rates(
-                            connection.as_ref().clone(),
-                            exchange_rates.clone(),
-                        ))
+                        // Keep polling if this fails so the rates are loaded again once Redis is back
+                        Either::A(
+                            update_rates(
+                                connection.as_ref().clone(),
+                                exchange_rates.clone(),
+                                rates_updated_at.clone(),
+                            )
+                            .then(|_| Ok(())),
+                        )
                     } else {
                         debug!("Not polling rates anymore because connection was closed");
                         // TODO make sure the interval stops
@@ -118,14 +261,18 @@ where
             // Note: if this behavior changes, make sure to update the Drop implementation
             let connection_clone = Arc::downgrade(&store.connection);
             let routing_table = store.routes.clone();
-            let poll_routes = Interval::new(Instant::now(), Duration::from_millis(poll_interval))
+            let poll_routes = polling_valve
+                .wrap(Interval::new(
+                    Instant::now(),
+                    Duration::from_millis(poll_interval),
+                ))
                 .map_err(|err| error!("Interval error: {:?}", err))
                 .for_each(move |_| {
                     if let Some(connection) = connection_clone.upgrade() {
-                        Either::A(update_routes(
-                            connection.as_ref().clone(),
-                            routing_table.clone(),
-                        ))
+                        Either::A(
+                            update_routes(connection.as_ref().clone(), routing_table.clone())
+                                .then(|_| Ok(())),
+                        )
                     } else {
                         debug!("Not polling routes anymore because connection was closed");
                         // TODO make sure the interval stops
@@ -134,10 +281,98 @@ where
                 });
             spawn(poll_routes);
 
-            Ok(store)
+            migrate_plaintext_credentials(
+                store.connection.as_ref().clone(),
+                store.encryption_key.clone(),
+                store.decryption_key.clone(),
+            )
+            .map(move |_| store)
         })
 }
 
+/// The HTTP outgoing authorization, BTP URI and BTP outgoing token stored for an account
+type StoredCredentials = (Option<Vec<u8>>, Option<String>, Option<Vec<u8>>);
+
+/// Encrypt any outgoing credentials that were stored in plaintext by versions of the
+/// store that did not encrypt them. Credentials that are already encrypted are left alone.
+fn migrate_plaintext_credentials(
+    connection: SharedConnection,
+    encryption_key: Arc<EncryptionKey>,
+    decryption_key: Arc<DecryptionKey>,
+) -> impl Future<Item = (), Error = ()> {
+    cmd("GET")
+        .arg(NEXT_ACCOUNT_ID_KEY)
+        .query_async(connection)
+        .and_then(|(connection, next_account_id): (_, Option<u64>)| {
+            let num_accounts = next_account_id.unwrap_or(0);
+            if num_accounts == 0 {
+                return Either::A(ok((connection, Vec::new())));
+            }
+            let mut pipe = redis::pipe();
+            for account_id in 0..num_accounts {
+                pipe.cmd("HMGET")
+                    .arg(account_details_key(account_id))
+                    .arg("http_outgoing_authorization")
+                    .arg("btp_uri")
+                    .arg("btp_outgoing_token");
+            }
+            Either::B(pipe.query_async(connection))
+        })
+        .map_err(|err| error!("Error loading account credentials to migrate: {:?}", err))
+        .and_then(
+            move |(connection, credentials): (_, Vec<StoredCredentials>)| {
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                let mut migrated = 0;
+                for (account_id, (http_auth, btp_uri, btp_token)) in
+                    credentials.into_iter().enumerate()
+                {
+                    let key = account_details_key(account_id as u64);
+                    let mut changed = false;
+                    if let Some(http_auth) = http_auth {
+                        if is_plaintext(&decryption_key, &http_auth[..]) {
+                            pipe.hset(
+                                &key,
+                                "http_outgoing_authorization",
+                                encrypt_token(&encryption_key, &http_auth[..]).to_vec(),
+                            )
+                            .ignore();
+                            changed = true;
+                        }
+                    }
+                    // Before encryption, the BTP token was stored as the password of the URI
+                    if let Some(mut btp_uri) = btp_uri.and_then(|uri| Url::parse(&uri).ok()) {
+                        if let (Some(token), None) = (btp_uri.password(), btp_token.as_ref()) {
+                            let token = encrypt_token(&encryption_key, token.as_bytes());
+                            btp_uri.set_password(None).unwrap();
+                            pipe.hset(&key, "btp_outgoing_token", token.to_vec())
+                                .ignore()
+                                .hset(&key, "btp_uri", btp_uri.as_str())
+                                .ignore();
+                            changed = true;
+                        }
+                    }
+                    if changed {
+                        migrated += 1;
+                    }
+                }
+
+                if migrated == 0 {
+                    return Either::A(ok(()));
+                }
+                info!(
+                    "Encrypting plaintext outgoing credentials for {} accounts",
+                    migrated
+                );
+                Either::B(
+                    pipe.query_async(connection)
+                        .map_err(|err| error!("Error encrypting account credentials: {:?}", err))
+                        .map(|(_connection, _): (_, Value)| ()),
+                )
+            },
+        )
+}
+
 /// A Store that uses Redis as its underlying database.
 ///
 /// This store leverages atomic Redis transactions to do operations such as balance updates.
@@ -148,15 +383,30 @@ where
 pub struct RedisStore {
     connection: Arc<SharedConnection>,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
+    rates_updated_at: Arc<RwLock<Option<SystemTime>>>,
     routes: Arc<RwLock<HashMap<Bytes, u64>>>,
+    encryption_key: Arc<EncryptionKey>,
+    decryption_key: Arc<DecryptionKey>,
+    /// Stops the rate and route polling when it is dropped
+    stop_polling: Arc<Mutex<Option<Trigger>>>,
 }
 
 impl RedisStore {
-    fn get_next_account_id(&self) -> impl Future<Item = u64, Error = ()> {
+    /// Stop polling the database for rate and routing table updates.
+    /// The store keeps using the last values it loaded.
+    pub fn stop_polling(&self) {
+        debug!("Stopping rate and route polling");
+        self.stop_polling.lock().take();
+    }
+
+    fn get_next_account_id(&self) -> impl Future<Item = u64, Error = StoreError> {
         cmd("INCR")
             .arg(NEXT_ACCOUNT_ID_KEY)
             .query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error incrementing account ID: {:?}", err))
+            .map_err(|err| {
+                error!("Error incrementing account ID: {:?}", err);
+                store_error(err)
+            })
             .and_then(|(_conn, next_account_id): (_, u64)| Ok(next_account_id - 1))
     }
 }
@@ -168,7 +418,8 @@ impl AccountStore for RedisStore {
     fn get_accounts(
         &self,
         account_ids: Vec<<Self::Account as AccountTrait>::AccountId>,
-    ) -> Box<Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Box<Future<Item = Vec<Account>, Error = StoreError> + Send> {
+        let decryption_key = self.decryption_key.clone();
         let num_accounts = account_ids.len();
         let mut pipe = redis::pipe();
         for account_id in account_ids.iter() {
@@ -180,21 +431,27 @@ impl AccountStore for RedisStore {
                     error!(
                         "Error querying details for accounts: {:?} {:?}",
                         account_ids, err
-                    )
+                    );
+                    store_error(err)
                 })
-                .and_then(move |(_conn, accounts): (_, Vec<Account>)| {
-                    if accounts.len() == num_accounts {
-                        Ok(accounts)
-                    } else {
-                        Err(())
-                    }
-                }),
+                .and_then(
+                    move |(_conn, accounts): (_, Vec<AccountWithEncryptedTokens>)| {
+                        if accounts.len() == num_accounts {
+                            decrypt_accounts(&decryption_key, accounts)
+                                .map_err(|_| decryption_error())
+                        } else {
+                            Err(StoreError::NotFound(
+                                "Not all of the accounts were found".to_string(),
+                            ))
+                        }
+                    },
+                ),
         )
     }
 }
 
 impl BalanceStore for RedisStore {
-    fn get_balance(&self, account: Account) -> Box<Future<Item = i64, Error = ()> + Send> {
+    fn get_balance(&self, account: Account) -> Box<Future<Item = i64, Error = StoreError> + Send> {
         Box::new(
             cmd("HGET")
                 .arg(balance_key(account.asset_code.as_str()))
@@ -204,7 +461,8 @@ impl BalanceStore for RedisStore {
                     error!(
                         "Error getting balance for account: {} {:?}",
                         account.id, err
-                    )
+                    );
+                    store_error(err)
                 })
                 .and_then(|(_connection, balance): (_, i64)| Ok(balance)),
         )
@@ -216,7 +474,7 @@ impl BalanceStore for RedisStore {
         incoming_amount: u64,
         to_account: Account,
         outgoing_amount: u64,
-    ) -> Box<Future<Item = (), Error = ()> + Send> {
+    ) -> Box<Future<Item = (i64, i64), Error = StoreError> + Send> {
         let from_account_id = from_account.id();
         let to_account_id = to_account.id();
 
@@ -243,7 +501,8 @@ impl BalanceStore for RedisStore {
                     from_account_id,
                     to_account_id,
                     err
-                )
+                );
+                    store_error(err)
                 })
                 .and_then(
                     move |(_connection, (from_balance, to_balance)): (_, (i64, i64))| {
@@ -251,7 +510,7 @@ impl BalanceStore for RedisStore {
                             "Updated account balances. Account {} has: {}, account {} has: {}",
                             from_account_id, from_balance, to_account_id, to_balance
                         );
-                        Ok(())
+                        Ok((from_balance, to_balance))
                     },
                 ),
         )
@@ -263,7 +522,7 @@ impl BalanceStore for RedisStore {
         incoming_amount: u64,
         to_account: Account,
         outgoing_amount: u64,
-    ) -> Box<Future<Item = (), Error = ()> + Send> {
+    ) -> Box<Future<Item = (i64, i64), Error = StoreError> + Send> {
         let from_account_id = from_account.id();
         let to_account_id = to_account.id();
 
@@ -293,14 +552,157 @@ impl BalanceStore for RedisStore {
                     from_account_id,
                     to_account_id,
                     err
-                )
+                );
+                    store_error(err)
                 })
                 .and_then(move |(_connection, balances): (_, Vec<i64>)| {
                     debug!(
                         "Updated account balances. Account {} has: {}, account {} has: {}",
                         from_account_id, balances[0], to_account_id, balances[1]
                     );
-                    Ok(())
+                    Ok((balances[0], balances[1]))
+                }),
+        )
+    }
+}
+
+impl TransactionStore for RedisStore {
+    fn record_transaction(
+        &self,
+        transaction: Transaction<u64>,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        let timestamp = transaction.timestamp;
+        let from_account_id = transaction.from_account;
+        let to_account_id = transaction.to_account;
+        let record = serde_json::to_string(&TransactionRecord::from(transaction))
+            .expect("Transaction records are always serializable");
+
+        Box::new(
+            cmd("EVAL")
+                .arg(RECORD_TRANSACTION)
+                .arg(3)
+                .arg(transactions_key(from_account_id))
+                .arg(transactions_key(to_account_id))
+                .arg(NEXT_TRANSACTION_ID_KEY)
+                .arg(timestamp)
+                .arg(record)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error recording transaction between accounts {} and {}: {:?}",
+                        from_account_id, to_account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(|(_connection, _id): (_, u64)| Ok(())),
+        )
+    }
+
+    fn get_transactions(
+        &self,
+        account_id: u64,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Box<Future<Item = Vec<Transaction<u64>>, Error = StoreError> + Send> {
+        Box::new(
+            cmd("ZRANGEBYSCORE")
+                .arg(transactions_key(account_id))
+                .arg(from.map_or_else(|| "-inf".to_string(), |from| from.to_string()))
+                .arg(to.map_or_else(|| "+inf".to_string(), |to| to.to_string()))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error loading transactions for account {}: {:?}",
+                        account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(move |(_connection, records): (_, Vec<String>)| {
+                    records
+                        .iter()
+                        .map(|record| {
+                            serde_json::from_str::<TransactionRecord>(record)
+                                .ok()
+                                .and_then(TransactionRecord::into_transaction)
+                                .ok_or_else(|| {
+                                    error!(
+                                        "Invalid transaction record for account {}: {}",
+                                        account_id, record
+                                    );
+                                    StoreError::Other("Invalid transaction record".to_string())
+                                })
+                        })
+                        .collect::<Result<Vec<Transaction<u64>>, StoreError>>()
+                }),
+        )
+    }
+}
+
+impl WebhookStore for RedisStore {
+    fn record_webhook_delivery(
+        &self,
+        account_id: u64,
+        delivery: WebhookDelivery,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        let record =
+            serde_json::to_string(&delivery).expect("Webhook deliveries are always serializable");
+
+        // The log is a list with the most recent delivery first, trimmed so it doesn't grow forever
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("LPUSH")
+            .arg(webhook_deliveries_key(account_id))
+            .arg(record)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(webhook_deliveries_key(account_id))
+            .arg(0)
+            .arg(WEBHOOK_DELIVERIES_TO_KEEP - 1)
+            .ignore();
+
+        Box::new(
+            pipe.query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error recording webhook delivery for account {}: {:?}",
+                        account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(|(_connection, _): (_, Value)| Ok(())),
+        )
+    }
+
+    fn get_webhook_deliveries(
+        &self,
+        account_id: u64,
+    ) -> Box<Future<Item = Vec<WebhookDelivery>, Error = StoreError> + Send> {
+        Box::new(
+            cmd("LRANGE")
+                .arg(webhook_deliveries_key(account_id))
+                .arg(0)
+                .arg(-1)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error loading webhook deliveries for account {}: {:?}",
+                        account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(move |(_connection, records): (_, Vec<String>)| {
+                    records
+                        .iter()
+                        .map(|record| {
+                            serde_json::from_str(record).map_err(|_| {
+                                error!(
+                                    "Invalid webhook delivery record for account {}: {}",
+                                    account_id, record
+                                );
+                                StoreError::Other("Invalid webhook delivery record".to_string())
+                            })
+                        })
+                        .collect::<Result<Vec<WebhookDelivery>, StoreError>>()
                 }),
         )
     }
@@ -330,10 +732,11 @@ impl BtpStore for RedisStore {
     fn get_account_from_btp_token(
         &self,
         token: &str,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send> {
         // TODO make sure it can't do script injection!
         // TODO cache the result so we don't hit redis for every packet (is that necessary if redis is often used as a cache?)
         let token = token.to_string();
+        let decryption_key = self.decryption_key.clone();
         Box::new(
             cmd("EVAL")
                 .arg(ACCOUNT_FROM_INDEX)
@@ -341,15 +744,24 @@ impl BtpStore for RedisStore {
                 .arg("btp_auth")
                 .arg(&token)
                 .query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting account from BTP token: {:?}", err))
-                .and_then(move |(_connection, account): (_, Option<Account>)| {
-                    if let Some(account) = account {
-                        Ok(account)
-                    } else {
-                        warn!("No account found with BTP token: {}", token);
-                        Err(())
-                    }
-                }),
+                .map_err(|err| {
+                    error!("Error getting account from BTP token: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(
+                    move |(_connection, account): (_, Option<AccountWithEncryptedTokens>)| {
+                        if let Some(account) = account {
+                            account
+                                .decrypt_tokens(&decryption_key)
+                                .map_err(|_| decryption_error())
+                        } else {
+                            warn!("No account found with BTP token: {}", token);
+                            Err(StoreError::NotFound(
+                                "No account found with the given BTP token".to_string(),
+                            ))
+                        }
+                    },
+                ),
         )
     }
 }
@@ -360,9 +772,10 @@ impl HttpStore for RedisStore {
     fn get_account_from_http_auth(
         &self,
         auth_header: &str,
-    ) -> Box<Future<Item = Self::Account, Error = ()> + Send> {
+    ) -> Box<Future<Item = Self::Account, Error = StoreError> + Send> {
         // TODO make sure it can't do script injection!
         let auth_header = auth_header.to_string();
+        let decryption_key = self.decryption_key.clone();
         Box::new(
             cmd("EVAL")
                 .arg(ACCOUNT_FROM_INDEX)
@@ -370,15 +783,24 @@ impl HttpStore for RedisStore {
                 .arg("http_auth")
                 .arg(&auth_header)
                 .query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting account from HTTP auth: {:?}", err))
-                .and_then(move |(_connection, account): (_, Option<Account>)| {
-                    if let Some(account) = account {
-                        Ok(account)
-                    } else {
-                        warn!("No account found with HTTP auth: {}", auth_header);
-                        Err(())
-                    }
-                }),
+                .map_err(|err| {
+                    error!("Error getting account from HTTP auth: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(
+                    move |(_connection, account): (_, Option<AccountWithEncryptedTokens>)| {
+                        if let Some(account) = account {
+                            account
+                                .decrypt_tokens(&decryption_key)
+                                .map_err(|_| decryption_error())
+                        } else {
+                            warn!("No account found with HTTP auth: {}", auth_header);
+                            Err(StoreError::NotFound(
+                                "No account found with the given HTTP auth".to_string(),
+                            ))
+                        }
+                    },
+                ),
         )
     }
 }
@@ -395,16 +817,20 @@ impl NodeStore for RedisStore {
     fn insert_account(
         &self,
         account: AccountDetails,
-    ) -> Box<Future<Item = Account, Error = ()> + Send> {
-        debug!("Inserting account: {:?}", account);
+    ) -> Box<Future<Item = Account, Error = StoreError> + Send> {
         let connection = self.connection.clone();
         let routing_table = self.routes.clone();
+        let encryption_key = self.encryption_key.clone();
 
         Box::new(
             self.get_next_account_id()
                 .and_then(|id| {
                     debug!("Next account id is: {}", id);
-                    Account::try_from(id, account)
+                    let account = Account::try_from(id, account).map_err(|_| {
+                        StoreError::Other("Invalid account details".to_string())
+                    })?;
+                    debug!("Inserting account: {:?}", account);
+                    Ok(account)
                 })
                 .and_then(move |account| {
                     // Check that there isn't already an account with values that must be unique
@@ -439,20 +865,21 @@ impl NodeStore for RedisStore {
                             error!(
                                 "Error checking whether account details already exist: {:?}",
                                 err
-                            )
+                            );
+                            store_error(err)
                         })
                         .and_then(
                             move |(connection, results): (SharedConnection, Vec<bool>)| {
                                 if let Some(index) = results.iter().position(|val| *val) {
                                     warn!("An account already exists with the same {}. Cannot insert account: {:?}", keys[index], account);
-                                    Err(())
+                                    Err(StoreError::Conflict(format!("An account already exists with the same {}", keys[index])))
                                 } else {
                                     Ok((connection, account))
                                 }
                             },
                         )
                 })
-                .and_then(|(connection, account)| {
+                .and_then(move |(connection, account)| {
                     let mut pipe = redis::pipe();
 
                     // Set balance
@@ -490,7 +917,7 @@ impl NodeStore for RedisStore {
 
                     if account.send_routes {
                         pipe.cmd("SADD")
-                            .arg("send_routes_to")
+                            .arg(SEND_ROUTES_TO_KEY)
                             .arg(account.id)
                             .ignore();
                     }
@@ -502,11 +929,14 @@ impl NodeStore for RedisStore {
                     // Set account details
                     pipe.cmd("HMSET")
                         .arg(account_details_key(account.id))
-                        .arg(account.clone())
+                        .arg(account.clone().encrypt_tokens(&encryption_key))
                         .ignore();
 
                     pipe.query_async(connection)
-                        .map_err(|err| error!("Error inserting account into DB: {:?}", err))
+                        .map_err(|err| {
+                            error!("Error inserting account into DB: {:?}", err);
+                            store_error(err)
+                        })
                         .and_then(move |(connection, _ret): (SharedConnection, Value)| {
                             update_routes(connection, routing_table)
                         })
@@ -516,7 +946,10 @@ impl NodeStore for RedisStore {
     }
 
     // TODO limit the number of results and page through them
-    fn get_all_accounts(&self) -> Box<Future<Item = Vec<Self::Account>, Error = ()> + Send> {
+    fn get_all_accounts(
+        &self,
+    ) -> Box<Future<Item = Vec<Self::Account>, Error = StoreError> + Send> {
+        let decryption_key = self.decryption_key.clone();
         Box::new(
             cmd("GET")
                 .arg(NEXT_ACCOUNT_ID_KEY)
@@ -526,19 +959,82 @@ impl NodeStore for RedisStore {
                     for i in 0..next_account_id {
                         pipe.cmd("HGETALL").arg(account_details_key(i));
                     }
-                    pipe.query_async(connection)
-                        .and_then(|(_, accounts): (_, Vec<Self::Account>)| Ok(accounts))
+                    pipe.query_async(connection).and_then(
+                        |(_, accounts): (_, Vec<AccountWithEncryptedTokens>)| Ok(accounts),
+                    )
+                })
+                .map_err(|err| {
+                    error!("Error getting all accounts: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(move |accounts| {
+                    decrypt_accounts(&decryption_key, accounts).map_err(|_| decryption_error())
+                }),
+        )
+    }
+
+    fn ping(&self) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        Box::new(
+            cmd("PING")
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error pinging Redis: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(|(_connection, _): (_, Value)| Ok(())),
+        )
+    }
+
+    fn rates_updated_at(&self) -> Option<SystemTime> {
+        *self.rates_updated_at.read()
+    }
+
+    fn update_account_settings(
+        &self,
+        account_id: u64,
+        settings: AccountSettings,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        // Empty strings mean the optional fields are unset
+        let optional_arg =
+            |value: Option<i64>| value.map(|value| value.to_string()).unwrap_or_default();
+        Box::new(
+            cmd("EVAL")
+                .arg(UPDATE_ACCOUNT_SETTINGS)
+                .arg(2)
+                .arg(account_details_key(account_id))
+                .arg(SEND_ROUTES_TO_KEY)
+                .arg(account_id)
+                .arg(settings.max_packet_amount)
+                .arg(settings.min_balance)
+                .arg(settings.send_routes)
+                .arg(settings.receive_routes)
+                .arg(optional_arg(settings.settle_threshold))
+                .arg(optional_arg(settings.settle_to))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    let err = store_error(err);
+                    if !err.is_not_found() {
+                        error!(
+                            "Error updating settings for account {}: {}",
+                            account_id, err
+                        );
+                    }
+                    err
                 })
-                .map_err(|err| error!("Error getting all accounts: {:?}", err)),
+                .and_then(move |(_connection, _): (_, Value)| {
+                    debug!("Updated settings for account {}", account_id);
+                    Ok(())
+                }),
         )
     }
 
-    fn set_rates<R>(&self, rates: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_rates<R>(&self, rates: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (String, f64)>,
     {
         let rates: Vec<(String, f64)> = rates.into_iter().collect();
         let exchange_rates = self.exchange_rates.clone();
+        let rates_updated_at = self.rates_updated_at.clone();
         let mut pipe = redis::pipe();
         pipe.atomic()
             .cmd("DEL")
@@ -550,20 +1046,38 @@ impl NodeStore for RedisStore {
             .ignore();
         Box::new(
             pipe.query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error setting rates: {:?}", err))
+                .map_err(|err| {
+                    error!("Error setting rates: {:?}", err);
+                    store_error(err)
+                })
                 .and_then(move |(connection, _): (SharedConnection, Value)| {
-                    update_rates(connection, exchange_rates)
+                    update_rates(connection, exchange_rates, rates_updated_at)
                 }),
         )
     }
 
     // TODO fix inconsistency betwen this method and set_routes which
     // takes the prefixes as Bytes and the account as an Account object
-    fn set_static_routes<R>(&self, routes: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_static_routes<R>(&self, routes: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (String, u64)>,
     {
         let routes: Vec<(String, u64)> = routes.into_iter().collect();
+        let routing_table = self.routes.clone();
+        if routes.is_empty() {
+            return Box::new(
+                cmd("DEL")
+                    .arg(STATIC_ROUTES_KEY)
+                    .query_async(self.connection.as_ref().clone())
+                    .map_err(|err| {
+                        error!("Error removing static routes: {:?}", err);
+                        store_error(err)
+                    })
+                    .and_then(move |(connection, _): (SharedConnection, Value)| {
+                        update_routes(connection, routing_table)
+                    }),
+            );
+        }
         let accounts: HashSet<u64> =
             HashSet::from_iter(routes.iter().map(|(_prefix, account_id)| *account_id));
         let mut pipe = redis::pipe();
@@ -571,15 +1085,17 @@ impl NodeStore for RedisStore {
             pipe.cmd("EXISTS").arg(account_details_key(account_id));
         }
 
-        let routing_table = self.routes.clone();
         Box::new(pipe.query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error checking if accounts exist while setting static routes: {:?}", err))
+            .map_err(|err| {
+                error!("Error checking if accounts exist while setting static routes: {:?}", err);
+                store_error(err)
+            })
             .and_then(|(connection, accounts_exist): (SharedConnection, Vec<bool>)| {
                 if accounts_exist.iter().all(|a| *a) {
                     Ok(connection)
                 } else {
                     error!("Error setting static routes because not all of the given accounts exist");
-                    Err(())
+                    Err(StoreError::NotFound("Not all of the given accounts exist".to_string()))
                 }
             })
             .and_then(move |connection| {
@@ -593,7 +1109,10 @@ impl NodeStore for RedisStore {
             .arg(routes)
             .ignore();
             pipe.query_async(connection)
-                .map_err(|err| error!("Error setting static routes: {:?}", err))
+                .map_err(|err| {
+                    error!("Error setting static routes: {:?}", err);
+                    store_error(err)
+                })
                 .and_then(move |(connection, _): (SharedConnection, Value)| {
                     update_routes(connection, routing_table)
                 })
@@ -604,20 +1123,23 @@ impl NodeStore for RedisStore {
         &self,
         prefix: String,
         account_id: u64,
-    ) -> Box<Future<Item = (), Error = ()> + Send> {
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
         let routing_table = self.routes.clone();
         let prefix_clone = prefix.clone();
         Box::new(
         cmd("EXISTS")
             .arg(account_details_key(account_id))
             .query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error checking if account exists before setting static route: {:?}", err))
+            .map_err(|err| {
+                error!("Error checking if account exists before setting static route: {:?}", err);
+                store_error(err)
+            })
             .and_then(move |(connection, exists): (SharedConnection, bool)| {
                 if exists {
                     Ok(connection)
                 } else {
                     error!("Cannot set static route for prefix: {} because account {} does not exist", prefix_clone, account_id);
-                    Err(())
+                    Err(StoreError::NotFound(format!("Account {} does not exist", account_id)))
                 }
             })
             .and_then(move |connection| {
@@ -626,13 +1148,87 @@ impl NodeStore for RedisStore {
                     .arg(prefix)
                     .arg(account_id)
                     .query_async(connection)
-                    .map_err(|err| error!("Error setting static route: {:?}", err))
+                    .map_err(|err| {
+                        error!("Error setting static route: {:?}", err);
+                        store_error(err)
+                    })
                     .and_then(move |(connection, _): (SharedConnection, Value)| {
                         update_routes(connection, routing_table)
                     })
             })
         )
     }
+
+    fn insert_api_token(
+        &self,
+        token_id: String,
+        scopes: Vec<ApiScope>,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        let scopes: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+        Box::new(
+            cmd("HSET")
+                .arg(API_TOKENS_KEY)
+                .arg(token_id)
+                .arg(scopes.join(","))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error inserting API token: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(|(_connection, _): (SharedConnection, Value)| Ok(())),
+        )
+    }
+
+    fn get_api_token_scopes(
+        &self,
+        token_id: &str,
+    ) -> Box<Future<Item = Vec<ApiScope>, Error = StoreError> + Send> {
+        Box::new(
+            cmd("HGET")
+                .arg(API_TOKENS_KEY)
+                .arg(token_id)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error getting API token: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(
+                    |(_connection, scopes): (SharedConnection, Option<String>)| {
+                        let scopes = scopes.ok_or_else(|| {
+                            StoreError::NotFound("API token does not exist".to_string())
+                        })?;
+                        Ok(scopes
+                            .split(',')
+                            .filter(|scope| !scope.is_empty())
+                            .filter_map(|scope| {
+                                ApiScope::from_str(scope)
+                                    .map_err(|_| {
+                                        warn!("Ignoring unknown API token scope: {}", scope)
+                                    })
+                                    .ok()
+                            })
+                            .collect())
+                    },
+                ),
+        )
+    }
+
+    fn delete_api_token(
+        &self,
+        token_id: &str,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        Box::new(
+            cmd("HDEL")
+                .arg(API_TOKENS_KEY)
+                .arg(token_id)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error deleting API token: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(|(_connection, _): (SharedConnection, Value)| Ok(())),
+        )
+    }
 }
 
 impl RouteManagerStore for RedisStore {
@@ -640,44 +1236,65 @@ impl RouteManagerStore for RedisStore {
 
     fn get_accounts_to_send_routes_to(
         &self,
-    ) -> Box<Future<Item = Vec<Account>, Error = ()> + Send> {
+    ) -> Box<Future<Item = Vec<Account>, Error = StoreError> + Send> {
+        let decryption_key = self.decryption_key.clone();
         Box::new(
             cmd("SMEMBERS")
-                .arg("send_routes_to")
+                .arg(SEND_ROUTES_TO_KEY)
                 .query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error getting members of set send_routes_to: {:?}", err))
-                .and_then(|(connection, account_ids): (SharedConnection, Vec<u64>)| {
-                    if account_ids.is_empty() {
-                        Either::A(ok(Vec::new()))
-                    } else {
-                        let mut pipe = redis::pipe();
-                        for id in account_ids {
-                            pipe.cmd("HGETALL").arg(account_details_key(id));
+                .map_err(|err| {
+                    error!("Error getting members of set send_routes_to: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(
+                    move |(connection, account_ids): (SharedConnection, Vec<u64>)| {
+                        if account_ids.is_empty() {
+                            Either::A(ok(Vec::new()))
+                        } else {
+                            let mut pipe = redis::pipe();
+                            for id in account_ids {
+                                pipe.cmd("HGETALL").arg(account_details_key(id));
+                            }
+                            Either::B(
+                                pipe.query_async(connection)
+                                    .map_err(|err| {
+                                        error!(
+                                            "Error getting accounts to send routes to: {:?}",
+                                            err
+                                        );
+                                        store_error(err)
+                                    })
+                                    .and_then(
+                                        move |(_connection, accounts): (
+                                            SharedConnection,
+                                            Vec<AccountWithEncryptedTokens>,
+                                        )| {
+                                            decrypt_accounts(&decryption_key, accounts)
+                                                .map_err(|_| decryption_error())
+                                        },
+                                    ),
+                            )
                         }
-                        Either::B(
-                            pipe.query_async(connection)
-                                .map_err(|err| {
-                                    error!("Error getting accounts to send routes to: {:?}", err)
-                                })
-                                .and_then(
-                                    |(_connection, accounts): (SharedConnection, Vec<Account>)| {
-                                        Ok(accounts)
-                                    },
-                                ),
-                        )
-                    }
-                }),
+                    },
+                ),
         )
     }
 
     fn get_local_and_configured_routes(
         &self,
-    ) -> Box<Future<Item = ((HashMap<Bytes, Account>), (HashMap<Bytes, Account>)), Error = ()> + Send>
-    {
+    ) -> Box<
+        Future<
+                Item = ((HashMap<Bytes, Account>), (HashMap<Bytes, Account>)),
+                Error = StoreError,
+            > + Send,
+    > {
         let get_static_routes = cmd("HGETALL")
             .arg(STATIC_ROUTES_KEY)
             .query_async(self.connection.as_ref().clone())
-            .map_err(|err| error!("Error getting static routes: {:?}", err))
+            .map_err(|err| {
+                error!("Error getting static routes: {:?}", err);
+                store_error(err)
+            })
             .and_then(
                 |(_, static_routes): (SharedConnection, Vec<(String, u64)>)| Ok(static_routes),
             );
@@ -705,7 +1322,7 @@ impl RouteManagerStore for RedisStore {
         ))
     }
 
-    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = ()> + Send>
+    fn set_routes<R>(&mut self, routes: R) -> Box<Future<Item = (), Error = StoreError> + Send>
     where
         R: IntoIterator<Item = (Bytes, Account)>,
     {
@@ -734,28 +1351,198 @@ impl RouteManagerStore for RedisStore {
             .ignore();
         Box::new(
             pipe.query_async(self.connection.as_ref().clone())
-                .map_err(|err| error!("Error setting routes: {:?}", err))
+                .map_err(|err| {
+                    error!("Error setting routes: {:?}", err);
+                    store_error(err)
+                })
                 .and_then(move |(connection, _): (SharedConnection, Value)| {
                     trace!("Saved {} routes to Redis", num_routes);
                     update_routes(connection, routing_tale)
                 }),
         )
     }
+
+    fn get_route_policies(
+        &self,
+        account_id: u64,
+    ) -> Box<Future<Item = RoutePolicies, Error = StoreError> + Send> {
+        Box::new(
+            cmd("HGET")
+                .arg(ROUTE_POLICIES_KEY)
+                .arg(account_id)
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error getting route policies for account {}: {:?}",
+                        account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(
+                    move |(_connection, policies): (SharedConnection, Option<String>)| {
+                        match policies {
+                            Some(policies) => serde_json::from_str(&policies).map_err(|err| {
+                                error!(
+                                    "Stored route policies for account {} are invalid: {:?}",
+                                    account_id, err
+                                );
+                                StoreError::Other(format!(
+                                    "Invalid route policies for account {}",
+                                    account_id
+                                ))
+                            }),
+                            None => Ok(RoutePolicies::default()),
+                        }
+                    },
+                ),
+        )
+    }
+
+    fn set_route_policies(
+        &self,
+        account_id: u64,
+        policies: RoutePolicies,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        let policies =
+            serde_json::to_string(&policies).expect("Route policies can always be serialized");
+        let connection = self.connection.clone();
+        Box::new(
+            cmd("EXISTS")
+                .arg(account_details_key(account_id))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(move |err| {
+                    error!(
+                        "Error checking if account {} exists before setting its route policies: {:?}",
+                        account_id, err
+                    );
+                    store_error(err)
+                })
+                .and_then(move |(_connection, exists): (_, bool)| {
+                    if exists {
+                        Ok(())
+                    } else {
+                        Err(StoreError::NotFound(format!(
+                            "Account {} does not exist",
+                            account_id
+                        )))
+                    }
+                })
+                .and_then(move |_| {
+                    cmd("HSET")
+                        .arg(ROUTE_POLICIES_KEY)
+                        .arg(account_id)
+                        .arg(policies)
+                        .query_async(connection.as_ref().clone())
+                        .map_err(move |err| {
+                            error!(
+                                "Error setting route policies for account {}: {:?}",
+                                account_id, err
+                            );
+                            store_error(err)
+                        })
+                        .and_then(|(_connection, _): (SharedConnection, Value)| Ok(()))
+                }),
+        )
+    }
+
+    fn save_routing_state(
+        &self,
+        ilp_address: &[u8],
+        state: Bytes,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+        Box::new(
+            cmd("SET")
+                .arg(routing_state_key(ilp_address))
+                .arg(&state[..])
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error saving routing state: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(|(_connection, _): (SharedConnection, Value)| Ok(())),
+        )
+    }
+
+    fn load_routing_state(
+        &self,
+        ilp_address: &[u8],
+    ) -> Box<Future<Item = Option<Bytes>, Error = StoreError> + Send> {
+        Box::new(
+            cmd("GET")
+                .arg(routing_state_key(ilp_address))
+                .query_async(self.connection.as_ref().clone())
+                .map_err(|err| {
+                    error!("Error loading routing state: {:?}", err);
+                    store_error(err)
+                })
+                .and_then(
+                    |(_connection, state): (SharedConnection, Option<Vec<u8>>)| {
+                        Ok(state.map(Bytes::from))
+                    },
+                ),
+        )
+    }
+}
+
+/// Convert an error from the Redis client into a StoreError.
+///
+/// Errors that mean the database could not be reached are reported as `Unavailable`.
+fn store_error(err: RedisError) -> StoreError {
+    if err.is_io_error()
+        || err.is_connection_refusal()
+        || err.is_connection_dropped()
+        || err.is_timeout()
+    {
+        StoreError::Unavailable(err.to_string())
+    } else if err.extension_error_code() == Some(LIMIT_EXCEEDED_ERROR_CODE) {
+        let message = err.to_string();
+        let message = message
+            .trim_start_matches(LIMIT_EXCEEDED_ERROR_CODE)
+            .trim_start_matches(": ");
+        StoreError::LimitExceeded(message.to_string())
+    } else if err.extension_error_code() == Some(NOT_FOUND_ERROR_CODE) {
+        let message = err.to_string();
+        let message = message
+            .trim_start_matches(NOT_FOUND_ERROR_CODE)
+            .trim_start_matches(": ");
+        StoreError::NotFound(message.to_string())
+    } else {
+        StoreError::Other(err.to_string())
+    }
+}
+
+fn decryption_error() -> StoreError {
+    StoreError::Other("Unable to decrypt account credentials".to_string())
+}
+
+fn decrypt_accounts(
+    decryption_key: &DecryptionKey,
+    accounts: Vec<AccountWithEncryptedTokens>,
+) -> Result<Vec<Account>, ()> {
+    accounts
+        .into_iter()
+        .map(|account| account.decrypt_tokens(decryption_key))
+        .collect()
 }
 
 // TODO replace this with pubsub when async pubsub is added upstream: https://github.com/mitsuhiko/redis-rs/issues/183
 fn update_rates(
     connection: SharedConnection,
     exchange_rates: Arc<RwLock<HashMap<String, f64>>>,
-) -> impl Future<Item = (), Error = ()> {
+    rates_updated_at: Arc<RwLock<Option<SystemTime>>>,
+) -> impl Future<Item = (), Error = StoreError> {
     cmd("HGETALL")
         .arg(RATES_KEY)
         .query_async(connection)
-        .map_err(|err| error!("Error polling for exchange rates: {:?}", err))
+        .map_err(|err| {
+            error!("Error polling for exchange rates: {:?}", err);
+            store_error(err)
+        })
         .and_then(move |(_connection, rates): (_, Vec<(String, f64)>)| {
             let num_assets = rates.len();
             let rates = HashMap::from_iter(rates.into_iter());
             (*exchange_rates.write()) = rates;
+            (*rates_updated_at.write()) = Some(SystemTime::now());
             debug!("Updated rates for {} assets", num_assets);
             Ok(())
         })
@@ -767,14 +1554,17 @@ type RouteVec = Vec<(String, u64)>;
 fn update_routes(
     connection: SharedConnection,
     routing_table: Arc<RwLock<HashMap<Bytes, u64>>>,
-) -> impl Future<Item = (), Error = ()> {
+) -> impl Future<Item = (), Error = StoreError> {
     let mut pipe = redis::pipe();
     pipe.cmd("HGETALL")
         .arg(ROUTES_KEY)
         .cmd("HGETALL")
         .arg(STATIC_ROUTES_KEY);
     pipe.query_async(connection)
-        .map_err(|err| error!("Error polling for routing table updates: {:?}", err))
+        .map_err(|err| {
+            error!("Error polling for routing table updates: {:?}", err);
+            store_error(err)
+        })
         .and_then(
             move |(_connection, (routes, static_routes)): (_, (RouteVec, RouteVec))| {
                 trace!(
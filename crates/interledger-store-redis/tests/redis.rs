@@ -6,6 +6,7 @@ use bytes::Bytes;
 use env_logger;
 use futures::{future, Future};
 use interledger_api::{AccountDetails, NodeStore};
+use interledger_service::StoreError;
 use interledger_store_redis::{connect, connect_with_poll_interval, Account, RedisStore};
 use parking_lot::Mutex;
 use redis;
@@ -37,6 +38,9 @@ lazy_static! {
         send_routes: false,
         receive_routes: false,
         routing_relation: None,
+        webhook_url: None,
+        webhook_secret: None,
+        webhook_balance_threshold: None,
     };
     static ref ACCOUNT_DETAILS_1: AccountDetails = AccountDetails {
         ilp_address: b"example.bob".to_vec(),
@@ -47,7 +51,7 @@ lazy_static! {
         http_endpoint: Some("http://example.com/ilp".to_string()),
         http_incoming_authorization: Some("Basic QWxhZGRpbjpPcGVuU2VzYW1l".to_string()),
         http_outgoing_authorization: Some("outgoing_auth_token".to_string()),
-        btp_uri: Some("btp+ws://example.com/btp".to_string()),
+        btp_uri: Some("btp+ws://:btp_outgoing_token@example.com/btp".to_string()),
         btp_incoming_authorization: Some("other_btp_token".to_string()),
         is_admin: true,
         xrp_address: Some("rMLwdY4w8FT8zCEUL9q9173NrvpLGLEFDu".to_string()),
@@ -56,18 +60,24 @@ lazy_static! {
         send_routes: true,
         receive_routes: false,
         routing_relation: None,
+        webhook_url: None,
+        webhook_secret: None,
+        webhook_balance_threshold: None,
     };
     static ref TEST_MUTEX: Mutex<()> = Mutex::new(());
 }
 
+const SECRET: [u8; 32] = [0; 32];
+
 fn test_store() -> impl Future<Item = (RedisStore, TestContext), Error = ()> {
     let context = TestContext::new();
-    connect(context.get_client_connection_info()).and_then(|store| {
+    connect(context.get_client_connection_info(), SECRET).and_then(|store| {
         let store_clone = store.clone();
         store
             .clone()
             .insert_account(ACCOUNT_DETAILS_0.clone())
             .and_then(move |_| store_clone.insert_account(ACCOUNT_DETAILS_1.clone()))
+            .map_err(|err| panic!("Error inserting test accounts: {}", err))
             .and_then(|_| Ok((store, context)))
     })
 }
@@ -96,7 +106,7 @@ mod connect_store {
         runtime
             .block_on(future::lazy(
                 || -> Box<Future<Item = (), Error = ()> + Send> {
-                    Box::new(connect("redis://127.0.0.1:0").then(|result| {
+                    Box::new(connect("redis://127.0.0.1:0", SECRET).then(|result| {
                         assert!(result.is_err());
                         Ok(())
                     }))
@@ -156,13 +166,40 @@ mod insert_accounts {
                     send_routes: false,
                     receive_routes: false,
                     routing_relation: None,
+                    webhook_url: None,
+                    webhook_secret: None,
+                    webhook_balance_threshold: None,
                 })
                 .then(move |result| {
                     let _ = context;
-                    result
+                    Ok(result)
                 })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::Conflict("An account already exists with the same XRP address".to_string())
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_ilp_address() {
+        let result = block_on(test_store().and_then(|(store, context)| {
+            store
+                .insert_account(AccountDetails {
+                    ilp_address: b"example.char lie".to_vec(),
+                    ..ACCOUNT_DETAILS_0.clone()
+                })
+                .then(move |result| {
+                    let _ = context;
+                    Ok(result)
+                })
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::Other("Invalid account details".to_string())
+        );
     }
 
     #[test]
@@ -187,13 +224,20 @@ mod insert_accounts {
                     send_routes: false,
                     receive_routes: false,
                     routing_relation: None,
+                    webhook_url: None,
+                    webhook_secret: None,
+                    webhook_balance_threshold: None,
                 })
                 .then(move |result| {
                     let _ = context;
-                    result
+                    Ok(result)
                 })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::Conflict("An account already exists with the same HTTP auth".to_string())
+        );
     }
 
     #[test]
@@ -218,29 +262,197 @@ mod insert_accounts {
                     send_routes: false,
                     receive_routes: false,
                     routing_relation: None,
+                    webhook_url: None,
+                    webhook_secret: None,
+                    webhook_balance_threshold: None,
                 })
                 .then(move |result| {
                     let _ = context;
-                    result
+                    Ok(result)
                 })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::Conflict("An account already exists with the same BTP auth".to_string())
+        );
+    }
+}
+
+mod encryption {
+    use super::*;
+    use interledger_btp::BtpAccount;
+    use interledger_http::HttpAccount;
+    use interledger_service::AccountStore;
+    use redis::PipelineCommands;
+
+    #[test]
+    fn stores_outgoing_credentials_encrypted() {
+        block_on(test_store().and_then(|(_store, context)| {
+            context
+                .async_connection()
+                .map_err(|err| panic!("{:?}", err))
+                .and_then(|connection| {
+                    redis::cmd("HMGET")
+                        .arg("accounts:1")
+                        .arg("http_outgoing_authorization")
+                        .arg("btp_uri")
+                        .arg("btp_outgoing_token")
+                        .query_async(connection)
+                        .map_err(|err| panic!("{:?}", err))
+                        .and_then(
+                            move |(_connection, (http_auth, btp_uri, btp_token)): (
+                                _,
+                                (Vec<u8>, String, Vec<u8>),
+                            )| {
+                                let _ = context;
+                                assert_ne!(http_auth, b"outgoing_auth_token".to_vec());
+                                assert_eq!(btp_uri, "btp+ws://example.com/btp");
+                                assert_ne!(btp_token, b"btp_outgoing_token".to_vec());
+                                Ok(())
+                            },
+                        )
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn decrypts_outgoing_credentials() {
+        block_on(test_store().and_then(|(store, context)| {
+            store
+                .get_accounts(vec![1])
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    let _ = context;
+                    assert_eq!(
+                        accounts[0].get_http_auth_header(),
+                        Some("outgoing_auth_token")
+                    );
+                    assert_eq!(
+                        accounts[0].get_btp_uri().unwrap().as_str(),
+                        "btp+ws://:btp_outgoing_token@example.com/btp"
+                    );
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn fails_to_load_accounts_with_different_secret() {
+        let result = block_on(test_store().and_then(|(_store, context)| {
+            connect(context.get_client_connection_info(), [1; 32]).and_then(move |store| {
+                store.get_accounts(vec![1]).then(move |result| {
+                    let _ = context;
+                    Ok(result)
+                })
+            })
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::Other("Unable to decrypt account credentials".to_string())
+        );
+    }
+
+    #[test]
+    fn encrypts_plaintext_credentials_on_connect() {
+        block_on(test_store().and_then(|(_store, context)| {
+            context
+                .async_connection()
+                .map_err(|err| panic!("{:?}", err))
+                .and_then(|connection| {
+                    // Credentials as they were stored before they were encrypted
+                    let mut pipe = redis::pipe();
+                    pipe.hset(
+                        "accounts:1",
+                        "http_outgoing_authorization",
+                        "outgoing_auth_token",
+                    )
+                    .ignore()
+                    .hset(
+                        "accounts:1",
+                        "btp_uri",
+                        "btp+ws://:btp_outgoing_token@example.com/btp",
+                    )
+                    .ignore()
+                    .hdel("accounts:1", "btp_outgoing_token")
+                    .ignore();
+                    pipe.query_async(connection)
+                        .map_err(|err| panic!("{:?}", err))
+                })
+                .and_then(move |(connection, _): (_, redis::Value)| {
+                    connect(context.get_client_connection_info(), SECRET)
+                        .and_then(|store| {
+                            store.get_accounts(vec![1]).map_err(|err| panic!("{}", err))
+                        })
+                        .and_then(move |accounts| {
+                            assert_eq!(
+                                accounts[0].get_http_auth_header(),
+                                Some("outgoing_auth_token")
+                            );
+                            assert_eq!(
+                                accounts[0].get_btp_uri().unwrap().as_str(),
+                                "btp+ws://:btp_outgoing_token@example.com/btp"
+                            );
+                            redis::cmd("HMGET")
+                                .arg("accounts:1")
+                                .arg("http_outgoing_authorization")
+                                .arg("btp_uri")
+                                .query_async(connection)
+                                .map_err(|err| panic!("{:?}", err))
+                        })
+                        .and_then(
+                            move |(_connection, (http_auth, btp_uri)): (_, (Vec<u8>, String))| {
+                                let _ = context;
+                                assert_ne!(http_auth, b"outgoing_auth_token".to_vec());
+                                assert_eq!(btp_uri, "btp+ws://example.com/btp");
+                                Ok(())
+                            },
+                        )
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn redacts_outgoing_credentials_from_debug_output() {
+        block_on(test_store().and_then(|(store, context)| {
+            store
+                .get_accounts(vec![1])
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    let _ = context;
+                    let debug = format!("{:?}", accounts[0]);
+                    assert!(!debug.contains("outgoing_auth_token"));
+                    assert!(!debug.contains("btp_outgoing_token"));
+                    Ok(())
+                })
+        }))
+        .unwrap();
     }
 }
 
 mod node_store {
     use super::*;
-    use interledger_api::NodeStore;
-    use interledger_service_util::ExchangeRateStore;
+    use interledger_api::{AccountSettings, NodeStore};
+    use interledger_ccp::{CcpRoutingAccount, RouteManagerStore};
+    use interledger_ildcp::IldcpAccount;
+    use interledger_service::{Account as AccountTrait, AccountStore};
+    use interledger_service_util::{ExchangeRateStore, MaxPacketAmountAccount};
 
     #[test]
     fn get_all_accounts() {
         block_on(test_store().and_then(|(store, context)| {
-            store.get_all_accounts().and_then(move |accounts| {
-                assert_eq!(accounts.len(), 2);
-                let _ = context;
-                Ok(())
-            })
+            store
+                .get_all_accounts()
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    assert_eq!(accounts.len(), 2);
+                    let _ = context;
+                    Ok(())
+                })
         }))
         .unwrap();
     }
@@ -253,10 +465,96 @@ mod node_store {
             assert!(rates.is_err());
             store
                 .set_rates(vec![("ABC".to_string(), 500.0), ("XYZ".to_string(), 0.005)])
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |_| {
                     let rates = store_clone.get_exchange_rates(&["XYZ", "ABC"]).unwrap();
                     assert_eq!(rates[0].to_string(), "0.005");
                     assert_eq!(rates[1].to_string(), "500");
+                    assert!(store_clone.rates_updated_at().is_some());
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn pings_store() {
+        block_on(test_store().and_then(|(store, context)| {
+            store
+                .ping()
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| {
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn updates_account_settings() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .update_account_settings(
+                    0,
+                    AccountSettings {
+                        max_packet_amount: 50,
+                        min_balance: -10,
+                        settle_threshold: None,
+                        settle_to: Some(5),
+                        send_routes: true,
+                        receive_routes: true,
+                    },
+                )
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| store_clone.get_accounts(vec![0]))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    let account = &accounts[0];
+                    assert_eq!(account.max_packet_amount(), 50);
+                    assert!(account.should_send_routes());
+                    assert!(account.should_receive_routes());
+                    assert_eq!(account.client_address(), b"example.alice");
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn updates_accounts_to_send_routes_to() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            let mut settings = AccountSettings::from(&*ACCOUNT_DETAILS_0);
+            settings.send_routes = true;
+            let mut other_settings = AccountSettings::from(&*ACCOUNT_DETAILS_1);
+            other_settings.send_routes = false;
+            store
+                .update_account_settings(0, settings)
+                .join(store.update_account_settings(1, other_settings))
+                .and_then(move |_| store_clone.get_accounts_to_send_routes_to())
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    let ids: Vec<u64> = accounts.iter().map(|account| account.id()).collect();
+                    assert_eq!(ids, vec![0]);
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn errors_updating_unknown_account() {
+        block_on(test_store().and_then(|(store, context)| {
+            let settings = AccountSettings::from(&*ACCOUNT_DETAILS_0);
+            store
+                .update_account_settings(5, settings)
+                .then(move |result| {
+                    assert!(result.unwrap_err().is_not_found());
                     let _ = context;
                     Ok(())
                 })
@@ -265,6 +563,69 @@ mod node_store {
     }
 }
 
+mod api_tokens {
+    use super::*;
+    use interledger_api::{ApiScope, NodeStore};
+
+    #[test]
+    fn inserts_and_gets_token_scopes() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .insert_api_token(
+                    "test_token".to_string(),
+                    vec![ApiScope::ReadAccounts, ApiScope::SetRates],
+                )
+                .and_then(move |_| store_clone.get_api_token_scopes("test_token"))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |scopes| {
+                    let _ = context;
+                    assert_eq!(scopes, vec![ApiScope::ReadAccounts, ApiScope::SetRates]);
+                    Ok(())
+                })
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn errors_on_unknown_token() {
+        let result = block_on(test_store().and_then(|(store, context)| {
+            store
+                .get_api_token_scopes("unknown_token")
+                .then(move |result| {
+                    let _ = context;
+                    Ok(result)
+                })
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::NotFound("API token does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn deletes_token() {
+        let result = block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            let store_clone_2 = store.clone();
+            store
+                .insert_api_token("test_token".to_string(), vec![ApiScope::ManageRoutes])
+                .and_then(move |_| store_clone.delete_api_token("test_token"))
+                .and_then(move |_| store_clone_2.get_api_token_scopes("test_token"))
+                .then(move |result| {
+                    let _ = context;
+                    Ok(result)
+                })
+        }))
+        .unwrap();
+        assert_eq!(
+            result.unwrap_err(),
+            StoreError::NotFound("API token does not exist".to_string())
+        );
+    }
+}
+
 mod get_accounts {
     use super::*;
     use interledger_ildcp::IldcpAccount;
@@ -273,11 +634,14 @@ mod get_accounts {
     #[test]
     fn gets_single_account() {
         block_on(test_store().and_then(|(store, context)| {
-            store.get_accounts(vec![1]).and_then(move |accounts| {
-                assert_eq!(accounts[0].client_address(), b"example.bob");
-                let _ = context;
-                Ok(())
-            })
+            store
+                .get_accounts(vec![1])
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    assert_eq!(accounts[0].client_address(), b"example.bob");
+                    let _ = context;
+                    Ok(())
+                })
         }))
         .unwrap();
     }
@@ -285,13 +649,16 @@ mod get_accounts {
     #[test]
     fn gets_multiple() {
         block_on(test_store().and_then(|(store, context)| {
-            store.get_accounts(vec![1, 0]).and_then(move |accounts| {
-                // note reverse order is intentional
-                assert_eq!(accounts[0].client_address(), b"example.bob");
-                assert_eq!(accounts[1].client_address(), b"example.alice");
-                let _ = context;
-                Ok(())
-            })
+            store
+                .get_accounts(vec![1, 0])
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |accounts| {
+                    // note reverse order is intentional
+                    assert_eq!(accounts[0].client_address(), b"example.bob");
+                    assert_eq!(accounts[1].client_address(), b"example.alice");
+                    let _ = context;
+                    Ok(())
+                })
         }))
         .unwrap();
     }
@@ -301,10 +668,11 @@ mod get_accounts {
         let result = block_on(test_store().and_then(|(store, context)| {
             store.get_accounts(vec![0, 2]).then(move |result| {
                 let _ = context;
-                result
+                Ok(result)
             })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert!(result.unwrap_err().is_not_found());
     }
 }
 
@@ -317,83 +685,97 @@ mod routes_and_rates {
     fn polls_for_route_updates() {
         let context = TestContext::new();
         block_on(
-            connect_with_poll_interval(context.get_client_connection_info(), 1).and_then(|store| {
-                let connection = context.async_connection();
-                assert_eq!(store.routing_table().len(), 0);
-                let store_clone_1 = store.clone();
-                let store_clone_2 = store.clone();
-                store
-                    .clone()
-                    .insert_account(ACCOUNT_DETAILS_0.clone())
-                    .and_then(move |_| {
-                        let routing_table = store_clone_1.routing_table();
-                        assert_eq!(routing_table.len(), 1);
-                        assert_eq!(
-                            *routing_table.get(&Bytes::from("example.alice")).unwrap(),
-                            0
-                        );
-                        store_clone_1.insert_account(AccountDetails {
-                            ilp_address: b"example.bob".to_vec(),
-                            asset_scale: 6,
-                            asset_code: "XYZ".to_string(),
-                            max_packet_amount: 1000,
-                            min_balance: -1000,
-                            http_endpoint: None,
-                            http_incoming_authorization: None,
-                            http_outgoing_authorization: None,
-                            btp_uri: None,
-                            btp_incoming_authorization: None,
-                            is_admin: false,
-                            xrp_address: None,
-                            settle_threshold: None,
-                            settle_to: None,
-                            send_routes: false,
-                            receive_routes: false,
-                            routing_relation: None,
+            connect_with_poll_interval(context.get_client_connection_info(), SECRET, 1).and_then(
+                |store| {
+                    let connection = context.async_connection();
+                    assert_eq!(store.routing_table().len(), 0);
+                    let store_clone_1 = store.clone();
+                    let store_clone_2 = store.clone();
+                    store
+                        .clone()
+                        .insert_account(ACCOUNT_DETAILS_0.clone())
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(move |_| {
+                            let routing_table = store_clone_1.routing_table();
+                            assert_eq!(routing_table.len(), 1);
+                            assert_eq!(
+                                *routing_table.get(&Bytes::from("example.alice")).unwrap(),
+                                0
+                            );
+                            store_clone_1
+                                .insert_account(AccountDetails {
+                                    ilp_address: b"example.bob".to_vec(),
+                                    asset_scale: 6,
+                                    asset_code: "XYZ".to_string(),
+                                    max_packet_amount: 1000,
+                                    min_balance: -1000,
+                                    http_endpoint: None,
+                                    http_incoming_authorization: None,
+                                    http_outgoing_authorization: None,
+                                    btp_uri: None,
+                                    btp_incoming_authorization: None,
+                                    is_admin: false,
+                                    xrp_address: None,
+                                    settle_threshold: None,
+                                    settle_to: None,
+                                    send_routes: false,
+                                    receive_routes: false,
+                                    routing_relation: None,
+                                    webhook_url: None,
+                                    webhook_secret: None,
+                                    webhook_balance_threshold: None,
+                                })
+                                .map_err(|err| panic!("{}", err))
                         })
-                    })
-                    .and_then(move |_| {
-                        let routing_table = store_clone_2.routing_table();
-                        assert_eq!(routing_table.len(), 2);
-                        assert_eq!(*routing_table.get(&Bytes::from("example.bob")).unwrap(), 1);
-                        connection
-                            .map_err(|err| panic!(err))
-                            .and_then(|connection| {
-                                redis::cmd("HMSET")
-                                    .arg("routes")
-                                    .arg("example.alice")
-                                    .arg(1)
-                                    .arg("example.charlie")
-                                    .arg(0)
-                                    .query_async(connection)
-                                    .and_then(|(_connection, _result): (_, redis::Value)| Ok(()))
-                                    .map_err(|err| panic!(err))
-                                    .and_then(|_| {
-                                        Delay::new(Instant::now() + Duration::from_millis(10))
-                                            .then(|_| Ok(()))
-                                    })
-                            })
-                            .and_then(move |_| {
-                                let routing_table = store_clone_2.routing_table();
-                                assert_eq!(routing_table.len(), 3);
-                                assert_eq!(
-                                    *routing_table.get(&Bytes::from("example.alice")).unwrap(),
-                                    1
-                                );
-                                assert_eq!(
-                                    *routing_table.get(&Bytes::from("example.bob")).unwrap(),
-                                    1
-                                );
-                                assert_eq!(
-                                    *routing_table.get(&Bytes::from("example.charlie")).unwrap(),
-                                    0
-                                );
-                                assert!(routing_table.get(&Bytes::from("example.other")).is_none());
-                                let _ = context;
-                                Ok(())
-                            })
-                    })
-            }),
+                        .and_then(move |_| {
+                            let routing_table = store_clone_2.routing_table();
+                            assert_eq!(routing_table.len(), 2);
+                            assert_eq!(*routing_table.get(&Bytes::from("example.bob")).unwrap(), 1);
+                            connection
+                                .map_err(|err| panic!(err))
+                                .and_then(|connection| {
+                                    redis::cmd("HMSET")
+                                        .arg("routes")
+                                        .arg("example.alice")
+                                        .arg(1)
+                                        .arg("example.charlie")
+                                        .arg(0)
+                                        .query_async(connection)
+                                        .and_then(
+                                            |(_connection, _result): (_, redis::Value)| Ok(()),
+                                        )
+                                        .map_err(|err| panic!(err))
+                                        .and_then(|_| {
+                                            Delay::new(Instant::now() + Duration::from_millis(10))
+                                                .then(|_| Ok(()))
+                                        })
+                                })
+                                .and_then(move |_| {
+                                    let routing_table = store_clone_2.routing_table();
+                                    assert_eq!(routing_table.len(), 3);
+                                    assert_eq!(
+                                        *routing_table.get(&Bytes::from("example.alice")).unwrap(),
+                                        1
+                                    );
+                                    assert_eq!(
+                                        *routing_table.get(&Bytes::from("example.bob")).unwrap(),
+                                        1
+                                    );
+                                    assert_eq!(
+                                        *routing_table
+                                            .get(&Bytes::from("example.charlie"))
+                                            .unwrap(),
+                                        0
+                                    );
+                                    assert!(routing_table
+                                        .get(&Bytes::from("example.other"))
+                                        .is_none());
+                                    let _ = context;
+                                    Ok(())
+                                })
+                        })
+                },
+            ),
         )
         .unwrap();
     }
@@ -402,28 +784,31 @@ mod routes_and_rates {
     fn polls_for_rate_updates() {
         let context = TestContext::new();
         block_on(
-            connect_with_poll_interval(context.get_client_connection_info(), 1).and_then(|store| {
-                assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
-                store
-                    .clone()
-                    .set_rates(vec![
-                        ("ABC".to_string(), 0.5f64),
-                        ("DEF".to_string(), 9_999_999_999.0f64),
-                    ])
-                    .and_then(|_| {
-                        Delay::new(Instant::now() + Duration::from_millis(10)).then(|_| Ok(()))
-                    })
-                    .and_then(move |_| {
-                        assert_eq!(store.get_exchange_rates(&["ABC"]).unwrap(), vec![0.5]);
-                        assert_eq!(
-                            store.get_exchange_rates(&["ABC", "DEF"]).unwrap(),
-                            vec![0.5, 9_999_999_999.0]
-                        );
-                        assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
-                        let _ = context;
-                        Ok(())
-                    })
-            }),
+            connect_with_poll_interval(context.get_client_connection_info(), SECRET, 1).and_then(
+                |store| {
+                    assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
+                    store
+                        .clone()
+                        .set_rates(vec![
+                            ("ABC".to_string(), 0.5f64),
+                            ("DEF".to_string(), 9_999_999_999.0f64),
+                        ])
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(|_| {
+                            Delay::new(Instant::now() + Duration::from_millis(10)).then(|_| Ok(()))
+                        })
+                        .and_then(move |_| {
+                            assert_eq!(store.get_exchange_rates(&["ABC"]).unwrap(), vec![0.5]);
+                            assert_eq!(
+                                store.get_exchange_rates(&["ABC", "DEF"]).unwrap(),
+                                vec![0.5, 9_999_999_999.0]
+                            );
+                            assert!(store.get_exchange_rates(&["ABC", "XYZ"]).is_err());
+                            let _ = context;
+                            Ok(())
+                        })
+                },
+            ),
         )
         .unwrap();
     }
@@ -448,11 +833,14 @@ mod balances {
                     let account1 = accounts[1].clone();
                     store
                         .update_balances(accounts[0].clone(), 100, accounts[1].clone(), 500)
-                        .and_then(move |_| {
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(move |new_balances| {
+                            assert_eq!(new_balances, (-100, 500));
                             store_clone_1
                                 .clone()
                                 .get_balance(accounts[0].clone())
                                 .join(store_clone_1.clone().get_balance(accounts[1].clone()))
+                                .map_err(|err| panic!("{}", err))
                                 .and_then(|(balance0, balance1)| {
                                     assert_eq!(balance0, -100);
                                     assert_eq!(balance1, 500);
@@ -463,11 +851,14 @@ mod balances {
                             store_clone_2
                                 .clone()
                                 .undo_balance_update(account0.clone(), 100, account1.clone(), 500)
-                                .and_then(move |_| {
+                                .map_err(|err| panic!("{}", err))
+                                .and_then(move |new_balances| {
+                                    assert_eq!(new_balances, (0, 0));
                                     store_clone_2
                                         .clone()
                                         .get_balance(account0.clone())
                                         .join(store_clone_2.clone().get_balance(account1.clone()))
+                                        .map_err(|err| panic!("{}", err))
                                         .and_then(move |(balance0, balance1)| {
                                             assert_eq!(balance0, 0);
                                             assert_eq!(balance1, 0);
@@ -492,7 +883,10 @@ mod balances {
                     store
                         .update_balances(accounts[0].clone(), 10000, accounts[1].clone(), 500)
                         .then(move |result| {
-                            assert!(result.is_err());
+                            match result {
+                                Err(StoreError::LimitExceeded(_)) => {}
+                                other => panic!("Expected limit exceeded error, got: {:?}", other),
+                            }
                             let _ = context;
                             Ok(())
                         })
@@ -502,6 +896,111 @@ mod balances {
     }
 }
 
+mod transactions {
+    use super::*;
+    use interledger_packet::ErrorCode;
+    use interledger_service_util::{Transaction, TransactionStore};
+
+    fn transaction(timestamp: u64, reject_code: Option<ErrorCode>) -> Transaction<u64> {
+        Transaction {
+            timestamp,
+            from_account: 0,
+            to_account: 1,
+            incoming_amount: 100,
+            outgoing_amount: 500,
+            execution_condition: [7; 32],
+            reject_code,
+        }
+    }
+
+    #[test]
+    fn records_transactions_for_both_accounts() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .record_transaction(transaction(1000, None))
+                .join(store.record_transaction(transaction(
+                    2000,
+                    Some(ErrorCode::T04_INSUFFICIENT_LIQUIDITY),
+                )))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| {
+                    store_clone
+                        .get_transactions(0, None, None)
+                        .join(store_clone.get_transactions(1, None, None))
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(move |(transactions0, transactions1)| {
+                            assert_eq!(
+                                transactions0,
+                                vec![
+                                    transaction(1000, None),
+                                    transaction(2000, Some(ErrorCode::T04_INSUFFICIENT_LIQUIDITY))
+                                ]
+                            );
+                            assert_eq!(transactions0, transactions1);
+                            let _ = context;
+                            Ok(())
+                        })
+                })
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn keeps_identical_transactions() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .record_transaction(transaction(1000, None))
+                .join(store.record_transaction(transaction(1000, None)))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| {
+                    store_clone
+                        .get_transactions(0, None, None)
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(move |transactions| {
+                            assert_eq!(
+                                transactions,
+                                vec![transaction(1000, None), transaction(1000, None)]
+                            );
+                            let _ = context;
+                            Ok(())
+                        })
+                })
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_transactions_by_time() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            future::join_all(vec![
+                store.record_transaction(transaction(1000, None)),
+                store.record_transaction(transaction(2000, None)),
+                store.record_transaction(transaction(3000, None)),
+            ])
+            .map_err(|err| panic!("{}", err))
+            .and_then(move |_| {
+                store_clone
+                    .get_transactions(0, Some(2000), None)
+                    .join(store_clone.get_transactions(0, None, Some(1999)))
+                    .map_err(|err| panic!("{}", err))
+                    .and_then(move |(after, before)| {
+                        assert_eq!(
+                            after,
+                            vec![transaction(2000, None), transaction(3000, None)]
+                        );
+                        assert_eq!(before, vec![transaction(1000, None)]);
+                        let _ = context;
+                        Ok(())
+                    })
+            })
+        }))
+        .unwrap()
+    }
+}
+
 mod from_btp {
     use super::*;
     use interledger_btp::BtpStore;
@@ -512,6 +1011,7 @@ mod from_btp {
         block_on(test_store().and_then(|(store, context)| {
             store
                 .get_account_from_btp_token("other_btp_token")
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |account| {
                     assert_eq!(account.id(), 1);
                     let _ = context;
@@ -528,10 +1028,11 @@ mod from_btp {
                 .get_account_from_btp_token("unknown_btp_token")
                 .then(move |result| {
                     let _ = context;
-                    result
+                    Ok(result)
                 })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert!(result.unwrap_err().is_not_found());
     }
 }
 
@@ -545,6 +1046,7 @@ mod from_http {
         block_on(test_store().and_then(|(store, context)| {
             store
                 .get_account_from_http_auth("Bearer incoming_auth_token")
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |account| {
                     assert_eq!(account.id(), 0);
                     let _ = context;
@@ -559,6 +1061,7 @@ mod from_http {
         block_on(test_store().and_then(|(store, context)| {
             store
                 .get_account_from_http_auth("Basic QWxhZGRpbjpPcGVuU2VzYW1l")
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |account| {
                     assert_eq!(account.id(), 1);
                     let _ = context;
@@ -575,16 +1078,17 @@ mod from_http {
                 .get_account_from_http_auth("Bearer unknown_token")
                 .then(move |result| {
                     let _ = context;
-                    result
+                    Ok(result)
                 })
-        }));
-        assert!(result.is_err());
+        }))
+        .unwrap();
+        assert!(result.unwrap_err().is_not_found());
     }
 }
 
 mod ccp_store {
     use super::*;
-    use interledger_ccp::RouteManagerStore;
+    use interledger_ccp::{RouteManagerStore, RoutePolicies, RoutePolicy};
     use interledger_router::RouterStore;
     use interledger_service::Account as AccountTrait;
 
@@ -593,6 +1097,7 @@ mod ccp_store {
         block_on(test_store().and_then(|(store, context)| {
             store
                 .get_accounts_to_send_routes_to()
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |accounts| {
                     assert_eq!(accounts[0].id(), 1);
                     assert_eq!(accounts.len(), 1);
@@ -608,6 +1113,7 @@ mod ccp_store {
         block_on(test_store().and_then(|(store, context)| {
             store
                 .get_local_and_configured_routes()
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |(local, configured)| {
                     assert_eq!(local.len(), 2);
                     assert!(configured.is_empty());
@@ -630,6 +1136,7 @@ mod ccp_store {
                     (Bytes::from("example.b"), account0.clone()),
                     (Bytes::from("example.c"), account1.clone()),
                 ])
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |_| {
                     get_connection.and_then(|connection| {
                         redis::cmd("HGETALL")
@@ -665,6 +1172,7 @@ mod ccp_store {
                     (Bytes::from("example.b"), account0.clone()),
                     (Bytes::from("example.c"), account1.clone()),
                 ])
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |_| {
                     let routes = store.routing_table();
                     assert_eq!(routes[&b"example.a"[..]], 0);
@@ -680,6 +1188,93 @@ mod ccp_store {
         }))
         .unwrap()
     }
+
+    #[test]
+    fn defaults_to_permissive_route_policies() {
+        block_on(test_store().and_then(|(store, context)| {
+            store
+                .get_route_policies(0)
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |policies| {
+                    assert_eq!(policies, RoutePolicies::default());
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn sets_and_gets_route_policies() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            let policies = RoutePolicies {
+                import: RoutePolicy {
+                    deny_prefixes: vec!["example.evil".to_string()],
+                    ..Default::default()
+                },
+                max_imported_prefixes: Some(10),
+                export_own_and_children_only: true,
+                ..Default::default()
+            };
+            let expected = policies.clone();
+            store
+                .set_route_policies(1, policies)
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| store_clone.get_route_policies(1))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |policies| {
+                    assert_eq!(policies, expected);
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads_routing_state() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .load_routing_state(b"example.node")
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |state| {
+                    assert!(state.is_none());
+                    store
+                        .save_routing_state(b"example.node", Bytes::from(&[1, 2, 3][..]))
+                        .map_err(|err| panic!("{}", err))
+                })
+                .and_then(move |_| {
+                    store_clone
+                        .load_routing_state(b"example.node")
+                        .join(store_clone.load_routing_state(b"example.other"))
+                })
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |(state, other_state)| {
+                    assert_eq!(state, Some(Bytes::from(&[1, 2, 3][..])));
+                    // Each node's state is saved separately
+                    assert!(other_state.is_none());
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn errors_setting_route_policies_for_unknown_account() {
+        block_on(test_store().and_then(|(store, context)| {
+            store
+                .set_route_policies(5, RoutePolicies::default())
+                .then(move |result| {
+                    assert!(result.unwrap_err().is_not_found());
+                    let _ = context;
+                    Ok(())
+                })
+        }))
+        .unwrap()
+    }
 }
 
 mod configured_routes {
@@ -700,6 +1295,7 @@ mod configured_routes {
                     ("example.b".to_string(), 0),
                     ("example.c".to_string(), 1),
                 ])
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |_| {
                     get_connection.and_then(|connection| {
                         redis::cmd("HGETALL")
@@ -720,6 +1316,32 @@ mod configured_routes {
         .unwrap()
     }
 
+    #[test]
+    fn removes_all_static_routes() {
+        block_on(test_store().and_then(|(store, context)| {
+            let get_connection = context.async_connection();
+            let store_clone = store.clone();
+            store
+                .set_static_route("example.a".to_string(), 0)
+                .and_then(move |_| store_clone.set_static_routes(Vec::new()))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| {
+                    get_connection.and_then(|connection| {
+                        redis::cmd("EXISTS")
+                            .arg("routes:static")
+                            .query_async(connection)
+                            .map_err(|err| panic!("{:?}", err))
+                            .and_then(move |(_, exists): (_, bool)| {
+                                assert!(!exists);
+                                let _ = context;
+                                Ok(())
+                            })
+                    })
+                })
+        }))
+        .unwrap()
+    }
+
     #[test]
     fn static_routes_override_others() {
         block_on(test_store().and_then(|(store, context)| {
@@ -738,6 +1360,7 @@ mod configured_routes {
                         (Bytes::from("example.c"), account1),
                     ])
                 })
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |_| {
                     let routes = store.routing_table();
                     assert_eq!(routes[&b"example.a"[..]], 0);
@@ -761,6 +1384,7 @@ mod configured_routes {
                     ("example.b".to_string(), 1),
                 ])
                 .and_then(move |_| store.get_local_and_configured_routes())
+                .map_err(|err| panic!("{}", err))
                 .and_then(move |(_local, configured)| {
                     assert_eq!(configured.len(), 2);
                     assert_eq!(configured[&b"example.a"[..]].id(), 0);
@@ -772,3 +1396,46 @@ mod configured_routes {
         .unwrap()
     }
 }
+
+mod webhooks {
+    use super::*;
+    use interledger_webhooks::{WebhookDelivery, WebhookStore};
+
+    fn delivery(timestamp: u64, delivered: bool) -> WebhookDelivery {
+        WebhookDelivery {
+            timestamp,
+            url: "http://example.com/webhook".to_string(),
+            body: "{}".to_string(),
+            attempts: if delivered { 1 } else { 5 },
+            status: Some(if delivered { 200 } else { 503 }),
+            delivered,
+        }
+    }
+
+    #[test]
+    fn lists_most_recent_deliveries_first() {
+        block_on(test_store().and_then(|(store, context)| {
+            let store_clone = store.clone();
+            store
+                .record_webhook_delivery(0, delivery(1000, false))
+                .and_then(move |_| store.record_webhook_delivery(0, delivery(2000, true)))
+                .map_err(|err| panic!("{}", err))
+                .and_then(move |_| {
+                    store_clone
+                        .get_webhook_deliveries(0)
+                        .join(store_clone.get_webhook_deliveries(1))
+                        .map_err(|err| panic!("{}", err))
+                        .and_then(move |(deliveries0, deliveries1)| {
+                            assert_eq!(
+                                deliveries0,
+                                vec![delivery(2000, true), delivery(1000, false)]
+                            );
+                            assert!(deliveries1.is_empty());
+                            let _ = context;
+                            Ok(())
+                        })
+                })
+        }))
+        .unwrap()
+    }
+}
@@ -6,8 +6,8 @@ use bytes::Bytes;
 use futures::{Async, Future, Poll};
 use interledger_ildcp::get_ildcp_info;
 use interledger_packet::{
-    ErrorClass, ErrorCode as IlpErrorCode, Fulfill, PacketType as IlpPacketType, PrepareBuilder,
-    Reject,
+    Address, ErrorClass, ErrorCode as IlpErrorCode, Fulfill, PacketType as IlpPacketType,
+    PrepareBuilder, Reject,
 };
 use interledger_service::*;
 use std::{
@@ -23,7 +23,7 @@ use std::{
 pub fn send_money<S, A>(
     service: S,
     from_account: &A,
-    destination_account: &[u8],
+    destination_account: &Address,
     shared_secret: &[u8],
     source_amount: u64,
 ) -> impl Future<Item = (u64, S), Error = Error>
@@ -31,7 +31,7 @@ where
     S: IncomingService<A> + Clone,
     A: Account,
 {
-    let destination_account = Bytes::from(destination_account);
+    let destination_account = destination_account.clone();
     let shared_secret = Bytes::from(shared_secret);
     let from_account = from_account.clone();
     // TODO can/should we avoid cloning the account?
@@ -41,7 +41,7 @@ where
             state: SendMoneyFutureState::SendMoney,
             next: Some(service),
             from_account,
-            source_account: Bytes::from(account_details.client_address()),
+            source_account: account_details.client_address().to_bytes(),
             destination_account,
             shared_secret,
             source_amount,
@@ -60,7 +60,7 @@ struct SendMoneyFuture<S: IncomingService<A>, A: Account> {
     next: Option<S>,
     from_account: A,
     source_account: Bytes,
-    destination_account: Bytes,
+    destination_account: Address,
     shared_secret: Bytes,
     source_amount: u64,
     congestion_controller: CongestionController,
@@ -133,7 +133,7 @@ where
             let data = stream_packet.into_encrypted(&self.shared_secret);
             let execution_condition = generate_condition(&self.shared_secret, &data);
             let prepare = PrepareBuilder {
-                destination: &self.destination_account[..],
+                destination: &self.destination_account,
                 amount,
                 execution_condition: &execution_condition,
                 expires_at: SystemTime::now() + Duration::from_secs(30),
@@ -177,7 +177,7 @@ where
         // Create the ILP Prepare packet
         let data = stream_packet.into_encrypted(&self.shared_secret);
         let prepare = PrepareBuilder {
-            destination: &self.destination_account[..],
+            destination: &self.destination_account,
             amount: 0,
             execution_condition: &random_condition(),
             expires_at: SystemTime::now() + Duration::from_secs(30),
@@ -334,7 +334,7 @@ mod send_money_tests {
     use interledger_packet::{ErrorCode as IlpErrorCode, RejectBuilder};
     use interledger_service::incoming_service_fn;
     use parking_lot::Mutex;
-    use std::sync::Arc;
+    use std::{str::FromStr, sync::Arc};
 
     #[test]
     fn stops_at_final_errors() {
@@ -358,7 +358,7 @@ mod send_money_tests {
                 .build())
             })),
             &account,
-            b"example.destination",
+            &Address::from_str("example.destination").unwrap(),
             &[0; 32][..],
             100,
         )
@@ -29,7 +29,7 @@ pub mod test_helpers {
     use hashbrown::HashMap;
     use interledger_ildcp::IldcpAccount;
     use interledger_router::RouterStore;
-    use interledger_service::{Account, AccountStore};
+    use interledger_service::{Account, AccountStore, StoreError};
     use std::iter::FromIterator;
 
     #[derive(Debug, Eq, PartialEq, Clone)]
@@ -73,7 +73,7 @@ pub mod test_helpers {
         fn get_accounts(
             &self,
             _account_ids: Vec<<<Self as AccountStore>::Account as Account>::AccountId>,
-        ) -> Box<Future<Item = Vec<TestAccount>, Error = ()> + Send> {
+        ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
             Box::new(ok(vec![self.route.1.clone()]))
         }
     }
@@ -92,7 +92,7 @@ mod send_money_to_receiver {
     use bytes::Bytes;
     use futures::Future;
     use interledger_ildcp::IldcpService;
-    use interledger_packet::{ErrorCode, RejectBuilder};
+    use interledger_packet::{Address, ErrorCode, RejectBuilder};
     use interledger_router::Router;
     use interledger_service::outgoing_service_fn;
     use tokio::runtime::Runtime;
@@ -137,7 +137,7 @@ mod send_money_to_receiver {
                 asset_scale: 9,
                 ilp_address: Bytes::from("example.receiver"),
             },
-            &destination_account[..],
+            &Address::try_from(destination_account).unwrap(),
             &shared_secret[..],
             100,
         )
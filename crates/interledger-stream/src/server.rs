@@ -8,7 +8,9 @@ use interledger_ildcp::IldcpAccount;
 use interledger_packet::{
     ErrorCode, Fulfill, FulfillBuilder, PacketType as IlpPacketType, Prepare, Reject, RejectBuilder,
 };
-use interledger_service::{Account, BoxedIlpFuture, OutgoingRequest, OutgoingService};
+use interledger_service::{
+    Account, BoxedIlpFuture, Event, EventBus, OutgoingRequest, OutgoingService,
+};
 use std::marker::PhantomData;
 use std::str;
 
@@ -98,6 +100,7 @@ impl ConnectionGenerator {
 pub struct StreamReceiverService<S: OutgoingService<A>, A: Account> {
     connection_generator: ConnectionGenerator,
     next: S,
+    events: Option<EventBus<A>>,
     account_type: PhantomData<A>,
 }
 
@@ -111,9 +114,16 @@ where
         StreamReceiverService {
             connection_generator,
             next,
+            events: None,
             account_type: PhantomData,
         }
     }
+
+    /// Publish a `PaymentReceived` event for every packet this service fulfills.
+    pub fn with_events(mut self, events: EventBus<A>) -> Self {
+        self.events = Some(events);
+        self
+    }
 }
 
 // TODO should this be an OutgoingService instead so the balance logic is applied before this is called?
@@ -134,18 +144,24 @@ where
         if request
             .prepare
             .destination()
-            .starts_with(request.to.client_address())
+            .has_prefix(request.to.client_address())
         {
             if let Ok(shared_secret) = self
                 .connection_generator
                 .rederive_secret(request.prepare.destination())
             {
                 {
-                    return Box::new(result(receive_money(
-                        &shared_secret,
-                        request.to.client_address(),
-                        request.prepare,
-                    )));
+                    let amount = request.prepare.amount();
+                    let fulfill =
+                        receive_money(&shared_secret, request.to.client_address(), request.prepare);
+                    if let (Ok(_), Some(ref events)) = (&fulfill, &self.events) {
+                        events.publish(Event::PaymentReceived {
+                            from: request.from,
+                            to: request.to,
+                            amount,
+                        });
+                    }
+                    return Box::new(result(fulfill));
                 }
             }
         }
@@ -304,7 +320,7 @@ fn test_stream_packet() -> StreamPacket {
 #[cfg(test)]
 mod receiving_money {
     use super::*;
-    use interledger_packet::PrepareBuilder;
+    use interledger_packet::{Address, PrepareBuilder};
     use std::time::UNIX_EPOCH;
 
     #[test]
@@ -319,7 +335,7 @@ mod receiving_money {
         let execution_condition = generate_condition(&shared_secret[..], &data);
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -346,7 +362,7 @@ mod receiving_money {
         let execution_condition = generate_condition(&shared_secret[..], &data);
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -374,7 +390,7 @@ mod receiving_money {
         let execution_condition = generate_condition(&shared_secret[..], &data);
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -412,7 +428,7 @@ mod receiving_money {
         let execution_condition = generate_condition(&shared_secret[..], &data);
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -433,7 +449,7 @@ mod stream_receiver_service {
     use super::*;
     use crate::test_helpers::*;
     use futures::Future;
-    use interledger_packet::PrepareBuilder;
+    use interledger_packet::{Address, PrepareBuilder};
     use interledger_service::outgoing_service_fn;
     use std::time::UNIX_EPOCH;
 
@@ -449,7 +465,7 @@ mod stream_receiver_service {
         let execution_condition = generate_condition(&shared_secret[..], &data);
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -498,7 +514,7 @@ mod stream_receiver_service {
         data.extend_from_slice(b"extra");
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -547,7 +563,7 @@ mod stream_receiver_service {
         destination_account.extend_from_slice(b"extra");
 
         let prepare = PrepareBuilder {
-            destination: &destination_account[..],
+            destination: &Address::try_from(destination_account.clone()).unwrap(),
             amount: 100,
             expires_at: UNIX_EPOCH,
             data: &data[..],
@@ -0,0 +1,63 @@
+//! # interledger-webhooks
+//!
+//! Notifies the owners of accounts on this node when they receive payments
+//! or when their balance crosses a configured threshold.
+//!
+//! The `WebhookNotifier` listens to the node's `EventBus` and POSTs a JSON
+//! notification to each account's webhook URL. Requests are signed with
+//! HMAC-SHA256 using the account's webhook secret, retried with exponential
+//! backoff, and the outcome of every delivery is recorded in the store.
+
+#[macro_use]
+extern crate log;
+
+use futures::Future;
+use interledger_ildcp::IldcpAccount;
+use interledger_service::{Account, AccountStore, StoreError};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+mod notifier;
+
+pub use notifier::{WebhookNotifier, SIGNATURE_HEADER};
+
+pub trait WebhookAccount: IldcpAccount {
+    /// The URL notifications for this account are POSTed to
+    fn webhook_url(&self) -> Option<&Url>;
+
+    /// The key used to sign the notifications sent to this account's webhook
+    fn webhook_secret(&self) -> Option<&[u8]>;
+
+    /// Notify the account's webhook when its balance goes above or below this value
+    fn webhook_balance_threshold(&self) -> Option<i64>;
+}
+
+/// The result of sending one notification to an account's webhook.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    /// Milliseconds since the UNIX epoch when the notification was created
+    pub timestamp: u64,
+    pub url: String,
+    /// The JSON notification that was sent
+    pub body: String,
+    pub attempts: u32,
+    /// HTTP status code returned by the last attempt, if the webhook responded at all
+    pub status: Option<u16>,
+    pub delivered: bool,
+}
+
+pub trait WebhookStore: AccountStore {
+    /// Add a delivery to the account's log.
+    /// Stores may only keep a limited number of recent deliveries.
+    fn record_webhook_delivery(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+        delivery: WebhookDelivery,
+    ) -> Box<Future<Item = (), Error = StoreError> + Send>;
+
+    /// Load the account's delivery log, most recent first.
+    fn get_webhook_deliveries(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+    ) -> Box<Future<Item = Vec<WebhookDelivery>, Error = StoreError> + Send>;
+}
@@ -0,0 +1,430 @@
+use super::{WebhookAccount, WebhookDelivery, WebhookStore};
+use futures::{
+    future::{loop_fn, ok, Either, FutureResult, Loop},
+    Future, Stream,
+};
+use interledger_service::{Event, EventBus};
+use reqwest::{header::CONTENT_TYPE, r#async::Client, StatusCode};
+use ring::{digest, hmac};
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{spawn, timer::Delay};
+use url::Url;
+
+/// Header containing the hex-encoded HMAC-SHA256 of the request body,
+/// keyed with the account's webhook secret.
+pub const SIGNATURE_HEADER: &str = "Ilp-Webhook-Signature";
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Notification {
+    PaymentReceived {
+        account_id: String,
+        from_account: String,
+        amount: u64,
+        asset_code: String,
+        asset_scale: u8,
+        timestamp: u64,
+    },
+    BalanceThreshold {
+        account_id: String,
+        balance: i64,
+        threshold: i64,
+        crossed: Crossed,
+        asset_code: String,
+        asset_scale: u8,
+        timestamp: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Crossed {
+    Above,
+    Below,
+}
+
+fn threshold_crossed(previous: i64, balance: i64, threshold: i64) -> Option<Crossed> {
+    if previous < threshold && balance >= threshold {
+        Some(Crossed::Above)
+    } else if previous >= threshold && balance < threshold {
+        Some(Crossed::Below)
+    } else {
+        None
+    }
+}
+
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret);
+    hex::encode(hmac::sign(&key, body).as_ref())
+}
+
+fn now_millis() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is set before the UNIX epoch");
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+/// Sends notifications about the events published on the node's `EventBus`
+/// to the webhooks of the accounts they concern.
+///
+/// Accounts are notified when they receive a payment (a `PaymentReceived` event)
+/// and when a `BalanceChanged` event moves their balance across their threshold.
+#[derive(Clone)]
+pub struct WebhookNotifier<T> {
+    store: T,
+    client: Client,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl<T, A> WebhookNotifier<T>
+where
+    T: WebhookStore<Account = A> + Clone + Send + Sync + 'static,
+    A: WebhookAccount + 'static,
+{
+    pub fn new(store: T) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .unwrap();
+        WebhookNotifier {
+            store,
+            client,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MS),
+        }
+    }
+
+    /// How many times each notification is sent before giving up (defaults to 5).
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// How long to wait before retrying a failed notification (defaults to 1 second).
+    /// The wait doubles after every failed attempt.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Subscribe to the bus and notify the webhooks about its events.
+    ///
+    /// The returned future must be run on a Tokio executor because each notification
+    /// is spawned as a separate task. It completes when every handle to the bus is dropped.
+    pub fn listen(self, events: &EventBus<A>) -> impl Future<Item = (), Error = ()> {
+        events.subscribe().for_each(move |event| {
+            self.handle_event(event);
+            Ok(())
+        })
+    }
+
+    fn handle_event(&self, event: Event<A>) {
+        match event {
+            Event::PaymentReceived { from, to, amount } => {
+                if let Some(url) = to.webhook_url().cloned() {
+                    let notification = Notification::PaymentReceived {
+                        account_id: to.id().to_string(),
+                        from_account: from.id().to_string(),
+                        amount,
+                        asset_code: to.asset_code().to_string(),
+                        asset_scale: to.asset_scale(),
+                        timestamp: now_millis(),
+                    };
+                    spawn(self.deliver(to, url, &notification));
+                }
+            }
+            Event::BalanceChanged {
+                account,
+                amount,
+                balance,
+            } => {
+                if let (Some(url), Some(threshold)) = (
+                    account.webhook_url().cloned(),
+                    account.webhook_balance_threshold(),
+                ) {
+                    let previous = balance.saturating_sub(amount);
+                    if let Some(crossed) = threshold_crossed(previous, balance, threshold) {
+                        let notification = Notification::BalanceThreshold {
+                            account_id: account.id().to_string(),
+                            balance,
+                            threshold,
+                            crossed,
+                            asset_code: account.asset_code().to_string(),
+                            asset_scale: account.asset_scale(),
+                            timestamp: now_millis(),
+                        };
+                        spawn(self.deliver(account, url, &notification));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// POST the notification to the webhook, retrying until it responds with
+    /// a success status or we run out of attempts, and then log the delivery.
+    fn deliver(
+        &self,
+        account: A,
+        url: Url,
+        notification: &Notification,
+    ) -> impl Future<Item = (), Error = ()> {
+        let timestamp = now_millis();
+        let body = serde_json::to_string(notification)
+            .expect("Notifications can always be serialized as JSON");
+        let signature = account
+            .webhook_secret()
+            .map(|secret| sign(secret, body.as_bytes()));
+        let account_id = account.id();
+        let client = self.client.clone();
+        let store = self.store.clone();
+        let max_attempts = self.max_attempts;
+        let request_url = url.clone();
+        let request_body = body.clone();
+
+        loop_fn(
+            (1, self.initial_backoff),
+            move |(attempt, backoff): (u32, Duration)| {
+                let mut request = client
+                    .post(request_url.clone())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(request_body.clone());
+                if let Some(ref signature) = signature {
+                    request = request.header(SIGNATURE_HEADER, signature.as_str());
+                }
+                let request_url = request_url.clone();
+                request.send().then(move |result| {
+                    let status: Option<StatusCode> = match result {
+                        Ok(response) => Some(response.status()),
+                        Err(err) => {
+                            warn!(
+                                "Error sending webhook notification to {}: {:?}",
+                                request_url, err
+                            );
+                            None
+                        }
+                    };
+                    let delivered = status.map(|status| status.is_success()).unwrap_or(false);
+                    if delivered || attempt >= max_attempts {
+                        let done: FutureResult<_, ()> = ok(Loop::Break((
+                            attempt,
+                            status.map(|status| status.as_u16()),
+                            delivered,
+                        )));
+                        Either::A(done)
+                    } else {
+                        debug!(
+                            "Webhook {} responded with {:?}, retrying in {:?}",
+                            request_url, status, backoff
+                        );
+                        Either::B(
+                            Delay::new(Instant::now() + backoff)
+                                .then(move |_| Ok(Loop::Continue((attempt + 1, backoff * 2)))),
+                        )
+                    }
+                })
+            },
+        )
+        .and_then(move |(attempts, status, delivered)| {
+            if !delivered {
+                warn!(
+                    "Giving up on webhook notification for account {} after {} attempts",
+                    account_id, attempts
+                );
+            }
+            store
+                .record_webhook_delivery(
+                    account_id,
+                    WebhookDelivery {
+                        timestamp,
+                        url: url.to_string(),
+                        body,
+                        attempts,
+                        status,
+                        delivered,
+                    },
+                )
+                .map_err(move |err| {
+                    error!(
+                        "Unable to record webhook delivery for account {}: {}",
+                        account_id, err
+                    )
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{
+        future::err,
+        sync::mpsc::{unbounded, UnboundedSender},
+    };
+    use hyper::{service::service_fn, Body, Request, Response, Server};
+    use interledger_ildcp::IldcpAccount;
+    use interledger_service::{Account, AccountStore, StoreError};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::runtime::Runtime;
+
+    /// The signature header and body of each request the test webhook received
+    type ReceivedRequests = Arc<Mutex<Vec<(Option<String>, Vec<u8>)>>>;
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        webhook_url: Url,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &[u8] {
+            b"example.account"
+        }
+
+        fn asset_code(&self) -> &str {
+            "XYZ"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+    }
+
+    impl WebhookAccount for TestAccount {
+        fn webhook_url(&self) -> Option<&Url> {
+            Some(&self.webhook_url)
+        }
+
+        fn webhook_secret(&self) -> Option<&[u8]> {
+            Some(b"webhook secret")
+        }
+
+        fn webhook_balance_threshold(&self) -> Option<i64> {
+            None
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestStore {
+        deliveries: UnboundedSender<WebhookDelivery>,
+    }
+
+    impl AccountStore for TestStore {
+        type Account = TestAccount;
+
+        fn get_accounts(
+            &self,
+            _account_ids: Vec<u64>,
+        ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
+            Box::new(err(StoreError::NotFound(
+                "No accounts in test store".to_string(),
+            )))
+        }
+    }
+
+    impl WebhookStore for TestStore {
+        fn record_webhook_delivery(
+            &self,
+            _account_id: u64,
+            delivery: WebhookDelivery,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            self.deliveries.unbounded_send(delivery).unwrap();
+            Box::new(ok(()))
+        }
+
+        fn get_webhook_deliveries(
+            &self,
+            _account_id: u64,
+        ) -> Box<Future<Item = Vec<WebhookDelivery>, Error = StoreError> + Send> {
+            Box::new(ok(Vec::new()))
+        }
+    }
+
+    #[test]
+    fn retries_signed_payment_notifications() {
+        let requests: ReceivedRequests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(move || {
+            let requests = requests_clone.clone();
+            service_fn(move |request: Request<Body>| {
+                let requests = requests.clone();
+                let signature = request
+                    .headers()
+                    .get(SIGNATURE_HEADER)
+                    .map(|value| value.to_str().unwrap().to_string());
+                request.into_body().concat2().map(move |body| {
+                    let mut requests = requests.lock();
+                    requests.push((signature, body.to_vec()));
+                    // Fail the first attempt so the notification has to be retried
+                    let status = if requests.len() == 1 { 500 } else { 200 };
+                    Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap()
+                })
+            })
+        });
+        let webhook_url = Url::parse(&format!("http://{}/webhook", server.local_addr())).unwrap();
+
+        let (sender, deliveries) = unbounded();
+        let notifier = WebhookNotifier::new(TestStore { deliveries: sender })
+            .initial_backoff(Duration::from_millis(10));
+        let events = EventBus::new();
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(server.map_err(|err| panic!("Webhook server error: {:?}", err)));
+        runtime.spawn(notifier.listen(&events));
+        events.publish(Event::PaymentReceived {
+            from: TestAccount {
+                id: 1,
+                webhook_url: webhook_url.clone(),
+            },
+            to: TestAccount { id: 2, webhook_url },
+            amount: 100,
+        });
+
+        let (delivery, _) = runtime
+            .block_on(deliveries.into_future().map_err(|_| ()))
+            .unwrap();
+        let delivery = delivery.unwrap();
+        assert!(delivery.delivered);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.status, Some(200));
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 2);
+        let (ref signature, ref body) = requests[1];
+        assert_eq!(
+            signature.as_deref(),
+            Some(sign(b"webhook secret", body).as_str())
+        );
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["type"], "payment_received");
+        assert_eq!(body["account_id"], "2");
+        assert_eq!(body["from_account"], "1");
+        assert_eq!(body["amount"], 100);
+    }
+
+    #[test]
+    fn detects_threshold_crossings() {
+        assert_eq!(threshold_crossed(50, 150, 100), Some(Crossed::Above));
+        assert_eq!(threshold_crossed(50, 100, 100), Some(Crossed::Above));
+        assert_eq!(threshold_crossed(100, 99, 100), Some(Crossed::Below));
+        assert_eq!(threshold_crossed(150, 120, 100), None);
+        assert_eq!(threshold_crossed(-10, 20, 100), None);
+    }
+}
@@ -1,32 +1,60 @@
+use crate::config::{reconcile, watch, NodeConfig};
 use base64;
 use bytes::Bytes;
-use futures::{future::ok, Future};
+use futures::{
+    future::{self, ok},
+    Future,
+};
+#[cfg(unix)]
+use futures::{Poll, Stream};
 use hyper::{
     header::{HeaderValue, ACCEPT},
     service::{service_fn, Service},
     Body, Error, Method, Request, Response, Server,
 };
-use interledger_api::{NodeApi, NodeStore};
+use interledger_api::{HealthMonitor, NodeAccount, NodeApi, NodeStore, PublicApi};
 use interledger_btp::{connect_client, create_open_signup_server, create_server, parse_btp_url};
-use interledger_ccp::CcpRouteManager;
-use interledger_http::{HttpClientService, HttpServerService};
+use interledger_ccp::{
+    CcpRouteManager, CcpRoutingAccount, PeerStats, PeerStatsService, RouteAuth, RouteManagerStore,
+    RouteSelection,
+};
+use interledger_http::{HttpAccount, HttpClientService, HttpServerService, HttpStore};
 use interledger_ildcp::{get_ildcp_info, IldcpAccount, IldcpResponse, IldcpService};
 use interledger_packet::{ErrorCode, RejectBuilder};
-use interledger_router::Router;
+use interledger_router::{Router, RouterStore};
 use interledger_service::{
-    incoming_service_fn, outgoing_service_fn, AccountStore, OutgoingRequest,
+    incoming_service_fn, outgoing_service_fn, Account as AccountTrait, AccountStore, EventBus,
+    IncomingService, OutgoingRequest,
 };
 use interledger_service_util::{
-    ExchangeRateAndBalanceService, MaxPacketAmountService, ValidatorService,
+    BalanceStore, DrainService, ExchangeRateAndBalanceService, MaxPacketAmountService,
+    MetricsService, PacketEventService, TransactionStore, ValidatorService,
 };
 use interledger_spsp::{pay, SpspResponder};
 use interledger_store_memory::{Account, AccountBuilder, InMemoryStore};
 use interledger_store_redis::{connect as connect_redis_store, IntoConnectionInfo};
 use interledger_stream::StreamReceiverService;
+use interledger_webhooks::{WebhookNotifier, WebhookStore};
 use parking_lot::RwLock;
 use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+#[cfg(unix)]
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+};
 use std::{net::SocketAddr, str, sync::Arc, u64};
+use stream_cancel::Valve;
 use tokio::{self, net::TcpListener};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{UnixListener, UnixStream},
+};
+#[cfg(unix)]
+use tower_web::net::Connection;
 use tower_web::ServiceBuilder;
 use url::Url;
 
@@ -210,7 +238,7 @@ pub fn run_spsp_server_btp(
 
         get_ildcp_info(&mut incoming_service, incoming_account.clone()).and_then(move |info| {
             debug!("SPSP server got ILDCP info: {:?}", info);
-            let client_address = Bytes::from(info.client_address());
+            let client_address = info.client_address().to_bytes();
             *ilp_address.write() = client_address.clone();
 
             let receiver_account = AccountBuilder::new()
@@ -248,7 +276,7 @@ pub fn run_spsp_server_http(
     if !quiet {
         println!(
             "Creating SPSP server. ILP Address: {}",
-            str::from_utf8(ildcp_info.client_address()).expect("ILP address is not valid UTF8")
+            ildcp_info.client_address()
         )
     }
     let account: Account = AccountBuilder::new()
@@ -257,10 +285,10 @@ pub fn run_spsp_server_http(
     let server_secret = Bytes::from(&random_secret()[..]);
     let store = InMemoryStore::from_accounts(vec![account.clone()]);
     let spsp_responder = SpspResponder::new(
-        Bytes::from(ildcp_info.client_address()),
+        ildcp_info.client_address().to_bytes(),
         server_secret.clone(),
     );
-    let ilp_address = Bytes::from(ildcp_info.client_address());
+    let ilp_address = ildcp_info.client_address().to_bytes();
     let outgoing_handler = StreamReceiverService::new(
         server_secret,
         outgoing_service_fn(move |request: OutgoingRequest<Account>| {
@@ -321,7 +349,7 @@ pub fn run_moneyd_local(
     address: SocketAddr,
     ildcp_info: IldcpResponse,
 ) -> impl Future<Item = (), Error = ()> {
-    let ilp_address = Bytes::from(ildcp_info.client_address());
+    let ilp_address = ildcp_info.client_address().to_bytes();
     let store = InMemoryStore::default();
     // TODO this needs a reference to the BtpService so it can send outgoing packets
     println!("Listening on: {}", address);
@@ -346,97 +374,314 @@ pub fn run_moneyd_local(
     )
 }
 
+/// Where the node serves the admin API (accounts, rates, routes, API tokens and payments).
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminAddress {
+    /// Serve the admin API over TCP. This must be a different port from the public HTTP address.
+    Tcp(SocketAddr),
+    /// Serve the admin API on a Unix domain socket so it is only reachable from this machine.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Optional behavior of the node run by `run_node_redis`
+#[derive(Clone, Debug, Default)]
+pub struct NodeOptions {
+    /// Record rejected packets in the transaction ledger as well as fulfilled ones
+    pub record_rejected_transactions: bool,
+    /// How the CCP Route Manager signs and verifies the routes it exchanges with peers
+    pub route_auth: RouteAuth,
+    /// If set, the CCP Route Manager chooses between peers' routes by their cost
+    pub route_selection: Option<RouteSelection>,
+    /// Reject ILP, BTP, and CCP packets that are not canonically encoded or have trailing bytes
+    pub strict_parsing: bool,
+}
+
+fn serve_admin_api<T, S, A>(admin_address: AdminAddress, admin_api: NodeApi<T, S>, valve: &Valve)
+where
+    T: NodeStore<Account = A>
+        + HttpStore<Account = A>
+        + BalanceStore<Account = A>
+        + TransactionStore<Account = A>
+        + WebhookStore<Account = A>
+        + RouterStore
+        + RouteManagerStore<Account = A>,
+    S: IncomingService<A> + Clone + Send + Sync + 'static,
+    A: AccountTrait
+        + HttpAccount
+        + NodeAccount
+        + IldcpAccount
+        + CcpRoutingAccount
+        + Serialize
+        + 'static,
+{
+    match admin_address {
+        AdminAddress::Tcp(address) => {
+            let listener =
+                TcpListener::bind(&address).expect("Unable to bind to admin API address");
+            println!("Admin API listening on: {}", address);
+            tokio::spawn(
+                ServiceBuilder::new()
+                    .resource(admin_api)
+                    .serve(valve.wrap(listener.incoming())),
+            );
+        }
+        #[cfg(unix)]
+        AdminAddress::Unix(path) => {
+            // Clean up the socket left behind if the node was not shut down cleanly
+            if fs::metadata(&path)
+                .map(|metadata| metadata.file_type().is_socket())
+                .unwrap_or(false)
+            {
+                fs::remove_file(&path).expect("Unable to remove old admin API socket");
+            }
+            let listener = UnixListener::bind(&path).expect("Unable to bind to admin API socket");
+            println!("Admin API listening on: {}", path.display());
+            tokio::spawn(
+                ServiceBuilder::new()
+                    .resource(admin_api)
+                    .serve(valve.wrap(listener.incoming()).map(UnixConnection)),
+            );
+        }
+    }
+}
+
+/// tower-web can only serve connections that report a peer address,
+/// which Unix sockets do not have.
+#[cfg(unix)]
+struct UnixConnection(UnixStream);
+
+#[cfg(unix)]
+impl Read for UnixConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl Write for UnixConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(unix)]
+impl AsyncRead for UnixConnection {}
+
+#[cfg(unix)]
+impl AsyncWrite for UnixConnection {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.0)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixConnection {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Runs the node until `shutdown` resolves and then shuts it down gracefully.
+///
+/// The node stops accepting connections and Prepare packets, waits for the packets in flight
+/// to be fulfilled or rejected (so their balance changes are settled or rolled back),
+/// closes the BTP connections, and stops polling Redis and broadcasting routes.
+/// Other tasks such as open HTTP connections are left to be dropped with the runtime.
 #[doc(hidden)]
 // TODO when a BTP connection is made, insert a outgoing HTTP entry into the Store to tell other
 // connector instances to forward packets for that account to us
-pub fn run_node_redis<R>(
+pub fn run_node_redis<R, F>(
     redis_uri: R,
     btp_address: SocketAddr,
     http_address: SocketAddr,
+    admin_address: AdminAddress,
     server_secret: &[u8; 32],
+    options: NodeOptions,
+    shutdown: F,
 ) -> impl Future<Item = (), Error = ()>
 where
     R: IntoConnectionInfo,
+    F: Future<Item = (), Error = ()> + Send + 'static,
 {
     debug!("Starting Interledger node with Redis store");
+    let redis_secret = *server_secret;
     let server_secret = Bytes::from(&server_secret[..]);
-    connect_redis_store(redis_uri)
+    connect_redis_store(redis_uri, redis_secret)
         .map_err(|err| eprintln!("Error connecting to Redis: {:?}", err))
         .and_then(move |store| {
             store
                 .clone()
                 .get_accounts(vec![0])
-                .map_err(|_| {
-                    eprintln!("Must add account 0 (the default account) before running the node")
+                .map_err(|err| {
+                    if err.is_not_found() {
+                        eprintln!(
+                            "Must add account 0 (the default account) before running the node"
+                        )
+                    } else {
+                        eprintln!("Error loading the default account: {}", err)
+                    }
                 })
                 .and_then(move |accounts| {
                     let default_account = accounts[0].clone();
+                    let ilp_address = Bytes::from(default_account.client_address());
                     let outgoing_service = HttpClientService::new(store.clone());
-                    create_server(btp_address, store.clone(), outgoing_service).and_then(
-                        move |btp_service| {
-                            // The BTP service is both an Incoming and Outgoing one so we pass it first as the Outgoing
-                            // service to others like the router and then call handle_incoming on it to set up the incoming handler
-                            let outgoing_service = btp_service.clone();
-                            let outgoing_service = ValidatorService::outgoing(outgoing_service);
-                            let outgoing_service =
-                                StreamReceiverService::new(server_secret.clone(), outgoing_service);
-                            let outgoing_service =
-                                ExchangeRateAndBalanceService::new(store.clone(), outgoing_service);
-
-                            // Set up the Router and Routing Manager
-                            let incoming_service =
-                                Router::new(store.clone(), outgoing_service.clone());
-                            let incoming_service = CcpRouteManager::new(
-                                default_account,
-                                store.clone(),
-                                outgoing_service,
-                                incoming_service,
+                    // All of the node's events are published on one bus
+                    let events = EventBus::new();
+                    create_server(
+                        btp_address,
+                        store.clone(),
+                        outgoing_service,
+                        events.clone(),
+                        options.strict_parsing,
+                    )
+                    .and_then(move |btp_service| {
+                        // The BTP service is both an Incoming and Outgoing one so we pass it first as the Outgoing
+                        // service to others like the router and then call handle_incoming on it to set up the incoming handler
+                        let outgoing_service = btp_service.clone();
+                        let outgoing_service = ValidatorService::outgoing(outgoing_service);
+                        let outgoing_service = MetricsService::outgoing(outgoing_service);
+                        // Records how each peer performs, for choosing routes by cost
+                        let peer_stats = PeerStats::new();
+                        let outgoing_service =
+                            PeerStatsService::new(peer_stats.clone(), outgoing_service);
+                        let outgoing_service =
+                            StreamReceiverService::new(server_secret.clone(), outgoing_service)
+                                .with_events(events.clone());
+                        let outgoing_service =
+                            ExchangeRateAndBalanceService::new(store.clone(), outgoing_service)
+                                .with_events(events.clone())
+                                .record_rejected(options.record_rejected_transactions);
+                        let outgoing_service =
+                            PacketEventService::new(events.clone(), outgoing_service);
+                        tokio::spawn(WebhookNotifier::new(store.clone()).listen(&events));
+
+                        // Set up the Router and Routing Manager
+                        if let Some(public_key) = options.route_auth.public_key() {
+                            println!(
+                                "Signing routes with public key: {}",
+                                hex::encode(&public_key[..])
                             );
+                        }
+                        let incoming_service = Router::new(store.clone(), outgoing_service.clone());
+                        let mut route_manager = CcpRouteManager::new(
+                            default_account,
+                            store.clone(),
+                            outgoing_service,
+                            incoming_service,
+                        )
+                        .with_events(events.clone())
+                        .with_route_auth(options.route_auth)
+                        .with_strict_parsing(options.strict_parsing);
+                        if let Some(route_selection) = options.route_selection {
+                            route_manager =
+                                route_manager.with_route_selection(route_selection, peer_stats);
+                        }
+                        let incoming_service = route_manager.clone();
 
-                            let incoming_service = IldcpService::new(incoming_service);
-                            let incoming_service = MaxPacketAmountService::new(incoming_service);
-                            let incoming_service = ValidatorService::incoming(incoming_service);
+                        let incoming_service = IldcpService::new(incoming_service);
+                        let incoming_service = MaxPacketAmountService::new(incoming_service);
+                        let incoming_service = ValidatorService::incoming(incoming_service);
+                        let incoming_service = MetricsService::incoming(incoming_service);
+                        let incoming_service = DrainService::new(ilp_address, incoming_service);
+                        let drain = incoming_service.clone();
 
-                            // Handle incoming packets sent via BTP
-                            btp_service.handle_incoming(incoming_service.clone());
+                        // Handle incoming packets sent via BTP
+                        let btp_service = btp_service.handle_incoming(incoming_service.clone());
 
-                            // TODO should this run the node api on a different port so it's easier to separate public/private?
-                            // Note the API also includes receiving ILP packets sent via HTTP
-                            let api = NodeApi::new(
-                                server_secret,
-                                store.clone(),
-                                incoming_service.clone(),
-                            );
-                            let listener = TcpListener::bind(&http_address)
-                                .expect("Unable to bind to HTTP address");
-                            println!("Interledger node listening on: {}", http_address);
-                            let server = ServiceBuilder::new()
-                                .resource(api)
-                                .serve(listener.incoming());
-                            tokio::spawn(server);
-                            Ok(())
-                        },
-                    )
+                        // Note the public API also includes receiving ILP packets sent via HTTP
+                        let public_api =
+                            PublicApi::new(server_secret, store.clone(), incoming_service.clone())
+                                .with_strict_parsing(options.strict_parsing);
+                        let health = HealthMonitor::new();
+                        tokio::spawn(health.listen(&events));
+                        let admin_api = NodeApi::new(store.clone(), incoming_service.clone())
+                            .with_events(events)
+                            .with_health(health)
+                            .with_route_manager(route_manager.clone());
+                        let listener = TcpListener::bind(&http_address)
+                            .expect("Unable to bind to HTTP address");
+                        let (stop_listening, valve) = Valve::new();
+                        println!("Interledger node listening on: {}", http_address);
+                        tokio::spawn(
+                            ServiceBuilder::new()
+                                .resource(public_api)
+                                .serve(valve.wrap(listener.incoming())),
+                        );
+                        serve_admin_api(admin_address, admin_api, &valve);
+
+                        shutdown.then(move |_| {
+                            println!("Shutting down, waiting for packets in flight...");
+                            drop(stop_listening);
+                            drain.drain().then(move |_| {
+                                btp_service.close();
+                                route_manager.stop_broadcasting();
+                                store.stop_polling();
+                                println!("Interledger node stopped");
+                                Ok(())
+                            })
+                        })
+                    })
                 })
         })
 }
 
+/// Resolves when the process receives Ctrl-C or, on Unix, `SIGTERM`.
+#[doc(hidden)]
+pub fn shutdown_signal() -> impl Future<Item = (), Error = ()> {
+    fn first_signal<S>(name: &'static str, signals: S) -> Box<Future<Item = (), Error = ()> + Send>
+    where
+        S: Future + Send + 'static,
+        S::Item: Stream<Error = io::Error> + Send,
+        S::Error: Into<io::Error>,
+    {
+        Box::new(
+            signals
+                .map_err(Into::into)
+                .flatten_stream()
+                .into_future()
+                .map(|_| ())
+                .or_else(move |(err, _)| {
+                    // Never resolve so the node doesn't shut down because it can't listen for the signal
+                    error!("Unable to listen for {}: {:?}", name, err);
+                    future::empty()
+                }),
+        )
+    }
+
+    let ctrl_c = first_signal("Ctrl-C", tokio_signal::ctrl_c());
+    #[cfg(unix)]
+    let ctrl_c = {
+        use tokio_signal::unix::{Signal, SIGTERM};
+        ctrl_c
+            .select(first_signal("SIGTERM", Signal::new(SIGTERM)))
+            .map(|_| ())
+            .map_err(|_| ())
+    };
+    ctrl_c
+}
+
 #[doc(hidden)]
 pub use interledger_api::AccountDetails;
 #[doc(hidden)]
 pub fn insert_account_redis<R>(
     redis_uri: R,
+    server_secret: &[u8; 32],
     account: AccountDetails,
 ) -> impl Future<Item = (), Error = ()>
 where
     R: IntoConnectionInfo,
 {
-    connect_redis_store(redis_uri)
+    connect_redis_store(redis_uri, *server_secret)
         .map_err(|err| eprintln!("Error connecting to Redis: {:?}", err))
         .and_then(move |store| {
             store
                 .insert_account(account)
-                .map_err(|_| eprintln!("Unable to create account"))
+                .map_err(|err| eprintln!("Unable to create account: {}", err))
                 .and_then(|account| {
                     // TODO add quiet option
                     println!("Created account: {:?}", account);
@@ -444,3 +689,30 @@ where
                 })
         })
 }
+
+#[doc(hidden)]
+pub fn configure_node_redis<R>(
+    redis_uri: R,
+    server_secret: &[u8; 32],
+    path: PathBuf,
+    config: NodeConfig,
+) -> impl Future<Item = (), Error = ()>
+where
+    R: IntoConnectionInfo,
+{
+    connect_redis_store(redis_uri, *server_secret)
+        .map_err(|err| eprintln!("Error connecting to Redis: {:?}", err))
+        .and_then(move |store| {
+            reconcile(store.clone(), config.clone())
+                .map_err(|err| {
+                    eprintln!(
+                        "Unable to apply the accounts, rates, and routes from the config: {}",
+                        err
+                    )
+                })
+                .map(move |_| {
+                    // Apply any later changes to the file while the node is running
+                    tokio::spawn(watch(store, path, config));
+                })
+        })
+}
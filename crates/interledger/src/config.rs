@@ -0,0 +1,862 @@
+//! The configuration file for `ilp node`.
+//!
+//! The file may be written in TOML or YAML (chosen by its extension) and describes
+//! the node's settings along with the accounts, exchange rates, and static routes
+//! that should exist in its store. Any `${VAR}` in the file is replaced with the value
+//! of that environment variable, so secrets do not need to be written in the file itself.
+//!
+//! While the node is running, changes to the file are applied to the store without
+//! restarting it (see `watch`).
+
+use futures::{
+    future::{ok, result, Either},
+    stream, Future, Stream,
+};
+use interledger_api::{AccountDetails, AccountSettings, NodeAccount, NodeStore};
+use interledger_ccp::RouteSelection;
+use interledger_ildcp::IldcpAccount;
+use interledger_service::StoreError;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str,
+    time::{Duration, SystemTime},
+};
+use tokio::timer::Interval;
+
+/// Environment variables that take precedence over the values in the file
+const SERVER_SECRET_ENV_VAR: &str = "ILP_SERVER_SECRET";
+const REDIS_URI_ENV_VAR: &str = "ILP_REDIS_URI";
+const ROUTE_SIGNING_KEY_ENV_VAR: &str = "ILP_ROUTE_SIGNING_KEY";
+/// How often the config file is checked for changes while the node is running
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    /// The file referenced an environment variable that is not set
+    MissingEnvVar(String),
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Unable to read config file: {}", err),
+            ConfigError::Toml(err) => write!(f, "Invalid TOML config: {}", err),
+            ConfigError::Yaml(err) => write!(f, "Invalid YAML config: {}", err),
+            ConfigError::MissingEnvVar(name) => {
+                write!(f, "Config references unset environment variable: {}", name)
+            }
+            ConfigError::UnknownFormat(path) => write!(
+                f,
+                "Config file must end in .toml, .yaml, or .yml: {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings for `ilp node`. Anything not set in the file falls back to the
+/// command-line flags and their defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub redis_uri: Option<String>,
+    /// Hex-encoded 32-byte secret
+    pub server_secret: Option<String>,
+    pub btp_port: Option<u16>,
+    pub http_port: Option<u16>,
+    pub admin_port: Option<u16>,
+    pub admin_bind_address: Option<IpAddr>,
+    pub admin_socket: Option<PathBuf>,
+    pub record_rejected_transactions: Option<bool>,
+    /// Hex-encoded 32-byte seed of the Ed25519 key the node signs the routes it advertises with.
+    /// There is no command-line flag for it, so it does not show up in the process list.
+    pub route_signing_key: Option<String>,
+    /// Hex-encoded Ed25519 public keys, by ILP address, of the nodes whose route signatures are checked
+    pub route_public_keys: Option<HashMap<String, String>>,
+    /// Accept unverified routes from origins that are not in `route_public_keys`,
+    /// which are otherwise rejected once any public keys are set
+    pub allow_unknown_route_origins: Option<bool>,
+    /// Choose between the routes peers advertise for the same prefix by their cost,
+    /// using these weights (any that are not set keep their defaults)
+    pub route_selection: Option<RouteSelection>,
+    /// Reject ILP, BTP, and CCP packets that are not canonically encoded or have trailing bytes
+    pub strict_parsing: Option<bool>,
+    /// Accounts that are inserted into the store if no account with the same ILP address exists.
+    /// The first account becomes the node's default account when the store is empty.
+    pub accounts: Vec<AccountConfig>,
+    /// Exchange rates by asset code. If present, these replace all of the rates in the store.
+    pub rates: Option<HashMap<String, f64>>,
+    /// Static routes from ILP address prefixes to the ILP address of the account packets
+    /// should be sent to. If present, these replace all of the static routes in the store.
+    pub routes: Option<HashMap<String, String>>,
+}
+
+/// An account in the config file. The fields are the same as the ones accepted
+/// by the API, except that the ILP address is a string.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub ilp_address: String,
+    pub asset_code: String,
+    pub asset_scale: u8,
+    #[serde(default = "u64::max_value")]
+    pub max_packet_amount: u64,
+    #[serde(default)]
+    pub min_balance: i64,
+    pub http_endpoint: Option<String>,
+    pub http_incoming_authorization: Option<String>,
+    pub http_outgoing_authorization: Option<String>,
+    pub btp_uri: Option<String>,
+    pub btp_incoming_authorization: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
+    pub xrp_address: Option<String>,
+    pub settle_threshold: Option<i64>,
+    pub settle_to: Option<i64>,
+    #[serde(default)]
+    pub send_routes: bool,
+    #[serde(default)]
+    pub receive_routes: bool,
+    pub routing_relation: Option<String>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_balance_threshold: Option<i64>,
+}
+
+impl AccountConfig {
+    fn settings(&self) -> AccountSettings {
+        AccountSettings {
+            max_packet_amount: self.max_packet_amount,
+            min_balance: self.min_balance,
+            settle_threshold: self.settle_threshold,
+            settle_to: self.settle_to,
+            send_routes: self.send_routes,
+            receive_routes: self.receive_routes,
+        }
+    }
+}
+
+impl From<AccountConfig> for AccountDetails {
+    fn from(account: AccountConfig) -> Self {
+        AccountDetails {
+            ilp_address: account.ilp_address.into_bytes(),
+            asset_code: account.asset_code,
+            asset_scale: account.asset_scale,
+            max_packet_amount: account.max_packet_amount,
+            min_balance: account.min_balance,
+            http_endpoint: account.http_endpoint,
+            http_incoming_authorization: account.http_incoming_authorization,
+            http_outgoing_authorization: account.http_outgoing_authorization,
+            btp_uri: account.btp_uri,
+            btp_incoming_authorization: account.btp_incoming_authorization,
+            is_admin: account.is_admin,
+            xrp_address: account.xrp_address,
+            settle_threshold: account.settle_threshold,
+            settle_to: account.settle_to,
+            send_routes: account.send_routes,
+            receive_routes: account.receive_routes,
+            routing_relation: account.routing_relation,
+            webhook_url: account.webhook_url,
+            webhook_secret: account.webhook_secret,
+            webhook_balance_threshold: account.webhook_balance_threshold,
+        }
+    }
+}
+
+enum Format {
+    Toml,
+    Yaml,
+}
+
+impl NodeConfig {
+    /// Load the config from a `.toml`, `.yaml`, or `.yml` file, substituting environment
+    /// variables and then applying the `ILP_SERVER_SECRET`, `ILP_REDIS_URI`, and
+    /// `ILP_ROUTE_SIGNING_KEY` overrides.
+    pub fn from_file(path: &Path) -> Result<NodeConfig, ConfigError> {
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => return Err(ConfigError::UnknownFormat(path.to_path_buf())),
+        };
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let lookup = |name: &str| env::var(name).ok();
+        let mut config = NodeConfig::parse(&contents, &format, lookup)?;
+        config.apply_overrides(lookup);
+        Ok(config)
+    }
+
+    /// A config with only the settings given by environment variables.
+    pub fn from_env() -> NodeConfig {
+        let mut config = NodeConfig::default();
+        config.apply_overrides(|name| env::var(name).ok());
+        config
+    }
+
+    fn parse<F>(contents: &str, format: &Format, lookup: F) -> Result<NodeConfig, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let contents = substitute_env_vars(contents, lookup)?;
+        match format {
+            Format::Toml => toml::from_str(&contents).map_err(ConfigError::Toml),
+            Format::Yaml => serde_yaml::from_str(&contents).map_err(ConfigError::Yaml),
+        }
+    }
+
+    fn apply_overrides<F>(&mut self, lookup: F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(secret) = lookup(SERVER_SECRET_ENV_VAR) {
+            self.server_secret = Some(secret);
+        }
+        if let Some(uri) = lookup(REDIS_URI_ENV_VAR) {
+            self.redis_uri = Some(uri);
+        }
+        if let Some(key) = lookup(ROUTE_SIGNING_KEY_ENV_VAR) {
+            self.route_signing_key = Some(key);
+        }
+    }
+}
+
+/// Make the store match the accounts, rates, and static routes in the config.
+///
+/// Accounts are identified by their ILP address. The ones that are not in the store yet
+/// are inserted in the order they are listed, and existing accounts whose limits or
+/// routing options differ from the config are updated.
+pub fn reconcile<T, A>(store: T, config: NodeConfig) -> impl Future<Item = (), Error = StoreError>
+where
+    T: NodeStore<Account = A>,
+    A: NodeAccount + IldcpAccount + 'static,
+{
+    apply_changes(store, &NodeConfig::default(), config)
+}
+
+/// Apply the differences between the config that was applied before and a new one.
+///
+/// Accounts that are not in the store yet are inserted, and accounts whose limits or
+/// routing options differ from the stored ones are updated in place. Rates and static
+/// routes are replaced only if they changed since the `previous` config.
+pub fn apply_changes<T, A>(
+    store: T,
+    previous: &NodeConfig,
+    config: NodeConfig,
+) -> impl Future<Item = (), Error = StoreError>
+where
+    T: NodeStore<Account = A>,
+    A: NodeAccount + IldcpAccount + 'static,
+{
+    let NodeConfig {
+        accounts,
+        rates,
+        routes,
+        ..
+    } = config;
+    let rates = if rates != previous.rates { rates } else { None };
+    let routes = if routes != previous.routes {
+        routes
+    } else {
+        None
+    };
+
+    store
+        .get_all_accounts()
+        .and_then(move |existing| {
+            let addresses: HashMap<Vec<u8>, A::AccountId> = existing
+                .iter()
+                .map(|account| (account.client_address().to_vec(), account.id()))
+                .collect();
+            // Compare with the stored accounts rather than the previous config so that
+            // edits to the file made while the node was stopped are also applied
+            let stored_settings: HashMap<&[u8], AccountSettings> = existing
+                .iter()
+                .map(|account| (account.client_address(), account.settings()))
+                .collect();
+            let changes: Vec<AccountChange<A::AccountId>> = accounts
+                .into_iter()
+                .filter_map(
+                    |account| match addresses.get(account.ilp_address.as_bytes()) {
+                        None => Some(AccountChange::Insert(Box::new(account))),
+                        Some(account_id) => {
+                            let settings = account.settings();
+                            if stored_settings.get(account.ilp_address.as_bytes())
+                                == Some(&settings)
+                            {
+                                None
+                            } else {
+                                Some(AccountChange::Update(*account_id, settings))
+                            }
+                        }
+                    },
+                )
+                .collect();
+            stream::iter_ok(changes).fold((store, addresses), |(store, mut addresses), change| {
+                match change {
+                    AccountChange::Insert(account) => {
+                        Either::A(store.insert_account(AccountDetails::from(*account)).map(
+                            move |inserted| {
+                                info!(
+                                    "Added account {} from config: {}",
+                                    inserted.id(),
+                                    str::from_utf8(inserted.client_address())
+                                        .unwrap_or("<not utf8>")
+                                );
+                                addresses.insert(inserted.client_address().to_vec(), inserted.id());
+                                (store, addresses)
+                            },
+                        ))
+                    }
+                    AccountChange::Update(account_id, settings) => {
+                        Either::B(store.update_account_settings(account_id, settings).map(
+                            move |_| {
+                                info!("Updated the settings of account {} from config", account_id);
+                                (store, addresses)
+                            },
+                        ))
+                    }
+                }
+            })
+        })
+        .and_then(move |(store, addresses)| {
+            let set_rates = match rates {
+                Some(rates) => Either::A(store.set_rates(rates)),
+                None => Either::B(ok(())),
+            };
+            let routes = routes.map(|routes| {
+                routes
+                    .into_iter()
+                    .map(
+                        |(prefix, address)| match addresses.get(address.as_bytes()) {
+                            Some(account_id) => Ok((prefix, *account_id)),
+                            None => Err(StoreError::NotFound(format!(
+                                "No account with ILP address {} for the static route to {}",
+                                address, prefix
+                            ))),
+                        },
+                    )
+                    .collect::<Result<Vec<(String, A::AccountId)>, StoreError>>()
+            });
+            set_rates.and_then(move |_| match routes {
+                Some(routes) => Either::A(
+                    result(routes).and_then(move |routes| store.set_static_routes(routes)),
+                ),
+                None => Either::B(ok(())),
+            })
+        })
+}
+
+enum AccountChange<I> {
+    Insert(Box<AccountConfig>),
+    Update(I, AccountSettings),
+}
+
+/// Reload the config file whenever it is modified or the process receives `SIGHUP`,
+/// and apply the changes to the store with `apply_changes`.
+///
+/// `config` should be the config that was already applied. If the new file cannot be
+/// loaded or applied, the error is logged and the node keeps running with what it has.
+/// Open BTP connections are not closed, so peers connected over BTP keep the maximum
+/// packet amount they had when they connected until they reconnect.
+pub fn watch<T, A>(
+    store: T,
+    path: PathBuf,
+    config: NodeConfig,
+) -> impl Future<Item = (), Error = ()>
+where
+    T: NodeStore<Account = A>,
+    A: NodeAccount + IldcpAccount + 'static,
+{
+    let modified = modified_time(&path);
+    Interval::new_interval(WATCH_INTERVAL)
+        .map(|_| false)
+        .map_err(|err| error!("Timer error while watching config file: {:?}", err))
+        .select(reload_signals())
+        .fold((config, modified), move |(config, modified), signaled| {
+            let now_modified = modified_time(&path);
+            if !signaled && now_modified == modified {
+                return Either::A(ok((config, modified)));
+            }
+
+            info!("Reloading config from {}", path.display());
+            let new_config = match NodeConfig::from_file(&path) {
+                Ok(new_config) => new_config,
+                Err(err) => {
+                    error!("Unable to reload config: {}", err);
+                    return Either::A(ok((config, now_modified)));
+                }
+            };
+            log_ignored_changes(&config, &new_config);
+            Either::B(
+                apply_changes(store.clone(), &config, new_config.clone()).then(move |result| {
+                    match result {
+                        Ok(_) => {
+                            info!("Applied changes from config");
+                            Ok((new_config, now_modified))
+                        }
+                        Err(err) => {
+                            // Keep the old config so the changes are tried again on the next reload
+                            error!("Unable to apply changes from config: {}", err);
+                            Ok((config, now_modified))
+                        }
+                    }
+                }),
+            )
+        })
+        .map(|_| ())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(unix)]
+fn reload_signals() -> Box<Stream<Item = bool, Error = ()> + Send> {
+    use tokio_signal::unix::{Signal, SIGHUP};
+    Box::new(
+        Signal::new(SIGHUP)
+            .flatten_stream()
+            .map(|_| true)
+            .map_err(|err| error!("Error listening for SIGHUP: {:?}", err)),
+    )
+}
+
+#[cfg(not(unix))]
+fn reload_signals() -> Box<Stream<Item = bool, Error = ()> + Send> {
+    Box::new(stream::empty())
+}
+
+/// Warn about the parts of the config that are only read when the node starts.
+fn log_ignored_changes(previous: &NodeConfig, config: &NodeConfig) {
+    let node_settings = |config: &NodeConfig| NodeConfig {
+        accounts: Vec::new(),
+        rates: None,
+        routes: None,
+        ..config.clone()
+    };
+    if node_settings(previous) != node_settings(config) {
+        warn!("The node's ports, Redis URI, and secret cannot be changed without restarting it");
+    }
+
+    for account in config.accounts.iter() {
+        let changed = previous
+            .accounts
+            .iter()
+            .find(|previous| previous.ilp_address == account.ilp_address)
+            .map(|previous| {
+                let mut previous = previous.clone();
+                previous.max_packet_amount = account.max_packet_amount;
+                previous.min_balance = account.min_balance;
+                previous.settle_threshold = account.settle_threshold;
+                previous.settle_to = account.settle_to;
+                previous.send_routes = account.send_routes;
+                previous.receive_routes = account.receive_routes;
+                previous != *account
+            })
+            .unwrap_or(false);
+        if changed {
+            warn!(
+                "Only the limits and routing options of account {} were updated. Its other details cannot be changed from the config",
+                account.ilp_address
+            );
+        }
+    }
+    for previous in previous.accounts.iter() {
+        if !config
+            .accounts
+            .iter()
+            .any(|account| account.ilp_address == previous.ilp_address)
+        {
+            warn!(
+                "Account {} was removed from the config but accounts are never deleted from the store",
+                previous.ilp_address
+            );
+        }
+    }
+}
+
+/// Replace every `${VAR}` with the value of the environment variable `VAR`.
+fn substitute_env_vars<F>(contents: &str, lookup: F) -> Result<String, ConfigError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| ConfigError::MissingEnvVar(after.to_string()))?;
+        let name = &after[..end];
+        let value = lookup(name).ok_or_else(|| ConfigError::MissingEnvVar(name.to_string()))?;
+        result.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::err;
+    use interledger_api::ApiScope;
+    use interledger_http::HttpAccount;
+    use interledger_service::Account;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use url::Url;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "ALICE_TOKEN" => Some("alice-secret".to_string()),
+            "ILP_SERVER_SECRET" => Some("00".repeat(32)),
+            "ILP_ROUTE_SIGNING_KEY" => Some("01".repeat(32)),
+            _ => None,
+        }
+    }
+
+    static TOML_CONFIG: &str = r#"
+redis_uri = "redis://127.0.0.1:6380"
+server_secret = "ff"
+http_port = 8000
+strict_parsing = true
+
+[[accounts]]
+ilp_address = "example.node"
+asset_code = "XRP"
+asset_scale = 9
+is_admin = true
+http_incoming_authorization = "Bearer admin"
+
+[[accounts]]
+ilp_address = "example.alice"
+asset_code = "XRP"
+asset_scale = 9
+http_outgoing_authorization = "Bearer ${ALICE_TOKEN}"
+routing_relation = "Peer"
+
+[rates]
+XRP = 1.0
+
+[routes]
+"example.bob" = "example.alice"
+
+[route_public_keys]
+"example.alice" = "0101"
+
+[route_selection]
+failure_cost = 20.0
+spread = 0.01
+"#;
+
+    static YAML_CONFIG: &str = r#"
+redis_uri: redis://127.0.0.1:6380
+server_secret: ff
+http_port: 8000
+strict_parsing: true
+accounts:
+  - ilp_address: example.node
+    asset_code: XRP
+    asset_scale: 9
+    is_admin: true
+    http_incoming_authorization: Bearer admin
+  - ilp_address: example.alice
+    asset_code: XRP
+    asset_scale: 9
+    http_outgoing_authorization: Bearer ${ALICE_TOKEN}
+    routing_relation: Peer
+rates:
+  XRP: 1.0
+routes:
+  example.bob: example.alice
+route_public_keys:
+  example.alice: "0101"
+route_selection:
+  failure_cost: 20.0
+  spread: 0.01
+"#;
+
+    #[test]
+    fn toml_and_yaml_are_equivalent() {
+        let toml = NodeConfig::parse(TOML_CONFIG, &Format::Toml, env).unwrap();
+        let yaml = NodeConfig::parse(YAML_CONFIG, &Format::Yaml, env).unwrap();
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.http_port, Some(8000));
+        assert_eq!(toml.btp_port, None);
+        assert_eq!(toml.strict_parsing, Some(true));
+        assert_eq!(toml.accounts.len(), 2);
+        assert_eq!(toml.accounts[1].max_packet_amount, u64::MAX);
+        assert_eq!(
+            toml.routes.unwrap().get("example.bob"),
+            Some(&"example.alice".to_string())
+        );
+        assert_eq!(
+            toml.route_public_keys.unwrap().get("example.alice"),
+            Some(&"0101".to_string())
+        );
+        assert_eq!(
+            toml.route_selection,
+            Some(RouteSelection {
+                failure_cost: 20.0,
+                spread: 0.01,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn substitutes_and_overrides_secrets_from_env() {
+        let mut config = NodeConfig::parse(TOML_CONFIG, &Format::Toml, env).unwrap();
+        assert_eq!(
+            config.accounts[1].http_outgoing_authorization,
+            Some("Bearer alice-secret".to_string())
+        );
+        config.apply_overrides(env);
+        assert_eq!(config.server_secret, Some("00".repeat(32)));
+        assert_eq!(config.route_signing_key, Some("01".repeat(32)));
+        assert_eq!(config.redis_uri, Some("redis://127.0.0.1:6380".to_string()));
+    }
+
+    #[test]
+    fn rejects_unset_env_vars() {
+        let result = NodeConfig::parse("server_secret = \"${NOT_SET}\"", &Format::Toml, env);
+        match result {
+            Err(ConfigError::MissingEnvVar(name)) => assert_eq!(name, "NOT_SET"),
+            other => panic!("Expected missing env var error, got: {:?}", other),
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct TestAccount {
+        id: u64,
+        ilp_address: Vec<u8>,
+        settings: AccountSettings,
+    }
+
+    impl Account for TestAccount {
+        type AccountId = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl IldcpAccount for TestAccount {
+        fn client_address(&self) -> &[u8] {
+            &self.ilp_address[..]
+        }
+
+        fn asset_code(&self) -> &str {
+            "XRP"
+        }
+
+        fn asset_scale(&self) -> u8 {
+            9
+        }
+    }
+
+    impl HttpAccount for TestAccount {
+        fn get_http_url(&self) -> Option<&Url> {
+            None
+        }
+
+        fn get_http_auth_header(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    impl NodeAccount for TestAccount {
+        fn is_admin(&self) -> bool {
+            false
+        }
+
+        fn settings(&self) -> AccountSettings {
+            self.settings.clone()
+        }
+    }
+
+    type Shared<T> = Arc<Mutex<T>>;
+
+    #[derive(Clone, Default)]
+    struct TestStore {
+        accounts: Shared<Vec<TestAccount>>,
+        updated: Shared<Vec<(u64, AccountSettings)>>,
+        rates: Shared<Option<Vec<(String, f64)>>>,
+        routes: Shared<Option<Vec<(String, u64)>>>,
+        api_tokens: Shared<HashMap<String, Vec<ApiScope>>>,
+    }
+
+    impl NodeStore for TestStore {
+        type Account = TestAccount;
+
+        fn insert_account(
+            &self,
+            account: AccountDetails,
+        ) -> Box<Future<Item = TestAccount, Error = StoreError> + Send> {
+            let mut accounts = self.accounts.lock();
+            let account = TestAccount {
+                id: accounts.len() as u64,
+                settings: AccountSettings::from(&account),
+                ilp_address: account.ilp_address,
+            };
+            accounts.push(account.clone());
+            Box::new(ok(account))
+        }
+
+        fn get_all_accounts(
+            &self,
+        ) -> Box<Future<Item = Vec<TestAccount>, Error = StoreError> + Send> {
+            Box::new(ok(self.accounts.lock().clone()))
+        }
+
+        fn ping(&self) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            Box::new(ok(()))
+        }
+
+        fn rates_updated_at(&self) -> Option<SystemTime> {
+            None
+        }
+
+        fn update_account_settings(
+            &self,
+            account_id: u64,
+            settings: AccountSettings,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            match self.accounts.lock().get_mut(account_id as usize) {
+                Some(account) => account.settings = settings.clone(),
+                None => {
+                    return Box::new(err(StoreError::NotFound(format!(
+                        "Account {} does not exist",
+                        account_id
+                    ))))
+                }
+            }
+            self.updated.lock().push((account_id, settings));
+            Box::new(ok(()))
+        }
+
+        fn set_rates<R>(&self, rates: R) -> Box<Future<Item = (), Error = StoreError> + Send>
+        where
+            R: IntoIterator<Item = (String, f64)>,
+        {
+            *self.rates.lock() = Some(rates.into_iter().collect());
+            Box::new(ok(()))
+        }
+
+        fn set_static_routes<R>(
+            &self,
+            routes: R,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send>
+        where
+            R: IntoIterator<Item = (String, u64)>,
+        {
+            *self.routes.lock() = Some(routes.into_iter().collect());
+            Box::new(ok(()))
+        }
+
+        fn set_static_route(
+            &self,
+            prefix: String,
+            account_id: u64,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            self.routes
+                .lock()
+                .get_or_insert_with(Vec::new)
+                .push((prefix, account_id));
+            Box::new(ok(()))
+        }
+
+        fn insert_api_token(
+            &self,
+            token_id: String,
+            scopes: Vec<ApiScope>,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            self.api_tokens.lock().insert(token_id, scopes);
+            Box::new(ok(()))
+        }
+
+        fn get_api_token_scopes(
+            &self,
+            token_id: &str,
+        ) -> Box<Future<Item = Vec<ApiScope>, Error = StoreError> + Send> {
+            Box::new(result(
+                self.api_tokens
+                    .lock()
+                    .get(token_id)
+                    .cloned()
+                    .ok_or_else(|| StoreError::NotFound("No API token with that ID".to_string())),
+            ))
+        }
+
+        fn delete_api_token(
+            &self,
+            token_id: &str,
+        ) -> Box<Future<Item = (), Error = StoreError> + Send> {
+            self.api_tokens.lock().remove(token_id);
+            Box::new(ok(()))
+        }
+    }
+
+    #[test]
+    fn applies_only_the_changes() {
+        let store = TestStore::default();
+        let previous = NodeConfig::parse(TOML_CONFIG, &Format::Toml, env).unwrap();
+        reconcile(store.clone(), previous.clone()).wait().unwrap();
+        assert_eq!(store.accounts.lock().len(), 2);
+        assert_eq!(
+            *store.routes.lock(),
+            Some(vec![("example.bob".to_string(), 1)])
+        );
+        store.rates.lock().take();
+        store.routes.lock().take();
+
+        let mut config = previous.clone();
+        config.accounts[1].min_balance = -500;
+        config.accounts.push(AccountConfig {
+            ilp_address: "example.carl".to_string(),
+            ..config.accounts[1].clone()
+        });
+        config.routes = Some(HashMap::new());
+        apply_changes(store.clone(), &previous, config.clone())
+            .wait()
+            .unwrap();
+
+        assert_eq!(store.accounts.lock().len(), 3);
+        let updated = store.updated.lock();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].0, 1);
+        assert_eq!(updated[0].1, config.accounts[1].settings());
+        assert!(store.rates.lock().is_none());
+        assert_eq!(*store.routes.lock(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn applies_settings_changed_while_stopped() {
+        let store = TestStore::default();
+        let mut config = NodeConfig::parse(TOML_CONFIG, &Format::Toml, env).unwrap();
+        reconcile(store.clone(), config.clone()).wait().unwrap();
+        assert!(store.updated.lock().is_empty());
+
+        // Restarting with an edited file updates the stored account
+        config.accounts[1].max_packet_amount = 1000;
+        reconcile(store.clone(), config.clone()).wait().unwrap();
+        assert_eq!(
+            *store.updated.lock(),
+            vec![(1, config.accounts[1].settings())]
+        );
+        assert_eq!(store.accounts.lock()[1].settings.max_packet_amount, 1000);
+    }
+}
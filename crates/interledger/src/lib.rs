@@ -20,6 +20,10 @@ pub mod service {
 #[cfg(feature = "cli")]
 pub mod cli;
 
+#[doc(hidden)]
+#[cfg(feature = "cli")]
+pub mod config;
+
 /// Bilateral Transport Protocol (BTP) client and server
 #[cfg(feature = "btp")]
 pub mod btp {
@@ -123,6 +127,12 @@ pub mod spsp {
     pub use interledger_spsp::*;
 }
 
+/// Signed HTTP notifications about payments received by accounts
+#[cfg(feature = "webhooks")]
+pub mod webhooks {
+    pub use interledger_webhooks::*;
+}
+
 /// Miscellaneous services
 #[cfg(feature = "service-util")]
 pub mod service_util {
@@ -3,17 +3,30 @@ extern crate interledger;
 extern crate clap;
 
 use base64;
-use clap::{App, Arg, ArgGroup, SubCommand};
+use bytes::Bytes;
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+use futures::{
+    future::{self, Either},
+    Future,
+};
 use hex;
 use interledger::cli::*;
+use interledger::config::NodeConfig;
+use interledger_ccp::RouteAuth;
 use interledger_ildcp::IldcpResponseBuilder;
-use tokio;
+use interledger_packet::Address;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    process,
+    str::FromStr,
+};
+use tokio::{self, runtime::Runtime};
+use tracing_subscriber::EnvFilter;
 use url::Url;
 
 #[allow(clippy::cyclomatic_complexity)]
 pub fn main() {
-    env_logger::init();
-
     let moneyd_uri = format!(
         "btp+ws://{}:{}@localhost:7768",
         random_token(),
@@ -21,6 +34,12 @@ pub fn main() {
     );
     let mut app = App::new("interledger")
         .about("Blazing fast Interledger CLI written in Rust")
+        .arg(
+            Arg::with_name("log_json")
+                .long("log_json")
+                .global(true)
+                .help("Write logs as JSON, including the details of the packet being handled (filtered by RUST_LOG)"),
+        )
         .subcommands(vec![
             SubCommand::with_name("spsp")
                 .about("Client and Server for the Simple Payment Setup Protocol (SPSP)")
@@ -106,6 +125,11 @@ pub fn main() {
                 SubCommand::with_name("node")
                     .about("Run an Interledger node (sender, connector, receiver bundle)")
                     .args(&[
+                        Arg::with_name("config")
+                            .long("config")
+                            .short("c")
+                            .takes_value(true)
+                            .help("TOML or YAML file with the node's settings and the accounts, rates, and static routes to add to the store. Flags given on the command line take precedence over the file"),
                         Arg::with_name("redis_uri")
                             .long("redis_uri")
                             .default_value("redis://127.0.0.1:6379"),
@@ -114,11 +138,37 @@ pub fn main() {
                             .default_value("7768"),
                         Arg::with_name("http_port")
                             .long("http_port")
-                            .default_value("7770"),
+                            .default_value("7770")
+                            .help("Port to listen for ILP-over-HTTP and SPSP requests on"),
+                        Arg::with_name("admin_port")
+                            .long("admin_port")
+                            .default_value("7771")
+                            .help("Port for the admin API. Must be different from the http_port so the admin API is never served publicly by accident"),
+                        Arg::with_name("admin_bind_address")
+                            .long("admin_bind_address")
+                            .default_value("127.0.0.1")
+                            .help("IP address to bind the admin API to. Defaults to localhost so it is not reachable from other machines"),
+                        Arg::with_name("admin_socket")
+                            .long("admin_socket")
+                            .takes_value(true)
+                            .conflicts_with_all(&["admin_port", "admin_bind_address"])
+                            .help("Path of a Unix domain socket to serve the admin API on instead of a TCP port"),
                         Arg::with_name("server_secret")
                             .long("server_secret")
-                            .help("Cryptographic seed used to derive keys for STREAM, specified in hex")
+                            .help("Cryptographic seed used to derive keys for STREAM and for encrypting account credentials in the database, specified in hex")
                             .takes_value(true),
+                        Arg::with_name("record_rejected_transactions")
+                            .long("record_rejected_transactions")
+                            .help("Record rejected packets in the transaction ledger as well as fulfilled ones"),
+                        Arg::with_name("allow_unknown_route_origins")
+                            .long("allow_unknown_route_origins")
+                            .help("Accept routes from origins that the config file has no public key for. By default they are rejected once any public keys are configured"),
+                        Arg::with_name("strict_parsing")
+                            .long("strict_parsing")
+                            .help("Reject ILP, BTP, and CCP packets from peers that are not canonically encoded or have bytes after the end of the packet"),
+                        Arg::with_name("cost_aware_routing")
+                            .long("cost_aware_routing")
+                            .help("Choose between peers' routes by their failure rate, latency, exchange rate and weight rather than only by path length. The weights can be set in the config file's route_selection section"),
                     ])
                     .group(ArgGroup::with_name("redis_connector").requires_all(&["redis_uri", "btp_port", "http_port"]))
                     .subcommand(SubCommand::with_name("accounts")
@@ -128,6 +178,11 @@ pub fn main() {
                                 .long("redis_uri")
                                 .help("Redis database to add the account to")
                                 .default_value("redis://127.0.0.1:6379"),
+                            Arg::with_name("server_secret")
+                                .long("server_secret")
+                                .help("Secret of the node this account is added to, used to encrypt the account's outgoing credentials, specified in hex")
+                                .takes_value(true)
+                                .required(true),
                             Arg::with_name("ilp_address")
                                 .long("ilp_address")
                                 .help("ILP Address of this account")
@@ -188,22 +243,39 @@ pub fn main() {
                                 .long("min_balance")
                                 .help("Minimum balance this account is allowed to have (can be negative)")
                                 .default_value("0"),
+                            Arg::with_name("webhook_url")
+                                .long("webhook_url")
+                                .help("URL the node should POST notifications to when this account receives a payment")
+                                .takes_value(true),
+                            Arg::with_name("webhook_secret")
+                                .long("webhook_secret")
+                                .help("Key used to sign the webhook notifications with HMAC-SHA256")
+                                .requires("webhook_url")
+                                .takes_value(true),
+                            Arg::with_name("webhook_balance_threshold")
+                                .long("webhook_balance_threshold")
+                                .help("Also notify the webhook when the account's balance goes above or below this amount")
+                                .requires("webhook_url")
+                                .takes_value(true),
                         ])
                         .group(ArgGroup::with_name("account_admin").arg("admin").requires("http_incoming_token")))),
         ]);
 
-    match app.clone().get_matches().subcommand() {
+    let matches = app.clone().get_matches();
+    init_logging(matches.is_present("log_json"));
+
+    match matches.subcommand() {
         ("spsp", Some(matches)) => match matches.subcommand() {
             ("server", Some(matches)) => {
                 let port = value_t!(matches, "port", u16).expect("Invalid port");
                 let quiet = matches.is_present("quiet");
                 if matches.is_present("ilp_over_http") {
-                    let client_address =
-                        value_t!(matches, "ilp_address", String).expect("ilp_address is required");
+                    let client_address = value_t!(matches, "ilp_address", Address)
+                        .expect("A valid ilp_address is required");
                     let auth_token = value_t!(matches, "incoming_auth_token", String)
                         .expect("incoming_auth_token is required");
                     let ildcp_info = IldcpResponseBuilder {
-                        client_address: &client_address.as_bytes(),
+                        client_address: &client_address,
                         asset_code: "",
                         asset_scale: 0,
                     }
@@ -248,14 +320,14 @@ pub fn main() {
         ("moneyd", Some(matches)) => match matches.subcommand() {
             ("local", Some(matches)) => {
                 let btp_port = value_t!(matches, "port", u16).expect("btp_port is required");
-                let ilp_address =
-                    value_t!(matches, "ilp_address", String).expect("ilp_address is required");
+                let ilp_address = value_t!(matches, "ilp_address", Address)
+                    .expect("A valid ilp_address is required");
                 let asset_code =
                     value_t!(matches, "asset_code", String).expect("asset_code is required");
                 let asset_scale =
                     value_t!(matches, "asset_scale", u8).expect("asset_scale is required");
                 let ildcp_info = IldcpResponseBuilder {
-                    client_address: ilp_address.as_str().as_bytes(),
+                    client_address: &ilp_address,
                     asset_code: &asset_code,
                     asset_scale,
                 }
@@ -319,36 +391,176 @@ pub fn main() {
                         send_routes: matches.is_present("send_routes"),
                         receive_routes: matches.is_present("receive_routes"),
                         routing_relation: value_t!(matches, "routing_relation", String).ok(),
+                        webhook_url: matches.value_of("webhook_url").map(|s| s.to_string()),
+                        webhook_secret: matches.value_of("webhook_secret").map(|s| s.to_string()),
+                        webhook_balance_threshold: value_t!(
+                            matches,
+                            "webhook_balance_threshold",
+                            i64
+                        )
+                        .ok(),
                     };
-                    tokio::run(insert_account_redis(redis_uri, account));
+                    let server_secret = parse_server_secret(matches.value_of("server_secret"));
+                    tokio::run(insert_account_redis(redis_uri, &server_secret, account));
                 }
                 _ => app.print_help().unwrap(),
             },
             _ => {
-                let redis_uri =
-                    value_t!(matches, "redis_uri", String).expect("redis_uri is required");
+                let config = match matches.value_of("config") {
+                    Some(path) => NodeConfig::from_file(Path::new(path)).unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        process::exit(1)
+                    }),
+                    None => NodeConfig::from_env(),
+                };
+                let redis_uri: String =
+                    node_setting(matches, "redis_uri", config.redis_uri.clone());
                 let redis_uri = Url::parse(&redis_uri).expect("redis_uri is not a valid URI");
-                let btp_port = value_t!(matches, "btp_port", u16).expect("btp_port is required");
-                let http_port = value_t!(matches, "http_port", u16).expect("http_port is required");
-                let server_secret: [u8; 32] = if let Some(secret) =
-                    matches.value_of("server_secret")
-                {
-                    let mut server_secret = [0; 32];
-                    let decoded = hex::decode(secret).expect("server_secret must be hex-encoded");
-                    assert_eq!(decoded.len(), 32, "server_secret must be 32 bytes");
-                    server_secret.clone_from_slice(&decoded);
-                    server_secret
+                let btp_port: u16 = node_setting(matches, "btp_port", config.btp_port);
+                let http_port: u16 = node_setting(matches, "http_port", config.http_port);
+                let http_address: SocketAddr = ([0, 0, 0, 0], http_port).into();
+                let admin_address = parse_admin_address(matches, &config, http_address);
+                let server_secret = if matches.is_present("server_secret") {
+                    parse_server_secret(matches.value_of("server_secret"))
                 } else {
-                    random_secret()
+                    parse_server_secret(config.server_secret.as_deref())
                 };
-                tokio::run(run_node_redis(
-                    redis_uri,
+                let options = NodeOptions {
+                    record_rejected_transactions: matches
+                        .is_present("record_rejected_transactions")
+                        || config.record_rejected_transactions.unwrap_or(false),
+                    route_auth: parse_route_auth(matches, &config),
+                    route_selection: if matches.is_present("cost_aware_routing") {
+                        Some(config.route_selection.clone().unwrap_or_default())
+                    } else {
+                        config.route_selection.clone()
+                    },
+                    strict_parsing: matches.is_present("strict_parsing")
+                        || config.strict_parsing.unwrap_or(false),
+                };
+                let node = run_node_redis(
+                    redis_uri.clone(),
                     ([0, 0, 0, 0], btp_port).into(),
-                    ([0, 0, 0, 0], http_port).into(),
+                    http_address,
+                    admin_address,
                     &server_secret,
-                ));
+                    options,
+                    shutdown_signal(),
+                );
+                // Add the configured accounts before starting, since the node needs the default account.
+                // The config file is then watched for changes
+                let setup = if let Some(path) = matches.value_of("config") {
+                    Either::A(configure_node_redis(
+                        redis_uri,
+                        &server_secret,
+                        path.into(),
+                        config,
+                    ))
+                } else {
+                    Either::B(future::ok(()))
+                };
+                let mut runtime = Runtime::new().expect("Unable to start the runtime");
+                if runtime.block_on(setup.and_then(|_| node)).is_err() {
+                    process::exit(1);
+                }
+                // Drop the tasks that are still running, like idle HTTP connections and the config watcher
+                runtime.shutdown_now().wait().ok();
             }
         },
         _ => app.print_help().unwrap(),
     }
 }
+
+fn init_logging(json: bool) {
+    // Log lines from the `log` macros are also captured, along with the span of the packet they relate to
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    if json {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+}
+
+/// Use the flag if it was given on the command line, then the value from the config file,
+/// and then the flag's default value.
+fn node_setting<T: FromStr>(matches: &ArgMatches, name: &str, from_config: Option<T>) -> T {
+    match from_config {
+        Some(value) if matches.occurrences_of(name) == 0 => value,
+        _ => value_t!(matches, name, T).unwrap_or_else(|err| err.exit()),
+    }
+}
+
+fn parse_admin_address(
+    matches: &ArgMatches,
+    config: &NodeConfig,
+    http_address: SocketAddr,
+) -> AdminAddress {
+    #[cfg(unix)]
+    {
+        if let Some(path) = matches.value_of("admin_socket") {
+            return AdminAddress::Unix(path.into());
+        }
+        // A port or address given on the command line replaces the socket from the config file
+        let tcp_flags =
+            matches.occurrences_of("admin_port") + matches.occurrences_of("admin_bind_address");
+        if let (Some(path), 0) = (&config.admin_socket, tcp_flags) {
+            return AdminAddress::Unix(path.clone());
+        }
+    }
+    let ip: IpAddr = node_setting(matches, "admin_bind_address", config.admin_bind_address);
+    let port: u16 = node_setting(matches, "admin_port", config.admin_port);
+    if port == http_address.port() {
+        eprintln!(
+            "admin_port must be different from http_port ({}), otherwise the admin API would be served publicly",
+            port
+        );
+        process::exit(1);
+    }
+    AdminAddress::Tcp((ip, port).into())
+}
+
+/// Sign the routes the node advertises if it has a key and check the signatures
+/// of the nodes whose public keys are in the config file
+fn parse_route_auth(matches: &ArgMatches, config: &NodeConfig) -> RouteAuth {
+    let allow_unknown_origins = matches.is_present("allow_unknown_route_origins")
+        || config.allow_unknown_route_origins.unwrap_or(false);
+    let mut route_auth = RouteAuth::new().allow_unknown_origins(allow_unknown_origins);
+    // The key is a secret, so it is only read from the config file or ILP_ROUTE_SIGNING_KEY
+    if let Some(ref seed) = config.route_signing_key {
+        route_auth = route_auth.with_signing_key(parse_key("route_signing_key", seed));
+    }
+    if let Some(ref public_keys) = config.route_public_keys {
+        for (ilp_address, public_key) in public_keys.iter() {
+            route_auth = route_auth.with_public_key(
+                Bytes::from(ilp_address.as_str()),
+                parse_key("route_public_keys", public_key),
+            );
+        }
+    }
+    route_auth
+}
+
+fn parse_key(name: &str, key: &str) -> [u8; 32] {
+    match hex::decode(key) {
+        Ok(ref decoded) if decoded.len() == 32 => {
+            let mut key = [0; 32];
+            key.copy_from_slice(decoded);
+            key
+        }
+        _ => {
+            eprintln!("{} must be 32 bytes, specified in hex", name);
+            process::exit(1)
+        }
+    }
+}
+
+fn parse_server_secret(secret: Option<&str>) -> [u8; 32] {
+    // The secret must stay the same across restarts because it is used to
+    // decrypt the account credentials stored in the database
+    let secret = secret.expect("server_secret is required");
+    let mut server_secret = [0; 32];
+    let decoded = hex::decode(secret).expect("server_secret must be hex-encoded");
+    assert_eq!(decoded.len(), 32, "server_secret must be 32 bytes");
+    server_secret.clone_from_slice(&decoded);
+    server_secret
+}
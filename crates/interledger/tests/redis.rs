@@ -3,7 +3,10 @@ extern crate interledger;
 extern crate log;
 
 use env_logger;
-use futures::{future::ok, Future};
+use futures::{
+    future::{self, ok},
+    Future,
+};
 use interledger::cli;
 use std::time::{Duration, Instant};
 use tokio::{runtime::Runtime, timer::Delay};
@@ -42,11 +45,14 @@ fn btp_end_to_end() {
     let connection_info2 = context.get_client_connection_info();
     let connection_info3 = context.get_client_connection_info();
     // let redis_port = 6379;
+    let server_secret = cli::random_secret();
     let btp_port = get_open_port(Some(7768));
     let http_port = get_open_port(Some(7770));
+    let admin_port = get_open_port(Some(7771));
     let run = ok(()).and_then(move |_| {
         let create_accounts = cli::insert_account_redis(
             connection_info1,
+            &server_secret,
             cli::AccountDetails {
                 ilp_address: Vec::from("example.one"),
                 asset_code: "XYZ".to_string(),
@@ -65,11 +71,15 @@ fn btp_end_to_end() {
                 send_routes: false,
                 receive_routes: false,
                 routing_relation: Some("Peer".to_string()),
+                webhook_url: None,
+                webhook_secret: None,
+                webhook_balance_threshold: None,
             },
         )
         .and_then(move |_| {
             cli::insert_account_redis(
                 connection_info2,
+                &server_secret,
                 cli::AccountDetails {
                     ilp_address: Vec::from("example.two"),
                     asset_code: "XYZ".to_string(),
@@ -88,6 +98,9 @@ fn btp_end_to_end() {
                     send_routes: false,
                     receive_routes: false,
                     routing_relation: Some("Peer".to_string()),
+                    webhook_url: None,
+                    webhook_secret: None,
+                    webhook_balance_threshold: None,
                 },
             )
         });
@@ -101,7 +114,10 @@ fn btp_end_to_end() {
                 connection_info3,
                 ([127, 0, 0, 1], btp_port).into(),
                 ([127, 0, 0, 1], http_port).into(),
-                &cli::random_secret(),
+                cli::AdminAddress::Tcp(([127, 0, 0, 1], admin_port).into()),
+                &server_secret,
+                cli::NodeOptions::default(),
+                future::empty(),
             );
             tokio::spawn(connector);
             Ok(())
@@ -18,6 +18,7 @@ async function run() {
     let adminToken = process.env.ADMIN_TOKEN
     let rippled = process.env.XRP_SERVER
     let ilpAddress = process.env.ILP_ADDRESS
+    let serverSecret = process.env.SERVER_SECRET
     const redisDir = process.env.REDIS_DIR || '.'
 
     let shouldCreateAdminAccount = true
@@ -35,10 +36,11 @@ async function run() {
         adminToken = config.adminToken
         rippled = config.rippled
         ilpAddress = config.ilpAddress
+        serverSecret = config.serverSecret
     }
 
-    if (!xrpAddress || !xrpSecret || !adminToken || !ilpAddress) {
-        console.error('Must provide XRP_ADDRESS, XRP_SECRET, ILP_ADDRESS, and ADMIN_TOKEN')
+    if (!xrpAddress || !xrpSecret || !adminToken || !ilpAddress || !serverSecret) {
+        console.error('Must provide XRP_ADDRESS, XRP_SECRET, ILP_ADDRESS, ADMIN_TOKEN, and SERVER_SECRET')
         process.exit(1)
     }
 
@@ -64,6 +66,7 @@ async function run() {
         'accounts',
         'add',
         `--redis_uri=unix:${REDIS_UNIX_SOCKET}`,
+        `--server_secret=${serverSecret}`,
         `--ilp_address=${ilpAddress}`,
         `--xrp_address=${xrpAddress}`,
         `--http_incoming_token=${adminToken}`,
@@ -82,6 +85,9 @@ async function run() {
     const node = spawn('interledger', [
         'node',
         `--redis_uri=unix:${REDIS_UNIX_SOCKET}`,
+        `--server_secret=${serverSecret}`,
+        // The admin API is only reachable through the container's network
+        '--admin_bind_address=0.0.0.0',
     ], {
             stdio: 'inherit',
             env: {
@@ -107,6 +113,8 @@ async function loadConfig() {
 async function generateTestnetCredentials() {
     const adminToken = randomBytes(20).toString('hex')
     console.log(`Admin HTTP Bearer token is: ${adminToken}`)
+    // The server secret encrypts account credentials in the database so it must not change between restarts
+    const serverSecret = randomBytes(32).toString('hex')
 
     console.log('Fetching XRP testnet credentials')
     const faucetResponse = await new Promise((resolve, reject) => {
@@ -143,7 +151,8 @@ async function generateTestnetCredentials() {
             xrpSecret,
             adminToken,
             ilpAddress,
-            rippled
+            rippled,
+            serverSecret
         }))
     } catch (err) {
         console.error("Error writing to config file. XRP Address, Secret, and admin token were not saved.", err)
@@ -153,7 +162,8 @@ async function generateTestnetCredentials() {
         xrpSecret,
         rippled,
         ilpAddress,
-        adminToken
+        adminToken,
+        serverSecret
     }
 }
 